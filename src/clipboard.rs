@@ -0,0 +1,390 @@
+//! System clipboard API
+
+use std::io::{self, Write};
+
+use crate::config::OutputStreamType;
+#[cfg(not(feature = "tracing"))]
+use log::debug;
+#[cfg(feature = "tracing")]
+use tracing::debug;
+
+/// Access to the system clipboard, pasted by `Cmd::PasteClipboard`.
+pub trait ClipboardBackend: Send + Sync {
+    /// Return the text content of the clipboard, if any.
+    fn get_clipboard(&self) -> Option<String>;
+    /// Replace the content of the clipboard with `text`.
+    fn set_clipboard(&self, text: &str);
+}
+
+#[cfg(all(target_os = "macos", feature = "macos-clipboard"))]
+pub use self::macos::MacosClipboard;
+#[cfg(windows)]
+pub use self::windows::WindowsClipboard;
+#[cfg(all(unix, feature = "x11-clipboard"))]
+pub use self::x11::{X11Clipboard, X11Selection};
+
+/// Clipboard used when none is set with `Editor::set_clipboard_backend`:
+/// OSC 52 when the terminal supports it, backed by the platform clipboard.
+#[cfg(not(test))]
+pub(crate) fn default_backend(out: OutputStreamType) -> Option<Box<dyn ClipboardBackend>> {
+    let platform: Option<Box<dyn ClipboardBackend>> = {
+        cfg_if::cfg_if! {
+            if #[cfg(windows)] {
+                Some(Box::new(WindowsClipboard))
+            } else if #[cfg(all(target_os = "macos", feature = "macos-clipboard"))] {
+                Some(Box::new(MacosClipboard::new()))
+            } else {
+                None
+            }
+        }
+    };
+    if crate::tty::TerminalCapabilities::detect().osc52_clipboard {
+        let mut osc52 = Osc52Clipboard::new(out);
+        osc52.fallback = platform;
+        Some(Box::new(osc52))
+    } else {
+        platform
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn default_backend(_: OutputStreamType) -> Option<Box<dyn ClipboardBackend>> {
+    None
+}
+
+/// Clipboard relayed by the terminal emulator with OSC 52 (which also works
+/// over SSH).
+///
+/// The clipboard is only written this way: the text is also given to the
+/// `fallback` backend, which is the one read.
+pub struct Osc52Clipboard {
+    out: OutputStreamType,
+    fallback: Option<Box<dyn ClipboardBackend>>,
+}
+
+impl Osc52Clipboard {
+    /// Send OSC 52 sequences to `out`.
+    pub fn new(out: OutputStreamType) -> Self {
+        Self {
+            out,
+            fallback: None,
+        }
+    }
+
+    /// Backend read by `get_clipboard`.
+    pub fn with_fallback(mut self, fallback: Box<dyn ClipboardBackend>) -> Self {
+        self.fallback = Some(fallback);
+        self
+    }
+
+    fn write(&self, seq: &[u8]) -> io::Result<()> {
+        match self.out {
+            OutputStreamType::Stdout => {
+                io::stdout().write_all(seq)?;
+                io::stdout().flush()
+            }
+            OutputStreamType::Stderr => {
+                io::stderr().write_all(seq)?;
+                io::stderr().flush()
+            }
+            #[cfg(unix)]
+            OutputStreamType::Fd(fd) => {
+                use std::fs::File;
+                use std::mem::ManuallyDrop;
+                use std::os::unix::io::FromRawFd;
+                // the file descriptor is owned by the caller
+                let mut file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+                file.write_all(seq)
+            }
+        }
+    }
+}
+
+impl ClipboardBackend for Osc52Clipboard {
+    fn get_clipboard(&self) -> Option<String> {
+        self.fallback.as_ref()?.get_clipboard()
+    }
+
+    fn set_clipboard(&self, text: &str) {
+        let seq = format!("\x1b]52;c;{}\x07", base64(text.as_bytes()));
+        if let Err(e) = self.write(seq.as_bytes()) {
+            debug!(target: "rustyline", "cannot send OSC 52 sequence: {}", e);
+        }
+        if let Some(ref fallback) = self.fallback {
+            fallback.set_clipboard(text);
+        }
+    }
+}
+
+/// Standard base64 encoding (with padding)
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len() / 3 * 4 + 4);
+    for chunk in data.chunks(3) {
+        let byte = |i| u32::from(chunk.get(i).cloned().unwrap_or(0));
+        let n = byte(0) << 16 | byte(1) << 8 | byte(2);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(char::from(ALPHABET[(n >> (18 - 6 * i)) as usize & 0x3f]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Output of `program`, `None` if it cannot be run or if it fails.
+#[cfg(any(
+    all(unix, feature = "x11-clipboard"),
+    all(target_os = "macos", feature = "macos-clipboard")
+))]
+fn read_from(program: &str, args: &[&str]) -> Option<String> {
+    use std::process::{Command, Stdio};
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok()
+}
+
+/// Feed `text` to `program` on its standard input.
+#[cfg(any(
+    all(unix, feature = "x11-clipboard"),
+    all(target_os = "macos", feature = "macos-clipboard")
+))]
+fn write_to(program: &str, args: &[&str], text: &str) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes())?;
+    }
+    child.wait()?;
+    Ok(())
+}
+
+#[cfg(all(unix, feature = "x11-clipboard"))]
+mod x11 {
+    #[cfg(not(feature = "tracing"))]
+    use log::debug;
+    #[cfg(feature = "tracing")]
+    use tracing::debug;
+
+    use super::{read_from, write_to, ClipboardBackend};
+
+    /// X11 selection
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum X11Selection {
+        /// Selected text, usually pasted with the middle button
+        Primary,
+        /// Text explicitly copied, usually pasted with Ctrl-Shift-V
+        Clipboard,
+    }
+
+    // `#[default]` on a variant would require Rust 1.62
+    #[allow(clippy::derivable_impls)]
+    impl Default for X11Selection {
+        fn default() -> Self {
+            X11Selection::Clipboard
+        }
+    }
+
+    /// X11 clipboard, accessed through the `xclip` program
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct X11Clipboard {
+        selection: X11Selection,
+    }
+
+    impl X11Clipboard {
+        /// Access the `selection` clipboard.
+        pub fn new(selection: X11Selection) -> Self {
+            Self { selection }
+        }
+
+        fn selection(&self) -> &'static str {
+            match self.selection {
+                X11Selection::Primary => "primary",
+                X11Selection::Clipboard => "clipboard",
+            }
+        }
+    }
+
+    impl ClipboardBackend for X11Clipboard {
+        fn get_clipboard(&self) -> Option<String> {
+            read_from("xclip", &["-selection", self.selection(), "-o"])
+        }
+
+        fn set_clipboard(&self, text: &str) {
+            if let Err(e) = write_to("xclip", &["-selection", self.selection(), "-i"], text) {
+                debug!(target: "rustyline", "cannot set X11 clipboard: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(all(target_os = "macos", feature = "macos-clipboard"))]
+mod macos {
+    use std::env;
+
+    #[cfg(not(feature = "tracing"))]
+    use log::debug;
+    #[cfg(feature = "tracing")]
+    use tracing::debug;
+
+    use super::{read_from, write_to, ClipboardBackend};
+
+    /// macOS clipboard, accessed through the `pbpaste` and `pbcopy` programs
+    ///
+    /// Over SSH, these programs would access the clipboard of the remote
+    /// host, so the clipboard is disabled.
+    #[derive(Clone, Copy, Debug)]
+    pub struct MacosClipboard {
+        ssh: bool,
+    }
+
+    impl MacosClipboard {
+        pub fn new() -> Self {
+            let ssh = env::var_os("SSH_CLIENT").is_some() || env::var_os("SSH_TTY").is_some();
+            if ssh {
+                debug!(target: "rustyline", "SSH session: macOS clipboard disabled");
+            }
+            Self { ssh }
+        }
+    }
+
+    impl Default for MacosClipboard {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl ClipboardBackend for MacosClipboard {
+        fn get_clipboard(&self) -> Option<String> {
+            if self.ssh {
+                return None;
+            }
+            read_from("pbpaste", &[])
+        }
+
+        fn set_clipboard(&self, text: &str) {
+            if self.ssh {
+                return;
+            }
+            if let Err(e) = write_to("pbcopy", &[], text) {
+                debug!(target: "rustyline", "cannot set macOS clipboard: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::ptr;
+    use std::slice;
+
+    use winapi::shared::minwindef::HGLOBAL;
+    use winapi::um::winbase::{GlobalAlloc, GlobalFree, GlobalLock, GlobalUnlock, GMEM_MOVEABLE};
+    use winapi::um::winuser::{
+        CloseClipboard, EmptyClipboard, GetClipboardData, OpenClipboard, SetClipboardData,
+        CF_UNICODETEXT,
+    };
+
+    use super::ClipboardBackend;
+
+    /// Windows clipboard (Unicode text only)
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct WindowsClipboard;
+
+    /// Clipboard opened until dropped
+    struct Opened;
+
+    impl Opened {
+        fn open() -> Option<Self> {
+            if unsafe { OpenClipboard(ptr::null_mut()) } == 0 {
+                None
+            } else {
+                Some(Opened)
+            }
+        }
+    }
+
+    impl Drop for Opened {
+        fn drop(&mut self) {
+            unsafe { CloseClipboard() };
+        }
+    }
+
+    impl ClipboardBackend for WindowsClipboard {
+        fn get_clipboard(&self) -> Option<String> {
+            let _opened = Opened::open()?;
+            unsafe {
+                let handle = GetClipboardData(CF_UNICODETEXT);
+                if handle.is_null() {
+                    return None;
+                }
+                let data = GlobalLock(handle) as *const u16;
+                if data.is_null() {
+                    return None;
+                }
+                let mut len = 0;
+                while *data.add(len) != 0 {
+                    len += 1;
+                }
+                let text = String::from_utf16_lossy(slice::from_raw_parts(data, len));
+                GlobalUnlock(handle);
+                Some(text)
+            }
+        }
+
+        fn set_clipboard(&self, text: &str) {
+            let _opened = match Opened::open() {
+                Some(opened) => opened,
+                None => return,
+            };
+            let utf16: Vec<u16> = text.encode_utf16().chain(Some(0)).collect();
+            unsafe {
+                EmptyClipboard();
+                let handle: HGLOBAL = GlobalAlloc(GMEM_MOVEABLE, utf16.len() * 2);
+                if handle.is_null() {
+                    return;
+                }
+                let data = GlobalLock(handle) as *mut u16;
+                if data.is_null() {
+                    GlobalFree(handle);
+                    return;
+                }
+                ptr::copy_nonoverlapping(utf16.as_ptr(), data, utf16.len());
+                GlobalUnlock(handle);
+                // the system owns the memory once it is set
+                if SetClipboardData(CF_UNICODETEXT, handle).is_null() {
+                    GlobalFree(handle);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn base64() {
+        use super::base64;
+        assert_eq!("", base64(b""));
+        assert_eq!("Zg==", base64(b"f"));
+        assert_eq!("Zm8=", base64(b"fo"));
+        assert_eq!("Zm9v", base64(b"foo"));
+        assert_eq!("Zm9vYmFy", base64(b"foobar"));
+        assert_eq!("w6k/", base64("\u{e9}?".as_bytes()));
+    }
+}
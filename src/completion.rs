@@ -1,9 +1,13 @@
 //! Completion API
 use std::borrow::Cow::{self, Borrowed, Owned};
 use std::fs;
+#[cfg(feature = "tokio")]
+use std::future::Future;
 use std::path::{self, Path};
+#[cfg(feature = "tokio")]
+use std::pin::Pin;
 
-use crate::line_buffer::LineBuffer;
+use crate::line_buffer::{word_range, LineBuffer, WordBreakPolicy};
 use crate::{Context, Result};
 use memchr::memchr;
 
@@ -18,6 +22,11 @@ pub trait Candidate {
     fn display(&self) -> &str;
     /// Text to insert in line.
     fn replacement(&self) -> &str;
+    /// Short description displayed next to the candidate in the completion
+    /// menu.
+    fn description(&self) -> Option<&str> {
+        None
+    }
 }
 
 impl Candidate for String {
@@ -30,6 +39,21 @@ impl Candidate for String {
     }
 }
 
+/// Allows heterogeneous candidates (`type Candidate = Box<dyn Candidate>`).
+impl<C: ?Sized + Candidate> Candidate for Box<C> {
+    fn display(&self) -> &str {
+        (**self).display()
+    }
+
+    fn replacement(&self) -> &str {
+        (**self).replacement()
+    }
+
+    fn description(&self) -> Option<&str> {
+        (**self).description()
+    }
+}
+
 pub struct Pair {
     pub display: String,
     pub replacement: String,
@@ -63,11 +87,59 @@ pub trait Completer {
         let _ = (line, pos, ctx);
         Ok((0, Vec::with_capacity(0)))
     }
+    /// Called when the completion key is pressed, before `complete` (or
+    /// `AsyncCompleter::complete_async`), with the same `line` and `pos`.
+    ///
+    /// Slow completers can start fetching their candidates in the background
+    /// here. By default, nothing is done.
+    fn completion_started(&self, line: &str, pos: usize) {
+        let _ = (line, pos);
+    }
+    /// Returns the start position of the word to complete in `line`,
+    /// replaced by the candidates, instead of the one returned by
+    /// `complete`.
+    ///
+    /// Meant for completers which tokenize the line themselves (JSON paths,
+    /// URLs, ...). By default, `None`: the start returned by `complete` is
+    /// used.
+    fn custom_word_break(&self, line: &str, pos: usize) -> Option<usize> {
+        let _ = (line, pos);
+        None
+    }
     /// Updates the edited `line` with the `elected` candidate.
     fn update(&self, line: &mut LineBuffer, start: usize, elected: &str) {
         let end = line.pos();
         line.replace(start..end, elected)
     }
+    /// Returns the asynchronous completer to use instead of `complete`, if
+    /// any.
+    #[cfg(feature = "tokio")]
+    fn async_completer(&self) -> Option<&dyn AsyncCompleter> {
+        None
+    }
+}
+
+/// Boxed future returned by `AsyncCompleter::complete_async`.
+#[cfg(feature = "tokio")]
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// To be called for tab-completion when candidates are computed by a slow
+/// source (a language server, a subprocess, ...).
+///
+/// The future is driven by a tokio runtime while a spinner is displayed in
+/// place of the hint. It is dropped (cancelled) as soon as the user presses
+/// a key before it completes.
+///
+/// `readline` must not be called from a tokio runtime: the completion fails
+/// with an error.
+#[cfg(feature = "tokio")]
+pub trait AsyncCompleter {
+    /// Same as `Completer::complete` but asynchronous.
+    fn complete_async<'a>(
+        &'a self,
+        line: &'a str,
+        pos: usize,
+    ) -> BoxFuture<'a, Result<(usize, Vec<Pair>)>>;
 }
 
 impl Completer for () {
@@ -90,9 +162,22 @@ impl<'c, C: ?Sized + Completer> Completer for &'c C {
         (**self).complete(line, pos, ctx)
     }
 
+    fn completion_started(&self, line: &str, pos: usize) {
+        (**self).completion_started(line, pos)
+    }
+
+    fn custom_word_break(&self, line: &str, pos: usize) -> Option<usize> {
+        (**self).custom_word_break(line, pos)
+    }
+
     fn update(&self, line: &mut LineBuffer, start: usize, elected: &str) {
         (**self).update(line, start, elected)
     }
+
+    #[cfg(feature = "tokio")]
+    fn async_completer(&self) -> Option<&dyn AsyncCompleter> {
+        (**self).async_completer()
+    }
 }
 macro_rules! box_completer {
     ($($id: ident)*) => {
@@ -103,9 +188,19 @@ macro_rules! box_completer {
                 fn complete(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Result<(usize, Vec<Self::Candidate>)> {
                     (**self).complete(line, pos, ctx)
                 }
+                fn completion_started(&self, line: &str, pos: usize) {
+                    (**self).completion_started(line, pos)
+                }
+                fn custom_word_break(&self, line: &str, pos: usize) -> Option<usize> {
+                    (**self).custom_word_break(line, pos)
+                }
                 fn update(&self, line: &mut LineBuffer, start: usize, elected: &str) {
                     (**self).update(line, start, elected)
                 }
+                #[cfg(feature = "tokio")]
+                fn async_completer(&self) -> Option<&dyn AsyncCompleter> {
+                    (**self).async_completer()
+                }
             }
         )*
     }
@@ -186,8 +281,14 @@ impl FilenameCompleter {
                     )
                 }
             } else {
-                let (start, path) = extract_word(line, pos, ESCAPE_CHAR, &self.break_chars);
-                let path = unescape(path, ESCAPE_CHAR);
+                let (mut start, _) = extract_word(line, pos, ESCAPE_CHAR, self.break_chars);
+                // `$` and `{` are break chars but start a variable to expand
+                if line[..start].ends_with("${") {
+                    start -= 2;
+                } else if line[..start].ends_with('$') {
+                    start -= 1;
+                }
+                let path = unescape(&line[start..pos], ESCAPE_CHAR);
                 (start, path, ESCAPE_CHAR, &self.break_chars, Quote::None)
             };
         let matches = filename_complete(&path, esc_char, break_chars, quote)?;
@@ -278,42 +379,88 @@ pub fn escape(
     result
 }
 
+/// Expand a leading `~` or `~user` and the `$VAR` or `${VAR}` environment
+/// variables in `path`.
+/// What cannot be expanded (unknown user, undefined variable) is kept as is.
+fn expand_path(path: &str) -> Cow<'_, str> {
+    let mut expanded = String::new();
+    let mut rest = path;
+    if path.starts_with('~') {
+        let end = path
+            .find(&['/', path::MAIN_SEPARATOR][..])
+            .unwrap_or(path.len());
+        if let Some(home) = home_dir(&path[1..end]) {
+            expanded.push_str(&home);
+            rest = &path[end..];
+        }
+    }
+    while let Some(idx) = rest.find('$') {
+        expanded.push_str(&rest[..idx]);
+        let var = &rest[idx + 1..];
+        let (name, len) = if var.starts_with('{') {
+            match var.find('}') {
+                Some(end) => (&var[1..end], end + 1),
+                None => ("", 0),
+            }
+        } else {
+            let end = var
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(var.len());
+            (&var[..end], end)
+        };
+        match std::env::var(name) {
+            Ok(value) if !name.is_empty() => {
+                expanded.push_str(&value);
+                rest = &var[len..];
+            }
+            _ => {
+                expanded.push('$');
+                rest = var;
+            }
+        }
+    }
+    if expanded.is_empty() {
+        return Borrowed(path);
+    }
+    expanded.push_str(rest);
+    Owned(expanded)
+}
+
+/// Home directory of `user` (of the current user if `user` is empty)
+fn home_dir(user: &str) -> Option<String> {
+    if user.is_empty() {
+        #[cfg(feature = "with-dirs")]
+        return dirs::home_dir().and_then(|home| home.to_str().map(String::from));
+        #[cfg(not(feature = "with-dirs"))]
+        return None;
+    }
+    #[cfg(unix)]
+    {
+        use nix::unistd::User;
+        if let Ok(Some(user)) = User::from_name(user) {
+            return user.dir.to_str().map(String::from);
+        }
+    }
+    None
+}
+
 fn filename_complete(
     path: &str,
     esc_char: Option<char>,
     break_chars: &[u8],
     quote: Quote,
 ) -> Result<Vec<Pair>> {
-    #[cfg(feature = "with-dirs")]
-    use dirs::home_dir;
     use std::env::current_dir;
 
+    let path = expand_path(path);
     let sep = path::MAIN_SEPARATOR;
     let (dir_name, file_name) = match path.rfind(sep) {
         Some(idx) => path.split_at(idx + sep.len_utf8()),
-        None => ("", path),
+        None => ("", path.as_ref()),
     };
 
     let dir_path = Path::new(dir_name);
-    let dir = if dir_path.starts_with("~") {
-        // ~[/...]
-        #[cfg(feature = "with-dirs")]
-        {
-            if let Some(home) = home_dir() {
-                match dir_path.strip_prefix("~") {
-                    Ok(rel_path) => home.join(rel_path),
-                    _ => home,
-                }
-            } else {
-                dir_path.to_path_buf()
-            }
-        }
-        #[cfg(not(feature = "with-dirs"))]
-        {
-            dir_path.to_path_buf()
-        }
-    } else if dir_path.is_relative() {
-        // TODO ~user[/...] (https://crates.io/crates/users)
+    let dir = if dir_path.is_relative() {
         if let Ok(cwd) = current_dir() {
             cwd.join(dir_path)
         } else {
@@ -325,12 +472,8 @@ fn filename_complete(
 
     let mut entries: Vec<Pair> = Vec::new();
 
-    // if dir doesn't exist, then don't offer any completions
-    if !dir.exists() {
-        return Ok(entries);
-    }
-
-    // if any of the below IO operations have errors, just ignore them
+    // if dir doesn't exist or if any of the below IO operations have errors,
+    // just ignore them
     if let Ok(read_dir) = dir.read_dir() {
         for entry in read_dir {
             if let Ok(entry) = entry {
@@ -351,9 +494,61 @@ fn filename_complete(
             }
         }
     }
+    // wildcards may also be part of actual file names: they are only
+    // expanded when no file name matches literally
+    #[cfg(feature = "with-glob")]
+    {
+        if entries.is_empty() && path.contains(&['*', '?', '['][..]) {
+            return Ok(glob_complete(&path, esc_char, break_chars, quote));
+        }
+    }
     Ok(entries)
 }
 
+/// Expand the wildcards (`*`, `?` and `[...]`) of `pattern`.
+///
+/// Candidates are displayed relative to the directory which precedes the
+/// first wildcard.
+#[cfg(feature = "with-glob")]
+fn glob_complete(
+    pattern: &str,
+    esc_char: Option<char>,
+    break_chars: &[u8],
+    quote: Quote,
+) -> Vec<Pair> {
+    let sep = path::MAIN_SEPARATOR;
+    let wildcard = pattern.find(&['*', '?', '['][..]).unwrap_or(0);
+    let prefix = match pattern[..wildcard].rfind(sep) {
+        Some(idx) => &pattern[..idx + sep.len_utf8()],
+        None => "",
+    };
+    let paths = match glob::glob(pattern) {
+        Ok(paths) => paths,
+        Err(_) => return Vec::new(),
+    };
+    let mut entries = Vec::new();
+    // if any of the below IO operations have errors, just ignore them
+    for path in paths.flatten() {
+        let mut path = match path.into_os_string().into_string() {
+            Ok(path) => path,
+            Err(_) => continue,
+        };
+        if Path::new(&path).is_dir() {
+            path.push(sep);
+        }
+        let display = if path.starts_with(prefix) {
+            String::from(&path[prefix.len()..])
+        } else {
+            path.clone()
+        };
+        entries.push(Pair {
+            display,
+            replacement: escape(path, esc_char, break_chars, quote),
+        });
+    }
+    entries
+}
+
 /// Given a `line` and a cursor `pos`ition,
 /// try to find backward the start of a word.
 /// Return (0, `line[..pos]`) if no break char has been found.
@@ -364,33 +559,12 @@ pub fn extract_word<'l>(
     esc_char: Option<char>,
     break_chars: &[u8],
 ) -> (usize, &'l str) {
-    let line = &line[..pos];
-    if line.is_empty() {
-        return (0, line);
-    }
-    let mut start = None;
-    for (i, c) in line.char_indices().rev() {
-        if let (Some(esc_char), true) = (esc_char, start.is_some()) {
-            if esc_char == c {
-                // escaped break char
-                start = None;
-                continue;
-            } else {
-                break;
-            }
-        }
-        if c.is_ascii() && memchr(c as u8, break_chars).is_some() {
-            start = Some(i + c.len_utf8());
-            if esc_char.is_none() {
-                break;
-            } // else maybe escaped...
-        }
-    }
-
-    match start {
-        Some(start) => (start, &line[start..]),
-        None => (0, line),
-    }
+    let policy = WordBreakPolicy::BreakChars {
+        break_chars,
+        esc_char,
+    };
+    let (start, _) = word_range(line, pos, &policy);
+    (start, &line[start..pos])
 }
 
 pub fn longest_common_prefix<C: Candidate>(candidates: &[C]) -> Option<&str> {
@@ -423,25 +597,90 @@ pub fn longest_common_prefix<C: Candidate>(candidates: &[C]) -> Option<&str> {
     Some(&candidate[0..longest_common_prefix])
 }
 
+/// Score how well `needle` matches `haystack`: `None` if the chars of `needle`
+/// do not all appear in order (case insensitively) in `haystack`.
+///
+/// Consecutive matches and matches at the start of a word score higher, skipped
+/// chars lower.
+pub fn fuzzy_match(needle: &str, haystack: &str) -> Option<i64> {
+    fuzzy_indices(needle, haystack).map(|(score, _)| score)
+}
+
+/// Same as `fuzzy_match` but also returns the (char) indices of the matched
+/// chars in `haystack`.
+pub fn fuzzy_indices(needle: &str, haystack: &str) -> Option<(i64, Vec<usize>)> {
+    const MATCH: i64 = 10;
+    const CONSECUTIVE: i64 = 15;
+    const WORD_START: i64 = 10;
+
+    let mut needle = needle.chars().flat_map(char::to_lowercase).peekable();
+    let mut indices = Vec::new();
+    let mut score = 0;
+    let mut prev: Option<char> = None;
+    let mut prev_matched = false;
+    for (idx, c) in haystack.chars().enumerate() {
+        let expected = match needle.peek() {
+            Some(expected) => *expected,
+            None => break,
+        };
+        if c.to_lowercase().eq(expected.to_lowercase()) {
+            needle.next();
+            indices.push(idx);
+            score += MATCH;
+            if prev_matched {
+                score += CONSECUTIVE;
+            }
+            let word_start = match prev {
+                None => true,
+                Some(p) => !p.is_alphanumeric() || (p.is_lowercase() && c.is_uppercase()),
+            };
+            if word_start {
+                score += WORD_START;
+            }
+            prev_matched = true;
+        } else {
+            score -= 1;
+            prev_matched = false;
+        }
+        prev = Some(c);
+    }
+    if needle.peek().is_some() {
+        None
+    } else {
+        Some((score, indices))
+    }
+}
+
 #[derive(PartialEq)]
 enum ScanMode {
-    DoubleQuote,
     Escape,
     EscapeInDoubleQuote,
     Normal,
-    SingleQuote,
+    Quoted(char),
 }
 
 /// try to find an unclosed single/double quote in `s`.
 /// Return `None` if no unclosed quote is found.
 /// Return the unclosed quote position and if it is a double quote.
 fn find_unclosed_quote(s: &str) -> Option<(usize, Quote)> {
+    let quote_chars = if cfg!(windows) { "\"" } else { "\"'" };
+    match find_unclosed_quote_char(s, quote_chars) {
+        Some((index, '"')) => Some((index, Quote::Double)),
+        Some((index, _)) => Some((index, Quote::Single)),
+        None => None,
+    }
+}
+
+/// try to find an unclosed quote in `s`, any of `quote_chars` starting and
+/// ending a quoted word.
+/// Return the unclosed quote position and character.
+pub(crate) fn find_unclosed_quote_char(s: &str, quote_chars: &str) -> Option<(usize, char)> {
     let char_indices = s.char_indices();
     let mut mode = ScanMode::Normal;
     let mut quote_index = 0;
     for (index, char) in char_indices {
         match mode {
-            ScanMode::DoubleQuote => {
+            ScanMode::Quoted('"') => {
                 if char == '"' {
                     mode = ScanMode::Normal;
                 } else if char == '\\' {
@@ -453,32 +692,28 @@ fn find_unclosed_quote(s: &str) -> Option<(usize, Quote)> {
                 mode = ScanMode::Normal;
             }
             ScanMode::EscapeInDoubleQuote => {
-                mode = ScanMode::DoubleQuote;
+                mode = ScanMode::Quoted('"');
             }
             ScanMode::Normal => {
-                if char == '"' {
-                    mode = ScanMode::DoubleQuote;
+                if quote_chars.contains(char) {
+                    mode = ScanMode::Quoted(char);
                     quote_index = index;
                 } else if char == '\\' && cfg!(not(windows)) {
                     mode = ScanMode::Escape;
-                } else if char == '\'' && cfg!(not(windows)) {
-                    mode = ScanMode::SingleQuote;
-                    quote_index = index;
                 }
             }
-            ScanMode::SingleQuote => {
-                if char == '\'' {
+            ScanMode::Quoted(quote) => {
+                if char == quote {
                     mode = ScanMode::Normal;
                 } // no escape in single quotes
             }
         };
     }
-    if ScanMode::DoubleQuote == mode || ScanMode::EscapeInDoubleQuote == mode {
-        return Some((quote_index, Quote::Double));
-    } else if ScanMode::SingleQuote == mode {
-        return Some((quote_index, Quote::Single));
+    match mode {
+        ScanMode::Quoted(quote) => Some((quote_index, quote)),
+        ScanMode::EscapeInDoubleQuote => Some((quote_index, '"')),
+        _ => None,
     }
-    None
 }
 
 #[cfg(test)]
@@ -498,6 +733,93 @@ mod tests {
         );
     }
 
+    #[test]
+    pub fn expand_path() {
+        std::env::set_var("RUSTYLINE_EXPAND", "/tmp");
+        assert_eq!("/tmp/a", super::expand_path("$RUSTYLINE_EXPAND/a"));
+        assert_eq!("/tmpa", super::expand_path("${RUSTYLINE_EXPAND}a"));
+        assert_eq!(
+            "$RUSTYLINE_UNDEFINED/a",
+            super::expand_path("$RUSTYLINE_UNDEFINED/a")
+        );
+        assert_eq!("a$/${", super::expand_path("a$/${"));
+        assert_eq!(
+            "~rustyline_unknown_user/a",
+            super::expand_path("~rustyline_unknown_user/a")
+        );
+        #[cfg(all(unix, feature = "with-dirs"))]
+        {
+            let home = dirs::home_dir().unwrap();
+            assert_eq!(format!("{}/a", home.display()), super::expand_path("~/a"));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    pub fn complete_expanded_path() {
+        use super::{Candidate, FilenameCompleter};
+        let td = tempdir::TempDir::new("completion").unwrap();
+        std::fs::File::create(td.path().join("file")).unwrap();
+        let dir = td.path().to_str().unwrap();
+        std::env::set_var("RUSTYLINE_COMPLETION", dir);
+        let completer = FilenameCompleter::new();
+        for line in &[
+            "ls $RUSTYLINE_COMPLETION/fi",
+            "ls ${RUSTYLINE_COMPLETION}/fi",
+        ] {
+            let (start, candidates) = completer.complete_path(line, line.len()).unwrap();
+            assert_eq!(3, start);
+            assert_eq!(1, candidates.len());
+            assert_eq!("file", candidates[0].display());
+            assert_eq!(format!("{}/file", dir), candidates[0].replacement());
+        }
+    }
+
+    #[cfg(all(unix, feature = "with-glob"))]
+    #[test]
+    pub fn complete_glob() {
+        use super::{Candidate, FilenameCompleter};
+        let td = tempdir::TempDir::new("completion").unwrap();
+        std::fs::create_dir(td.path().join("dir")).unwrap();
+        std::fs::File::create(td.path().join("a.rs")).unwrap();
+        std::fs::File::create(td.path().join("b.rs")).unwrap();
+        std::fs::File::create(td.path().join("dir").join("c.rs")).unwrap();
+        let dir = td.path().to_str().unwrap();
+        let completer = FilenameCompleter::new();
+
+        let line = format!("ls {}/*.rs", dir);
+        let (start, candidates) = completer.complete_path(&line, line.len()).unwrap();
+        assert_eq!(3, start);
+        let displays: Vec<&str> = candidates.iter().map(Candidate::display).collect();
+        assert_eq!(vec!["a.rs", "b.rs"], displays);
+        assert_eq!(format!("{}/a.rs", dir), candidates[0].replacement());
+
+        let line = format!("ls {}/?i?/*", dir);
+        let (_, candidates) = completer.complete_path(&line, line.len()).unwrap();
+        assert_eq!(1, candidates.len());
+        assert_eq!("dir/c.rs", candidates[0].display());
+
+        let line = format!("ls {}/d*", dir);
+        let (_, candidates) = completer.complete_path(&line, line.len()).unwrap();
+        assert_eq!(format!("{}/dir/", dir), candidates[0].replacement());
+
+        // file names with wildcards are completed literally
+        std::fs::File::create(td.path().join("e[1].rs")).unwrap();
+        std::fs::File::create(td.path().join("e1.rs")).unwrap();
+        let line = format!("ls {}/e[", dir);
+        let (_, candidates) = completer.complete_path(&line, line.len()).unwrap();
+        assert_eq!(1, candidates.len());
+        assert_eq!("e[1].rs", candidates[0].display());
+        let line = format!("ls {}/e[1]", dir);
+        let (_, candidates) = completer.complete_path(&line, line.len()).unwrap();
+        assert_eq!(1, candidates.len());
+        assert_eq!("e[1].rs", candidates[0].display());
+        let line = format!("ls {}/e[0-9]*", dir);
+        let (_, candidates) = completer.complete_path(&line, line.len()).unwrap();
+        assert_eq!(1, candidates.len());
+        assert_eq!("e1.rs", candidates[0].display());
+    }
+
     #[test]
     pub fn unescape() {
         use std::borrow::Cow::{self, Borrowed, Owned};
@@ -565,6 +887,23 @@ mod tests {
         assert_eq!(Some("f"), lcp);
     }
 
+    #[test]
+    pub fn fuzzy_match() {
+        assert_eq!(Some(0), super::fuzzy_match("", "foobar"));
+        assert_eq!(None, super::fuzzy_match("fob", "baz"));
+        assert_eq!(None, super::fuzzy_match("bof", "foobar"));
+        assert_eq!(
+            Some((54, vec![0, 1, 3])),
+            super::fuzzy_indices("fob", "foobar")
+        );
+        assert!(super::fuzzy_match("fb", "foo_bar") > super::fuzzy_match("fb", "fooabar"));
+        assert!(super::fuzzy_match("fb", "fooBar") > super::fuzzy_match("fb", "foobar"));
+        assert_eq!(
+            Some((70, vec![0, 1, 2])),
+            super::fuzzy_indices("FÉE", "fée")
+        );
+    }
+
     #[test]
     pub fn find_unclosed_quote() {
         assert_eq!(None, super::find_unclosed_quote("ls /etc"));
@@ -581,4 +920,25 @@ mod tests {
             super::find_unclosed_quote("\"c:\\users\\All Users\\")
         )
     }
+
+    #[test]
+    pub fn find_unclosed_quote_char() {
+        assert_eq!(
+            None,
+            super::find_unclosed_quote_char("ls 'a b' \"c d\"", "'\"")
+        );
+        assert_eq!(
+            Some((3, '\'')),
+            super::find_unclosed_quote_char("ls 'a \"b", "'\"")
+        );
+        assert_eq!(
+            Some((4, '"')),
+            super::find_unclosed_quote_char("ls '\"a", "\"")
+        );
+        assert_eq!(None, super::find_unclosed_quote_char("ls `a\\`", "`"));
+        assert_eq!(
+            Some((3, '`')),
+            super::find_unclosed_quote_char("ls `a", "`")
+        );
+    }
 }
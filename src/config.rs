@@ -1,19 +1,72 @@
 //! Customize line editor
 use std::default::Default;
+use std::fmt;
+#[cfg(feature = "with-serde")]
+use std::path::Path;
+use std::result;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(feature = "with-serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::error::ConfigError;
+
+/// Reject the `keyseq_timeout` values that `Builder::keyseq_timeout` rejects
+#[cfg(feature = "with-serde")]
+fn deserialize_keyseq_timeout<'de, D>(deserializer: D) -> result::Result<i32, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let keyseq_timeout_ms = i32::deserialize(deserializer)?;
+    if keyseq_timeout_ms < -1 {
+        return Err(serde::de::Error::custom(ConfigError::KeyseqTimeout(
+            keyseq_timeout_ms,
+        )));
+    }
+    Ok(keyseq_timeout_ms)
+}
 
 /// User preferences
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "with-serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "with-serde", serde(default))]
 pub struct Config {
     /// Maximum number of entries in History.
     max_history_size: usize, // history_max_entries
+    /// Maximum size (in bytes) of the history file.
+    max_history_file_size: Option<usize>,
+    /// Maximum number of entries in the history file.
+    max_history_len: Option<usize>,
+    /// Whether the previous history file is kept as a `.bak` file on save.
+    history_backup: bool,
+    /// Whether entries appended to the history file by other processes are
+    /// loaded before each `readline`.
+    history_file_sync: bool,
+    /// Whether the prefix history search skips the entries equal to the
+    /// current line.
+    history_search_no_duplicates: bool,
     history_duplicates: HistoryDuplicates,
     history_ignore_space: bool,
     completion_type: CompletionType,
     /// When listing completion alternatives, only display
     /// one screen of possibilities at a time.
     completion_prompt_limit: usize,
+    /// Number of candidates visible at once in the completion menu.
+    completion_menu_rows: usize,
+    /// Minimum column width when listing completion alternatives.
+    completion_menu_min_width: usize,
+    /// Maximum column width when listing completion alternatives.
+    completion_menu_max_width: usize,
+    /// Characters which start and end a quoted word for completion.
+    #[cfg_attr(feature = "with-serde", serde(skip))]
+    completion_quote_characters: &'static str,
     /// Duration (milliseconds) Rustyline will wait for a character when
     /// reading an ambiguous key sequence.
+    #[cfg_attr(
+        feature = "with-serde",
+        serde(deserialize_with = "deserialize_keyseq_timeout")
+    )]
     keyseq_timeout: i32,
     /// Emacs or Vi mode
     edit_mode: EditMode,
@@ -28,13 +81,41 @@ pub struct Config {
     output_stream: OutputStreamType,
     /// Horizontal space taken by a tab.
     tab_stop: usize,
+    /// Only redraw the part of the line which changed.
+    incremental_render: bool,
+    /// Render links of highlighted spans as OSC 8 hyperlinks.
+    hyperlinks_enabled: bool,
+    /// Ask the terminal to report focus changes.
+    enable_focus_events: bool,
+    /// Ask the terminal to bracket pasted text.
+    enable_bracketed_paste: bool,
+    /// Discard pending input when `readline` is interrupted.
+    drain_on_interrupt: bool,
+    /// Maximum number of characters of the edited line.
+    max_line_length: Option<usize>,
 }
 
 impl Config {
+    /// Default configuration, to be customized with the [`Configurer`]
+    /// setters.
+    pub fn new() -> Config {
+        Config::default()
+    }
+
     pub fn builder() -> Builder {
         Builder::new()
     }
 
+    /// Load the preferences saved in a TOML file.
+    ///
+    /// Missing preferences keep their default value.
+    #[cfg(feature = "with-serde")]
+    pub fn from_toml_file(path: &Path) -> crate::Result<Config> {
+        use std::io;
+        let content = std::fs::read_to_string(path)?;
+        toml::from_str(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e).into())
+    }
+
     /// Tell the maximum length (i.e. number of entries) for the history.
     pub fn max_history_size(&self) -> usize {
         self.max_history_size
@@ -44,6 +125,64 @@ impl Config {
         self.max_history_size = max_size;
     }
 
+    /// Tell the maximum size (in bytes) of the history file.
+    ///
+    /// By default, there is no limit.
+    pub fn max_history_file_size(&self) -> Option<usize> {
+        self.max_history_file_size
+    }
+
+    pub(crate) fn set_max_history_file_size(&mut self, max_size: Option<usize>) {
+        self.max_history_file_size = max_size;
+    }
+
+    /// Tell the maximum number of entries saved in the history file.
+    ///
+    /// By default, there is no limit (other than `max_history_size`).
+    pub fn max_history_len(&self) -> Option<usize> {
+        self.max_history_len
+    }
+
+    pub(crate) fn set_max_history_len(&mut self, max_len: Option<usize>) {
+        self.max_history_len = max_len;
+    }
+
+    /// Tell if the previous history file is renamed to `<path>.bak` before
+    /// being overwritten.
+    ///
+    /// By default, it is not.
+    pub fn history_backup(&self) -> bool {
+        self.history_backup
+    }
+
+    pub(crate) fn set_history_backup(&mut self, yes: bool) {
+        self.history_backup = yes;
+    }
+
+    /// Tell if entries appended to the history file by other processes are
+    /// loaded before each `readline`.
+    ///
+    /// By default, they are not.
+    pub fn history_file_sync(&self) -> bool {
+        self.history_file_sync
+    }
+
+    pub(crate) fn set_history_file_sync(&mut self, yes: bool) {
+        self.history_file_sync = yes;
+    }
+
+    /// Tell if the prefix history search skips the entries equal to the
+    /// current line.
+    ///
+    /// By default, they are not skipped.
+    pub fn history_search_no_duplicates(&self) -> bool {
+        self.history_search_no_duplicates
+    }
+
+    pub(crate) fn set_history_search_no_duplicates(&mut self, yes: bool) {
+        self.history_search_no_duplicates = yes;
+    }
+
     /// Tell if lines which match the previous history entry are saved or not
     /// in the history list.
     ///
@@ -80,14 +219,70 @@ impl Config {
         self.completion_prompt_limit
     }
 
+    /// Number of candidates visible at once with `CompletionType::Menu`.
+    ///
+    /// By default, 8.
+    pub fn completion_menu_rows(&self) -> usize {
+        self.completion_menu_rows
+    }
+
+    /// Minimum width of the columns with `CompletionType::List`.
+    ///
+    /// By default, 0: columns are as wide as the longest candidate plus
+    /// padding.
+    pub fn completion_menu_min_width(&self) -> usize {
+        self.completion_menu_min_width
+    }
+
+    /// Maximum width of the columns with `CompletionType::List`.
+    ///
+    /// By default, `usize::MAX`: only the terminal width is a limit.
+    pub fn completion_menu_max_width(&self) -> usize {
+        self.completion_menu_max_width
+    }
+
+    /// Characters which start and end a quoted word: the quote active at the
+    /// cursor is given to the completer by `Context::quote`.
+    ///
+    /// By default, `'` and `"`.
+    pub fn completion_quote_characters(&self) -> &'static str {
+        self.completion_quote_characters
+    }
+
+    /// Timeout for ambiguous key sequences in milliseconds (-1 means no
+    /// timeout).
     pub fn keyseq_timeout(&self) -> i32 {
         self.keyseq_timeout
     }
 
+    /// Alias of `keyseq_timeout`.
+    pub fn keyseq_timeout_ms(&self) -> i32 {
+        self.keyseq_timeout
+    }
+
+    pub(crate) fn set_keyseq_timeout(
+        &mut self,
+        keyseq_timeout_ms: i32,
+    ) -> result::Result<(), ConfigError> {
+        if keyseq_timeout_ms < -1 {
+            return Err(ConfigError::KeyseqTimeout(keyseq_timeout_ms));
+        }
+        self.keyseq_timeout = keyseq_timeout_ms;
+        Ok(())
+    }
+
     pub fn edit_mode(&self) -> EditMode {
         self.edit_mode
     }
 
+    pub(crate) fn set_edit_mode(&mut self, edit_mode: EditMode) {
+        self.edit_mode = edit_mode;
+        match edit_mode {
+            EditMode::Emacs => self.keyseq_timeout = -1, // no timeout
+            EditMode::Vi => self.keyseq_timeout = 500,
+        }
+    }
+
     /// Tell if lines are automatically added to the history.
     ///
     /// By default, they are not.
@@ -97,7 +292,11 @@ impl Config {
 
     /// Bell style: beep, flash or nothing.
     pub fn bell_style(&self) -> BellStyle {
-        self.bell_style
+        self.bell_style.clone()
+    }
+
+    pub(crate) fn set_bell_style(&mut self, bell_style: BellStyle) {
+        self.bell_style = bell_style;
     }
 
     /// Tell if colors should be enabled.
@@ -127,16 +326,96 @@ impl Config {
     pub(crate) fn set_tab_stop(&mut self, tab_stop: usize) {
         self.tab_stop = tab_stop;
     }
+
+    /// Tell if only the part of the line which changed is redrawn.
+    ///
+    /// By default, the whole line is redrawn.
+    pub fn incremental_render(&self) -> bool {
+        self.incremental_render
+    }
+
+    pub(crate) fn set_incremental_render(&mut self, yes: bool) {
+        self.incremental_render = yes;
+    }
+
+    /// Tell if links of highlighted spans are rendered as (OSC 8)
+    /// hyperlinks.
+    ///
+    /// By default, they are not.
+    pub fn hyperlinks_enabled(&self) -> bool {
+        self.hyperlinks_enabled
+    }
+
+    pub(crate) fn set_hyperlinks_enabled(&mut self, yes: bool) {
+        self.hyperlinks_enabled = yes;
+    }
+
+    /// Tell if the terminal is asked to report focus changes
+    /// (`KeyPress::FocusGained` and `KeyPress::FocusLost`).
+    ///
+    /// By default, it is not.
+    pub fn enable_focus_events(&self) -> bool {
+        self.enable_focus_events
+    }
+
+    pub(crate) fn set_enable_focus_events(&mut self, yes: bool) {
+        self.enable_focus_events = yes;
+    }
+
+    /// Tell if the terminal is asked to bracket pasted text, so that it is
+    /// inserted as is (unix only).
+    ///
+    /// By default, it is.
+    pub fn enable_bracketed_paste(&self) -> bool {
+        self.enable_bracketed_paste
+    }
+
+    pub(crate) fn set_enable_bracketed_paste(&mut self, yes: bool) {
+        self.enable_bracketed_paste = yes;
+    }
+
+    /// Tell if the input typed ahead is discarded when `readline` is
+    /// interrupted (by Ctrl-C).
+    ///
+    /// By default, it is kept for the next `readline`.
+    pub fn drain_on_interrupt(&self) -> bool {
+        self.drain_on_interrupt
+    }
+
+    pub(crate) fn set_drain_on_interrupt(&mut self, yes: bool) {
+        self.drain_on_interrupt = yes;
+    }
+
+    /// Tell the maximum number of characters which can be typed (or pasted)
+    /// in the edited line.
+    ///
+    /// By default, there is no limit.
+    pub fn max_line_length(&self) -> Option<usize> {
+        self.max_line_length
+    }
+
+    pub(crate) fn set_max_line_length(&mut self, max_len: Option<usize>) {
+        self.max_line_length = max_len;
+    }
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             max_history_size: 100,
+            max_history_file_size: None,
+            max_history_len: None,
+            history_backup: false,
+            history_file_sync: false,
+            history_search_no_duplicates: false,
             history_duplicates: HistoryDuplicates::IgnoreConsecutive,
             history_ignore_space: false,
             completion_type: CompletionType::Circular, // TODO Validate
             completion_prompt_limit: 100,
+            completion_menu_rows: 8,
+            completion_menu_min_width: 0,
+            completion_menu_max_width: usize::MAX,
+            completion_quote_characters: "'\"",
             keyseq_timeout: -1,
             edit_mode: EditMode::Emacs,
             auto_add_history: false,
@@ -144,21 +423,59 @@ impl Default for Config {
             color_mode: ColorMode::Enabled,
             output_stream: OutputStreamType::Stdout,
             tab_stop: 8,
+            incremental_render: false,
+            hyperlinks_enabled: false,
+            enable_focus_events: false,
+            enable_bracketed_paste: true,
+            drain_on_interrupt: false,
+            max_line_length: None,
         }
     }
 }
 
 /// Beep or flash or nothing
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone)]
+#[cfg_attr(feature = "with-serde", derive(Serialize, Deserialize))]
 pub enum BellStyle {
     /// Beep
     Audible,
     /// Silent
     None,
-    /// Flash screen (not supported)
+    /// Flash screen (reverse video until the line is redrawn, not supported
+    /// on Windows)
     Visible,
+    /// Call a user-defined function (to play a sound, send a notification,
+    /// ...)
+    #[cfg_attr(feature = "with-serde", serde(skip))]
+    Callback(Arc<dyn Fn() + Send + Sync>),
+}
+
+impl fmt::Debug for BellStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BellStyle::Audible => f.write_str("Audible"),
+            BellStyle::None => f.write_str("None"),
+            BellStyle::Visible => f.write_str("Visible"),
+            BellStyle::Callback(_) => f.write_str("Callback(..)"),
+        }
+    }
 }
 
+/// Callbacks are equal only if they share the same function.
+impl PartialEq for BellStyle {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (BellStyle::Audible, BellStyle::Audible)
+            | (BellStyle::None, BellStyle::None)
+            | (BellStyle::Visible, BellStyle::Visible) => true,
+            (BellStyle::Callback(a), BellStyle::Callback(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for BellStyle {}
+
 /// `Audible` by default on unix (overriden by current Terminal settings).
 /// `None` on windows.
 impl Default for BellStyle {
@@ -174,6 +491,7 @@ impl Default for BellStyle {
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "with-serde", derive(Serialize, Deserialize))]
 pub enum HistoryDuplicates {
     AlwaysAdd,
     /// a line will not be added to the history if it matches the previous entry
@@ -181,6 +499,7 @@ pub enum HistoryDuplicates {
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "with-serde", derive(Serialize, Deserialize))]
 pub enum CompletionType {
     /// Complete the next full match (like in Vim by default)
     Circular,
@@ -188,17 +507,26 @@ pub enum CompletionType {
     /// When more than one match, list all matches
     /// (like in Bash/Readline).
     List,
+    /// Complete the match selected in a scrollable menu displayed below the
+    /// line.
+    Menu,
+    /// Complete till longest match.
+    /// When more than one match, cycle through the matches on each
+    /// subsequent completion (like zsh `menu-complete`).
+    Cycle,
 
     /// Complete the match using fuzzy search and selection
-    /// (like fzf and plugins)
-    /// Currently only available for unix platforms as dependency on
-    /// skim->tuikit Compile with `--features=fuzzy` to enable
-    #[cfg(all(unix, feature = "with-fuzzy"))]
+    /// (like fzf and plugins).
+    /// By default, candidates are ranked with `completion::fuzzy_match` and
+    /// displayed in the completion menu.
+    /// On unix platforms, compile with `--features=with-fuzzy` to use
+    /// skim instead.
     Fuzzy,
 }
 
 /// Style of editing / Standard keymaps
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "with-serde", derive(Serialize, Deserialize))]
 pub enum EditMode {
     Emacs,
     Vi,
@@ -206,18 +534,23 @@ pub enum EditMode {
 
 /// Colorization mode
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "with-serde", derive(Serialize, Deserialize))]
 pub enum ColorMode {
     Enabled,
     Forced,
     Disabled,
 }
 
-/// Should the editor use stdout or stderr
+/// Should the editor use stdout, stderr or another file descriptor
 // TODO console term::TermTarget
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "with-serde", derive(Serialize, Deserialize))]
 pub enum OutputStreamType {
     Stderr,
     Stdout,
+    /// File descriptor owned by the caller: rustyline never closes it.
+    #[cfg(unix)]
+    Fd(std::os::unix::io::RawFd),
 }
 
 /// Configuration builder
@@ -239,6 +572,49 @@ impl Builder {
         self
     }
 
+    /// Set the maximum size (in bytes) of the history file.
+    ///
+    /// Oldest entries are dropped on save until the file fits.
+    pub fn max_history_file_size(mut self, max_size: Option<usize>) -> Self {
+        self.set_max_history_file_size(max_size);
+        self
+    }
+
+    /// Set the maximum number of entries saved in the history file.
+    ///
+    /// Oldest entries are dropped on save.
+    pub fn max_history_len(mut self, max_len: Option<usize>) -> Self {
+        self.set_max_history_len(max_len);
+        self
+    }
+
+    /// Tell if the previous history file is renamed to `<path>.bak` before
+    /// being overwritten.
+    ///
+    /// By default, it is not.
+    pub fn history_backup(mut self, yes: bool) -> Self {
+        self.set_history_backup(yes);
+        self
+    }
+
+    /// Tell if entries appended to the history file by other processes are
+    /// loaded before each `readline`.
+    ///
+    /// By default, they are not.
+    pub fn history_file_sync(mut self, yes: bool) -> Self {
+        self.set_history_file_sync(yes);
+        self
+    }
+
+    /// Tell if the prefix history search skips the entries equal to the
+    /// current line.
+    ///
+    /// By default, they are not skipped.
+    pub fn history_search_no_duplicates(mut self, yes: bool) -> Self {
+        self.set_history_search_no_duplicates(yes);
+        self
+    }
+
     /// Tell if lines which match the previous history entry are saved or not
     /// in the history list.
     ///
@@ -270,13 +646,50 @@ impl Builder {
         self
     }
 
+    /// The number of candidates visible at once in the completion menu
+    /// (`CompletionType::Menu`).
+    pub fn completion_menu_rows(mut self, completion_menu_rows: usize) -> Self {
+        self.set_completion_menu_rows(completion_menu_rows);
+        self
+    }
+
+    /// The minimum width of the columns when listing completion alternatives
+    /// (`CompletionType::List`).
+    pub fn completion_menu_min_width(mut self, min_width: usize) -> Self {
+        self.set_completion_menu_min_width(min_width);
+        self
+    }
+
+    /// The maximum width of the columns when listing completion alternatives
+    /// (`CompletionType::List`).
+    pub fn completion_menu_max_width(mut self, max_width: usize) -> Self {
+        self.set_completion_menu_max_width(max_width);
+        self
+    }
+
+    /// Characters which start and end a quoted word for completion.
+    pub fn completion_quote_characters(mut self, quote_characters: &'static str) -> Self {
+        self.set_completion_quote_characters(quote_characters);
+        self
+    }
+
     /// Timeout for ambiguous key sequences in milliseconds.
     /// Currently, it is used only to distinguish a single ESC from an ESC
     /// sequence.
     /// After seeing an ESC key, wait at most `keyseq_timeout_ms` for another
     /// byte.
-    pub fn keyseq_timeout(mut self, keyseq_timeout_ms: i32) -> Self {
-        self.set_keyseq_timeout(keyseq_timeout_ms);
+    ///
+    /// -1 means no timeout, other negative values are rejected.
+    #[must_use = "the builder is only returned when the timeout is valid"]
+    pub fn keyseq_timeout(mut self, keyseq_timeout_ms: i32) -> result::Result<Self, ConfigError> {
+        self.set_keyseq_timeout(keyseq_timeout_ms)?;
+        Ok(self)
+    }
+
+    /// Timeout for ambiguous key sequences (clamped to `i32::MAX`
+    /// milliseconds).
+    pub fn keyseq_timeout_duration(mut self, keyseq_timeout: Duration) -> Self {
+        self.set_keyseq_timeout_duration(keyseq_timeout);
         self
     }
 
@@ -324,6 +737,63 @@ impl Builder {
         self
     }
 
+    /// Only redraw the part of a single-row line which changed
+    /// (unix only).
+    ///
+    /// By default, the whole line is redrawn.
+    pub fn incremental_render(mut self, yes: bool) -> Self {
+        self.set_incremental_render(yes);
+        self
+    }
+
+    /// Render the links of highlighted spans as (OSC 8) hyperlinks
+    /// (unix only).
+    ///
+    /// Not all terminals support them so, by default, they are not.
+    pub fn hyperlinks_enabled(mut self, yes: bool) -> Self {
+        self.set_hyperlinks_enabled(yes);
+        self
+    }
+
+    /// Ask the terminal to report focus changes, as `KeyPress::FocusGained`
+    /// and `KeyPress::FocusLost` keys (unix only).
+    ///
+    /// By default, it is not.
+    pub fn enable_focus_events(mut self, yes: bool) -> Self {
+        self.set_enable_focus_events(yes);
+        self
+    }
+
+    /// Ask the terminal to bracket pasted text, so that it is inserted as
+    /// is instead of being interpreted as typed keys (unix only).
+    ///
+    /// Disable it for terminals which do not handle bracketed paste
+    /// correctly. By default, it is enabled.
+    pub fn enable_bracketed_paste(mut self, yes: bool) -> Self {
+        self.set_enable_bracketed_paste(yes);
+        self
+    }
+
+    /// Discard the input typed ahead when `readline` is interrupted (by
+    /// Ctrl-C).
+    ///
+    /// By default, it is kept for the next `readline`.
+    pub fn drain_on_interrupt(mut self, yes: bool) -> Self {
+        self.set_drain_on_interrupt(yes);
+        self
+    }
+
+    /// Set the maximum number of characters of the edited line.
+    ///
+    /// Insertions which would make the line longer are rejected with a beep,
+    /// the initial line, completions and external edits are truncated.
+    /// Lines recalled from the history are not truncated: see
+    /// `Highlighter::highlight_overflow`.
+    pub fn max_line_length(mut self, max_len: Option<usize>) -> Self {
+        self.set_max_line_length(max_len);
+        self
+    }
+
     pub fn build(self) -> Config {
         self.p
     }
@@ -335,6 +805,12 @@ impl Configurer for Builder {
     }
 }
 
+impl Configurer for Config {
+    fn config_mut(&mut self) -> &mut Config {
+        self
+    }
+}
+
 pub trait Configurer {
     fn config_mut(&mut self) -> &mut Config;
 
@@ -343,6 +819,40 @@ pub trait Configurer {
         self.config_mut().set_max_history_size(max_size);
     }
 
+    /// Set the maximum size (in bytes) of the history file.
+    fn set_max_history_file_size(&mut self, max_size: Option<usize>) {
+        self.config_mut().set_max_history_file_size(max_size);
+    }
+
+    /// Set the maximum number of entries saved in the history file.
+    fn set_max_history_len(&mut self, max_len: Option<usize>) {
+        self.config_mut().set_max_history_len(max_len);
+    }
+
+    /// Tell if the previous history file is renamed to `<path>.bak` before
+    /// being overwritten.
+    ///
+    /// By default, it is not.
+    fn set_history_backup(&mut self, yes: bool) {
+        self.config_mut().set_history_backup(yes);
+    }
+
+    /// Tell if entries appended to the history file by other processes are
+    /// loaded before each `readline`.
+    ///
+    /// By default, they are not.
+    fn set_history_file_sync(&mut self, yes: bool) {
+        self.config_mut().set_history_file_sync(yes);
+    }
+
+    /// Tell if the prefix history search skips the entries equal to the
+    /// current line.
+    ///
+    /// By default, they are not skipped.
+    fn set_history_search_no_duplicates(&mut self, yes: bool) {
+        self.config_mut().set_history_search_no_duplicates(yes);
+    }
+
     /// Tell if lines which match the previous history entry are saved or not
     /// in the history list.
     ///
@@ -369,18 +879,47 @@ pub trait Configurer {
         self.config_mut().completion_prompt_limit = completion_prompt_limit;
     }
 
+    /// The number of candidates visible at once in the completion menu
+    /// (`CompletionType::Menu`).
+    fn set_completion_menu_rows(&mut self, completion_menu_rows: usize) {
+        self.config_mut().completion_menu_rows = completion_menu_rows;
+    }
+
+    /// The minimum width of the columns when listing completion alternatives
+    /// (`CompletionType::List`).
+    fn set_completion_menu_min_width(&mut self, min_width: usize) {
+        self.config_mut().completion_menu_min_width = min_width;
+    }
+
+    /// The maximum width of the columns when listing completion alternatives
+    /// (`CompletionType::List`).
+    fn set_completion_menu_max_width(&mut self, max_width: usize) {
+        self.config_mut().completion_menu_max_width = max_width;
+    }
+
+    /// Characters which start and end a quoted word for completion.
+    fn set_completion_quote_characters(&mut self, quote_characters: &'static str) {
+        self.config_mut().completion_quote_characters = quote_characters;
+    }
+
     /// Timeout for ambiguous key sequences in milliseconds.
-    fn set_keyseq_timeout(&mut self, keyseq_timeout_ms: i32) {
-        self.config_mut().keyseq_timeout = keyseq_timeout_ms;
+    ///
+    /// -1 means no timeout, other negative values are rejected.
+    #[must_use = "the timeout is left unchanged when it is invalid"]
+    fn set_keyseq_timeout(&mut self, keyseq_timeout_ms: i32) -> result::Result<(), ConfigError> {
+        self.config_mut().set_keyseq_timeout(keyseq_timeout_ms)
+    }
+
+    /// Timeout for ambiguous key sequences (clamped to `i32::MAX`
+    /// milliseconds).
+    fn set_keyseq_timeout_duration(&mut self, keyseq_timeout: Duration) {
+        let ms = keyseq_timeout.as_millis().min(i32::MAX as u128) as i32;
+        self.config_mut().keyseq_timeout = ms;
     }
 
     /// Choose between Emacs or Vi mode.
     fn set_edit_mode(&mut self, edit_mode: EditMode) {
-        self.config_mut().edit_mode = edit_mode;
-        match edit_mode {
-            EditMode::Emacs => self.set_keyseq_timeout(-1), // no timeout
-            EditMode::Vi => self.set_keyseq_timeout(500),
-        }
+        self.config_mut().set_edit_mode(edit_mode);
     }
 
     /// Tell if lines are automatically added to the history.
@@ -415,4 +954,50 @@ pub trait Configurer {
     fn set_tab_stop(&mut self, tab_stop: usize) {
         self.config_mut().set_tab_stop(tab_stop);
     }
+
+    /// Only redraw the part of a single-row line which changed
+    /// (unix only).
+    ///
+    /// By default, the whole line is redrawn.
+    fn set_incremental_render(&mut self, yes: bool) {
+        self.config_mut().set_incremental_render(yes);
+    }
+
+    /// Render the links of highlighted spans as (OSC 8) hyperlinks
+    /// (unix only).
+    ///
+    /// Not all terminals support them so, by default, they are not.
+    fn set_hyperlinks_enabled(&mut self, yes: bool) {
+        self.config_mut().set_hyperlinks_enabled(yes);
+    }
+
+    /// Ask the terminal to report focus changes, as `KeyPress::FocusGained`
+    /// and `KeyPress::FocusLost` keys (unix only).
+    ///
+    /// By default, it is not.
+    fn set_enable_focus_events(&mut self, yes: bool) {
+        self.config_mut().set_enable_focus_events(yes);
+    }
+
+    /// Ask the terminal to bracket pasted text, so that it is inserted as
+    /// is instead of being interpreted as typed keys (unix only).
+    ///
+    /// Disable it for terminals which do not handle bracketed paste
+    /// correctly. By default, it is enabled.
+    fn set_enable_bracketed_paste(&mut self, yes: bool) {
+        self.config_mut().set_enable_bracketed_paste(yes);
+    }
+
+    /// Discard the input typed ahead when `readline` is interrupted (by
+    /// Ctrl-C).
+    ///
+    /// By default, it is kept for the next `readline`.
+    fn set_drain_on_interrupt(&mut self, yes: bool) {
+        self.config_mut().set_drain_on_interrupt(yes);
+    }
+
+    /// Set the maximum number of characters of the edited line.
+    fn set_max_line_length(&mut self, max_len: Option<usize>) {
+        self.config_mut().set_max_line_length(max_len);
+    }
 }
@@ -1,6 +1,10 @@
 //! Command processor
 
+#[cfg(not(feature = "tracing"))]
 use log::debug;
+#[cfg(feature = "tracing")]
+use tracing::debug;
+use std::borrow::Cow::Borrowed;
 use std::cell::RefCell;
 use std::fmt;
 use std::rc::Rc;
@@ -9,12 +13,13 @@ use unicode_width::UnicodeWidthChar;
 
 use super::{Context, Helper, Result};
 use crate::highlight::Highlighter;
+use crate::hint::StyledHint;
 use crate::history::Direction;
 use crate::keymap::{Anchor, At, CharSearch, Cmd, Movement, RepeatCount, Word};
 use crate::keymap::{InputState, Invoke, Refresher};
 use crate::layout::{Layout, Position};
 use crate::line_buffer::{LineBuffer, WordAction, MAX_LINE};
-use crate::tty::{Renderer, Term, Terminal};
+use crate::tty::{Renderer, Term, Terminal, WinSize};
 use crate::undo::Changeset;
 use crate::validate::{ValidationContext, ValidationResult};
 
@@ -31,12 +36,11 @@ pub struct State<'out, 'prompt, H: Helper> {
     pub changes: Rc<RefCell<Changeset>>, // changes to line, for undo/redo
     pub helper: Option<&'out H>,
     pub ctx: Context<'out>,   // Give access to history for `hinter`
-    pub hint: Option<String>, // last hint displayed
+    pub hint: Option<StyledHint>, // last hint displayed
     highlight_char: bool,     // `true` if a char has been highlighted
 }
 
 enum Info<'m> {
-    NoHint,
     Hint,
     Msg(Option<&'m str>),
 }
@@ -73,11 +77,33 @@ impl<'out, 'prompt, H: Helper> State<'out, 'prompt, H> {
         }
     }
 
+    /// Tells if the highlighter wants the line to be refreshed even if no
+    /// key is pressed.
+    pub fn needs_update(&self) -> bool {
+        match self.highlighter() {
+            Some(highlighter) => highlighter.needs_update(),
+            None => false,
+        }
+    }
+
     pub fn next_cmd(
         &mut self,
         input_state: &mut InputState,
         rdr: &mut <Terminal as Term>::Reader,
         single_esc_abort: bool,
+    ) -> Result<Cmd> {
+        self.next_cmd_or_resize(input_state, rdr, single_esc_abort, false)
+    }
+
+    /// Same as `next_cmd` but, if `report_resize`, returns
+    /// `Cmd::WindowResize` once the line has been redrawn for the new
+    /// terminal size.
+    pub fn next_cmd_or_resize(
+        &mut self,
+        input_state: &mut InputState,
+        rdr: &mut <Terminal as Term>::Reader,
+        single_esc_abort: bool,
+        report_resize: bool,
     ) -> Result<Cmd> {
         loop {
             let rc = input_state.next_cmd(rdr, self, single_esc_abort);
@@ -87,6 +113,12 @@ impl<'out, 'prompt, H: Helper> State<'out, 'prompt, H> {
                     .out
                     .calculate_position(self.prompt, Position::default());
                 self.refresh_line()?;
+                if report_resize {
+                    return Ok(Cmd::WindowResize(WinSize {
+                        cols: self.out.get_columns(),
+                        rows: self.out.get_rows(),
+                    }));
+                }
                 continue;
             }
             if let Ok(Cmd::Replace(_, _)) = rc {
@@ -102,7 +134,7 @@ impl<'out, 'prompt, H: Helper> State<'out, 'prompt, H> {
     }
 
     pub fn restore(&mut self) {
-        self.line.update(
+        self.line.update_overflowing(
             self.saved_line_for_history.as_str(),
             self.saved_line_for_history.pos(),
         );
@@ -116,9 +148,13 @@ impl<'out, 'prompt, H: Helper> State<'out, 'prompt, H> {
         if self.layout.cursor == cursor {
             return Ok(());
         }
-        if self.highlight_char() {
+        // the hint may depend on the cursor position
+        let previous_hint = self.hint.take();
+        self.hint();
+        let hint_changed = self.hint != previous_hint;
+        if self.highlight_char() || hint_changed {
             let prompt_size = self.prompt_size;
-            self.refresh(self.prompt, prompt_size, true, Info::NoHint)?;
+            self.refresh(self.prompt, prompt_size, true, Info::Hint)?;
         } else {
             self.out.move_cursor(self.layout.cursor, cursor)?;
             self.layout.prompt_size = self.prompt_size;
@@ -140,10 +176,12 @@ impl<'out, 'prompt, H: Helper> State<'out, 'prompt, H> {
         default_prompt: bool,
         info: Info<'_>,
     ) -> Result<()> {
-        let info = match info {
-            Info::NoHint => None,
-            Info::Hint => self.hint.as_deref(),
-            Info::Msg(msg) => msg,
+        let (info, already_styled) = match info {
+            Info::Hint => match self.hint {
+                Some(ref hint) => (Some(hint.display.as_str()), hint.already_styled),
+                None => (None, false),
+            },
+            Info::Msg(msg) => (msg, false),
         };
         let highlighter = if self.out.colors_enabled() {
             self.helper.map(|h| h as &dyn Highlighter)
@@ -163,6 +201,10 @@ impl<'out, 'prompt, H: Helper> State<'out, 'prompt, H> {
         if let Some(info) = info {
             end = self.out.calculate_position(&info, end);
         }
+        let info = info.map(|info| match highlighter {
+            Some(highlighter) if !already_styled => highlighter.highlight_hint(info),
+            _ => Borrowed(info),
+        });
 
         let new_layout = Layout {
             prompt_size,
@@ -178,7 +220,7 @@ impl<'out, 'prompt, H: Helper> State<'out, 'prompt, H> {
         self.out.refresh_line(
             prompt,
             &self.line,
-            info,
+            info.as_deref(),
             &self.layout,
             &new_layout,
             highlighter,
@@ -190,7 +232,7 @@ impl<'out, 'prompt, H: Helper> State<'out, 'prompt, H> {
 
     pub fn hint(&mut self) {
         if let Some(hinter) = self.helper {
-            let hint = hinter.hint(self.line.as_str(), self.line.pos(), &self.ctx);
+            let hint = hinter.hint_styled(self.line.as_str(), self.line.pos(), &self.ctx);
             self.hint = hint;
         } else {
             self.hint = None
@@ -199,7 +241,9 @@ impl<'out, 'prompt, H: Helper> State<'out, 'prompt, H> {
 
     fn highlight_char(&mut self) -> bool {
         if let Some(highlighter) = self.highlighter() {
-            let highlight_char = highlighter.highlight_char(&self.line, self.line.pos());
+            // the region changes with the cursor
+            let highlight_char = highlighter.highlight_char(&self.line, self.line.pos())
+                || self.line.mark().is_some();
             if highlight_char {
                 self.highlight_char = true;
                 true
@@ -350,8 +394,29 @@ impl<'out, 'prompt, H: Helper> State<'out, 'prompt, H> {
                 self.refresh_line()
             }
         } else {
-            Ok(())
+            // the line is too long
+            self.out.beep()
+        }
+    }
+
+    /// Swap the case of a single (or n) character(s) under the cursor (Vi
+    /// mode)
+    pub fn edit_swap_case(&mut self, n: RepeatCount) -> Result<()> {
+        if self.line.is_empty() {
+            return Ok(());
+        }
+        self.changes.borrow_mut().begin();
+        let mut pos = self.line.pos();
+        for _ in 0..n {
+            pos = self.line.swap_case_at(pos);
+        }
+        self.changes.borrow_mut().end();
+        self.line.set_pos(pos);
+        if pos == self.line.len() {
+            // stay on the last character
+            self.line.move_backward(1);
         }
+        self.refresh_line()
     }
 
     /// Replace a single (or n) character(s) under the cursor (Vi mode)
@@ -403,8 +468,11 @@ impl<'out, 'prompt, H: Helper> State<'out, 'prompt, H> {
                 self.line.move_backward(1);
             }
             self.refresh_line()
-        } else {
+        } else if text.is_empty() {
             Ok(())
+        } else {
+            // the line is too long
+            self.out.beep()
         }
     }
 
@@ -420,6 +488,58 @@ impl<'out, 'prompt, H: Helper> State<'out, 'prompt, H> {
         result
     }
 
+    /// Insert a word of the history entry preceding `previous` (or of the last
+    /// entry) in place of the word inserted by the previous call.
+    /// Returns the history index and length of the inserted word.
+    pub fn edit_yank_last_arg(
+        &mut self,
+        arg: Option<i32>,
+        previous: Option<(usize, usize)>,
+    ) -> Result<Option<(usize, usize)>> {
+        let history = self.ctx.history;
+        let index = previous.map_or(history.len(), |(index, _)| index);
+        if index == 0 {
+            self.out.beep()?;
+            return Ok(previous);
+        }
+        let index = index - 1;
+        let yank_size = previous.map_or(0, |(_, size)| size);
+        let words: Vec<&str> = history[index].split_whitespace().collect();
+        #[allow(clippy::cast_sign_loss)]
+        let word = match arg {
+            None => words.last(),
+            Some(n) if n >= 0 => words.get(n as usize),
+            Some(n) => words
+                .len()
+                .checked_sub(n.wrapping_neg() as usize)
+                .and_then(|i| words.get(i)),
+        };
+        let word = match word {
+            Some(word) => *word,
+            None => {
+                self.out.beep()?;
+                return Ok(Some((index, yank_size)));
+            }
+        };
+        self.changes.borrow_mut().begin();
+        let result = if self.line.yank_pop(yank_size, word).is_some() {
+            self.refresh_line()
+        } else {
+            Ok(())
+        };
+        self.changes.borrow_mut().end();
+        result.map(|_| Some((index, word.len())))
+    }
+
+    /// Swap the cursor and the mark.
+    pub fn edit_exchange_point_and_mark(&mut self) -> Result<()> {
+        if self.line.exchange_point_and_mark() {
+            self.move_cursor()
+        } else {
+            self.out.beep()
+        }
+    }
+
     /// Move cursor on the left.
     pub fn edit_move_backward(&mut self, n: RepeatCount) -> Result<()> {
         if self.line.move_backward(n) {
@@ -464,6 +584,14 @@ impl<'out, 'prompt, H: Helper> State<'out, 'prompt, H> {
         }
     }
 
+    /// Replace the whole line (after an external edit).
+    pub fn edit_replace_line(&mut self, text: &str) -> Result<()> {
+        self.changes.borrow_mut().begin();
+        self.line.update(text, text.len());
+        self.changes.borrow_mut().end();
+        self.refresh_line()
+    }
+
     pub fn edit_insert_text(&mut self, text: &str) -> Result<()> {
         if text.is_empty() {
             return Ok(());
@@ -501,6 +629,22 @@ impl<'out, 'prompt, H: Helper> State<'out, 'prompt, H> {
         }
     }
 
+    pub fn edit_move_to_prev_word_end(&mut self, word_def: Word, n: RepeatCount) -> Result<()> {
+        if self.line.move_to_prev_word_end(word_def, n) {
+            self.move_cursor()
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn edit_move_to_matching_bracket(&mut self) -> Result<()> {
+        if self.line.move_to_matching_bracket() {
+            self.move_cursor()
+        } else {
+            Ok(())
+        }
+    }
+
     pub fn edit_move_to_next_word(&mut self, at: At, word_def: Word, n: RepeatCount) -> Result<()> {
         if self.line.move_to_next_word(at, word_def, n) {
             self.move_cursor()
@@ -584,7 +728,7 @@ impl<'out, 'prompt, H: Helper> State<'out, 'prompt, H> {
         if self.ctx.history_index < history.len() {
             let buf = history.get(self.ctx.history_index).unwrap();
             self.changes.borrow_mut().begin();
-            self.line.update(buf, buf.len());
+            self.line.update_overflowing(buf, buf.len());
             self.changes.borrow_mut().end();
         } else {
             // Restore current edited line
@@ -594,7 +738,10 @@ impl<'out, 'prompt, H: Helper> State<'out, 'prompt, H> {
     }
 
     // Non-incremental, anchored search
-    pub fn edit_history_search(&mut self, dir: Direction) -> Result<()> {
+    /// Search the history for an entry starting with the text before the
+    /// cursor, skipping the entries equal to the current line if
+    /// `no_duplicates`.
+    pub fn edit_history_search(&mut self, dir: Direction, no_duplicates: bool) -> Result<()> {
         let history = self.ctx.history;
         if history.is_empty() {
             return self.out.beep();
@@ -609,15 +756,51 @@ impl<'out, 'prompt, H: Helper> State<'out, 'prompt, H> {
         } else {
             self.ctx.history_index += 1;
         }
-        if let Some(history_index) = history.starts_with(
-            &self.line.as_str()[..self.line.pos()],
-            self.ctx.history_index,
-            dir,
-        ) {
+        let prefix = &self.line.as_str()[..self.line.pos()];
+        let mut start = Some(self.ctx.history_index);
+        let found = loop {
+            let index = start.and_then(|start| history.starts_with(prefix, start, dir));
+            match index {
+                Some(index) if no_duplicates && history[index] == self.line.as_str() => {
+                    start = match dir {
+                        Direction::Reverse => index.checked_sub(1),
+                        Direction::Forward => Some(index + 1),
+                    };
+                }
+                _ => break index,
+            }
+        };
+        if let Some(history_index) = found {
+            self.ctx.history_index = history_index;
+            let buf = history.get(history_index).unwrap();
+            self.changes.borrow_mut().begin();
+            self.line.update_overflowing(buf, buf.len());
+            self.changes.borrow_mut().end();
+            self.refresh_line()
+        } else {
+            self.out.beep()
+        }
+    }
+
+    // Non-incremental, unanchored search (vi-search)
+    pub fn edit_history_search_term(&mut self, term: &str, dir: Direction) -> Result<()> {
+        let history = self.ctx.history;
+        let start = match dir {
+            Direction::Reverse => self.ctx.history_index.checked_sub(1),
+            Direction::Forward if self.ctx.history_index + 1 < history.len() => {
+                Some(self.ctx.history_index + 1)
+            }
+            Direction::Forward => None,
+        };
+        if let Some(history_index) = start.and_then(|start| history.search(term, start, dir)) {
+            if self.ctx.history_index == history.len() {
+                // Save the current edited line before overwriting it
+                self.backup();
+            }
             self.ctx.history_index = history_index;
             let buf = history.get(history_index).unwrap();
             self.changes.borrow_mut().begin();
-            self.line.update(buf, buf.len());
+            self.line.update_overflowing(buf, 0);
             self.changes.borrow_mut().end();
             self.refresh_line()
         } else {
@@ -645,7 +828,7 @@ impl<'out, 'prompt, H: Helper> State<'out, 'prompt, H> {
             self.ctx.history_index = 0;
             let buf = history.get(self.ctx.history_index).unwrap();
             self.changes.borrow_mut().begin();
-            self.line.update(buf, buf.len());
+            self.line.update_overflowing(buf, buf.len());
             self.changes.borrow_mut().end();
         } else {
             self.ctx.history_index = history.len();
@@ -675,7 +858,7 @@ pub fn init_state<'out, H: Helper>(
         changes: Rc::new(RefCell::new(Changeset::new())),
         helper,
         ctx: Context::new(history),
-        hint: Some("hint".to_owned()),
+        hint: Some(StyledHint::from("hint".to_owned())),
         highlight_char: false,
     }
 }
@@ -683,8 +866,44 @@ pub fn init_state<'out, H: Helper>(
 #[cfg(test)]
 mod test {
     use super::init_state;
+    use crate::completion::Completer;
+    use crate::highlight::Highlighter;
+    use crate::hint::Hinter;
     use crate::history::History;
     use crate::tty::Sink;
+    use crate::validate::Validator;
+    use crate::{Context, Helper};
+
+    struct EndOfLineHinter;
+    impl Completer for EndOfLineHinter {
+        type Candidate = String;
+    }
+    impl Hinter for EndOfLineHinter {
+        fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<String> {
+            if pos == line.len() {
+                Some(" <tab>".to_owned())
+            } else {
+                None
+            }
+        }
+    }
+    impl Highlighter for EndOfLineHinter {}
+    impl Validator for EndOfLineHinter {}
+    impl Helper for EndOfLineHinter {}
+
+    #[test]
+    fn hint_follows_cursor() {
+        let mut out = Sink::new();
+        let history = History::new();
+        let helper = Some(EndOfLineHinter);
+        let mut s = init_state(&mut out, "ab", 2, helper.as_ref(), &history);
+        s.hint();
+        assert_eq!(Some(" <tab>"), s.hint.as_ref().map(|h| h.display.as_str()));
+        s.edit_move_backward(1).unwrap();
+        assert_eq!(None, s.hint);
+        s.edit_move_end().unwrap();
+        assert_eq!(Some(" <tab>"), s.hint.as_ref().map(|h| h.display.as_str()));
+    }
 
     #[test]
     fn edit_history_next() {
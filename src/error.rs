@@ -19,14 +19,19 @@ pub enum ReadlineError {
     Eof,
     /// Ctrl-C
     Interrupted,
+    /// History file cannot be rotated (backup file cannot be created)
+    HistoryRotation(io::Error),
     /// Chars Error
-    #[cfg(unix)]
+    #[cfg(any(unix, feature = "crossterm"))]
     Utf8Error,
     /// Unix Error from syscall
     #[cfg(unix)]
     Errno(nix::Error),
-    #[cfg(windows)]
+    #[cfg(any(windows, feature = "crossterm"))]
     WindowResize,
+    /// Error from the `crossterm` backend
+    #[cfg(feature = "crossterm")]
+    Crossterm(crossterm::ErrorKind),
     #[cfg(windows)]
     Decode(char::DecodeUtf16Error),
 }
@@ -37,12 +42,17 @@ impl fmt::Display for ReadlineError {
             ReadlineError::Io(ref err) => err.fmt(f),
             ReadlineError::Eof => write!(f, "EOF"),
             ReadlineError::Interrupted => write!(f, "Interrupted"),
-            #[cfg(unix)]
+            ReadlineError::HistoryRotation(ref err) => {
+                write!(f, "Cannot rotate history file: {}", err)
+            }
+            #[cfg(any(unix, feature = "crossterm"))]
             ReadlineError::Utf8Error => write!(f, "invalid utf-8: corrupt contents"),
             #[cfg(unix)]
             ReadlineError::Errno(ref err) => err.fmt(f),
-            #[cfg(windows)]
+            #[cfg(any(windows, feature = "crossterm"))]
             ReadlineError::WindowResize => write!(f, "WindowResize"),
+            #[cfg(feature = "crossterm")]
+            ReadlineError::Crossterm(ref err) => err.fmt(f),
             #[cfg(windows)]
             ReadlineError::Decode(ref err) => err.fmt(f),
         }
@@ -51,6 +61,28 @@ impl fmt::Display for ReadlineError {
 
 impl error::Error for ReadlineError {}
 
+/// The error type for invalid configuration values
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// `keyseq_timeout` must be -1 (no timeout) or a number of milliseconds
+    KeyseqTimeout(i32),
+    /// A raw sequence must be 1 to 16 bytes long
+    RawSequenceLength(usize),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ConfigError::KeyseqTimeout(ms) => write!(f, "Invalid keyseq timeout: {}", ms),
+            ConfigError::RawSequenceLength(len) => {
+                write!(f, "Invalid raw sequence length: {}", len)
+            }
+        }
+    }
+}
+
+impl error::Error for ConfigError {}
+
 impl From<io::Error> for ReadlineError {
     fn from(err: io::Error) -> Self {
         ReadlineError::Io(err)
@@ -70,6 +102,16 @@ impl From<nix::Error> for ReadlineError {
     }
 }
 
+#[cfg(feature = "crossterm")]
+impl From<crossterm::ErrorKind> for ReadlineError {
+    fn from(err: crossterm::ErrorKind) -> Self {
+        match err {
+            crossterm::ErrorKind::IoError(err) => ReadlineError::Io(err),
+            err => ReadlineError::Crossterm(err),
+        }
+    }
+}
+
 #[cfg(windows)]
 impl From<char::DecodeUtf16Error> for ReadlineError {
     fn from(err: char::DecodeUtf16Error) -> Self {
@@ -4,6 +4,122 @@ use crate::config::CompletionType;
 use memchr::memchr;
 use std::borrow::Cow::{self, Borrowed, Owned};
 use std::cell::Cell;
+use std::ops::Range;
+
+/// Terminal color
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Color {
+    /// One of the 16 standard colors (8-15 being the bright ones)
+    Ansi(u8),
+    /// One of the 256 indexed colors
+    Ansi256(u8),
+    /// 24-bit color
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    /// SGR parameters, `base` being 30 for foreground and 40 for background
+    fn write_sgr(self, base: u8, sgr: &mut String) {
+        use std::fmt::Write;
+        match self {
+            Color::Ansi(n) if n < 8 => write!(sgr, "{}", base + n),
+            Color::Ansi(n) if n < 16 => write!(sgr, "{}", base + 60 + n - 8),
+            Color::Ansi(n) | Color::Ansi256(n) => write!(sgr, "{};5;{}", base + 8, n),
+            Color::Rgb(r, g, b) => write!(sgr, "{};2;{};{};{}", base + 8, r, g, b),
+        }
+        .unwrap();
+    }
+}
+
+/// Style of a span of text
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Style {
+    /// Foreground color
+    pub fg: Option<Color>,
+    /// Background color
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    /// URI the text links to, rendered as an OSC 8 hyperlink when
+    /// `Config::hyperlinks_enabled` is set.
+    pub link: Option<String>,
+}
+
+impl Style {
+    /// Escape sequence (SGR) which sets this style, empty for the default
+    /// style.
+    pub fn prefix(&self) -> String {
+        let mut sgr = String::new();
+        for (set, code) in &[(self.bold, "1"), (self.italic, "3"), (self.underline, "4")] {
+            if *set {
+                sgr.push_str(code);
+                sgr.push(';');
+            }
+        }
+        if let Some(fg) = self.fg {
+            fg.write_sgr(30, &mut sgr);
+            sgr.push(';');
+        }
+        if let Some(bg) = self.bg {
+            bg.write_sgr(40, &mut sgr);
+            sgr.push(';');
+        }
+        if sgr.pop().is_none() {
+            return sgr;
+        }
+        format!("\x1b[{}m", sgr)
+    }
+}
+
+/// Render the `spans` of `line` with their style.
+///
+/// Spans must be sorted and must not overlap: the ones which do not (or are
+/// out of `line` bounds) are ignored.
+pub fn style_spans(line: &str, spans: &[(Range<usize>, Style)]) -> String {
+    render_spans(line, spans, false)
+}
+
+/// Same as `style_spans` but linked spans are wrapped in OSC 8 sequences
+/// when `hyperlinks` is set.
+pub(crate) fn render_spans(
+    line: &str,
+    spans: &[(Range<usize>, Style)],
+    hyperlinks: bool,
+) -> String {
+    let mut styled = String::with_capacity(line.len());
+    let mut pos = 0;
+    for (range, style) in spans {
+        if range.start < pos {
+            continue;
+        }
+        let (text, prefix) = match line.get(range.clone()) {
+            Some(text) if !text.is_empty() => (text, style.prefix()),
+            _ => continue,
+        };
+        styled.push_str(&line[pos..range.start]);
+        let link = style.link.as_ref().filter(|_| hyperlinks);
+        if let Some(uri) = link {
+            styled.push_str("\x1b]8;;");
+            // control characters would terminate the sequence early
+            styled.extend(uri.chars().filter(|c| !c.is_control()));
+            styled.push_str("\x1b\\");
+        }
+        if prefix.is_empty() {
+            styled.push_str(text);
+        } else {
+            styled.push_str(&prefix);
+            styled.push_str(text);
+            styled.push_str("\x1b[0m");
+        }
+        if link.is_some() {
+            styled.push_str("\x1b]8;;\x1b\\");
+        }
+        pos = range.end;
+    }
+    styled.push_str(&line[pos..]);
+    styled
+}
 
 /// Syntax highlighter with [ANSI color](https://en.wikipedia.org/wiki/ANSI_escape_code#SGR_(Select_Graphic_Rendition)_parameters).
 /// Rustyline will try to handle escape sequence for ANSI color on windows
@@ -17,9 +133,51 @@ pub trait Highlighter {
     ///
     /// For example, you can implement
     /// [blink-matching-paren](https://www.gnu.org/software/bash/manual/html_node/Readline-Init-File-Syntax.html).
+    ///
+    /// By default, the spans returned by `highlight_spans` are styled.
     fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
-        let _ = pos;
-        Borrowed(line)
+        let spans = self.highlight_spans(line, pos);
+        if spans.is_empty() {
+            Borrowed(line)
+        } else {
+            Owned(style_spans(line, &spans))
+        }
+    }
+    /// Takes the currently edited `line` with the cursor `pos`ition and
+    /// returns the styled spans of `line` (byte ranges, sorted and not
+    /// overlapping) instead of escape sequences.
+    ///
+    /// Only used by the default implementation of `highlight`.
+    fn highlight_spans(&self, line: &str, pos: usize) -> Vec<(Range<usize>, Style)> {
+        let _ = (line, pos);
+        Vec::new()
+    }
+    /// Same as `highlight` but when a mark is set: `region` is the text
+    /// between the mark and the cursor.
+    ///
+    /// By default, the region is not highlighted.
+    fn highlight_region<'l>(
+        &self,
+        line: &'l str,
+        pos: usize,
+        region: Range<usize>,
+    ) -> Cow<'l, str> {
+        let _ = region;
+        self.highlight(line, pos)
+    }
+    /// Same as `highlight` but told whether the line is longer than
+    /// `Config::max_line_length` (`line_too_long`), which is possible when it
+    /// comes from the history, so the overflow can be styled differently.
+    ///
+    /// By default, `highlight` is called.
+    fn highlight_overflow<'l>(
+        &self,
+        line: &'l str,
+        pos: usize,
+        line_too_long: bool,
+    ) -> Cow<'l, str> {
+        let _ = line_too_long;
+        self.highlight(line, pos)
     }
     /// Takes the `prompt` and
     /// returns the highlighted version (with ANSI color).
@@ -39,7 +197,9 @@ pub trait Highlighter {
     /// Takes the completion `candidate` and
     /// returns the highlighted version (with ANSI color).
     ///
-    /// Currently, used only with `CompletionType::List`.
+    /// Currently, used only with `CompletionType::List`,
+    /// `CompletionType::Menu` and `CompletionType::Fuzzy` (through
+    /// `highlight_candidate_matches`).
     fn highlight_candidate<'c>(
         &self,
         candidate: &'c str,
@@ -48,6 +208,19 @@ pub trait Highlighter {
         let _ = completion;
         Borrowed(candidate)
     }
+    /// Same as `highlight_candidate` with the indices of the chars `matched`
+    /// by fuzzy completion (`CompletionType::Fuzzy`).
+    ///
+    /// `candidate` may be truncated so some indices may be out of range.
+    fn highlight_candidate_matches<'c>(
+        &self,
+        candidate: &'c str,
+        completion: CompletionType,
+        matched: &[usize],
+    ) -> Cow<'c, str> {
+        let _ = matched;
+        self.highlight_candidate(candidate, completion)
+    }
     /// Tells if `line` needs to be highlighted when a specific char is typed or
     /// when cursor is moved under a specific char.
     ///
@@ -57,6 +230,14 @@ pub trait Highlighter {
         let _ = (line, pos);
         false
     }
+    /// Tells if the line needs to be highlighted again even if no key is
+    /// pressed (to animate a spinner for example).
+    ///
+    /// Checked after each refresh: while `true`, the line is refreshed
+    /// periodically until a key is pressed.
+    fn needs_update(&self) -> bool {
+        false
+    }
 }
 
 impl Highlighter for () {}
@@ -66,6 +247,28 @@ impl<'r, H: ?Sized + Highlighter> Highlighter for &'r H {
         (**self).highlight(line, pos)
     }
 
+    fn highlight_spans(&self, line: &str, pos: usize) -> Vec<(Range<usize>, Style)> {
+        (**self).highlight_spans(line, pos)
+    }
+
+    fn highlight_region<'l>(
+        &self,
+        line: &'l str,
+        pos: usize,
+        region: Range<usize>,
+    ) -> Cow<'l, str> {
+        (**self).highlight_region(line, pos, region)
+    }
+
+    fn highlight_overflow<'l>(
+        &self,
+        line: &'l str,
+        pos: usize,
+        line_too_long: bool,
+    ) -> Cow<'l, str> {
+        (**self).highlight_overflow(line, pos, line_too_long)
+    }
+
     fn highlight_prompt<'b, 's: 'b, 'p: 'b>(
         &'s self,
         prompt: &'p str,
@@ -86,9 +289,22 @@ impl<'r, H: ?Sized + Highlighter> Highlighter for &'r H {
         (**self).highlight_candidate(candidate, completion)
     }
 
+    fn highlight_candidate_matches<'c>(
+        &self,
+        candidate: &'c str,
+        completion: CompletionType,
+        matched: &[usize],
+    ) -> Cow<'c, str> {
+        (**self).highlight_candidate_matches(candidate, completion, matched)
+    }
+
     fn highlight_char(&self, line: &str, pos: usize) -> bool {
         (**self).highlight_char(line, pos)
     }
+
+    fn needs_update(&self) -> bool {
+        (**self).needs_update()
+    }
 }
 
 const OPENS: &[u8; 3] = b"{[(";
@@ -232,6 +448,84 @@ fn is_close_bracket(bracket: u8) -> bool {
 
 #[cfg(test)]
 mod tests {
+    use super::{Color, Highlighter, Style};
+    use std::ops::Range;
+
+    #[test]
+    pub fn style_prefix() {
+        assert_eq!("", Style::default().prefix());
+        let style = Style {
+            fg: Some(Color::Ansi(1)),
+            bold: true,
+            ..Style::default()
+        };
+        assert_eq!("\x1b[1;31m", style.prefix());
+        let style = Style {
+            fg: Some(Color::Ansi(9)),
+            bg: Some(Color::Ansi256(236)),
+            underline: true,
+            ..Style::default()
+        };
+        assert_eq!("\x1b[4;91;48;5;236m", style.prefix());
+        let style = Style {
+            fg: Some(Color::Rgb(255, 128, 0)),
+            italic: true,
+            ..Style::default()
+        };
+        assert_eq!("\x1b[3;38;2;255;128;0m", style.prefix());
+    }
+
+    struct Keywords;
+    impl Highlighter for Keywords {
+        fn highlight_spans(&self, line: &str, _: usize) -> Vec<(Range<usize>, Style)> {
+            let bold = Style {
+                bold: true,
+                ..Style::default()
+            };
+            line.match_indices("fn")
+                .map(|(i, s)| (i..i + s.len(), bold.clone()))
+                .collect()
+        }
+    }
+
+    #[test]
+    pub fn highlight_spans() {
+        assert_eq!("let x", Keywords.highlight("let x", 0));
+        assert_eq!(
+            "\x1b[1mfn\x1b[0m f() {}",
+            Keywords.highlight("fn f() {}", 0)
+        );
+        let spans = [
+            (2..4, Style::default()),
+            (
+                0..1,
+                Style {
+                    bold: true,
+                    ..Style::default()
+                },
+            ),
+            (3..9, Style::default()),
+        ];
+        assert_eq!("abcd", super::style_spans("abcd", &spans));
+    }
+
+    #[test]
+    pub fn render_links() {
+        use super::render_spans;
+        let spans = [(
+            4..8,
+            Style {
+                link: Some("https://docs.rs/\x07x\x1b".to_owned()),
+                ..Style::default()
+            },
+        )];
+        assert_eq!("see docs", render_spans("see docs", &spans, false));
+        assert_eq!(
+            "see \x1b]8;;https://docs.rs/x\x1b\\docs\x1b]8;;\x1b\\",
+            render_spans("see docs", &spans, true)
+        );
+    }
+
     #[test]
     pub fn find_matching_bracket() {
         use super::find_matching_bracket;
@@ -3,16 +3,60 @@
 use crate::history::Direction;
 use crate::Context;
 
+/// Hint displayed at the right of the cursor
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StyledHint {
+    /// Text displayed
+    pub display: String,
+    /// Text inserted when the hint is completed
+    pub completion: String,
+    /// `display` already contains ANSI escape sequences so
+    /// `Highlighter::highlight_hint` is not applied
+    pub already_styled: bool,
+}
+
+impl StyledHint {
+    /// Hint displayed as is: `completion` should be `display` without
+    /// escape sequences.
+    pub fn styled(display: String, completion: String) -> Self {
+        Self {
+            display,
+            completion,
+            already_styled: true,
+        }
+    }
+}
+
+impl From<String> for StyledHint {
+    /// Plain hint styled by `Highlighter::highlight_hint`
+    fn from(hint: String) -> Self {
+        Self {
+            display: hint.clone(),
+            completion: hint,
+            already_styled: false,
+        }
+    }
+}
+
 /// Hints provider
 pub trait Hinter {
     /// Takes the currently edited `line` with the cursor `pos`ition and
     /// returns the string that should be displayed or `None`
     /// if no hint is available for the text the user currently typed.
-    // TODO Validate: called while editing line but not while moving cursor.
+    ///
+    /// Called each time the line is edited or the cursor is moved, so the
+    /// hint can depend on the cursor position.
     fn hint(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Option<String> {
         let _ = (line, pos, ctx);
         None
     }
+
+    /// Like `hint` but lets the hinter style the hint itself.
+    ///
+    /// By default, the `hint` is styled by `Highlighter::highlight_hint`.
+    fn hint_styled(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Option<StyledHint> {
+        self.hint(line, pos, ctx).map(StyledHint::from)
+    }
 }
 
 impl Hinter for () {}
@@ -21,6 +65,10 @@ impl<'r, H: ?Sized + Hinter> Hinter for &'r H {
     fn hint(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Option<String> {
         (**self).hint(line, pos, ctx)
     }
+
+    fn hint_styled(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Option<StyledHint> {
+        (**self).hint_styled(line, pos, ctx)
+    }
 }
 
 pub struct HistoryHinter {}
@@ -2,30 +2,141 @@
 
 #[cfg(unix)]
 use libc;
+use std::borrow::Cow;
 use std::collections::vec_deque;
 use std::collections::VecDeque;
-use std::fs::File;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::iter::DoubleEndedIterator;
+use std::mem;
 use std::ops::Index;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use super::Result;
 use crate::config::{Config, HistoryDuplicates};
+use crate::error::ReadlineError;
+
+/// First line of the history files with annotations and escaped entries
+const FILE_HEADER: &str = "#V2";
 
 /// Search direction
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "with-serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Direction {
     Forward,
     Reverse,
 }
 
+impl Direction {
+    pub(crate) fn opposite(self) -> Self {
+        match self {
+            Direction::Forward => Direction::Reverse,
+            Direction::Reverse => Direction::Forward,
+        }
+    }
+}
+
+/// Metadata attached to a history entry.
+///
+/// In the history file, each field which is set is written on its own line
+/// before the entry, as a shell comment: `# <key>=<value>` (with `\\`, `\n`
+/// and `\r` escaped). An entry starting with `#` is written with a leading
+/// `\` so that it is never mistaken for an annotation.
+///
+/// Such a file starts with a `#V2` header line. It is only written when some
+/// entry has metadata or must be escaped: other files contain one entry per
+/// line, as written by previous versions and other tools, and are read
+/// verbatim.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HistoryMeta {
+    /// When the entry was added (saved with a precision of one second)
+    pub timestamp: Option<SystemTime>,
+    /// Working directory
+    pub cwd: Option<PathBuf>,
+    /// Exit code of the command
+    pub exit_code: Option<i32>,
+    /// Session which added the entry
+    pub session_id: Option<u64>,
+}
+
+impl HistoryMeta {
+    /// Annotation lines written before the entry in the history file
+    fn annotations(&self) -> String {
+        use std::fmt::Write;
+        let mut annotations = String::new();
+        if let Some(timestamp) = self.timestamp {
+            if let Ok(secs) = timestamp.duration_since(UNIX_EPOCH) {
+                writeln!(annotations, "# timestamp={}", secs.as_secs()).unwrap();
+            }
+        }
+        if let Some(ref cwd) = self.cwd {
+            let cwd = cwd.to_string_lossy();
+            writeln!(annotations, "# cwd={}", escape_value(&cwd)).unwrap();
+        }
+        if let Some(exit_code) = self.exit_code {
+            writeln!(annotations, "# exit_code={}", exit_code).unwrap();
+        }
+        if let Some(session_id) = self.session_id {
+            writeln!(annotations, "# session_id={}", session_id).unwrap();
+        }
+        annotations
+    }
+
+    /// Parse an annotation line into `self`.
+    ///
+    /// Return false if `line` is not a (known) annotation, i.e. if it is an
+    /// entry.
+    fn parse_annotation(&mut self, line: &str) -> bool {
+        if !line.starts_with("# ") {
+            return false;
+        }
+        let line = &line[2..];
+        let (key, value) = match line.find('=') {
+            Some(i) => (&line[..i], &line[i + 1..]),
+            None => return false,
+        };
+        match key {
+            "timestamp" => match value.parse() {
+                Ok(secs) => self.timestamp = Some(UNIX_EPOCH + Duration::from_secs(secs)),
+                Err(_) => return false,
+            },
+            "cwd" if !value.is_empty() => self.cwd = Some(PathBuf::from(unescape_value(value))),
+            "exit_code" => match value.parse() {
+                Ok(exit_code) => self.exit_code = Some(exit_code),
+                Err(_) => return false,
+            },
+            "session_id" => match value.parse() {
+                Ok(session_id) => self.session_id = Some(session_id),
+                Err(_) => return false,
+            },
+            _ => return false,
+        }
+        true
+    }
+}
+
+struct Entry {
+    line: String,
+    meta: HistoryMeta,
+    /// Added by this session and not written by `save` or `append` yet
+    unsaved: AtomicBool,
+}
+
 /// Current state of the history.
 #[derive(Default)]
 pub struct History {
-    entries: VecDeque<String>,
+    entries: VecDeque<Entry>,
     max_len: usize,
+    pub(crate) max_file_size: Option<usize>,
+    pub(crate) max_file_len: Option<usize>,
+    pub(crate) backup: bool,
     pub(crate) ignore_space: bool,
     pub(crate) ignore_dups: bool,
+    /// Offset in the history file up to which this process has read or
+    /// written it
+    file_len: AtomicUsize,
 }
 
 impl History {
@@ -37,23 +148,50 @@ impl History {
         Self {
             entries: VecDeque::new(),
             max_len: config.max_history_size(),
+            max_file_size: config.max_history_file_size(),
+            max_file_len: config.max_history_len(),
+            backup: config.history_backup(),
             ignore_space: config.history_ignore_space(),
             ignore_dups: config.history_duplicates() == HistoryDuplicates::IgnoreConsecutive,
+            file_len: AtomicUsize::new(0),
         }
     }
 
     /// Return the history entry at position `index`, starting from 0.
     pub fn get(&self, index: usize) -> Option<&String> {
-        self.entries.get(index)
+        self.entries.get(index).map(|entry| &entry.line)
+    }
+
+    /// Return the metadata of the history entry at position `index`.
+    pub fn metadata(&self, index: usize) -> Option<&HistoryMeta> {
+        self.entries.get(index).map(|entry| &entry.meta)
     }
 
     /// Return the last history entry (i.e. previous command)
     pub fn last(&self) -> Option<&String> {
-        self.entries.back()
+        self.entries.back().map(|entry| &entry.line)
     }
 
     /// Add a new entry in the history.
     pub fn add<S: AsRef<str> + Into<String>>(&mut self, line: S) -> bool {
+        self.add_with_metadata(line, HistoryMeta::default())
+    }
+
+    /// Add a new entry with its metadata in the history.
+    pub fn add_with_metadata<S: AsRef<str> + Into<String>>(
+        &mut self,
+        line: S,
+        meta: HistoryMeta,
+    ) -> bool {
+        self.insert(line, meta, true)
+    }
+
+    fn insert<S: AsRef<str> + Into<String>>(
+        &mut self,
+        line: S,
+        meta: HistoryMeta,
+        unsaved: bool,
+    ) -> bool {
         if self.max_len == 0 {
             return false;
         }
@@ -68,8 +206,8 @@ impl History {
             return false;
         }
         if self.ignore_dups {
-            if let Some(s) = self.entries.back() {
-                if s == line.as_ref() {
+            if let Some(entry) = self.entries.back() {
+                if entry.line == line.as_ref() {
                     return false;
                 }
             }
@@ -77,7 +215,11 @@ impl History {
         if self.entries.len() == self.max_len {
             self.entries.pop_front();
         }
-        self.entries.push_back(line.into());
+        self.entries.push_back(Entry {
+            line: line.into(),
+            meta,
+            unsaved: AtomicBool::new(unsaved),
+        });
         true
     }
 
@@ -113,42 +255,233 @@ impl History {
     }
 
     /// Save the history in the specified file.
-    // TODO append_history
-    // http://cnswww.cns.cwru.edu/php/chet/readline/history.html#IDX30
-    // TODO history_truncate_file
-    // http://cnswww.cns.cwru.edu/php/chet/readline/history.html#IDX31
+    ///
+    /// Oldest entries are skipped if the file would exceed `max_file_size`
+    /// bytes or `max_file_len` entries.
+    ///
+    /// An exclusive advisory lock is held on the file while writing.
+    ///
+    /// # Errors
+    /// Will return `Err(ReadlineError::HistoryRotation)` if the backup file
+    /// cannot be created.
     pub fn save<P: AsRef<Path> + ?Sized>(&self, path: &P) -> Result<()> {
-        use std::io::{BufWriter, Write};
-
         if self.is_empty() {
             return Ok(());
         }
+        let path = path.as_ref();
+        if self.backup && path.exists() {
+            let mut backup = path.as_os_str().to_owned();
+            backup.push(".bak");
+            fs::rename(path, backup).map_err(ReadlineError::HistoryRotation)?;
+        }
+        let old_umask = umask();
+        let f = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false) // truncated once locked
+            .open(path);
+        restore_umask(old_umask);
+        let mut file = f?;
+        fix_perm(&file);
+        lock(&file, true)?;
+        file.set_len(0)?;
+        self.save_to(&mut file)?;
+        self.file_len
+            .store(file.metadata()?.len() as usize, Ordering::Relaxed);
+        self.mark_saved();
+        Ok(())
+    }
+
+    /// Append the entries added since the last call to `append` or `save` (or
+    /// since the creation of the history) at the end of the specified file.
+    ///
+    /// Like [append_history](http://cnswww.cns.cwru.
+    /// edu/php/chet/readline/history.html#IDX30).
+    /// An exclusive advisory lock is held on the file while writing so that
+    /// concurrent sessions can share the same file. The entries appended by
+    /// other processes since the file was last read are loaded first (like
+    /// `sync`). The metadata of the entries is only written if the file is
+    /// empty or already has annotations (see `HistoryMeta`).
+    pub fn append<P: AsRef<Path> + ?Sized>(&mut self, path: &P) -> Result<()> {
+        if !self
+            .entries
+            .iter()
+            .any(|entry| entry.unsaved.load(Ordering::Relaxed))
+        {
+            return Ok(());
+        }
         let old_umask = umask();
-        let f = File::create(path);
+        let f = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path);
         restore_umask(old_umask);
-        let file = f?;
+        let mut file = f?;
         fix_perm(&file);
-        let mut wtr = BufWriter::new(file);
+        lock(&file, true)?;
+        self.read_new_entries(&mut file)?;
+        {
+            let unsaved = self
+                .entries
+                .iter()
+                .filter(|entry| entry.unsaved.load(Ordering::Relaxed));
+            // the format of a non empty file is kept
+            let empty = file.metadata()?.len() == 0;
+            let annotated = if empty {
+                unsaved.clone().any(Entry::needs_header)
+            } else {
+                has_header(&mut file)?
+            };
+            let mut wtr = BufWriter::new(&mut file);
+            if annotated && empty {
+                writeln!(wtr, "{}", FILE_HEADER)?;
+            }
+            for entry in unsaved {
+                write_entry(&mut wtr, entry, annotated)?;
+            }
+            wtr.flush()?;
+        }
+        self.mark_saved();
+        let offset = file.seek(SeekFrom::End(0))?;
+        self.file_len.store(offset as usize, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Entries are not appended again once they have been written.
+    fn mark_saved(&self) {
         for entry in &self.entries {
-            wtr.write_all(entry.as_bytes())?;
-            wtr.write_all(b"\n")?;
+            entry.unsaved.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Load the entries appended to the specified file by other processes
+    /// since it was last read or written.
+    ///
+    /// Nothing is done if the file does not exist.
+    pub fn sync<P: AsRef<Path> + ?Sized>(&mut self, path: &P) -> Result<()> {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        lock(&file, false)?;
+        self.read_new_entries(&mut file)
+    }
+
+    /// Read the entries after the offset this process has read or written
+    /// the (locked) history file up to.
+    fn read_new_entries(&mut self, file: &mut File) -> Result<()> {
+        let len = file.metadata()?.len() as usize;
+        let offset = self.file_len.load(Ordering::Relaxed);
+        if len > offset {
+            let annotated = if offset == 0 {
+                None // read from the header
+            } else {
+                Some(has_header(file)?)
+            };
+            file.seek(SeekFrom::Start(offset as u64))?;
+            self.read_entries(&mut *file, annotated)?;
+        } // else the file may also have been truncated by another process
+        self.file_len.store(len, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Save the history to the specified writer, one entry per line.
+    ///
+    /// Oldest entries are skipped if the output would exceed `max_file_size`
+    /// bytes or `max_file_len` entries.
+    pub fn save_to<W: Write>(&self, writer: W) -> Result<()> {
+        let mut wtr = BufWriter::new(writer);
+        let entries = self.entries.iter().skip(self.truncate_index());
+        let annotated = entries.clone().any(Entry::needs_header);
+        if annotated {
+            writeln!(wtr, "{}", FILE_HEADER)?;
+        }
+        for entry in entries {
+            write_entry(&mut wtr, entry, annotated)?;
         }
         // https://github.com/rust-lang/rust/issues/32677#issuecomment-204833485
         wtr.flush()?;
         Ok(())
     }
 
+    /// Return the index of the oldest entry which fits in the history file.
+    ///
+    /// Like [history_truncate_file](http://cnswww.cns.cwru.
+    /// edu/php/chet/readline/history.html#IDX31).
+    fn truncate_index(&self) -> usize {
+        let mut start = self.entries.len();
+        let mut size = 0;
+        for entry in self.entries.iter().rev() {
+            if let Some(max_len) = self.max_file_len {
+                if self.entries.len() - start >= max_len {
+                    break;
+                }
+            }
+            let line_size = escape_entry(&entry.line).len() + 1; // '\n'
+            let entry_size = entry.meta.annotations().len() + line_size;
+            if let Some(max_size) = self.max_file_size {
+                if size + entry_size > max_size {
+                    break;
+                }
+            }
+            size += entry_size;
+            start -= 1;
+        }
+        start
+    }
+
     /// Load the history from the specified file.
     ///
+    /// A shared advisory lock is held on the file while reading.
+    ///
     /// # Errors
     /// Will return `Err` if path does not already exist or could not be read.
     pub fn load<P: AsRef<Path> + ?Sized>(&mut self, path: &P) -> Result<()> {
-        use std::io::{BufRead, BufReader};
+        let mut file = File::open(path)?;
+        lock(&file, false)?;
+        self.read_entries(&mut file, None)?;
+        let len = file.metadata()?.len();
+        self.file_len.store(len as usize, Ordering::Relaxed);
+        Ok(())
+    }
 
-        let file = File::open(&path)?;
-        let rdr = BufReader::new(file);
-        for line in rdr.lines() {
-            self.add(line?); // TODO truncate to MAX_LINE
+    /// Load the history from the specified reader, one entry per line.
+    ///
+    /// # Errors
+    /// Will return `Err` if the reader fails or if its content is not valid
+    /// UTF-8.
+    pub fn load_from<R: Read>(&mut self, reader: R) -> Result<()> {
+        self.read_entries(reader, None)
+    }
+
+    // Loaded entries are not considered as new entries by `append`.
+    // Whether the entries are `annotated` is given by the first line when
+    // `None`.
+    fn read_entries<R: Read>(&mut self, reader: R, annotated: Option<bool>) -> Result<()> {
+        let rdr = BufReader::new(reader);
+        let mut lines = rdr.lines().peekable();
+        let annotated = match annotated {
+            Some(annotated) => annotated,
+            None => match lines.peek() {
+                Some(Ok(line)) if line == FILE_HEADER => {
+                    lines.next();
+                    true
+                }
+                _ => false,
+            },
+        };
+        let mut meta = HistoryMeta::default();
+        for line in lines {
+            let mut line = line?;
+            if annotated {
+                if meta.parse_annotation(&line) {
+                    continue;
+                }
+                line = unescape_entry(line);
+            }
+            self.insert(line, mem::take(&mut meta), false); // TODO truncate to MAX_LINE
         }
         Ok(())
     }
@@ -191,11 +524,17 @@ impl History {
                     .iter()
                     .rev()
                     .skip(self.entries.len() - 1 - start)
+                    .map(|entry| &entry.line)
                     .position(test);
                 index.map(|index| start - index)
             }
             Direction::Forward => {
-                let index = self.entries.iter().skip(start).position(test);
+                let index = self
+                    .entries
+                    .iter()
+                    .skip(start)
+                    .map(|entry| &entry.line)
+                    .position(test);
                 index.map(|index| index + start)
             }
         }
@@ -211,7 +550,7 @@ impl Index<usize> for History {
     type Output = String;
 
     fn index(&self, index: usize) -> &String {
-        &self.entries[index]
+        &self.entries[index].line
     }
 }
 
@@ -225,13 +564,13 @@ impl<'a> IntoIterator for &'a History {
 }
 
 /// History iterator.
-pub struct Iter<'a>(vec_deque::Iter<'a, String>);
+pub struct Iter<'a>(vec_deque::Iter<'a, Entry>);
 
 impl<'a> Iterator for Iter<'a> {
     type Item = &'a String;
 
     fn next(&mut self) -> Option<&'a String> {
-        self.0.next()
+        self.0.next().map(|entry| &entry.line)
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
@@ -241,8 +580,167 @@ impl<'a> Iterator for Iter<'a> {
 
 impl<'a> DoubleEndedIterator for Iter<'a> {
     fn next_back(&mut self) -> Option<&'a String> {
-        self.0.next_back()
+        self.0.next_back().map(|entry| &entry.line)
+    }
+}
+
+/// Cursor over the history entries matching a search term.
+///
+/// Entries are returned from the most recent one for a `Reverse` search and
+/// from the oldest one for a `Forward` search.
+pub struct HistorySearcher<'a> {
+    history: &'a History,
+    term: String,
+    dir: Direction,
+    anchored: bool,
+    /// Index of the current match
+    pos: Option<usize>,
+}
+
+impl<'a> HistorySearcher<'a> {
+    pub(crate) fn new(history: &'a History, term: &str, dir: Direction) -> Self {
+        Self {
+            history,
+            term: term.to_owned(),
+            dir,
+            anchored: false,
+            pos: None,
+        }
+    }
+
+    /// Only match the entries starting with the term (instead of containing
+    /// it).
+    pub fn anchored(mut self, anchored: bool) -> Self {
+        self.anchored = anchored;
+        self
+    }
+
+    /// Return the index in the history of the current match.
+    pub fn index(&self) -> Option<usize> {
+        self.pos
+    }
+
+    /// Go back to the previous match.
+    ///
+    /// Return `None` (and stay on the current match) if there is none.
+    pub fn prev(&mut self) -> Option<&'a str> {
+        self.pos?;
+        self.find(self.dir.opposite())
+    }
+
+    /// Restart the search from the beginning.
+    pub fn reset(&mut self) {
+        self.pos = None;
+    }
+
+    fn find(&mut self, dir: Direction) -> Option<&'a str> {
+        let start = match (self.pos, dir) {
+            (None, Direction::Reverse) => self.history.len().checked_sub(1)?,
+            (None, Direction::Forward) => 0,
+            (Some(pos), Direction::Reverse) => pos.checked_sub(1)?,
+            (Some(pos), Direction::Forward) => pos + 1,
+        };
+        let index = if self.anchored {
+            self.history.starts_with(&self.term, start, dir)
+        } else {
+            self.history.search(&self.term, start, dir)
+        }?;
+        self.pos = Some(index);
+        Some(&self.history[index])
+    }
+}
+
+impl<'a> Iterator for HistorySearcher<'a> {
+    type Item = &'a str;
+
+    /// Go to the next match.
+    ///
+    /// Return `None` (and stay on the current match) if there is none.
+    fn next(&mut self) -> Option<&'a str> {
+        self.find(self.dir)
+    }
+}
+
+impl Entry {
+    /// Tell if the entry cannot be written verbatim, without a header
+    fn needs_header(&self) -> bool {
+        self.meta != HistoryMeta::default() || escape_entry(&self.line) != self.line
+    }
+}
+
+/// Write `entry`, preceded by its metadata annotations and escaped when the
+/// file is `annotated`
+fn write_entry<W: Write>(wtr: &mut W, entry: &Entry, annotated: bool) -> io::Result<()> {
+    if annotated {
+        wtr.write_all(entry.meta.annotations().as_bytes())?;
+        wtr.write_all(escape_entry(&entry.line).as_bytes())?;
+    } else {
+        wtr.write_all(entry.line.as_bytes())?;
+    }
+    wtr.write_all(b"\n")
+}
+
+/// Tell if `file` starts with `FILE_HEADER`
+fn has_header(file: &mut File) -> io::Result<bool> {
+    let mut header = Vec::with_capacity(FILE_HEADER.len() + 1);
+    file.seek(SeekFrom::Start(0))?;
+    file.take(FILE_HEADER.len() as u64 + 1)
+        .read_to_end(&mut header)?;
+    Ok(header == format!("{}\n", FILE_HEADER).as_bytes())
+}
+
+/// Prefix `line` with a `\` if it could be read back as an annotation (or if
+/// it already starts with an escaped `#` or `\`).
+fn escape_entry(line: &str) -> Cow<'_, str> {
+    if line.starts_with('#') || line.starts_with("\\#") || line.starts_with("\\\\") {
+        Cow::Owned(format!("\\{}", line))
+    } else {
+        Cow::Borrowed(line)
+    }
+}
+
+/// Reverse `escape_entry`
+fn unescape_entry(mut line: String) -> String {
+    if line.starts_with("\\#") || line.starts_with("\\\\") {
+        line.remove(0);
     }
+    line
+}
+
+/// Escape `\`, `\n` and `\r` so that an annotation value fits on one line
+fn escape_value(value: &str) -> Cow<'_, str> {
+    if !value.contains(&['\\', '\n', '\r'][..]) {
+        return Cow::Borrowed(value);
+    }
+    let mut escaped = String::with_capacity(value.len() + 1);
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Reverse `escape_value`
+fn unescape_value(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
 }
 
 cfg_if::cfg_if! {
@@ -274,6 +772,48 @@ cfg_if::cfg_if! {
     }
 }
 
+cfg_if::cfg_if! {
+    if #[cfg(windows)] {
+        fn lock(file: &File, exclusive: bool) -> io::Result<()> {
+            use std::os::windows::io::AsRawHandle;
+            use winapi::um::fileapi::LockFileEx;
+            use winapi::um::minwinbase::{LOCKFILE_EXCLUSIVE_LOCK, OVERLAPPED};
+            use winapi::um::winnt::HANDLE;
+
+            let flags = if exclusive { LOCKFILE_EXCLUSIVE_LOCK } else { 0 };
+            let mut overlapped: OVERLAPPED = unsafe { std::mem::zeroed() };
+            let rc = unsafe {
+                LockFileEx(file.as_raw_handle() as HANDLE, flags, 0, !0, !0, &mut overlapped)
+            };
+            if rc == 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        }
+    } else if #[cfg(target_arch = "wasm32")] {
+        fn lock(_: &File, _: bool) -> io::Result<()> {
+            Ok(())
+        }
+    } else if #[cfg(unix)] {
+        // The lock is released when the file is closed.
+        fn lock(file: &File, exclusive: bool) -> io::Result<()> {
+            use std::os::unix::io::AsRawFd;
+
+            let operation = if exclusive { libc::LOCK_EX } else { libc::LOCK_SH };
+            loop {
+                if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+                    return Ok(());
+                }
+                let error = io::Error::last_os_error();
+                if error.kind() != io::ErrorKind::Interrupted {
+                    return Err(error);
+                }
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::{Direction, History};
@@ -298,7 +838,7 @@ mod tests {
     #[test]
     fn add() {
         let config = Config::builder().history_ignore_space(true).build();
-        let mut history = History::with_config(config);
+        let mut history = History::with_config(config.clone());
         assert_eq!(config.max_history_size(), history.max_len);
         assert!(history.add("line1"));
         assert!(history.add("line2"));
@@ -326,6 +866,262 @@ mod tests {
         td.close().unwrap();
     }
 
+    #[test]
+    fn save_truncated() {
+        let mut history = init();
+        history.max_file_len = Some(2);
+        history.backup = true;
+        let td = tempdir::TempDir::new_in(&Path::new("."), "histo").unwrap();
+        let history_path = td.path().join(".history");
+
+        history.save(&history_path).unwrap();
+        history.clear();
+        history.load(&history_path).unwrap();
+        assert_eq!(2, history.len());
+        assert_eq!(Some(&"line2".to_owned()), history.get(0));
+
+        history.max_file_len = None;
+        history.max_file_size = Some(6);
+        history.save(&history_path).unwrap();
+        history.clear();
+        history.load(&history_path).unwrap();
+        assert_eq!(1, history.len());
+        assert_eq!(Some(&"line3".to_owned()), history.last());
+        assert!(td.path().join(".history.bak").exists());
+        td.close().unwrap();
+    }
+
+    #[test]
+    fn save_to_load_from() {
+        let history = init();
+        let mut buf = Vec::new();
+        history.save_to(&mut buf).unwrap();
+        assert_eq!(b"line1\nline2\nline3\n", buf.as_slice());
+
+        let mut history = History::new();
+        history.load_from(buf.as_slice()).unwrap();
+        assert_eq!(3, history.len());
+        assert_eq!(Some(&"line3".to_owned()), history.last());
+    }
+
+    #[test]
+    fn add_with_metadata() {
+        use super::HistoryMeta;
+        use std::path::PathBuf;
+        use std::time::{Duration, UNIX_EPOCH};
+        let mut history = History::new();
+        let meta = HistoryMeta {
+            timestamp: Some(UNIX_EPOCH + Duration::from_secs(1_600_000_000)),
+            cwd: Some(PathBuf::from("/tmp")),
+            exit_code: Some(1),
+            session_id: None,
+        };
+        assert!(history.add_with_metadata("false", meta.clone()));
+        assert!(history.add("# a comment"));
+        let mut buf = Vec::new();
+        history.save_to(&mut buf).unwrap();
+        assert_eq!(
+            "#V2\n# timestamp=1600000000\n# cwd=/tmp\n# exit_code=1\nfalse\n\\# a comment\n",
+            String::from_utf8(buf.clone()).unwrap()
+        );
+
+        let mut history = History::new();
+        history.load_from(buf.as_slice()).unwrap();
+        assert_eq!(2, history.len());
+        assert_eq!(Some(&"false".to_owned()), history.get(0));
+        assert_eq!(Some(&meta), history.metadata(0));
+        assert_eq!(Some(&HistoryMeta::default()), history.metadata(1));
+    }
+
+    #[test]
+    fn escaped_annotation_value() {
+        use super::HistoryMeta;
+        use std::path::PathBuf;
+        let mut history = History::new();
+        let meta = HistoryMeta {
+            cwd: Some(PathBuf::from("/tmp/a\nls\\n\r")),
+            ..HistoryMeta::default()
+        };
+        assert!(history.add_with_metadata("pwd", meta.clone()));
+        let mut buf = Vec::new();
+        history.save_to(&mut buf).unwrap();
+        assert_eq!(
+            "#V2\n# cwd=/tmp/a\\nls\\\\n\\r\npwd\n",
+            String::from_utf8(buf.clone()).unwrap()
+        );
+
+        let mut history = History::new();
+        history.load_from(buf.as_slice()).unwrap();
+        assert_eq!(1, history.len());
+        assert_eq!(Some(&"pwd".to_owned()), history.get(0));
+        assert_eq!(Some(&meta), history.metadata(0));
+    }
+
+    #[test]
+    fn escaped_entry() {
+        use super::HistoryMeta;
+        let entries = [
+            "# cwd=/tmp",
+            "# timestamp=1600000000",
+            "# exit_code=1",
+            "#",
+            "\\# cwd=/tmp",
+            "\\\\",
+            "\\n",
+        ];
+        let mut history = History::new();
+        for entry in &entries {
+            assert!(history.add(*entry));
+        }
+        let mut buf = Vec::new();
+        history.save_to(&mut buf).unwrap();
+
+        let mut history = History::new();
+        history.load_from(buf.as_slice()).unwrap();
+        assert_eq!(entries.len(), history.len());
+        for (i, entry) in entries.iter().enumerate() {
+            assert_eq!(Some(&(*entry).to_owned()), history.get(i));
+            assert_eq!(Some(&HistoryMeta::default()), history.metadata(i));
+        }
+    }
+
+    #[test]
+    fn load_legacy_file() {
+        use super::HistoryMeta;
+        let entries = [
+            "\\\\server\\share",
+            "# timestamp=5",
+            "\\# not escaped",
+            "#V2",
+        ];
+        let mut buf = entries.join("\n");
+        buf.push('\n');
+        let mut history = History::new();
+        history.load_from(buf.as_bytes()).unwrap();
+        assert_eq!(entries.len(), history.len());
+        for (i, entry) in entries.iter().enumerate() {
+            assert_eq!(Some(&(*entry).to_owned()), history.get(i));
+            assert_eq!(Some(&HistoryMeta::default()), history.metadata(i));
+        }
+
+        // entries which look like annotations are escaped when saved back
+        let mut saved = Vec::new();
+        history.save_to(&mut saved).unwrap();
+        let mut history = History::new();
+        history.load_from(saved.as_slice()).unwrap();
+        let loaded: Vec<&String> = history.iter().collect();
+        assert_eq!(entries.to_vec(), loaded);
+    }
+
+    #[test]
+    fn append_keeps_format() {
+        use super::HistoryMeta;
+        use std::fs;
+        let td = tempdir::TempDir::new("histo").unwrap();
+        let meta = HistoryMeta {
+            exit_code: Some(1),
+            ..HistoryMeta::default()
+        };
+
+        let legacy_path = td.path().join("legacy");
+        fs::write(&legacy_path, "# timestamp=5\n").unwrap();
+        let mut history = History::new();
+        assert!(history.add_with_metadata("a", meta.clone()));
+        history.append(&legacy_path).unwrap();
+        assert_eq!(
+            "# timestamp=5\na\n",
+            fs::read_to_string(&legacy_path).unwrap()
+        );
+
+        let path = td.path().join("annotated");
+        let mut history = History::new();
+        assert!(history.add_with_metadata("a", meta.clone()));
+        history.append(&path).unwrap();
+        assert!(history.add("#b"));
+        history.append(&path).unwrap();
+        assert_eq!(
+            "#V2\n# exit_code=1\na\n\\#b\n",
+            fs::read_to_string(&path).unwrap()
+        );
+        let mut other = History::new();
+        other.sync(&path).unwrap();
+        assert!(history.add("#c"));
+        history.append(&path).unwrap();
+        other.sync(&path).unwrap();
+        let loaded: Vec<&String> = other.iter().collect();
+        assert_eq!(vec!["a", "#b", "#c"], loaded);
+        assert_eq!(Some(&meta), other.metadata(0));
+        td.close().unwrap();
+    }
+
+    #[test]
+    fn append_and_sync() {
+        let td = tempdir::TempDir::new_in(&Path::new("."), "histo").unwrap();
+        let history_path = td.path().join(".history");
+
+        let mut history = init();
+        history.append(&history_path).unwrap();
+        let mut other = History::new();
+        other.load(&history_path).unwrap();
+        assert_eq!(3, other.len());
+
+        assert!(history.add("line4"));
+        history.append(&history_path).unwrap();
+        history.append(&history_path).unwrap(); // nothing new
+        other.sync(&history_path).unwrap();
+        assert_eq!(4, other.len());
+        assert_eq!(Some(&"line4".to_owned()), other.last());
+
+        history.clear();
+        history.load(&history_path).unwrap();
+        assert_eq!(4, history.len());
+        td.close().unwrap();
+    }
+
+    #[test]
+    fn save_and_append() {
+        let td = tempdir::TempDir::new("histo").unwrap();
+        let history_path = td.path().join(".history");
+
+        let mut history = History::new();
+        assert!(history.add("a"));
+        history.save(&history_path).unwrap();
+        history.append(&history_path).unwrap(); // nothing new
+        assert_eq!("a\n", std::fs::read_to_string(&history_path).unwrap());
+
+        assert!(history.add("b"));
+        history.append(&history_path).unwrap();
+        assert_eq!("a\nb\n", std::fs::read_to_string(&history_path).unwrap());
+        td.close().unwrap();
+    }
+
+    #[test]
+    fn append_interleaved() {
+        let td = tempdir::TempDir::new("histo").unwrap();
+        let history_path = td.path().join(".history");
+
+        let mut first = History::new();
+        let mut second = History::new();
+        assert!(first.add("first1"));
+        first.append(&history_path).unwrap();
+        assert!(second.add("second1"));
+        second.append(&history_path).unwrap();
+        assert!(first.add("first2"));
+        second.sync(&history_path).unwrap();
+        assert!(second.add("second2"));
+        first.append(&history_path).unwrap();
+        second.append(&history_path).unwrap();
+        first.sync(&history_path).unwrap();
+
+        let mut history = History::new();
+        history.load(&history_path).unwrap();
+        let expected = vec!["first1", "second1", "first2", "second2"];
+        assert_eq!(expected, history.iter().collect::<Vec<_>>());
+        assert_eq!(4, first.len());
+        assert_eq!(4, second.len());
+        td.close().unwrap();
+    }
+
     #[test]
     fn search() {
         let history = init();
@@ -338,6 +1134,33 @@ mod tests {
         assert_eq!(Some(2), history.search("line3", 1, Direction::Forward));
     }
 
+    #[test]
+    fn searcher() {
+        use super::HistorySearcher;
+        let mut history = init();
+        assert!(history.add("other"));
+        let mut searcher = HistorySearcher::new(&history, "line", Direction::Reverse);
+        assert_eq!(None, searcher.prev());
+        assert_eq!(Some("line3"), searcher.next());
+        assert_eq!(Some("line2"), searcher.next());
+        assert_eq!(Some("line3"), searcher.prev());
+        assert_eq!(None, searcher.prev());
+        assert_eq!(Some(2), searcher.index());
+        searcher.reset();
+        assert_eq!(
+            vec!["line3", "line2", "line1"],
+            searcher.collect::<Vec<_>>()
+        );
+
+        let mut searcher = HistorySearcher::new(&history, "e", Direction::Forward).anchored(true);
+        assert_eq!(None, searcher.next());
+        let searcher = HistorySearcher::new(&history, "e", Direction::Forward);
+        assert_eq!(
+            vec!["line1", "line2", "line3", "other"],
+            searcher.collect::<Vec<_>>()
+        );
+    }
+
     #[test]
     fn reverse_search() {
         let history = init();
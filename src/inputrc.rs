@@ -0,0 +1,403 @@
+//! Best-effort parsing of GNU Readline init files (`~/.inputrc`)
+#[cfg(not(feature = "tracing"))]
+use log::debug;
+#[cfg(feature = "tracing")]
+use tracing::debug;
+
+use crate::config::{BellStyle, EditMode};
+use crate::keymap::{Cmd, MAX_SEQUENCE_LEN};
+use crate::keys::{self, KeyPress};
+
+/// Directive of an init file supported by rustyline
+#[derive(Debug, PartialEq)]
+pub enum Directive {
+    /// `set editing-mode`
+    EditMode(EditMode),
+    /// `set bell-style`
+    BellStyle(BellStyle),
+    /// `set completion-query-items`
+    CompletionQueryItems(usize),
+    /// `set keyseq-timeout`
+    KeyseqTimeout(i32),
+    /// `set history-size`
+    HistorySize(usize),
+    /// Single key bound to a command
+    BindKey(KeyPress, Cmd),
+    /// Sequence of bytes (like an escape sequence) bound to a command
+    BindSequence(Vec<u8>, Cmd),
+}
+
+/// Parse the content of an init file, ignoring (with a debug log) what is
+/// not supported.
+///
+/// `$if mode=...` conditionals are evaluated against `edit_mode`, or the
+/// mode set by a previous `set editing-mode`. Other conditionals (terminal
+/// or application name) are considered false.
+pub fn parse(content: &str, mut edit_mode: EditMode) -> Vec<Directive> {
+    let mut directives = Vec::new();
+    // whether the lines of each nested `$if` are skipped
+    let mut skipped: Vec<bool> = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('$') {
+            let mut words = line.splitn(2, char::is_whitespace);
+            let keyword = words.next().unwrap_or("");
+            let arg = words.next().unwrap_or("").trim();
+            match keyword {
+                "$if" => {
+                    let outer = skipped.last().cloned().unwrap_or(false);
+                    skipped.push(outer || !condition(arg, edit_mode));
+                }
+                "$else" => {
+                    let outer = skipped.len() > 1 && skipped[skipped.len() - 2];
+                    if let Some(skip) = skipped.last_mut() {
+                        *skip = outer || !*skip;
+                    }
+                }
+                "$endif" => {
+                    skipped.pop();
+                }
+                _ => debug!(target: "rustyline", "unsupported inputrc directive: {}", line),
+            }
+            continue;
+        }
+        if skipped.last().cloned().unwrap_or(false) {
+            continue;
+        }
+        let directive = match line.strip_prefix("set ") {
+            Some(variable) => parse_variable(variable),
+            None => parse_binding(line),
+        };
+        match directive {
+            Some(directive) => {
+                if let Directive::EditMode(mode) = directive {
+                    edit_mode = mode;
+                }
+                directives.push(directive);
+            }
+            None => debug!(target: "rustyline", "unsupported inputrc line: {}", line),
+        }
+    }
+    directives
+}
+
+/// Evaluate the test of a `$if`
+fn condition(test: &str, edit_mode: EditMode) -> bool {
+    let mut parts = test.splitn(2, '=');
+    let name = parts.next().unwrap_or("").trim();
+    match (name, parts.next().map(str::trim)) {
+        ("mode", Some("emacs")) => edit_mode == EditMode::Emacs,
+        ("mode", Some("vi")) => edit_mode == EditMode::Vi,
+        _ => false,
+    }
+}
+
+/// `set <variable> <value>`
+fn parse_variable(line: &str) -> Option<Directive> {
+    let mut words = line.split_whitespace();
+    let variable = words.next()?.to_ascii_lowercase();
+    let value = words.next()?;
+    let directive = match variable.as_str() {
+        "editing-mode" => match value {
+            "emacs" => Directive::EditMode(EditMode::Emacs),
+            "vi" => Directive::EditMode(EditMode::Vi),
+            _ => return None,
+        },
+        "bell-style" => match value {
+            "none" => Directive::BellStyle(BellStyle::None),
+            "audible" => Directive::BellStyle(BellStyle::Audible),
+            "visible" => Directive::BellStyle(BellStyle::Visible),
+            _ => return None,
+        },
+        "completion-query-items" => Directive::CompletionQueryItems(value.parse().ok()?),
+        // like readline, zero or negative values mean no timeout
+        "keyseq-timeout" => match value.parse::<i32>().ok()? {
+            ms if ms <= 0 => Directive::KeyseqTimeout(-1),
+            ms => Directive::KeyseqTimeout(ms),
+        },
+        "history-size" => Directive::HistorySize(value.parse().ok()?),
+        _ => return None,
+    };
+    Some(directive)
+}
+
+/// `"<sequence>": <command>` or `<key name>: <command>`
+fn parse_binding(line: &str) -> Option<Directive> {
+    let (seq, rest) = if line.starts_with('"') {
+        let end = closing_quote(line)?;
+        (unescape(&line[1..end])?, &line[end + 1..])
+    } else {
+        let colon = line.find(':')?;
+        (key_name(line[..colon].trim())?, &line[colon..])
+    };
+    let rest = rest.trim_start();
+    if !rest.starts_with(':') {
+        return None;
+    }
+    // macros (quoted strings) are not supported
+    let name = rest[1..].split_whitespace().next()?;
+    let cmd = Cmd::from_name(name)?;
+    if let Some(key) = single_key(&seq) {
+        Some(Directive::BindKey(key, cmd))
+    } else if seq.len() <= MAX_SEQUENCE_LEN {
+        Some(Directive::BindSequence(seq, cmd))
+    } else {
+        None
+    }
+}
+
+/// Index of the double quote ending the sequence which starts `line`
+fn closing_quote(line: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in line.char_indices().skip(1) {
+        match c {
+            '\\' if !escaped => escaped = true,
+            '"' if !escaped => return Some(i),
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+fn control(c: char) -> u8 {
+    if c == '?' {
+        0x7f
+    } else {
+        (c as u8) & 0x1f
+    }
+}
+
+/// Decode the escapes of a quoted key sequence (`\C-x`, `\M-x`, `\e`, ...)
+fn unescape(seq: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(seq.len());
+    let mut chars = seq.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next()? {
+            'C' if chars.peek() == Some(&'-') => {
+                chars.next();
+                let c = chars.next()?;
+                if !c.is_ascii() {
+                    return None;
+                }
+                bytes.push(control(c));
+            }
+            'M' if chars.peek() == Some(&'-') => {
+                chars.next();
+                bytes.push(b'\x1b');
+            }
+            'e' => bytes.push(b'\x1b'),
+            'a' => bytes.push(b'\x07'),
+            'b' => bytes.push(b'\x08'),
+            'd' => bytes.push(b'\x7f'),
+            'f' => bytes.push(b'\x0c'),
+            'n' => bytes.push(b'\n'),
+            'r' => bytes.push(b'\r'),
+            't' => bytes.push(b'\t'),
+            'v' => bytes.push(b'\x0b'),
+            'x' => {
+                let mut n = 0u8;
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(16)) {
+                        Some(digit) => n = n * 16 + digit as u8,
+                        None => break,
+                    }
+                    chars.next();
+                }
+                bytes.push(n);
+            }
+            c @ '0'..='7' => {
+                let mut n = c.to_digit(8)?;
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => n = n * 8 + digit,
+                        None => break,
+                    }
+                    chars.next();
+                }
+                bytes.push(n as u8);
+            }
+            c => {
+                // `\\`, `\"`, `\'`, ...
+                let mut buf = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+    if bytes.is_empty() {
+        None
+    } else {
+        Some(bytes)
+    }
+}
+
+/// Decode a key name like `Control-u` or `Meta-Rubout`
+fn key_name(name: &str) -> Option<Vec<u8>> {
+    let mut name = name;
+    let mut ctrl = false;
+    let mut meta = false;
+    loop {
+        let lower = name.to_ascii_lowercase();
+        if lower.starts_with("control-") {
+            ctrl = true;
+            name = &name[8..];
+        } else if lower.starts_with("c-") {
+            ctrl = true;
+            name = &name[2..];
+        } else if lower.starts_with("meta-") {
+            meta = true;
+            name = &name[5..];
+        } else if lower.starts_with("m-") {
+            meta = true;
+            name = &name[2..];
+        } else {
+            break;
+        }
+    }
+    let c = match name.to_ascii_lowercase().as_str() {
+        "del" | "rubout" => '\x7f',
+        "esc" | "escape" => '\x1b',
+        "lfd" | "newline" => '\n',
+        "ret" | "return" => '\r',
+        "space" | "spc" => ' ',
+        "tab" => '\t',
+        _ => {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => c,
+                _ => return None,
+            }
+        }
+    };
+    let mut bytes = Vec::with_capacity(2);
+    if meta {
+        bytes.push(b'\x1b');
+    }
+    if ctrl {
+        if !c.is_ascii() {
+            return None;
+        }
+        bytes.push(control(c));
+    } else {
+        let mut buf = [0; 4];
+        bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+    }
+    Some(bytes)
+}
+
+/// Key read by rustyline when the terminal sends `seq`, if any
+fn single_key(seq: &[u8]) -> Option<KeyPress> {
+    let s = std::str::from_utf8(seq).ok()?;
+    let mut chars = s.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some(c), None, _) => Some(keys::char_to_key_press(c)),
+        // `ESC [` and `ESC O` start escape sequences
+        (Some('\x1b'), Some(c), None) if c != '[' && c != 'O' && c != '\x1b' => {
+            Some(KeyPress::Meta(c))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, Directive};
+    use crate::config::{BellStyle, EditMode};
+    use crate::keymap::{Anchor, Cmd, Movement, Word};
+    use crate::keys::KeyPress;
+
+    #[test]
+    fn variables() {
+        let content = "\
+# comment
+set editing-mode vi
+set bell-style visible
+set completion-query-items 50
+set keyseq-timeout 250
+set history-size 1000
+set show-all-if-ambiguous on
+";
+        assert_eq!(
+            vec![
+                Directive::EditMode(EditMode::Vi),
+                Directive::BellStyle(BellStyle::Visible),
+                Directive::CompletionQueryItems(50),
+                Directive::KeyseqTimeout(250),
+                Directive::HistorySize(1000),
+            ],
+            parse(content, EditMode::Emacs)
+        );
+    }
+
+    #[test]
+    fn bindings() {
+        let content = r#"
+"\C-w": backward-kill-word
+"\M-\C-h": unix-word-rubout
+"\e[A": history-search-backward
+"\e[5~": "macro"
+Control-y: yank
+Meta-Rubout: backward-kill-word
+TAB: menu-complete
+"\C-xq": unknown-command
+"#;
+        assert_eq!(
+            vec![
+                Directive::BindKey(
+                    KeyPress::Ctrl('W'),
+                    Cmd::Kill(Movement::BackwardWord(1, Word::Emacs))
+                ),
+                Directive::BindKey(
+                    KeyPress::Meta('\x08'),
+                    Cmd::Kill(Movement::BackwardWord(1, Word::Big))
+                ),
+                Directive::BindSequence(b"\x1b[A".to_vec(), Cmd::HistorySearchBackward),
+                Directive::BindKey(KeyPress::Ctrl('Y'), Cmd::Yank(1, Anchor::Before)),
+                Directive::BindKey(
+                    KeyPress::Meta('\x7f'),
+                    Cmd::Kill(Movement::BackwardWord(1, Word::Emacs))
+                ),
+                Directive::BindKey(KeyPress::Tab, Cmd::Complete),
+            ],
+            parse(content, EditMode::Emacs)
+        );
+    }
+
+    #[test]
+    fn conditionals() {
+        let content = "\
+$if mode=vi
+set bell-style none
+$else
+set bell-style audible
+$endif
+$if Bash
+$if mode=emacs
+set completion-query-items 10
+$endif
+$else
+set keyseq-timeout 100
+$endif
+set editing-mode vi
+$if mode=vi
+set history-size 10
+$endif
+";
+        assert_eq!(
+            vec![
+                Directive::BellStyle(BellStyle::Audible),
+                Directive::KeyseqTimeout(100),
+                Directive::EditMode(EditMode::Vi),
+                Directive::HistorySize(10),
+            ],
+            parse(content, EditMode::Emacs)
+        );
+    }
+}
@@ -1,14 +1,23 @@
 //! Bindings from keys to command for Emacs and Vi modes
+use std::any::Any;
 use std::collections::HashMap;
+use std::fmt;
 use std::sync::{Arc, RwLock};
 
+#[cfg(not(feature = "tracing"))]
 use log::debug;
+#[cfg(feature = "with-serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "tracing")]
+use tracing::debug;
 
 use super::Result;
 use crate::config::Config;
 use crate::config::EditMode;
+use crate::error::ReadlineError;
+use crate::history::Direction;
 use crate::keys::KeyPress;
-use crate::tty::{RawReader, Term, Terminal};
+use crate::tty::{RawReader, Term, Terminal, WinSize};
 
 /// The number of times one command should be repeated.
 pub type RepeatCount = usize;
@@ -16,6 +25,7 @@ pub type RepeatCount = usize;
 /// Commands
 // #[non_exhaustive]
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "with-serde", derive(Serialize, Deserialize))]
 pub enum Cmd {
     /// abort
     Abort, // Miscellaneous Command
@@ -33,12 +43,19 @@ pub enum Cmd {
     CompleteBackward,
     /// complete-hint
     CompleteHint,
+    /// copy the region between the mark and the cursor to the system
+    /// clipboard (see `Editor::set_clipboard_backend`)
+    CopyRegion,
     /// downcase-word
     DowncaseWord,
     /// vi-eof-maybe
     EndOfFile,
     /// end-of-history
     EndOfHistory,
+    /// exchange-point-and-mark
+    ExchangePointAndMark,
+    /// edit the line with `$VISUAL` or `$EDITOR`
+    ExternalEdit,
     /// forward-search-history
     ForwardSearchHistory,
     /// history-search-backward
@@ -60,6 +77,9 @@ pub enum Cmd {
     Noop,
     /// vi-replace
     Overwrite(char),
+    /// paste the content of the system clipboard (see
+    /// `Editor::set_clipboard_backend`)
+    PasteClipboard,
     /// previous-history
     PreviousHistory,
     /// quoted-insert
@@ -72,7 +92,11 @@ pub enum Cmd {
     ReverseSearchHistory,
     /// self-insert
     SelfInsert(RepeatCount, char),
+    /// set-mark
+    SetMark,
     Suspend,
+    /// vi-change-case: swap the case of the characters under the cursor
+    SwapCase(RepeatCount),
     /// transpose-chars
     TransposeChars,
     /// transpose-words
@@ -82,12 +106,20 @@ pub enum Cmd {
     Unknown,
     /// upcase-word
     UpcaseWord,
+    /// vi-search: read a search term and search the history with it
+    ViSearchHistory(Direction),
+    /// vi-search-again: repeat the last vi-search, in the opposite direction
+    /// if `true`
+    ViSearchAgain(bool),
     /// vi-yank-to
     ViYankTo(Movement),
     /// yank, vi-put
     Yank(RepeatCount, Anchor),
     /// yank-pop
     YankPop,
+    /// yank-last-arg, yank-nth-arg: insert the last (or n-th) word of the
+    /// previous history entry, from the end if negative
+    YankLastArg(Option<i32>),
     /// moves cursor to the line above or switches to prev history entry if
     /// the cursor is already on the first line
     LineUpOrPreviousHistory,
@@ -97,6 +129,44 @@ pub enum Cmd {
     /// accepts the line when cursor is at the end of the text (non including
     /// trailing whitespace), inserts newline character otherwise
     AcceptOrInsertLine,
+    /// the terminal has been resized (not bound to any key, see
+    /// `Editor::set_resize_handler`)
+    #[cfg_attr(feature = "with-serde", serde(skip))]
+    WindowResize(WinSize),
+    /// user-defined command with its name and payload, ignored unless
+    /// claimed by `Editor::set_custom_command_handler`
+    #[cfg_attr(feature = "with-serde", serde(skip))]
+    Custom(String, CustomPayload),
+}
+
+/// Payload of a `Cmd::Custom` command
+#[derive(Clone)]
+pub struct CustomPayload(Arc<dyn Any + Send + Sync>);
+
+impl CustomPayload {
+    pub fn new<T: Any + Send + Sync>(payload: T) -> Self {
+        CustomPayload(Arc::new(payload))
+    }
+
+    /// Return the payload if it is of type `T`.
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.0.downcast_ref()
+    }
+}
+
+impl fmt::Debug for CustomPayload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CustomPayload")
+    }
+}
+
+/// Payloads are equal only if they are the same value (shared by clones).
+impl PartialEq for CustomPayload {
+    fn eq(&self, other: &Self) -> bool {
+        let this: *const (dyn Any + Send + Sync) = &*self.0;
+        let other: *const (dyn Any + Send + Sync) = &*other.0;
+        this as *const u8 == other as *const u8
+    }
 }
 
 impl Cmd {
@@ -109,12 +179,161 @@ impl Cmd {
             | Cmd::Replace(_, _)
             | Cmd::Noop
             | Cmd::Suspend
+            | Cmd::WindowResize(_)
             | Cmd::Yank(_, _)
             | Cmd::YankPop => false,
             _ => true,
         }
     }
 
+    /// Name of the command, the one used by GNU Readline when there is an
+    /// equivalent.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Cmd::Abort => "abort",
+            Cmd::AcceptLine => "accept-line",
+            Cmd::BeginningOfHistory => "beginning-of-history",
+            Cmd::CapitalizeWord => "capitalize-word",
+            Cmd::ClearScreen => "clear-screen",
+            Cmd::Complete => "complete",
+            Cmd::CompleteBackward => "complete-backward",
+            Cmd::CompleteHint => "complete-hint",
+            Cmd::CopyRegion => "copy-region-to-clipboard",
+            Cmd::DowncaseWord => "downcase-word",
+            Cmd::EndOfFile => "end-of-file",
+            Cmd::EndOfHistory => "end-of-history",
+            Cmd::ExchangePointAndMark => "exchange-point-and-mark",
+            Cmd::ExternalEdit => "external-edit",
+            Cmd::ForwardSearchHistory => "forward-search-history",
+            Cmd::HistorySearchBackward => "history-search-backward",
+            Cmd::HistorySearchForward => "history-search-forward",
+            Cmd::Insert(_, _) => "bracketed-paste-begin",
+            Cmd::Interrupt => "interrupt",
+            Cmd::Kill(ref mvt) => match *mvt {
+                Movement::BackwardChar(_) => "backward-delete-char",
+                Movement::ForwardChar(_) => "delete-char",
+                Movement::BeginningOfLine => "unix-line-discard",
+                Movement::EndOfLine => "kill-line",
+                Movement::WholeLine => "kill-whole-line",
+                Movement::BackwardWord(_, Word::Big) => "unix-word-rubout",
+                Movement::BackwardWord(_, _) => "backward-kill-word",
+                Movement::ForwardWord(_, _, _) => "kill-word",
+                _ => "vi-delete-to",
+            },
+            Cmd::Move(ref mvt) => match *mvt {
+                Movement::BeginningOfLine => "beginning-of-line",
+                Movement::EndOfLine => "end-of-line",
+                Movement::BackwardWord(_, Word::Emacs) => "backward-word",
+                Movement::BackwardWord(_, _) => "vi-prev-word",
+                Movement::BackwardWordEnd(_, _) => "vi-backward-end-word",
+                Movement::ForwardWord(_, At::AfterEnd, _) => "forward-word",
+                Movement::ForwardWord(_, At::Start, _) => "vi-next-word",
+                Movement::ForwardWord(_, At::BeforeEnd, _) => "vi-end-word",
+                Movement::ViCharSearch(_, _) => "vi-char-search",
+                Movement::ViFirstPrint => "vi-first-print",
+                Movement::ViMatchingBracket => "vi-match",
+                Movement::BackwardChar(_) => "backward-char",
+                Movement::ForwardChar(_) => "forward-char",
+                Movement::LineUp(_) => "previous-screen-line",
+                Movement::LineDown(_) => "next-screen-line",
+                Movement::WholeLine | Movement::TextObject(_) => "vi-text-object",
+            },
+            Cmd::NextHistory => "next-history",
+            Cmd::Noop => "noop",
+            Cmd::Overwrite(_) => "vi-replace",
+            Cmd::PasteClipboard => "paste-from-clipboard",
+            Cmd::PreviousHistory => "previous-history",
+            Cmd::QuotedInsert => "quoted-insert",
+            Cmd::ReplaceChar(_, _) => "vi-change-char",
+            Cmd::Replace(_, _) => "vi-change-to",
+            Cmd::ReverseSearchHistory => "reverse-search-history",
+            Cmd::SelfInsert(_, _) => "self-insert",
+            Cmd::SetMark => "set-mark",
+            Cmd::Suspend => "suspend",
+            Cmd::SwapCase(_) => "vi-change-case",
+            Cmd::TransposeChars => "transpose-chars",
+            Cmd::TransposeWords(_) => "transpose-words",
+            Cmd::Undo(_) => "undo",
+            Cmd::Unknown => "unknown",
+            Cmd::UpcaseWord => "upcase-word",
+            Cmd::ViSearchHistory(_) => "vi-search",
+            Cmd::ViSearchAgain(_) => "vi-search-again",
+            Cmd::ViYankTo(_) => "vi-yank-to",
+            Cmd::Yank(_, _) => "yank",
+            Cmd::YankPop => "yank-pop",
+            Cmd::YankLastArg(None) => "yank-last-arg",
+            Cmd::YankLastArg(Some(_)) => "yank-nth-arg",
+            Cmd::LineUpOrPreviousHistory => "previous-line-or-history",
+            Cmd::LineDownOrNextHistory => "next-line-or-history",
+            Cmd::AcceptOrInsertLine => "accept-or-insert-line",
+            Cmd::WindowResize(_) => "window-resize",
+            Cmd::Custom(_, _) => "custom",
+        }
+    }
+
+    /// Command named `name` by GNU Readline (see `name`), with a repeat
+    /// count of one.
+    ///
+    /// Commands needing an argument (like `self-insert`) are not supported.
+    pub(crate) fn from_name(name: &str) -> Option<Cmd> {
+        let cmd = match name {
+            "abort" => Cmd::Abort,
+            "accept-line" => Cmd::AcceptLine,
+            "accept-or-insert-line" => Cmd::AcceptOrInsertLine,
+            "backward-char" => Cmd::Move(Movement::BackwardChar(1)),
+            "backward-delete-char" => Cmd::Kill(Movement::BackwardChar(1)),
+            "backward-kill-word" => Cmd::Kill(Movement::BackwardWord(1, Word::Emacs)),
+            "backward-word" => Cmd::Move(Movement::BackwardWord(1, Word::Emacs)),
+            "beginning-of-history" => Cmd::BeginningOfHistory,
+            "beginning-of-line" => Cmd::Move(Movement::BeginningOfLine),
+            "capitalize-word" => Cmd::CapitalizeWord,
+            "clear-screen" => Cmd::ClearScreen,
+            "complete" | "menu-complete" => Cmd::Complete,
+            "complete-backward" | "menu-complete-backward" => Cmd::CompleteBackward,
+            "complete-hint" => Cmd::CompleteHint,
+            "copy-region-to-clipboard" => Cmd::CopyRegion,
+            "delete-char" => Cmd::Kill(Movement::ForwardChar(1)),
+            "downcase-word" => Cmd::DowncaseWord,
+            "end-of-file" => Cmd::EndOfFile,
+            "end-of-history" => Cmd::EndOfHistory,
+            "end-of-line" => Cmd::Move(Movement::EndOfLine),
+            "exchange-point-and-mark" => Cmd::ExchangePointAndMark,
+            "external-edit" => Cmd::ExternalEdit,
+            "forward-char" => Cmd::Move(Movement::ForwardChar(1)),
+            "forward-search-history" => Cmd::ForwardSearchHistory,
+            "forward-word" => Cmd::Move(Movement::ForwardWord(1, At::AfterEnd, Word::Emacs)),
+            "history-search-backward" => Cmd::HistorySearchBackward,
+            "history-search-forward" => Cmd::HistorySearchForward,
+            "interrupt" => Cmd::Interrupt,
+            "kill-line" => Cmd::Kill(Movement::EndOfLine),
+            "kill-whole-line" => Cmd::Kill(Movement::WholeLine),
+            "kill-word" => Cmd::Kill(Movement::ForwardWord(1, At::AfterEnd, Word::Emacs)),
+            "next-history" => Cmd::NextHistory,
+            "next-line-or-history" => Cmd::LineDownOrNextHistory,
+            "next-screen-line" => Cmd::Move(Movement::LineDown(1)),
+            "noop" => Cmd::Noop,
+            "paste-from-clipboard" => Cmd::PasteClipboard,
+            "previous-history" => Cmd::PreviousHistory,
+            "previous-line-or-history" => Cmd::LineUpOrPreviousHistory,
+            "previous-screen-line" => Cmd::Move(Movement::LineUp(1)),
+            "quoted-insert" => Cmd::QuotedInsert,
+            "reverse-search-history" => Cmd::ReverseSearchHistory,
+            "set-mark" => Cmd::SetMark,
+            "suspend" => Cmd::Suspend,
+            "transpose-chars" => Cmd::TransposeChars,
+            "transpose-words" => Cmd::TransposeWords(1),
+            "undo" => Cmd::Undo(1),
+            "unix-line-discard" | "backward-kill-line" => Cmd::Kill(Movement::BeginningOfLine),
+            "unix-word-rubout" => Cmd::Kill(Movement::BackwardWord(1, Word::Big)),
+            "upcase-word" => Cmd::UpcaseWord,
+            "yank" => Cmd::Yank(1, Anchor::Before),
+            "yank-last-arg" => Cmd::YankLastArg(None),
+            "yank-pop" => Cmd::YankPop,
+            _ => return None,
+        };
+        Some(cmd)
+    }
+
     fn is_repeatable_change(&self) -> bool {
         match *self {
             Cmd::Insert(_, _)
@@ -122,6 +341,7 @@ impl Cmd {
             | Cmd::ReplaceChar(_, _)
             | Cmd::Replace(_, _)
             | Cmd::SelfInsert(_, _)
+            | Cmd::SwapCase(_)
             | Cmd::ViYankTo(_)
             | Cmd::Yank(_, _) => true,
             // Cmd::TransposeChars | TODO Validate
@@ -168,6 +388,7 @@ impl Cmd {
                     Cmd::SelfInsert(repeat_count(previous, new), c)
                 }
             }
+            Cmd::SwapCase(previous) => Cmd::SwapCase(repeat_count(previous, new)),
             // Cmd::TransposeChars => Cmd::TransposeChars,
             Cmd::ViYankTo(ref mvt) => Cmd::ViYankTo(mvt.redo(new)),
             Cmd::Yank(previous, anchor) => Cmd::Yank(repeat_count(previous, new), anchor),
@@ -185,6 +406,7 @@ fn repeat_count(previous: RepeatCount, new: Option<RepeatCount>) -> RepeatCount
 
 /// Different word definitions
 #[derive(Debug, Clone, PartialEq, Copy)]
+#[cfg_attr(feature = "with-serde", derive(Serialize, Deserialize))]
 pub enum Word {
     /// non-blanks characters
     Big,
@@ -196,6 +418,7 @@ pub enum Word {
 
 /// Where to move with respect to word boundary
 #[derive(Debug, Clone, PartialEq, Copy)]
+#[cfg_attr(feature = "with-serde", derive(Serialize, Deserialize))]
 pub enum At {
     Start,
     BeforeEnd,
@@ -204,6 +427,7 @@ pub enum At {
 
 /// Where to paste (relative to cursor position)
 #[derive(Debug, Clone, PartialEq, Copy)]
+#[cfg_attr(feature = "with-serde", derive(Serialize, Deserialize))]
 pub enum Anchor {
     After,
     Before,
@@ -211,6 +435,7 @@ pub enum Anchor {
 
 /// Vi character search
 #[derive(Debug, Clone, PartialEq, Copy)]
+#[cfg_attr(feature = "with-serde", derive(Serialize, Deserialize))]
 pub enum CharSearch {
     Forward(char),
     // until
@@ -231,8 +456,42 @@ impl CharSearch {
     }
 }
 
+/// Vi text object (`i"`, `a(`, ...): the text between a pair of delimiters
+#[derive(Debug, Clone, PartialEq, Copy)]
+#[cfg_attr(feature = "with-serde", derive(Serialize, Deserialize))]
+pub struct TextObject {
+    /// exclude the delimiters (`i`) or include them (`a`)
+    pub inner: bool,
+    /// opening delimiter
+    pub delim: char,
+}
+
+impl TextObject {
+    fn new(inner: bool, c: char) -> Option<Self> {
+        let delim = match c {
+            '"' | '\'' | '`' => c,
+            '(' | ')' | 'b' => '(',
+            '[' | ']' => '[',
+            '{' | '}' | 'B' => '{',
+            _ => return None,
+        };
+        Some(TextObject { inner, delim })
+    }
+
+    /// Opening and closing delimiters
+    pub(crate) fn delimiters(self) -> (char, char) {
+        match self.delim {
+            '(' => ('(', ')'),
+            '[' => ('[', ']'),
+            '{' => ('{', '}'),
+            c => (c, c),
+        }
+    }
+}
+
 /// Where to move
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "with-serde", derive(Serialize, Deserialize))]
 pub enum Movement {
     WholeLine, // not really a movement
     /// beginning-of-line
@@ -241,12 +500,16 @@ pub enum Movement {
     EndOfLine,
     /// backward-word, vi-prev-word
     BackwardWord(RepeatCount, Word), // Backward until start of word
+    /// vi `ge` and `gE`
+    BackwardWordEnd(RepeatCount, Word), // Backward until end of previous word
     /// forward-word, vi-end-word, vi-next-word
     ForwardWord(RepeatCount, At, Word), // Forward until start/end of word
     /// vi-char-search
     ViCharSearch(RepeatCount, CharSearch),
     /// vi-first-print
     ViFirstPrint,
+    /// vi-match
+    ViMatchingBracket,
     /// backward-char
     BackwardChar(RepeatCount),
     /// forward-char
@@ -255,6 +518,8 @@ pub enum Movement {
     LineUp(RepeatCount),
     /// move to the same column on the next line
     LineDown(RepeatCount),
+    /// vi text object (only meaningful after `c`, `d` or `y`)
+    TextObject(TextObject),
 }
 
 impl Movement {
@@ -264,10 +529,14 @@ impl Movement {
             Movement::WholeLine => Movement::WholeLine,
             Movement::BeginningOfLine => Movement::BeginningOfLine,
             Movement::ViFirstPrint => Movement::ViFirstPrint,
+            Movement::ViMatchingBracket => Movement::ViMatchingBracket,
             Movement::EndOfLine => Movement::EndOfLine,
             Movement::BackwardWord(previous, word) => {
                 Movement::BackwardWord(repeat_count(previous, new), word)
             }
+            Movement::BackwardWordEnd(previous, word) => {
+                Movement::BackwardWordEnd(repeat_count(previous, new), word)
+            }
             Movement::ForwardWord(previous, at, word) => {
                 Movement::ForwardWord(repeat_count(previous, new), at, word)
             }
@@ -278,12 +547,27 @@ impl Movement {
             Movement::ForwardChar(previous) => Movement::ForwardChar(repeat_count(previous, new)),
             Movement::LineUp(previous) => Movement::LineUp(repeat_count(previous, new)),
             Movement::LineDown(previous) => Movement::LineDown(repeat_count(previous, new)),
+            Movement::TextObject(obj) => Movement::TextObject(obj),
         }
     }
 }
 
+/// Input mode of the line being edited
+// #[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputMode {
+    /// Emacs mode
+    Emacs,
+    /// Vi insert mode
+    ViInsert,
+    /// Vi command mode
+    ViNormal,
+    /// Vi replace mode
+    ViReplace,
+}
+
 #[derive(PartialEq)]
-enum InputMode {
+enum ViMode {
     /// Vi Command/Alternate
     Command,
     /// Insert/Input mode
@@ -296,7 +580,7 @@ enum InputMode {
 pub struct InputState {
     mode: EditMode,
     custom_bindings: Arc<RwLock<HashMap<KeyPress, Cmd>>>,
-    input_mode: InputMode, // vi only ?
+    input_mode: ViMode, // vi only ?
     // numeric arguments: http://web.mit.edu/gnu/doc/html/rlman_1.html#SEC7
     num_args: i16,
     last_cmd: Cmd,                        // vi only
@@ -336,7 +620,7 @@ impl InputState {
         Self {
             mode: config.edit_mode(),
             custom_bindings,
-            input_mode: InputMode::Insert,
+            input_mode: ViMode::Insert,
             num_args: 0,
             last_cmd: Cmd::Noop,
             last_char_search: None,
@@ -347,6 +631,15 @@ impl InputState {
         self.mode == EditMode::Emacs
     }
 
+    pub fn input_mode(&self) -> InputMode {
+        match (self.mode, &self.input_mode) {
+            (EditMode::Emacs, _) => InputMode::Emacs,
+            (EditMode::Vi, ViMode::Insert) => InputMode::ViInsert,
+            (EditMode::Vi, ViMode::Command) => InputMode::ViNormal,
+            (EditMode::Vi, ViMode::Replace) => InputMode::ViReplace,
+        }
+    }
+
     /// Parse user input into one command
     /// `single_esc_abort` is used in emacs mode on unix platform when a single
     /// esc key is expected to abort current action.
@@ -358,11 +651,37 @@ impl InputState {
     ) -> Result<Cmd> {
         match self.mode {
             EditMode::Emacs => self.emacs(rdr, wrt, single_esc_abort),
-            EditMode::Vi if self.input_mode != InputMode::Command => self.vi_insert(rdr, wrt),
+            EditMode::Vi if self.input_mode != ViMode::Command => self.vi_insert(rdr, wrt),
             EditMode::Vi => self.vi_command(rdr, wrt),
         }
     }
 
+    /// Command bound to `key` alone, without any numeric argument, in Emacs
+    /// or Vi insert mode. `None` for prefix keys (like `C-x`).
+    pub(crate) fn key_cmd(&mut self, key: KeyPress) -> Option<Cmd> {
+        let mut rdr = KeyReader(Some(key));
+        let cmd = match self.mode {
+            EditMode::Emacs => self.emacs(&mut rdr, &mut NoRefresher, false),
+            EditMode::Vi => self.vi_insert(&mut rdr, &mut NoRefresher),
+        };
+        cmd.ok()
+    }
+
+    /// Only `Ctrl+Shift+C` and `Ctrl+Shift+V` are bound by default: other
+    /// unbound `Ctrl+Shift+letter` keys behave like `Ctrl+letter`.
+    fn ctrl_shift_fallback(&self, key: KeyPress) -> KeyPress {
+        match key {
+            KeyPress::CtrlShift(c)
+                if c != 'C'
+                    && c != 'V'
+                    && !self.custom_bindings.read().unwrap().contains_key(&key) =>
+            {
+                KeyPress::Ctrl(c)
+            }
+            _ => key,
+        }
+    }
+
     fn emacs_digit_argument<R: RawReader>(
         &mut self,
         rdr: &mut R,
@@ -404,6 +723,37 @@ impl InputState {
         }
     }
 
+    /// universal-argument: `C-u` alone multiplies the argument by four,
+    /// digits following it replace it.
+    fn emacs_universal_argument<R: RawReader>(
+        &mut self,
+        rdr: &mut R,
+        wrt: &mut dyn Refresher,
+    ) -> Result<KeyPress> {
+        self.num_args = 4;
+        loop {
+            wrt.refresh_prompt_and_line(&format!("(arg: {}) ", self.num_args))?;
+            let key = rdr.next_key(true)?;
+            match key {
+                KeyPress::Ctrl('U') => {
+                    if self.num_args < 1000 {
+                        self.num_args *= 4;
+                    }
+                }
+                KeyPress::Char(digit @ '0'..='9')
+                | KeyPress::Meta(digit @ '0'..='9')
+                | KeyPress::Char(digit @ '-')
+                | KeyPress::Meta(digit @ '-') => {
+                    return self.emacs_digit_argument(rdr, wrt, digit);
+                }
+                _ => {
+                    wrt.refresh_line()?;
+                    return Ok(key);
+                }
+            };
+        }
+    }
+
     fn emacs<R: RawReader>(
         &mut self,
         rdr: &mut R,
@@ -411,12 +761,23 @@ impl InputState {
         single_esc_abort: bool,
     ) -> Result<Cmd> {
         let mut key = rdr.next_key(single_esc_abort)?;
+        if let Some(cmd) = rdr.take_bound_cmd() {
+            debug!(target: "rustyline", "Raw sequence command: {:?}", cmd);
+            return Ok(cmd);
+        }
         if let KeyPress::Meta(digit @ '-') = key {
             key = self.emacs_digit_argument(rdr, wrt, digit)?;
         } else if let KeyPress::Meta(digit @ '0'..='9') = key {
             key = self.emacs_digit_argument(rdr, wrt, digit)?;
+        } else if key == KeyPress::Ctrl('U') {
+            key = self.emacs_universal_argument(rdr, wrt)?;
         }
+        let arg = match self.num_args {
+            0 => None,
+            num_args => Some(i32::from(num_args)),
+        };
         let (n, positive) = self.emacs_num_args(); // consume them in all cases
+        let key = self.ctrl_shift_fallback(key);
         {
             let bindings = self.custom_bindings.read().unwrap();
             if let Some(cmd) = bindings.get(&key) {
@@ -477,14 +838,24 @@ impl InputState {
                     Cmd::Kill(Movement::BeginningOfLine)
                 }
             }
+            KeyPress::Ctrl(' ') => Cmd::SetMark,
             KeyPress::Ctrl('L') => Cmd::ClearScreen,
             KeyPress::Ctrl('N') => Cmd::NextHistory,
             KeyPress::Ctrl('P') => Cmd::PreviousHistory,
             KeyPress::Ctrl('X') => {
                 let snd_key = rdr.next_key(true)?;
                 match snd_key {
+                    KeyPress::Backspace => {
+                        if positive {
+                            Cmd::Kill(Movement::BeginningOfLine)
+                        } else {
+                            Cmd::Kill(Movement::EndOfLine)
+                        }
+                    }
+                    KeyPress::Ctrl('E') => Cmd::ExternalEdit,
                     KeyPress::Ctrl('G') | KeyPress::Esc => Cmd::Abort,
                     KeyPress::Ctrl('U') => Cmd::Undo(n),
+                    KeyPress::Ctrl('X') => Cmd::ExchangePointAndMark,
                     _ => Cmd::Unknown,
                 }
             }
@@ -495,6 +866,7 @@ impl InputState {
                     Cmd::Kill(Movement::ForwardWord(n, At::AfterEnd, Word::Emacs))
                 }
             }
+            KeyPress::Meta('.') | KeyPress::Meta('_') => Cmd::YankLastArg(arg),
             KeyPress::Meta('<') => Cmd::BeginningOfHistory,
             KeyPress::Meta('>') => Cmd::EndOfHistory,
             KeyPress::Meta('B') | KeyPress::Meta('b') => {
@@ -557,11 +929,16 @@ impl InputState {
 
     fn vi_command<R: RawReader>(&mut self, rdr: &mut R, wrt: &mut dyn Refresher) -> Result<Cmd> {
         let mut key = rdr.next_key(false)?;
+        if let Some(cmd) = rdr.take_bound_cmd() {
+            debug!(target: "rustyline", "Raw sequence command: {:?}", cmd);
+            return Ok(cmd);
+        }
         if let KeyPress::Char(digit @ '1'..='9') = key {
             key = self.vi_arg_digit(rdr, wrt, digit)?;
         }
         let no_num_args = self.num_args == 0;
         let n = self.vi_num_args(); // consume them in all cases
+        let key = self.ctrl_shift_fallback(key);
         {
             let bindings = self.custom_bindings.read().unwrap();
             if let Some(cmd) = bindings.get(&key) {
@@ -587,32 +964,33 @@ impl InputState {
                     self.last_cmd.redo(Some(n), wrt)
                 }
             },
-            // TODO KeyPress::Char('%') => Cmd::???, Move to the corresponding opening/closing bracket
+            KeyPress::Char('%') => Cmd::Move(Movement::ViMatchingBracket), // vi-match
             KeyPress::Char('0') => Cmd::Move(Movement::BeginningOfLine),
             KeyPress::Char('^') => Cmd::Move(Movement::ViFirstPrint),
+            KeyPress::Char('~') => Cmd::SwapCase(n), // vi-change-case
             KeyPress::Char('a') => {
                 // vi-append-mode
-                self.input_mode = InputMode::Insert;
+                self.input_mode = ViMode::Insert;
                 wrt.doing_insert();
                 Cmd::Move(Movement::ForwardChar(n))
             }
             KeyPress::Char('A') => {
                 // vi-append-eol
-                self.input_mode = InputMode::Insert;
+                self.input_mode = ViMode::Insert;
                 wrt.doing_insert();
                 Cmd::Move(Movement::EndOfLine)
             }
             KeyPress::Char('b') => Cmd::Move(Movement::BackwardWord(n, Word::Vi)), // vi-prev-word
             KeyPress::Char('B') => Cmd::Move(Movement::BackwardWord(n, Word::Big)),
             KeyPress::Char('c') => {
-                self.input_mode = InputMode::Insert;
+                self.input_mode = ViMode::Insert;
                 match self.vi_cmd_motion(rdr, wrt, key, n)? {
                     Some(mvt) => Cmd::Replace(mvt, None),
                     None => Cmd::Unknown,
                 }
             }
             KeyPress::Char('C') => {
-                self.input_mode = InputMode::Insert;
+                self.input_mode = ViMode::Insert;
                 Cmd::Replace(Movement::EndOfLine, None)
             }
             KeyPress::Char('d') => {
@@ -627,13 +1005,13 @@ impl InputState {
             KeyPress::Char('E') => Cmd::Move(Movement::ForwardWord(n, At::BeforeEnd, Word::Big)),
             KeyPress::Char('i') => {
                 // vi-insertion-mode
-                self.input_mode = InputMode::Insert;
+                self.input_mode = ViMode::Insert;
                 wrt.doing_insert();
                 Cmd::Noop
             }
             KeyPress::Char('I') => {
                 // vi-insert-beg
-                self.input_mode = InputMode::Insert;
+                self.input_mode = ViMode::Insert;
                 wrt.doing_insert();
                 Cmd::Move(Movement::BeginningOfLine)
             }
@@ -657,7 +1035,14 @@ impl InputState {
                     None => Cmd::Noop,
                 }
             }
-            // TODO KeyPress::Char('G') => Cmd::???, Move to the history line n
+            KeyPress::Char('g') => match rdr.next_key(false)? {
+                KeyPress::Char('g') => Cmd::Move(Movement::BeginningOfLine),
+                KeyPress::Char('e') => Cmd::Move(Movement::BackwardWordEnd(n, Word::Vi)),
+                KeyPress::Char('E') => Cmd::Move(Movement::BackwardWordEnd(n, Word::Big)),
+                KeyPress::Esc => Cmd::Noop,
+                _ => Cmd::Unknown,
+            },
+            KeyPress::Char('G') => Cmd::Move(Movement::EndOfLine),
             KeyPress::Char('p') => Cmd::Yank(n, Anchor::After), // vi-put
             KeyPress::Char('P') => Cmd::Yank(n, Anchor::Before), // vi-put
             KeyPress::Char('r') => {
@@ -671,17 +1056,17 @@ impl InputState {
             }
             KeyPress::Char('R') => {
                 //  vi-replace-mode (overwrite-mode)
-                self.input_mode = InputMode::Replace;
+                self.input_mode = ViMode::Replace;
                 Cmd::Replace(Movement::ForwardChar(0), None)
             }
             KeyPress::Char('s') => {
                 // vi-substitute-char:
-                self.input_mode = InputMode::Insert;
+                self.input_mode = ViMode::Insert;
                 Cmd::Replace(Movement::ForwardChar(n), None)
             }
             KeyPress::Char('S') => {
                 // vi-substitute-line:
-                self.input_mode = InputMode::Insert;
+                self.input_mode = ViMode::Insert;
                 Cmd::Replace(Movement::WholeLine, None)
             }
             KeyPress::Char('u') => Cmd::Undo(n),
@@ -712,12 +1097,16 @@ impl InputState {
             KeyPress::Char('k') => Cmd::Move(Movement::LineUp(n)),
             // TODO: move to the start of the line.
             KeyPress::Ctrl('P') => Cmd::PreviousHistory,
+            KeyPress::Char('/') => Cmd::ViSearchHistory(Direction::Reverse), // vi-search
+            KeyPress::Char('?') => Cmd::ViSearchHistory(Direction::Forward), // vi-search
+            KeyPress::Char('n') => Cmd::ViSearchAgain(false), // vi-search-again
+            KeyPress::Char('N') => Cmd::ViSearchAgain(true), // vi-search-again
             KeyPress::Ctrl('R') => {
-                self.input_mode = InputMode::Insert; // TODO Validate
+                self.input_mode = ViMode::Insert; // TODO Validate
                 Cmd::ReverseSearchHistory
             }
             KeyPress::Ctrl('S') => {
-                self.input_mode = InputMode::Insert; // TODO Validate
+                self.input_mode = ViMode::Insert; // TODO Validate
                 Cmd::ForwardSearchHistory
             }
             KeyPress::Esc => Cmd::Noop,
@@ -732,6 +1121,11 @@ impl InputState {
 
     fn vi_insert<R: RawReader>(&mut self, rdr: &mut R, wrt: &mut dyn Refresher) -> Result<Cmd> {
         let key = rdr.next_key(false)?;
+        if let Some(cmd) = rdr.take_bound_cmd() {
+            debug!(target: "rustyline", "Raw sequence command: {:?}", cmd);
+            return Ok(cmd);
+        }
+        let key = self.ctrl_shift_fallback(key);
         {
             let bindings = self.custom_bindings.read().unwrap();
             if let Some(cmd) = bindings.get(&key) {
@@ -745,7 +1139,7 @@ impl InputState {
         }
         let cmd = match key {
             KeyPress::Char(c) => {
-                if self.input_mode == InputMode::Replace {
+                if self.input_mode == ViMode::Replace {
                     Cmd::Overwrite(c)
                 } else {
                     Cmd::SelfInsert(1, c)
@@ -758,7 +1152,7 @@ impl InputState {
             KeyPress::Right if wrt.has_hint() && wrt.is_cursor_at_end() => Cmd::CompleteHint,
             KeyPress::Esc => {
                 // vi-movement-mode/vi-command-mode
-                self.input_mode = InputMode::Command;
+                self.input_mode = ViMode::Command;
                 wrt.done_inserting();
                 Cmd::Move(Movement::BackwardChar(1))
             }
@@ -798,10 +1192,18 @@ impl InputState {
             KeyPress::Char('$') => Some(Movement::EndOfLine),
             KeyPress::Char('0') => Some(Movement::BeginningOfLine),
             KeyPress::Char('^') => Some(Movement::ViFirstPrint),
+            KeyPress::Char('%') => Some(Movement::ViMatchingBracket),
             KeyPress::Char('b') => Some(Movement::BackwardWord(n, Word::Vi)),
             KeyPress::Char('B') => Some(Movement::BackwardWord(n, Word::Big)),
             KeyPress::Char('e') => Some(Movement::ForwardWord(n, At::AfterEnd, Word::Vi)),
             KeyPress::Char('E') => Some(Movement::ForwardWord(n, At::AfterEnd, Word::Big)),
+            KeyPress::Char('g') => match rdr.next_key(false)? {
+                KeyPress::Char('g') => Some(Movement::BeginningOfLine),
+                KeyPress::Char('e') => Some(Movement::BackwardWordEnd(n, Word::Vi)),
+                KeyPress::Char('E') => Some(Movement::BackwardWordEnd(n, Word::Big)),
+                _ => None,
+            },
+            KeyPress::Char('G') => Some(Movement::EndOfLine),
             KeyPress::Char(c) if c == 'f' || c == 'F' || c == 't' || c == 'T' => {
                 let cs = self.vi_char_search(rdr, c)?;
                 match cs {
@@ -839,6 +1241,10 @@ impl InputState {
                     Some(Movement::ForwardWord(n, At::Start, Word::Big))
                 }
             }
+            KeyPress::Char(c) if c == 'i' || c == 'a' => match rdr.next_key(false)? {
+                KeyPress::Char(delim) => TextObject::new(c == 'i', delim).map(Movement::TextObject),
+                _ => None,
+            },
             _ => None,
         })
     }
@@ -914,6 +1320,8 @@ impl InputState {
             },
             KeyPress::Ctrl('Q') | // most terminals override Ctrl+Q to resume execution
             KeyPress::Ctrl('V') => Cmd::QuotedInsert,
+            KeyPress::CtrlShift('C') => Cmd::CopyRegion,
+            KeyPress::CtrlShift('V') => Cmd::PasteClipboard,
             KeyPress::Ctrl('W') => {
                 if positive {
                     Cmd::Kill(Movement::BackwardWord(n, Word::Big))
@@ -931,6 +1339,8 @@ impl InputState {
             KeyPress::Ctrl('Z') => Cmd::Suspend,
             KeyPress::Ctrl('_') => Cmd::Undo(n),
             KeyPress::UnknownEscSeq => Cmd::Noop,
+            KeyPress::MousePress { .. } | KeyPress::MouseRelease { .. } => Cmd::Noop,
+            KeyPress::FocusGained | KeyPress::FocusLost => Cmd::Noop,
             KeyPress::BracketedPasteStart => {
                 let paste = rdr.read_pasted_text()?;
                 Cmd::Insert(1, paste)
@@ -972,3 +1382,259 @@ impl InputState {
         }
     }
 }
+
+/// Reader of a single key, used to look up the command bound to it
+struct KeyReader(Option<KeyPress>);
+
+impl RawReader for KeyReader {
+    fn next_key(&mut self, _: bool) -> Result<KeyPress> {
+        self.0.take().ok_or(ReadlineError::Eof)
+    }
+
+    #[cfg(any(unix, windows))]
+    fn next_char(&mut self) -> Result<char> {
+        Err(ReadlineError::Eof)
+    }
+
+    fn read_pasted_text(&mut self) -> Result<String> {
+        Ok(String::new())
+    }
+
+    fn wait_for_input(&mut self, _: i32) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+/// Refresher of nothing, used to look up the command bound to a key
+struct NoRefresher;
+
+impl Refresher for NoRefresher {
+    fn refresh_line(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn refresh_line_with_msg(&mut self, _: Option<String>) -> Result<()> {
+        Ok(())
+    }
+
+    fn refresh_prompt_and_line(&mut self, _: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn doing_insert(&mut self) {}
+
+    fn done_inserting(&mut self) {}
+
+    fn last_insert(&self) -> Option<String> {
+        None
+    }
+
+    fn is_cursor_at_end(&self) -> bool {
+        true
+    }
+
+    fn has_hint(&self) -> bool {
+        false
+    }
+}
+
+/// Maximum length (in bytes) of a raw sequence bound by
+/// `Editor::bind_raw_sequence`
+pub(crate) const MAX_SEQUENCE_LEN: usize = 16;
+
+/// Raw byte sequence bound to a command (see `Editor::raw_bindings`)
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "with-serde", derive(Serialize, Deserialize))]
+pub struct RawBinding {
+    /// Bytes sent by the terminal
+    pub seq: Vec<u8>,
+    /// Bound command
+    pub cmd: Cmd,
+}
+
+/// Raw byte sequences bound to commands
+#[derive(Debug, Default)]
+pub(crate) struct SequenceTrie {
+    cmd: Option<Cmd>,
+    children: Vec<(u8, SequenceTrie)>,
+}
+
+impl SequenceTrie {
+    pub fn is_empty(&self) -> bool {
+        self.cmd.is_none() && self.children.is_empty()
+    }
+
+    pub fn insert(&mut self, seq: &[u8], cmd: Cmd) -> Option<Cmd> {
+        match seq.split_first() {
+            None => self.cmd.replace(cmd),
+            Some((b, rest)) => {
+                let idx = match self.children.iter().position(|(k, _)| k == b) {
+                    Some(idx) => idx,
+                    None => {
+                        self.children.push((*b, SequenceTrie::default()));
+                        self.children.len() - 1
+                    }
+                };
+                self.children[idx].1.insert(rest, cmd)
+            }
+        }
+    }
+
+    pub fn remove(&mut self, seq: &[u8]) -> Option<Cmd> {
+        match seq.split_first() {
+            None => self.cmd.take(),
+            Some((b, rest)) => {
+                let idx = self.children.iter().position(|(k, _)| k == b)?;
+                let cmd = self.children[idx].1.remove(rest);
+                if self.children[idx].1.is_empty() {
+                    self.children.swap_remove(idx);
+                }
+                cmd
+            }
+        }
+    }
+
+    /// Returns `None` if `seq` is not a prefix of any bound sequence.
+    /// Otherwise returns the command bound to `seq` (if any) and whether a
+    /// longer sequence starts with `seq`.
+    #[cfg_attr(any(windows, feature = "crossterm"), allow(dead_code))]
+    pub fn lookup(&self, seq: &[u8]) -> Option<(Option<&Cmd>, bool)> {
+        let mut node = self;
+        for b in seq {
+            node = &node.children.iter().find(|(k, _)| k == b)?.1;
+        }
+        Some((node.cmd.as_ref(), !node.children.is_empty()))
+    }
+
+    /// All the bound sequences
+    pub fn bindings(&self) -> Vec<RawBinding> {
+        let mut bindings = Vec::new();
+        self.collect(&mut Vec::new(), &mut bindings);
+        bindings
+    }
+
+    fn collect(&self, seq: &mut Vec<u8>, bindings: &mut Vec<RawBinding>) {
+        if let Some(ref cmd) = self.cmd {
+            bindings.push(RawBinding {
+                seq: seq.clone(),
+                cmd: cmd.clone(),
+            });
+        }
+        for (b, child) in &self.children {
+            seq.push(*b);
+            child.collect(seq, bindings);
+            seq.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cmd, RawBinding, SequenceTrie};
+
+    #[test]
+    fn sequence_trie() {
+        let mut trie = SequenceTrie::default();
+        assert!(trie.is_empty());
+        assert_eq!(None, trie.insert(b"\x1b[1;5A", Cmd::BeginningOfHistory));
+        assert_eq!(None, trie.insert(b"\x1b[1", Cmd::EndOfHistory));
+        assert_eq!(None, trie.lookup(b"\x1b[2"));
+        assert_eq!(Some((None, true)), trie.lookup(b"\x1b["));
+        assert_eq!(
+            Some((Some(&Cmd::EndOfHistory), true)),
+            trie.lookup(b"\x1b[1")
+        );
+        assert_eq!(
+            Some((Some(&Cmd::BeginningOfHistory), false)),
+            trie.lookup(b"\x1b[1;5A")
+        );
+        assert_eq!(
+            Some(Cmd::BeginningOfHistory),
+            trie.insert(b"\x1b[1;5A", Cmd::Noop)
+        );
+        assert_eq!(Some(Cmd::Noop), trie.remove(b"\x1b[1;5A"));
+        assert_eq!(
+            Some((Some(&Cmd::EndOfHistory), false)),
+            trie.lookup(b"\x1b[1")
+        );
+        assert_eq!(None, trie.remove(b"\x1b[1;5A"));
+        assert_eq!(
+            vec![RawBinding {
+                seq: b"\x1b[1".to_vec(),
+                cmd: Cmd::EndOfHistory
+            }],
+            trie.bindings()
+        );
+        assert_eq!(Some(Cmd::EndOfHistory), trie.remove(b"\x1b[1"));
+        assert!(trie.is_empty());
+    }
+
+    #[test]
+    fn from_name() {
+        for name in &[
+            "accept-line",
+            "backward-delete-char",
+            "backward-kill-word",
+            "backward-word",
+            "complete",
+            "forward-word",
+            "history-search-backward",
+            "kill-word",
+            "unix-line-discard",
+            "unix-word-rubout",
+            "yank",
+        ] {
+            assert_eq!(*name, Cmd::from_name(name).unwrap().name());
+        }
+        assert_eq!(Some(Cmd::Complete), Cmd::from_name("menu-complete"));
+        assert_eq!(None, Cmd::from_name("self-insert"));
+        assert_eq!(None, Cmd::from_name("unknown"));
+    }
+
+    #[test]
+    #[cfg(feature = "with-serde")]
+    fn serde_bindings() {
+        use super::Movement;
+        use crate::keys::KeyPress;
+        use std::collections::HashMap;
+
+        #[derive(serde::Deserialize)]
+        struct Bindings {
+            keys: HashMap<KeyPress, Cmd>,
+            // `Editor::bind_raw_sequence`
+            sequences: Vec<RawBinding>,
+        }
+
+        let bindings: Bindings = toml::from_str(
+            r#"
+            sequences = [{ seq = [27, 91, 49, 59, 53, 65], cmd = "BeginningOfHistory" }]
+            [keys]
+            "Ctrl+X" = "ClearScreen"
+            "Ctrl+H" = { Kill = { BackwardChar = 1 } }
+            "Meta+b" = { Move = "BeginningOfLine" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            Some(&Cmd::ClearScreen),
+            bindings.keys.get(&KeyPress::Ctrl('X'))
+        );
+        assert_eq!(
+            Some(&Cmd::Kill(Movement::BackwardChar(1))),
+            bindings.keys.get(&KeyPress::Ctrl('H'))
+        );
+        assert_eq!(
+            Some(&Cmd::Move(Movement::BeginningOfLine)),
+            bindings.keys.get(&KeyPress::Meta('b'))
+        );
+        assert_eq!(b"\x1b[1;5A", bindings.sequences[0].seq.as_slice());
+        assert_eq!(Cmd::BeginningOfHistory, bindings.sequences[0].cmd);
+
+        let mut keys = HashMap::new();
+        keys.insert(KeyPress::Ctrl('X'), Cmd::ClearScreen);
+        assert_eq!(
+            "\"Ctrl+X\" = \"ClearScreen\"\n",
+            toml::to_string(&keys).unwrap()
+        );
+    }
+}
@@ -14,16 +14,34 @@ pub enum KeyPress {
     ControlRight,
     ControlUp,
     Ctrl(char),
+    CtrlShift(char), // not reported by unix terminals
     Delete,
     Down,
     End,
     Enter, // Ctrl('M')
     Esc,   // Ctrl('[')
     F(u8),
+    FocusGained,
+    FocusLost,
     Home,
     Insert,
     Left,
     Meta(char),
+    /// Mouse button pressed at (0-based) `col` and `row`: `btn` is the xterm
+    /// button code (0 to 2 for left, middle and right buttons, 64 and 65 for
+    /// the wheel, +4 with Shift, +8 with Meta, +16 with Ctrl, +32 on motion)
+    MousePress {
+        col: u16,
+        row: u16,
+        btn: u8,
+    },
+    /// Mouse button released (`btn` is 3 when the terminal does not report
+    /// which one)
+    MouseRelease {
+        col: u16,
+        row: u16,
+        btn: u8,
+    },
     Null,
     PageDown,
     PageUp,
@@ -36,7 +54,6 @@ pub enum KeyPress {
     Up,
 }
 
-#[cfg(any(windows, unix))]
 pub fn char_to_key_press(c: char) -> KeyPress {
     if !c.is_control() {
         return KeyPress::Char(c);
@@ -79,6 +96,131 @@ pub fn char_to_key_press(c: char) -> KeyPress {
     }
 }
 
+/// Human readable name of `key` (like `"Ctrl+A"`)
+#[cfg(feature = "with-serde")]
+fn key_name(key: KeyPress) -> String {
+    let name = match key {
+        KeyPress::UnknownEscSeq => "UnknownEscSeq",
+        KeyPress::Backspace => "Backspace",
+        KeyPress::BackTab => "BackTab",
+        KeyPress::BracketedPasteStart => "BracketedPasteStart",
+        KeyPress::BracketedPasteEnd => "BracketedPasteEnd",
+        KeyPress::Char(c) => return c.to_string(),
+        KeyPress::ControlDown => "Ctrl+Down",
+        KeyPress::ControlLeft => "Ctrl+Left",
+        KeyPress::ControlRight => "Ctrl+Right",
+        KeyPress::ControlUp => "Ctrl+Up",
+        KeyPress::Ctrl(c) => return format!("Ctrl+{}", c),
+        KeyPress::CtrlShift(c) => return format!("Ctrl+Shift+{}", c),
+        KeyPress::Delete => "Delete",
+        KeyPress::Down => "Down",
+        KeyPress::End => "End",
+        KeyPress::Enter => "Enter",
+        KeyPress::Esc => "Esc",
+        KeyPress::F(n) => return format!("F{}", n),
+        KeyPress::FocusGained => "FocusGained",
+        KeyPress::FocusLost => "FocusLost",
+        KeyPress::Home => "Home",
+        KeyPress::Insert => "Insert",
+        KeyPress::Left => "Left",
+        KeyPress::Meta(c) => return format!("Meta+{}", c),
+        KeyPress::MousePress { .. } => "MousePress",
+        KeyPress::MouseRelease { .. } => "MouseRelease",
+        KeyPress::Null => "Null",
+        KeyPress::PageDown => "PageDown",
+        KeyPress::PageUp => "PageUp",
+        KeyPress::Right => "Right",
+        KeyPress::ShiftDown => "Shift+Down",
+        KeyPress::ShiftLeft => "Shift+Left",
+        KeyPress::ShiftRight => "Shift+Right",
+        KeyPress::ShiftUp => "Shift+Up",
+        KeyPress::Tab => "Tab",
+        KeyPress::Up => "Up",
+    };
+    name.to_owned()
+}
+
+/// Inverse of `key_name`
+#[cfg(feature = "with-serde")]
+fn parse_key_name(name: &str) -> Option<KeyPress> {
+    fn single_char(s: &str) -> Option<char> {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ => None,
+        }
+    }
+    let key = match name {
+        "UnknownEscSeq" => KeyPress::UnknownEscSeq,
+        "Backspace" => KeyPress::Backspace,
+        "BackTab" => KeyPress::BackTab,
+        "BracketedPasteStart" => KeyPress::BracketedPasteStart,
+        "BracketedPasteEnd" => KeyPress::BracketedPasteEnd,
+        "Ctrl+Down" => KeyPress::ControlDown,
+        "Ctrl+Left" => KeyPress::ControlLeft,
+        "Ctrl+Right" => KeyPress::ControlRight,
+        "Ctrl+Up" => KeyPress::ControlUp,
+        "Delete" => KeyPress::Delete,
+        "Down" => KeyPress::Down,
+        "End" => KeyPress::End,
+        "Enter" => KeyPress::Enter,
+        "Esc" => KeyPress::Esc,
+        "FocusGained" => KeyPress::FocusGained,
+        "FocusLost" => KeyPress::FocusLost,
+        "Home" => KeyPress::Home,
+        "Insert" => KeyPress::Insert,
+        "Left" => KeyPress::Left,
+        "Null" => KeyPress::Null,
+        "PageDown" => KeyPress::PageDown,
+        "PageUp" => KeyPress::PageUp,
+        "Right" => KeyPress::Right,
+        "Shift+Down" => KeyPress::ShiftDown,
+        "Shift+Left" => KeyPress::ShiftLeft,
+        "Shift+Right" => KeyPress::ShiftRight,
+        "Shift+Up" => KeyPress::ShiftUp,
+        "Tab" => KeyPress::Tab,
+        "Up" => KeyPress::Up,
+        _ => {
+            if let Some(c) = single_char(name) {
+                return Some(KeyPress::Char(c));
+            }
+            if let Some(c) = name.strip_prefix("Ctrl+Shift+") {
+                return Some(KeyPress::CtrlShift(single_char(c)?.to_ascii_uppercase()));
+            }
+            let mut parts = name.splitn(2, '+');
+            match (parts.next(), parts.next()) {
+                (Some("Ctrl"), Some(c)) => KeyPress::Ctrl(single_char(c)?.to_ascii_uppercase()),
+                (Some("Meta"), Some(c)) => KeyPress::Meta(single_char(c)?),
+                (Some(f), None) => {
+                    let mut chars = f.chars();
+                    if chars.next() != Some('F') {
+                        return None;
+                    }
+                    KeyPress::F(chars.as_str().parse().ok()?)
+                }
+                _ => return None,
+            }
+        }
+    };
+    Some(key)
+}
+
+#[cfg(feature = "with-serde")]
+impl serde::Serialize for KeyPress {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&key_name(*self))
+    }
+}
+
+#[cfg(feature = "with-serde")]
+impl<'de> serde::Deserialize<'de> for KeyPress {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+        let name = String::deserialize(deserializer)?;
+        parse_key_name(&name).ok_or_else(|| D::Error::custom(format!("unknown key: {:?}", name)))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::{char_to_key_press, KeyPress};
@@ -87,4 +229,27 @@ mod tests {
     fn char_to_key() {
         assert_eq!(KeyPress::Esc, char_to_key_press('\x1b'));
     }
+
+    #[test]
+    #[cfg(feature = "with-serde")]
+    fn key_name() {
+        use super::{key_name, parse_key_name};
+        for key in &[
+            KeyPress::Char('+'),
+            KeyPress::Ctrl('A'),
+            KeyPress::CtrlShift('V'),
+            KeyPress::Meta('b'),
+            KeyPress::ControlRight,
+            KeyPress::ShiftUp,
+            KeyPress::F(12),
+            KeyPress::FocusLost,
+            KeyPress::Tab,
+        ] {
+            assert_eq!(Some(*key), parse_key_name(&key_name(*key)));
+        }
+        assert_eq!("Ctrl+A", key_name(KeyPress::Ctrl('A')));
+        assert_eq!(Some(KeyPress::Ctrl('A')), parse_key_name("Ctrl+a"));
+        assert_eq!(Some(KeyPress::Ctrl('+')), parse_key_name("Ctrl++"));
+        assert_eq!(None, parse_key_name("Hyper+X"));
+    }
 }
@@ -17,6 +17,7 @@
 //! ```
 // #![feature(non_exhaustive)]
 
+pub mod clipboard;
 pub mod completion;
 pub mod config;
 mod edit;
@@ -24,6 +25,7 @@ pub mod error;
 pub mod highlight;
 pub mod hint;
 pub mod history;
+mod inputrc;
 mod keymap;
 mod keys;
 mod kill_ring;
@@ -33,28 +35,42 @@ mod tty;
 mod undo;
 pub mod validate;
 
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
 use std::io::{self, Write};
-use std::path::Path;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
 use std::result;
 use std::sync::{Arc, Mutex, RwLock};
 
-use log::debug;
+#[cfg(not(feature = "tracing"))]
+use log::{debug, warn};
+#[cfg(feature = "tracing")]
+use tracing::{debug, warn};
 use unicode_width::UnicodeWidthStr;
 
-use crate::tty::{RawMode, Renderer, Term, Terminal};
+use crate::tty::{RawMode, RawModeGuard, RawReader, Renderer, Term, Terminal};
+pub use crate::tty::{terminal_size, TerminalCapabilities, WinSize};
+pub use crate::tty::AbortHandle;
+#[cfg(all(feature = "mock", not(any(test, target_arch = "wasm32"))))]
+pub use crate::tty::MockTerminal;
 
-use crate::completion::{longest_common_prefix, Candidate, Completer};
+use crate::clipboard::ClipboardBackend;
+use crate::completion::{longest_common_prefix, Candidate, Completer, Pair};
 pub use crate::config::{
     ColorMode, CompletionType, Config, EditMode, HistoryDuplicates, OutputStreamType,
 };
 use crate::edit::State;
+use crate::error::ConfigError;
 use crate::highlight::Highlighter;
 use crate::hint::Hinter;
-use crate::history::{Direction, History};
-pub use crate::keymap::{Anchor, At, CharSearch, Cmd, Movement, RepeatCount, Word};
-use crate::keymap::{InputState, Refresher};
+use crate::history::{Direction, History, HistorySearcher};
+pub use crate::keymap::{
+    Anchor, At, CharSearch, Cmd, CustomPayload, InputMode, Movement, RawBinding, RepeatCount,
+    TextObject, Word,
+};
+use crate::keymap::{InputState, Refresher, SequenceTrie};
 pub use crate::keys::KeyPress;
 use crate::kill_ring::{KillRing, Mode};
 use crate::line_buffer::WordAction;
@@ -63,6 +79,20 @@ use crate::validate::Validator;
 /// The error type for I/O and Linux Syscalls (Errno)
 pub type Result<T> = result::Result<T, error::ReadlineError>;
 
+/// Start of the word replaced by the completion candidates: `start` unless
+/// overridden by `Completer::custom_word_break`
+fn completion_start<C: ?Sized + Completer>(
+    completer: &C,
+    line: &str,
+    pos: usize,
+    start: usize,
+) -> usize {
+    completer
+        .custom_word_break(line, pos)
+        .filter(|&start| start <= pos && line.is_char_boundary(start))
+        .unwrap_or(start)
+}
+
 /// Completes the line/word
 fn complete_line<H: Helper>(
     rdr: &mut <Terminal as Term>::Reader,
@@ -70,12 +100,20 @@ fn complete_line<H: Helper>(
     input_state: &mut InputState,
     config: &Config,
 ) -> Result<Option<Cmd>> {
-    #[cfg(all(unix, feature = "with-fuzzy"))]
-    use skim::{Skim, SkimOptionsBuilder};
-
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!(target: "rustyline", "complete").entered();
     let completer = s.helper.unwrap();
+    completer.completion_started(&s.line, s.line.pos());
+    s.ctx.update_quote(&s.line, s.line.pos());
+    #[cfg(feature = "tokio")]
+    {
+        if let Some(async_completer) = completer.async_completer() {
+            return async_complete_line(rdr, s, input_state, async_completer, config);
+        }
+    }
     // get a list of completions
     let (start, candidates) = completer.complete(&s.line, s.line.pos(), &s.ctx)?;
+    let start = completion_start(completer, &s.line, s.line.pos(), start);
     // if no completions, we are done
     if candidates.is_empty() {
         s.out.beep()?;
@@ -101,7 +139,7 @@ fn complete_line<H: Helper>(
                 s.refresh_line()?;
             } else {
                 // Restore current edited line
-                s.line.update(&backup, backup_pos);
+                s.line.update_overflowing(&backup, backup_pos);
                 s.refresh_line()?;
             }
 
@@ -124,7 +162,7 @@ fn complete_line<H: Helper>(
                 Cmd::Abort => {
                     // Re-show original buffer
                     if i < candidates.len() {
-                        s.line.update(&backup, backup_pos);
+                        s.line.update_overflowing(&backup, backup_pos);
                         s.refresh_line()?;
                     }
                     s.changes.borrow_mut().truncate(mark);
@@ -182,52 +220,288 @@ fn complete_line<H: Helper>(
             true
         };
         if show_completions {
-            page_completions(rdr, s, input_state, &candidates)
+            page_completions(rdr, s, input_state, &candidates, config)
         } else {
             s.refresh_line()?;
             Ok(None)
         }
+    } else if CompletionType::Menu == config.completion_type() {
+        if candidates.len() == 1 {
+            completer.update(&mut s.line, start, candidates[0].replacement());
+            s.refresh_line()?;
+            return Ok(None);
+        }
+        menu_completions(rdr, s, input_state, start, &candidates, &[], config)
     } else {
-        // if fuzzy feature is enabled and on unix based systems check for the
-        // corresponding completion_type
-        #[cfg(all(unix, feature = "with-fuzzy"))]
-        {
-            if CompletionType::Fuzzy == config.completion_type() {
-                // skim takes input of candidates separated by new line
-                let input = candidates
-                    .iter()
-                    .map(|c| c.display())
-                    .collect::<Vec<_>>()
-                    .join("\n");
-
-                // setup skim and run with input options
-                // will display UI for fuzzy search and return selected results
-                // by default skim multi select is off so only expect one selection
-
-                let options = SkimOptionsBuilder::default()
-                    .height(Some("20%"))
-                    .prompt(Some("? "))
-                    .reverse(true)
-                    .build()
-                    .unwrap();
-
-                let selected_items =
-                    Skim::run_with(&options, Some(Box::new(std::io::Cursor::new(input))))
-                        .map(|out| out.selected_items)
-                        .unwrap_or_else(Vec::new);
-
-                // match the first (and only) returned option with the candidate and update the
-                // line otherwise only refresh line to clear the skim UI changes
-                if let Some(item) = selected_items.first() {
-                    if let Some(candidate) = candidates.get(item.get_index()) {
-                        completer.update(&mut s.line, start, candidate.replacement());
-                    }
-                }
+        fuzzy_completions(rdr, s, input_state, start, candidates, config)
+    }
+}
+
+/// Candidates cycled through by repeated completions
+struct CycleState {
+    candidates: Vec<Pair>,
+    /// Index of the candidate inserted in the line (`candidates.len()` when
+    /// only their longest common prefix has been inserted)
+    index: usize,
+    start: usize,
+}
+
+/// Completes the line/word till the longest match then cycles through the
+/// candidates (`CompletionType::Cycle`)
+fn cycle_completion<H: Helper>(
+    s: &mut State<'_, '_, H>,
+    state: Option<CycleState>,
+    forward: bool,
+) -> Result<Option<CycleState>> {
+    let completer = s.helper.unwrap();
+    let mut state = match state {
+        Some(state) => state,
+        None => {
+            completer.completion_started(&s.line, s.line.pos());
+            s.ctx.update_quote(&s.line, s.line.pos());
+            let (start, candidates) = completer.complete(&s.line, s.line.pos(), &s.ctx)?;
+            let start = completion_start(completer, &s.line, s.line.pos(), start);
+            if candidates.is_empty() {
+                s.out.beep()?;
+                return Ok(None);
+            } else if candidates.len() == 1 {
+                completer.update(&mut s.line, start, candidates[0].replacement());
                 s.refresh_line()?;
+                return Ok(None);
             }
-        };
+            let candidates: Vec<Pair> = candidates
+                .iter()
+                .map(|c| Pair {
+                    display: c.display().to_owned(),
+                    replacement: c.replacement().to_owned(),
+                })
+                .collect();
+            let index = candidates.len();
+            if let Some(lcp) = longest_common_prefix(&candidates) {
+                // if we can extend the item, extend it and wait for the next
+                // completion to start cycling
+                if lcp.len() > s.line.pos() - start {
+                    completer.update(&mut s.line, start, lcp);
+                    s.refresh_line()?;
+                    return Ok(Some(CycleState {
+                        candidates,
+                        index,
+                        start,
+                    }));
+                }
+            }
+            CycleState {
+                candidates,
+                index,
+                start,
+            }
+        }
+    };
+    let len = state.candidates.len();
+    state.index = if forward {
+        if state.index + 1 >= len {
+            0
+        } else {
+            state.index + 1
+        }
+    } else if state.index == 0 {
+        len - 1
+    } else {
+        state.index - 1
+    };
+    completer.update(
+        &mut s.line,
+        state.start,
+        state.candidates[state.index].replacement(),
+    );
+    s.refresh_line()?;
+    Ok(Some(state))
+}
+
+/// Completes the line/word with candidates computed by `completer` on a tokio
+/// runtime, showing a spinner until they are available. Any key pressed in
+/// the meantime cancels the completion.
+#[cfg(feature = "tokio")]
+fn async_complete_line<H: Helper>(
+    rdr: &mut <Terminal as Term>::Reader,
+    s: &mut State<'_, '_, H>,
+    input_state: &mut InputState,
+    completer: &dyn completion::AsyncCompleter,
+    config: &Config,
+) -> Result<Option<Cmd>> {
+    use std::time::Duration;
+    const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+    const TICK_MS: u64 = 100;
+
+    let line = s.line.as_str().to_owned();
+    let mut future = completer.complete_async(&line, s.line.pos());
+    let mut frame = 0;
+    let (start, candidates) = loop {
+        if let Some(result) = wait_for_future(&mut future, Duration::from_millis(TICK_MS))? {
+            break result?;
+        }
+        if rdr.wait_for_input(0)? {
+            // the user is typing: drop the future and handle the key
+            debug!(target: "rustyline", "async completion cancelled");
+            s.refresh_line()?;
+            return Ok(None);
+        }
+        let spinner = format!(" {} completing\u{2026}", SPINNER[frame % SPINNER.len()]);
+        s.refresh_line_with_msg(Some(spinner))?;
+        frame += 1;
+    };
+    drop(future);
+    let start = completion_start(s.helper.unwrap(), &s.line, s.line.pos(), start);
+    if candidates.is_empty() {
+        s.refresh_line()?;
+        s.out.beep()?;
+        Ok(None)
+    } else if candidates.len() == 1 {
+        s.helper
+            .unwrap()
+            .update(&mut s.line, start, candidates[0].replacement());
+        s.refresh_line()?;
         Ok(None)
+    } else {
+        s.refresh_line()?;
+        menu_completions(rdr, s, input_state, start, &candidates, &[], config)
+    }
+}
+
+#[cfg(feature = "tokio")]
+thread_local! {
+    /// Runtime driving the async completions when `readline` is not called
+    /// from a tokio runtime
+    // a `const` initializer would require Rust 1.59
+    #[allow(clippy::missing_const_for_thread_local)]
+    static COMPLETION_RUNTIME: std::cell::RefCell<Option<tokio::runtime::Runtime>> =
+        std::cell::RefCell::new(None);
+}
+
+/// Wait at most `timeout` for `future`, driven by a runtime built once per
+/// thread.
+///
+/// A runtime cannot be blocked on from inside another one, and the IO and
+/// timer drivers of the calling runtime would not run while `readline`
+/// blocks its thread: an error is returned when `readline` is called from a
+/// tokio runtime.
+#[cfg(feature = "tokio")]
+fn wait_for_future<F: std::future::Future + Unpin>(
+    future: &mut F,
+    timeout: std::time::Duration,
+) -> Result<Option<F::Output>> {
+    if tokio::runtime::Handle::try_current().is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "async completion is not supported from inside a tokio runtime",
+        )
+        .into());
+    }
+    COMPLETION_RUNTIME.with(|runtime| {
+        let mut runtime = runtime.borrow_mut();
+        if runtime.is_none() {
+            *runtime = Some(
+                tokio::runtime::Builder::new()
+                    .basic_scheduler()
+                    .enable_all()
+                    .build()?,
+            );
+        }
+        let runtime = runtime.as_mut().unwrap();
+        let tick = async { tokio::time::timeout(timeout, future).await };
+        Ok(runtime.block_on(tick).ok())
+    })
+}
+
+/// Let the user select one of the `candidates` with skim.
+#[cfg(all(unix, feature = "with-fuzzy"))]
+fn fuzzy_completions<C: Candidate, H: Helper>(
+    _rdr: &mut <Terminal as Term>::Reader,
+    s: &mut State<'_, '_, H>,
+    _input_state: &mut InputState,
+    start: usize,
+    candidates: Vec<C>,
+    _config: &Config,
+) -> Result<Option<Cmd>> {
+    use skim::{Skim, SkimOptionsBuilder};
+
+    let completer = s.helper.unwrap();
+    // skim takes input of candidates separated by new line
+    let input = candidates
+        .iter()
+        .map(|c| c.display())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    // setup skim and run with input options
+    // will display UI for fuzzy search and return selected results
+    // by default skim multi select is off so only expect one selection
+
+    let options = SkimOptionsBuilder::default()
+        .height(Some("20%"))
+        .prompt(Some("? "))
+        .reverse(true)
+        .build()
+        .unwrap();
+
+    let selected_items = Skim::run_with(&options, Some(Box::new(std::io::Cursor::new(input))))
+        .map(|out| out.selected_items)
+        .unwrap_or_else(Vec::new);
+
+    // match the first (and only) returned option with the candidate and update the
+    // line otherwise only refresh line to clear the skim UI changes
+    if let Some(item) = selected_items.first() {
+        if let Some(candidate) = candidates.get(item.get_index()) {
+            completer.update(&mut s.line, start, candidate.replacement());
+        }
+    }
+    s.refresh_line()?;
+    Ok(None)
+}
+
+/// Rank the `candidates` matching the word being completed and let the user
+/// select one of them in the completion menu.
+#[cfg(not(all(unix, feature = "with-fuzzy")))]
+fn fuzzy_completions<C: Candidate, H: Helper>(
+    rdr: &mut <Terminal as Term>::Reader,
+    s: &mut State<'_, '_, H>,
+    input_state: &mut InputState,
+    start: usize,
+    candidates: Vec<C>,
+    config: &Config,
+) -> Result<Option<Cmd>> {
+    use crate::completion::fuzzy_indices;
+    use std::cmp;
+
+    let completer = s.helper.unwrap();
+    let mut scored: Vec<_> = {
+        // `start` comes from the completer
+        let needle = match s.line.as_str().get(start..s.line.pos()) {
+            Some(needle) => needle,
+            None => {
+                s.out.beep()?;
+                return Ok(None);
+            }
+        };
+        candidates
+            .into_iter()
+            .filter_map(|c| fuzzy_indices(needle, c.display()).map(|(score, m)| (score, m, c)))
+            .collect()
+    };
+    if scored.is_empty() {
+        s.out.beep()?;
+        return Ok(None);
+    }
+    // best matches first, ties in the completer order
+    scored.sort_by_key(|(score, _, _)| cmp::Reverse(*score));
+    let (matches, candidates): (Vec<_>, Vec<_>) =
+        scored.into_iter().map(|(_, m, c)| (m, c)).unzip();
+    if candidates.len() == 1 {
+        completer.update(&mut s.line, start, candidates[0].replacement());
+        s.refresh_line()?;
+        return Ok(None);
     }
+    menu_completions(rdr, s, input_state, start, &candidates, &matches, config)
 }
 
 /// Completes the current hint
@@ -237,33 +511,42 @@ fn complete_hint_line<H: Helper>(s: &mut State<'_, '_, H>) -> Result<()> {
         None => return Ok(()),
     };
     s.line.move_end();
-    if s.line.yank(hint, 1).is_none() {
+    if s.line.yank(&hint.completion, 1).is_none() {
         s.out.beep()?;
     }
     s.refresh_line_with_msg(None)?;
     Ok(())
 }
 
+/// Width of the columns when listing completion alternatives: the longest
+/// candidate plus padding, bounded by the configured min / max widths and by
+/// the terminal width.
+fn list_column_width(longest: usize, cols: usize, config: &Config) -> usize {
+    use std::cmp;
+
+    let min_col_pad = 2;
+    let width = cmp::max(longest + min_col_pad, config.completion_menu_min_width());
+    let width = cmp::min(width, config.completion_menu_max_width());
+    cmp::max(cmp::min(width, cols), 1)
+}
+
 fn page_completions<C: Candidate, H: Helper>(
     rdr: &mut <Terminal as Term>::Reader,
     s: &mut State<'_, '_, H>,
     input_state: &mut InputState,
     candidates: &[C],
+    config: &Config,
 ) -> Result<Option<Cmd>> {
-    use std::cmp;
-
-    let min_col_pad = 2;
     let cols = s.out.get_columns();
-    let max_width = cmp::min(
-        cols,
-        candidates
-            .iter()
-            .map(|s| s.display().width())
-            .max()
-            .unwrap()
-            + min_col_pad,
-    );
+    let longest = candidates
+        .iter()
+        .map(|s| s.display().width())
+        .max()
+        .unwrap();
+    let max_width = list_column_width(longest, cols, config);
     let num_cols = cols / max_width;
+    // keep the padding between columns
+    let text_width = max_width.saturating_sub(2);
 
     let mut pause_row = s.out.get_rows() - 1;
     let num_rows = (candidates.len() + num_cols - 1) / num_cols;
@@ -302,12 +585,12 @@ fn page_completions<C: Candidate, H: Helper>(
         for col in 0..num_cols {
             let i = (col * num_rows) + row;
             if i < candidates.len() {
-                let candidate = &candidates[i].display();
+                let candidate = ellipsize(candidates[i].display(), text_width);
                 let width = candidate.width();
                 if let Some(highlighter) = s.highlighter() {
-                    ab.push_str(&highlighter.highlight_candidate(candidate, CompletionType::List));
+                    ab.push_str(&highlighter.highlight_candidate(&candidate, CompletionType::List));
                 } else {
-                    ab.push_str(candidate);
+                    ab.push_str(&candidate);
                 }
                 if ((col + 1) * num_rows) + row < candidates.len() {
                     for _ in width..max_width {
@@ -323,6 +606,199 @@ fn page_completions<C: Candidate, H: Helper>(
     Ok(None)
 }
 
+/// Let the user select one of the `candidates` in a menu displayed below the
+/// line.
+/// `matches` are the indices of the chars matched by fuzzy completion.
+fn menu_completions<C: Candidate, H: Helper>(
+    rdr: &mut <Terminal as Term>::Reader,
+    s: &mut State<'_, '_, H>,
+    input_state: &mut InputState,
+    start: usize,
+    candidates: &[C],
+    matches: &[Vec<usize>],
+    config: &Config,
+) -> Result<Option<Cmd>> {
+    use std::cmp;
+
+    let completer = s.helper.unwrap();
+    let rows = cmp::min(cmp::max(config.completion_menu_rows(), 1), candidates.len());
+    let mut selected = 0;
+    let mut top = 0;
+    let next = loop {
+        if selected < top {
+            top = selected;
+        } else if selected >= top + rows {
+            top = selected + 1 - rows;
+        }
+        draw_menu(s, candidates, matches, rows, top, selected)?;
+        let cmd = s.next_cmd(input_state, rdr, true)?;
+        match cmd {
+            Cmd::NextHistory | Cmd::LineDownOrNextHistory => {
+                selected = (selected + 1) % candidates.len();
+            }
+            Cmd::PreviousHistory | Cmd::LineUpOrPreviousHistory | Cmd::CompleteBackward => {
+                selected = (selected + candidates.len() - 1) % candidates.len();
+            }
+            Cmd::Complete | Cmd::AcceptLine | Cmd::AcceptOrInsertLine => {
+                completer.update(&mut s.line, start, candidates[selected].replacement());
+                break None;
+            }
+            Cmd::Abort => break None,
+            _ => break Some(cmd),
+        }
+    };
+    // erase the menu
+    s.refresh_line()?;
+    Ok(next)
+}
+
+/// Draw a framed menu showing `rows` candidates from `top` below the line
+/// and move the cursor back to its position in the line.
+fn draw_menu<C: Candidate, H: Helper>(
+    s: &mut State<'_, '_, H>,
+    candidates: &[C],
+    matches: &[Vec<usize>],
+    rows: usize,
+    top: usize,
+    selected: usize,
+) -> Result<()> {
+    use crate::layout::Position;
+    use std::cmp;
+
+    // erase the previous menu
+    s.refresh_line()?;
+    let cols = s.out.get_columns();
+    // candidates and descriptions columns
+    let display_width = candidates
+        .iter()
+        .map(|c| c.display().width())
+        .max()
+        .unwrap();
+    let description_width = candidates
+        .iter()
+        .filter_map(|c| c.description())
+        .map(str::width)
+        .max()
+        .unwrap_or(0);
+    let full_width = if description_width > 0 {
+        display_width + 2 + description_width
+    } else {
+        display_width
+    };
+    // borders, selection marker and scrollbar
+    let width = cmp::min(full_width, cols.saturating_sub(4));
+    // scrollbar thumb
+    let (thumb_top, thumb_size) = if candidates.len() > rows {
+        let size = cmp::max(1, rows * rows / candidates.len());
+        (cmp::min(top * rows / candidates.len(), rows - size), size)
+    } else {
+        (0, 0)
+    };
+
+    let mut ab = String::from("\n\u{250c}");
+    for _ in 0..=width {
+        ab.push('\u{2500}');
+    }
+    ab.push('\u{2510}');
+    for row in 0..rows {
+        let i = top + row;
+        ab.push_str("\n\u{2502}");
+        ab.push(if i == selected { '>' } else { ' ' });
+        let (candidate, mut w) = truncate_to_width(candidates[i].display(), width);
+        if let Some(highlighter) = s.highlighter() {
+            if let Some(matched) = matches.get(i) {
+                ab.push_str(&highlighter.highlight_candidate_matches(
+                    candidate,
+                    CompletionType::Fuzzy,
+                    matched,
+                ));
+            } else {
+                ab.push_str(&highlighter.highlight_candidate(candidate, CompletionType::Menu));
+            }
+        } else {
+            ab.push_str(candidate);
+        }
+        if let Some(description) = candidates[i].description() {
+            if width > display_width + 2 {
+                for _ in w..display_width + 2 {
+                    ab.push(' ');
+                }
+                let (description, dw) = truncate_to_width(description, width - display_width - 2);
+                if s.out.colors_enabled() {
+                    // dim
+                    ab.push_str("\x1b[2m");
+                    ab.push_str(description);
+                    ab.push_str("\x1b[0m");
+                } else {
+                    ab.push_str(description);
+                }
+                w = display_width + 2 + dw;
+            }
+        }
+        for _ in w..width {
+            ab.push(' ');
+        }
+        if row >= thumb_top && row < thumb_top + thumb_size {
+            ab.push('\u{2588}');
+        } else {
+            ab.push('\u{2502}');
+        }
+    }
+    ab.push_str("\n\u{2514}");
+    for _ in 0..=width {
+        ab.push('\u{2500}');
+    }
+    ab.push('\u{2518}');
+
+    // make room for the menu first: the saved cursor position is lost if the
+    // screen scrolls while the menu is written
+    let (cursor, end) = (s.layout.cursor, s.layout.end);
+    let menu_rows = rows + 2;
+    s.out.move_cursor(cursor, end)?;
+    s.out.write_and_flush("\n".repeat(menu_rows).as_bytes())?;
+    let menu_end = Position {
+        col: 0,
+        row: end.row + menu_rows,
+    };
+    s.out.move_cursor(menu_end, cursor)?;
+    // write the menu after the end of the line
+    s.out.save_cursor()?;
+    s.out.move_cursor(cursor, end)?;
+    s.out.write_and_flush(ab.as_bytes())?;
+    s.out.restore_cursor()?;
+    // so that the menu is erased by the next refresh
+    s.layout.end.row += menu_rows;
+    Ok(())
+}
+
+/// `s` shortened to at most `width` columns, ending with an ellipsis when it
+/// is truncated.
+fn ellipsize(s: &str, width: usize) -> Cow<'_, str> {
+    if s.width() <= width {
+        return Cow::Borrowed(s);
+    } else if width == 0 {
+        return Cow::Borrowed("");
+    }
+    let (prefix, _) = truncate_to_width(s, width - 1);
+    Cow::Owned(format!("{}\u{2026}", prefix))
+}
+
+/// Longest prefix of `s` whose display width does not exceed `width` and its
+/// display width.
+fn truncate_to_width(s: &str, width: usize) -> (&str, usize) {
+    use unicode_width::UnicodeWidthChar;
+
+    let mut w = 0;
+    for (idx, c) in s.char_indices() {
+        let cw = c.width().unwrap_or(0);
+        if w + cw > width {
+            return (&s[..idx], w);
+        }
+        w += cw;
+    }
+    (s, w)
+}
+
 /// Incremental search
 fn reverse_incremental_search<H: Helper>(
     rdr: &mut <Terminal as Term>::Reader,
@@ -382,7 +858,7 @@ fn reverse_incremental_search<H: Helper>(
                 }
                 Cmd::Abort => {
                     // Restore current edited line (before search)
-                    s.line.update(&backup, backup_pos);
+                    s.line.update_overflowing(&backup, backup_pos);
                     s.refresh_line()?;
                     s.changes.borrow_mut().truncate(mark);
                     return Ok(None);
@@ -399,7 +875,7 @@ fn reverse_incremental_search<H: Helper>(
                 history_idx = idx;
                 let entry = history.get(idx).unwrap();
                 let pos = entry.find(&search_buf).unwrap();
-                s.line.update(entry, pos);
+                s.line.update_overflowing(entry, pos);
                 true
             }
             _ => false,
@@ -409,6 +885,47 @@ fn reverse_incremental_search<H: Helper>(
     Ok(Some(cmd))
 }
 
+/// Read the search term of a vi-search (`/` or `?`)
+fn vi_search_term<H: Helper>(
+    rdr: &mut <Terminal as Term>::Reader,
+    s: &mut State<'_, '_, H>,
+    dir: Direction,
+) -> Result<Option<String>> {
+    let prompt = match dir {
+        Direction::Reverse => "/",
+        Direction::Forward => "?",
+    };
+    let mark = s.changes.borrow_mut().begin();
+    // Save the current edited line (and cursor position) while the term is
+    // displayed in its place
+    let backup = s.line.as_str().to_owned();
+    let backup_pos = s.line.pos();
+
+    let mut term = String::new();
+    let accepted = loop {
+        s.line.update_overflowing(&term, term.len());
+        s.refresh_prompt_and_line(prompt)?;
+        match rdr.next_key(true)? {
+            KeyPress::Char(c) => term.push(c),
+            KeyPress::Backspace | KeyPress::Ctrl('H') if term.is_empty() => break false,
+            KeyPress::Backspace | KeyPress::Ctrl('H') => {
+                term.pop();
+            }
+            KeyPress::Enter | KeyPress::Ctrl('J') => break true,
+            KeyPress::Esc | KeyPress::Ctrl('C') | KeyPress::Ctrl('G') => break false,
+            _ => {}
+        }
+    };
+    s.line.update_overflowing(&backup, backup_pos);
+    s.changes.borrow_mut().end();
+    s.changes.borrow_mut().truncate(mark);
+    s.refresh_line()?; // restore prompt
+    Ok(if accepted { Some(term) } else { None })
+}
+
+/// Delay between two refreshes requested by `Highlighter::needs_update`
+const HIGHLIGHT_UPDATE_MS: i32 = 100;
+
 /// Handles reading and editing the readline buffer.
 /// It will also handle special inputs in an appropriate fashion
 /// (e.g., C-c will exit readline)
@@ -421,33 +938,104 @@ fn readline_edit<H: Helper>(
     let helper = editor.helper.as_ref();
 
     let mut stdout = editor.term.create_writer();
+    if let Some(size) = editor.size {
+        stdout.set_size(size);
+    }
 
     editor.reset_kill_ring(); // TODO recreate a new kill ring vs Arc<Mutex<KillRing>>
-    let ctx = Context::new(&editor.history);
+    let mut ctx = Context::new(&editor.history);
+    ctx.quote_chars = editor.config.completion_quote_characters();
     let mut s = State::new(&mut stdout, prompt, helper, ctx);
     let mut input_state = InputState::new(&editor.config, Arc::clone(&editor.custom_bindings));
+    editor.input_mode = input_state.input_mode();
 
     s.line.set_delete_listener(editor.kill_ring.clone());
     s.line.set_change_listener(s.changes.clone());
+    s.line.set_max_len(editor.config.max_line_length());
 
     if let Some((left, right)) = initial {
         s.line
             .update((left.to_owned() + right).as_ref(), left.len());
     }
 
-    let mut rdr = editor.term.create_reader(&editor.config)?;
+    let mut rdr = editor
+        .term
+        .create_reader(&editor.config, Arc::clone(&editor.custom_sequences))?;
     if editor.term.is_output_tty() {
         s.move_cursor_at_leftmost(&mut rdr)?;
     }
     s.refresh_line()?;
+    // history index and length of the word inserted by the last yank-last-arg
+    let mut last_arg_state = None;
+    // candidates cycled through by `CompletionType::Cycle`
+    let mut cycle_state = None;
 
     loop {
-        let rc = s.next_cmd(&mut input_state, &mut rdr, false);
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!(target: "rustyline", "key").entered();
+        // commands queued by `execute_command` run before any key is read
+        let rc = if let Some(cmd) = editor.pending_commands.pop_front() {
+            Ok(cmd)
+        } else {
+            // let an animated highlighter repaint the line until a key is pressed
+            while s.needs_update() && !rdr.wait_for_input(HIGHLIGHT_UPDATE_MS)? {
+                s.refresh_line()?;
+            }
+            let report_resize = editor.resize_handler.is_some();
+            s.next_cmd_or_resize(&mut input_state, &mut rdr, false, report_resize)
+        };
+        #[cfg(unix)]
+        {
+            if rc.is_err() && tty::sigcont() {
+                // stopped then resumed: the terminal may be back in cooked mode
+                editor.term.enable_raw_mode()?;
+                s.refresh_line()?;
+                continue;
+            }
+        }
         let mut cmd = rc?;
 
+        let input_mode = input_state.input_mode();
+        if input_mode != editor.input_mode {
+            editor.input_mode = input_mode;
+            if let Some(ref mut handler) = editor.mode_change_handler {
+                handler(input_mode);
+            }
+        }
+        if let Cmd::WindowResize(size) = cmd {
+            if let Some(ref mut handler) = editor.resize_handler {
+                handler(size);
+            }
+            continue;
+        }
+        if let Cmd::Custom(ref name, ref payload) = cmd {
+            let claimed = match editor.custom_command_handler {
+                Some(ref mut handler) => handler(name, payload),
+                None => None,
+            };
+            cmd = claimed.unwrap_or(Cmd::Noop);
+        }
+
         if cmd.should_reset_kill_ring() {
             editor.reset_kill_ring();
         }
+        if let Cmd::YankLastArg(arg) = cmd {
+            last_arg_state = s.edit_yank_last_arg(arg, last_arg_state)?;
+            continue;
+        }
+        last_arg_state = None;
+        if CompletionType::Cycle == editor.config.completion_type() && s.helper.is_some() {
+            let forward = match cmd {
+                Cmd::Complete => Some(true),
+                Cmd::CompleteBackward if cycle_state.is_some() => Some(false),
+                _ => None,
+            };
+            if let Some(forward) = forward {
+                cycle_state = cycle_completion(&mut s, cycle_state.take(), forward)?;
+                continue;
+            }
+        }
+        cycle_state = None;
 
         // autocomplete
         if cmd == Cmd::Complete && s.helper.is_some() {
@@ -497,6 +1085,7 @@ fn readline_edit<H: Helper>(
                 s.edit_move_backward(n)?
             }
             Cmd::ReplaceChar(n, c) => s.edit_replace_char(c, n)?,
+            Cmd::SwapCase(n) => s.edit_swap_case(n)?,
             Cmd::Replace(mvt, text) => {
                 s.edit_kill(&mvt)?;
                 if let Some(text) = text {
@@ -547,13 +1136,19 @@ fn readline_edit<H: Helper>(
                     s.edit_history_next(false)?
                 }
             }
-            Cmd::HistorySearchBackward => s.edit_history_search(Direction::Reverse)?,
-            Cmd::HistorySearchForward => s.edit_history_search(Direction::Forward)?,
+            Cmd::HistorySearchBackward => s.edit_history_search(
+                Direction::Reverse,
+                editor.config.history_search_no_duplicates(),
+            )?,
+            Cmd::HistorySearchForward => s.edit_history_search(
+                Direction::Forward,
+                editor.config.history_search_no_duplicates(),
+            )?,
             Cmd::TransposeChars => {
                 // Exchange the char before cursor with the character at cursor.
                 s.edit_transpose_chars()?
             }
-            #[cfg(unix)]
+            #[cfg(any(unix, windows))]
             Cmd::QuotedInsert => {
                 // Quoted insert
                 use tty::RawReader;
@@ -567,6 +1162,29 @@ fn readline_edit<H: Helper>(
                     s.edit_yank(&input_state, text, anchor, n)?
                 }
             }
+            Cmd::ViSearchHistory(dir) => {
+                if let Some(term) = vi_search_term(&mut rdr, &mut s, dir)? {
+                    // an empty term repeats the last search
+                    if !term.is_empty() {
+                        editor.vi_search = Some((term, dir));
+                    } else if let Some(last) = editor.vi_search.as_mut() {
+                        last.1 = dir;
+                    }
+                    if let Some((ref term, _)) = editor.vi_search {
+                        s.edit_history_search_term(term, dir)?
+                    }
+                }
+            }
+            Cmd::ViSearchAgain(opposite) => {
+                if let Some((ref term, dir)) = editor.vi_search {
+                    let dir = match (dir, opposite) {
+                        (Direction::Reverse, true) => Direction::Forward,
+                        (Direction::Forward, true) => Direction::Reverse,
+                        (dir, false) => dir,
+                    };
+                    s.edit_history_search_term(term, dir)?
+                }
+            }
             Cmd::ViYankTo(ref mvt) => {
                 if let Some(text) = s.line.copy(mvt) {
                     let mut kill_ring = editor.kill_ring.lock().unwrap();
@@ -603,6 +1221,10 @@ fn readline_edit<H: Helper>(
                 // move backwards one word
                 s.edit_move_to_prev_word(word_def, n)?
             }
+            Cmd::Move(Movement::BackwardWordEnd(n, word_def)) => {
+                // move backwards to the end of the previous word
+                s.edit_move_to_prev_word_end(word_def, n)?
+            }
             Cmd::CapitalizeWord => {
                 // capitalize word after point
                 s.edit_word(WordAction::CAPITALIZE)?
@@ -632,6 +1254,15 @@ fn readline_edit<H: Helper>(
                 // uppercase word after point
                 s.edit_word(WordAction::UPPERCASE)?
             }
+            Cmd::PasteClipboard => {
+                let text = editor
+                    .clipboard
+                    .as_ref()
+                    .and_then(|clipboard| clipboard.get_clipboard());
+                if let Some(text) = text {
+                    s.edit_yank(&input_state, &text, Anchor::Before, 1)?
+                }
+            }
             Cmd::YankPop => {
                 // yank-pop
                 let mut kill_ring = editor.kill_ring.lock().unwrap();
@@ -640,14 +1271,41 @@ fn readline_edit<H: Helper>(
                 }
             }
             Cmd::Move(Movement::ViCharSearch(n, cs)) => s.edit_move_to(cs, n)?,
+            Cmd::Move(Movement::ViMatchingBracket) => s.edit_move_to_matching_bracket()?,
             Cmd::Undo(n) => {
                 if s.changes.borrow_mut().undo(&mut s.line, n) {
                     s.refresh_line()?;
                 }
             }
             Cmd::Interrupt => {
+                if editor.config.drain_on_interrupt() {
+                    rdr.drain_input()?;
+                }
                 return Err(error::ReadlineError::Interrupted);
             }
+            Cmd::SetMark => {
+                s.line.set_mark();
+                s.refresh_line()?;
+            }
+            Cmd::ExchangePointAndMark => s.edit_exchange_point_and_mark()?,
+            Cmd::CopyRegion => match (s.line.region(), editor.clipboard.as_ref()) {
+                (Some(region), Some(clipboard)) => clipboard.set_clipboard(&s.line[region]),
+                _ => s.out.beep()?,
+            },
+            Cmd::ExternalEdit => {
+                original_mode.disable_raw_mode()?;
+                let edited = external_edit(s.line.as_str());
+                editor.term.enable_raw_mode()?; // TODO original_mode may have changed
+                match edited {
+                    Ok(text) => s.edit_replace_line(&text)?,
+                    Err(e) => {
+                        // keep the original line
+                        debug!(target: "rustyline", "external edit failed: {}", e);
+                        s.refresh_line()?;
+                    }
+                }
+                continue;
+            }
             #[cfg(unix)]
             Cmd::Suspend => {
                 original_mode.disable_raw_mode()?;
@@ -667,16 +1325,6 @@ fn readline_edit<H: Helper>(
     Ok(s.line.into_string())
 }
 
-struct Guard<'m>(&'m tty::Mode);
-
-#[allow(unused_must_use)]
-impl Drop for Guard<'_> {
-    fn drop(&mut self) {
-        let Guard(mode) = *self;
-        mode.disable_raw_mode();
-    }
-}
-
 /// Readline method that will enable RAW mode, call the `readline_edit()`
 /// method and disable raw mode
 fn readline_raw<H: Helper>(
@@ -684,22 +1332,78 @@ fn readline_raw<H: Helper>(
     initial: Option<(&str, &str)>,
     editor: &mut Editor<H>,
 ) -> Result<String> {
-    let original_mode = editor.term.enable_raw_mode()?;
-    let guard = Guard(&original_mode);
-    let user_input = readline_edit(prompt, initial, editor, &original_mode);
+    let original_mode = RawModeGuard::new(editor.term.enable_raw_mode()?);
+    let user_input = panic::catch_unwind(AssertUnwindSafe(|| {
+        readline_edit(prompt, initial, editor, &original_mode)
+    }));
+    let user_input = match user_input {
+        Ok(user_input) => user_input,
+        Err(payload) => {
+            // a callback (helper, handler) panicked: restore the terminal
+            // before propagating the panic
+            drop(original_mode);
+            let _ = editor.term.create_writer().write_and_flush(b"\n");
+            panic::resume_unwind(payload)
+        }
+    };
     if editor.config.auto_add_history() {
         if let Ok(ref line) = user_input {
             editor.add_history_entry(line.as_str());
         }
     }
-    drop(guard); // disable_raw_mode(original_mode)?;
-    match editor.config.output_stream() {
-        OutputStreamType::Stdout => writeln!(io::stdout())?,
-        OutputStreamType::Stderr => writeln!(io::stderr())?,
-    };
+    drop(original_mode); // disable_raw_mode(original_mode)
+    editor.term.create_writer().write_and_flush(b"\n")?;
     user_input
 }
 
+/// Edit `line` in `$VISUAL` or `$EDITOR` (`vi` / `notepad.exe` by default)
+/// through a temporary file.
+fn external_edit(line: &str) -> io::Result<String> {
+    use std::env;
+    use std::fs::{self, OpenOptions};
+    use std::process::Command;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let default_editor = if cfg!(windows) { "notepad.exe" } else { "vi" };
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| default_editor.to_owned());
+    let mut args = editor.split_whitespace();
+    let program = args.next().unwrap_or(default_editor);
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    let path = env::temp_dir().join(format!("rustyline-{}-{}.txt", std::process::id(), nanos));
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    let result = file
+        .write_all(line.as_bytes())
+        .and_then(|_| file.sync_all())
+        .and_then(|_| Command::new(program).args(args).arg(&path).status())
+        .and_then(|status| {
+            if status.success() {
+                fs::read_to_string(&path)
+            } else {
+                debug!(target: "rustyline", "{} exited with {}", program, status);
+                Err(io::ErrorKind::Other.into())
+            }
+        });
+    drop(file);
+    let _ = fs::remove_file(&path);
+    let mut text = result?;
+    if text.ends_with('\n') {
+        text.pop();
+        if text.ends_with('\r') {
+            text.pop();
+        }
+    }
+    Ok(text)
+}
+
 fn readline_direct() -> Result<String> {
     let mut line = String::new();
     if io::stdin().read_line(&mut line)? > 0 {
@@ -727,6 +1431,8 @@ impl<'h, H: ?Sized + Helper> Helper for &'h H {}
 pub struct Context<'h> {
     history: &'h History,
     history_index: usize,
+    quote_chars: &'static str,
+    quote: Option<char>,
 }
 
 impl<'h> Context<'h> {
@@ -735,6 +1441,8 @@ impl<'h> Context<'h> {
         Context {
             history,
             history_index: history.len(),
+            quote_chars: Config::default().completion_quote_characters(),
+            quote: None,
         }
     }
 
@@ -747,6 +1455,19 @@ impl<'h> Context<'h> {
     pub fn history_index(&self) -> usize {
         self.history_index
     }
+
+    /// The quote character (one of `Config::completion_quote_characters`)
+    /// of the quoted word being completed, if any: the completer should
+    /// escape its candidates accordingly.
+    pub fn quote(&self) -> Option<char> {
+        self.quote
+    }
+
+    /// Find the quote active at `pos` before asking for completions
+    fn update_quote(&mut self, line: &str, pos: usize) {
+        self.quote = completion::find_unclosed_quote_char(&line[..pos], self.quote_chars)
+            .map(|(_, quote)| quote);
+    }
 }
 
 /// Line editor
@@ -757,8 +1478,29 @@ pub struct Editor<H: Helper> {
     kill_ring: Arc<Mutex<KillRing>>,
     config: Config,
     custom_bindings: Arc<RwLock<HashMap<KeyPress, Cmd>>>,
+    custom_sequences: Arc<RwLock<SequenceTrie>>,
+    /// Last history file loaded or appended, used by `history_file_sync`
+    history_file: Option<PathBuf>,
+    /// History file loaded by the first `readline` and saved on drop
+    default_history_file: Option<PathBuf>,
+    /// Whether `default_history_file` has been loaded
+    default_history_loaded: bool,
+    resize_handler: Option<Box<dyn FnMut(WinSize) + Send + Sync>>,
+    /// Terminal size set by `resize`
+    size: Option<WinSize>,
+    custom_command_handler: Option<Box<CustomCommandHandler>>,
+    clipboard: Option<Box<dyn ClipboardBackend>>,
+    input_mode: InputMode,
+    mode_change_handler: Option<Box<dyn FnMut(InputMode) + Send + Sync>>,
+    /// Last vi-search term and direction, repeated by `n` and `N`
+    vi_search: Option<(String, Direction)>,
+    /// Commands queued by `execute_command`
+    pending_commands: VecDeque<Cmd>,
 }
 
+/// Callback run for `Cmd::Custom` commands
+type CustomCommandHandler = dyn FnMut(&str, &CustomPayload) -> Option<Cmd> + Send + Sync;
+
 #[allow(clippy::new_without_default)]
 impl<H: Helper> Editor<H> {
     /// Create an editor with the default configuration
@@ -768,19 +1510,26 @@ impl<H: Helper> Editor<H> {
 
     /// Create an editor with a specific configuration.
     pub fn with_config(config: Config) -> Self {
-        let term = Terminal::new(
-            config.color_mode(),
-            config.output_stream(),
-            config.tab_stop(),
-            config.bell_style(),
-        );
+        let term = Terminal::new(&config);
         Self {
             term,
-            history: History::with_config(config),
+            history: History::with_config(config.clone()),
             helper: None,
             kill_ring: Arc::new(Mutex::new(KillRing::new(60))),
+            clipboard: clipboard::default_backend(config.output_stream()),
+            input_mode: initial_input_mode(config.edit_mode()),
             config,
             custom_bindings: Arc::new(RwLock::new(HashMap::new())),
+            custom_sequences: Arc::new(RwLock::new(SequenceTrie::default())),
+            history_file: None,
+            default_history_file: None,
+            default_history_loaded: false,
+            resize_handler: None,
+            size: None,
+            custom_command_handler: None,
+            mode_change_handler: None,
+            vi_search: None,
+            pending_commands: VecDeque::new(),
         }
     }
 
@@ -794,6 +1543,54 @@ impl<H: Helper> Editor<H> {
         self.readline_with(prompt, None)
     }
 
+    /// Same as `readline` but returns `Ok(None)` immediately, without
+    /// entering raw mode, when no input is available on `stdin`.
+    ///
+    /// Otherwise, the buffered input is processed like `readline` does
+    /// (blocking until the line is accepted if the buffered input does not
+    /// end with a newline). As a terminal in canonical mode only makes input
+    /// available once Enter is pressed, the whole line is usually already
+    /// buffered.
+    pub fn readline_try(&mut self, prompt: &str) -> Result<Option<String>> {
+        let mut rdr = self
+            .term
+            .create_reader(&self.config, Arc::clone(&self.custom_sequences))?;
+        if !rdr.wait_for_input(0)? {
+            return Ok(None);
+        }
+        drop(rdr);
+        self.readline(prompt).map(Some)
+    }
+
+    /// Same as `readline` but reads from the terminal `input` and displays
+    /// the prompt and the line on `output` instead of stdin and the
+    /// configured output stream.
+    ///
+    /// Both file descriptors are owned by the caller: they are not closed.
+    /// `input` must be a terminal supported by rustyline, there is no
+    /// fallback to file-style interaction.
+    #[cfg(unix)]
+    pub fn readline_from_fds(
+        &mut self,
+        input: std::os::unix::io::RawFd,
+        output: std::os::unix::io::RawFd,
+        prompt: &str,
+    ) -> Result<String> {
+        let (prev_input, prev_output) = self.term.set_fds(input, OutputStreamType::Fd(output))?;
+        let user_input = if self.term.is_unsupported() || !self.term.is_stdin_tty() {
+            Ok(Err(io::Error::from_raw_os_error(libc::ENOTTY).into()))
+        } else {
+            panic::catch_unwind(AssertUnwindSafe(|| self.readline(prompt)))
+        };
+        let restored = self.term.set_fds(prev_input, prev_output);
+        let line = match user_input {
+            Ok(result) => result?,
+            Err(payload) => panic::resume_unwind(payload),
+        };
+        restored?;
+        Ok(line)
+    }
+
     /// This function behaves in the exact same manner as `readline`, except
     /// that it pre-populates the input area.
     ///
@@ -806,6 +1603,14 @@ impl<H: Helper> Editor<H> {
     }
 
     fn readline_with(&mut self, prompt: &str, initial: Option<(&str, &str)>) -> Result<String> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!(target: "rustyline", "readline", prompt).entered();
+        self.load_default_history()?;
+        if self.config.history_file_sync() {
+            if let Some(ref path) = self.history_file {
+                self.history.sync(path)?;
+            }
+        }
         if self.term.is_unsupported() {
             debug!(target: "rustyline", "unsupported terminal");
             // Write prompt and flush it to stdout
@@ -825,14 +1630,62 @@ impl<H: Helper> Editor<H> {
 
     /// Load the history from the specified file.
     pub fn load_history<P: AsRef<Path> + ?Sized>(&mut self, path: &P) -> Result<()> {
-        self.history.load(path)
+        self.history.load(path)?;
+        self.history_file = Some(path.as_ref().to_path_buf());
+        Ok(())
+    }
+
+    /// Set the history file loaded by the first call to `readline` (if it
+    /// exists) and saved when the editor is dropped.
+    ///
+    /// The history is not saved if it has not been loaded, so that the file
+    /// is not overwritten when `readline` has never been called or the file
+    /// could not be read (a warning is logged and `readline` proceeds with
+    /// an empty history).
+    pub fn set_default_history_file<P: AsRef<Path>>(&mut self, path: P) {
+        self.default_history_file = Some(path.as_ref().to_path_buf());
+        self.default_history_loaded = false;
+    }
+
+    fn load_default_history(&mut self) -> Result<()> {
+        if self.default_history_loaded {
+            return Ok(());
+        }
+        if let Some(path) = self.default_history_file.clone() {
+            match self.load_history(&path) {
+                Ok(()) => {}
+                // created on drop
+                Err(error::ReadlineError::Io(ref e)) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    // do not overwrite a file which could not be read
+                    warn!(target: "rustyline", "cannot load history from {:?}: {}", path, e);
+                    self.default_history_file = None;
+                }
+            }
+            self.default_history_loaded = true;
+        }
+        Ok(())
     }
 
     /// Save the history in the specified file.
+    ///
+    /// Oldest entries are dropped if the file would exceed
+    /// `max_history_file_size` or `max_history_len`.
     pub fn save_history<P: AsRef<Path> + ?Sized>(&self, path: &P) -> Result<()> {
         self.history.save(path)
     }
 
+    /// Append the entries added during this session (and not yet appended)
+    /// to the specified file.
+    ///
+    /// Unlike `save_history`, entries written by other processes are
+    /// preserved.
+    pub fn append_history<P: AsRef<Path> + ?Sized>(&mut self, path: &P) -> Result<()> {
+        self.history.append(path)?;
+        self.history_file = Some(path.as_ref().to_path_buf());
+        Ok(())
+    }
+
     /// Add a new entry in the history.
     pub fn add_history_entry<S: AsRef<str> + Into<String>>(&mut self, line: S) -> bool {
         self.history.add(line)
@@ -853,6 +1706,15 @@ impl<H: Helper> Editor<H> {
         &self.history
     }
 
+    /// Search the history for the entries matching `term`, without any
+    /// interaction with the terminal (unlike `Cmd::ReverseSearchHistory`).
+    ///
+    /// Entries containing `term` are matched, unless
+    /// `HistorySearcher::anchored` is used.
+    pub fn search_history<'a>(&'a self, term: &str, dir: Direction) -> HistorySearcher<'a> {
+        HistorySearcher::new(&self.history, term, dir)
+    }
+
     /// Register a callback function to be called for tab-completion
     /// or to show hints to the user at the right of the prompt.
     pub fn set_helper(&mut self, helper: Option<H>) {
@@ -869,6 +1731,103 @@ impl<H: Helper> Editor<H> {
         self.helper.as_ref()
     }
 
+    /// Register a callback function to be called with the new terminal size
+    /// when the terminal is resized during `readline`, once the line has been
+    /// redrawn.
+    pub fn set_resize_handler<F: FnMut(WinSize) + Send + Sync + 'static>(&mut self, handler: F) {
+        self.resize_handler = Some(Box::new(handler));
+    }
+
+    /// Render the line for a terminal of `cols` columns and `rows` rows,
+    /// for applications which manage the terminal size themselves.
+    ///
+    /// The actual size of the terminal is not queried anymore, even when it
+    /// is resized, until `reset_size` is called. The size is used from the
+    /// next `readline` call, which renders its line accordingly.
+    pub fn resize(&mut self, cols: usize, rows: usize) {
+        self.size = Some(WinSize { cols, rows });
+    }
+
+    /// Render the line for the actual size of the terminal again, after
+    /// `resize`.
+    pub fn reset_size(&mut self) {
+        self.size = None;
+    }
+
+    /// Register a callback function to be called with the name and the
+    /// payload of the `Cmd::Custom` commands bound with `bind_sequence`.
+    /// The command it returns, if any, is executed in their place.
+    pub fn set_custom_command_handler<F>(&mut self, handler: F)
+    where
+        F: FnMut(&str, &CustomPayload) -> Option<Cmd> + Send + Sync + 'static,
+    {
+        self.custom_command_handler = Some(Box::new(handler));
+    }
+
+    /// Queue `cmd` to be executed by the next `readline` call, as if it were
+    /// bound to a key pressed before any other input.
+    ///
+    /// Commands go through the same dispatch as key presses (including the
+    /// custom command handler), so `readline` returns the line if one of them
+    /// accepts it; the remaining commands are executed by the following call.
+    /// They are ignored when the input is not read from a terminal.
+    /// Nothing is returned here: outside of `readline`, there is no line
+    /// being edited.
+    pub fn execute_command(&mut self, cmd: Cmd) {
+        self.pending_commands.push_back(cmd);
+    }
+
+    /// Set the system clipboard used by `Cmd::CopyRegion` and
+    /// `Cmd::PasteClipboard`.
+    ///
+    /// By default, the Windows clipboard is used on Windows and, with the
+    /// `macos-clipboard` feature, the macOS one on macOS. When the terminal
+    /// supports it, the clipboard is also written with OSC 52.
+    pub fn set_clipboard_backend(&mut self, backend: Box<dyn ClipboardBackend>) {
+        self.clipboard = Some(backend);
+    }
+
+    /// Return the input mode of the line being edited, or of the last one.
+    pub fn current_input_mode(&self) -> InputMode {
+        self.input_mode
+    }
+
+    /// Register a callback function to be called with the new input mode
+    /// when it changes during `readline` (vi insert to normal mode for
+    /// example).
+    pub fn set_mode_change_handler<F: FnMut(InputMode) + Send + Sync + 'static>(
+        &mut self,
+        handler: F,
+    ) {
+        self.mode_change_handler = Some(Box::new(handler));
+    }
+
+    /// Clear the screen, like `Cmd::ClearScreen` does during `readline`.
+    ///
+    /// It is meant to be called between two `readline` calls: there is no
+    /// prompt nor line to redraw then. Nothing is done if the output is not
+    /// a supported terminal.
+    pub fn clear_screen(&mut self) -> Result<()> {
+        if self.term.is_unsupported() || !self.term.is_output_tty() {
+            return Ok(());
+        }
+        self.term.create_writer().clear_screen()
+    }
+
+    /// Return a handle to interrupt `readline` from another thread: the
+    /// pending (or next) call returns `ReadlineError::Interrupted`.
+    ///
+    /// Only supported when input is read from a terminal.
+    pub fn abort_handle(&self) -> AbortHandle {
+        self.term.abort_handle()
+    }
+
+    /// Read keys from and render to `term` instead of the real terminal.
+    #[cfg(all(feature = "mock", not(any(test, target_arch = "wasm32"))))]
+    pub fn set_mock_terminal(&mut self, term: MockTerminal) {
+        self.term = tty::Terminal::Mock(term);
+    }
+
     /// Bind a sequence to a command.
     pub fn bind_sequence(&mut self, key_seq: KeyPress, cmd: Cmd) -> Option<Cmd> {
         if let Ok(mut bindings) = self.custom_bindings.write() {
@@ -887,6 +1846,147 @@ impl<H: Helper> Editor<H> {
         }
     }
 
+    /// Name of the command bound to `key` (like `reverse-search-history`
+    /// for `Ctrl-R`), in Emacs or Vi insert mode depending on the
+    /// configuration.
+    ///
+    /// Returns `None` if `key` is not bound or is only a prefix (like
+    /// `Ctrl-X`).
+    pub fn describe_key(&self, key: KeyPress) -> Option<&str> {
+        let mut input_state = InputState::new(&self.config, Arc::clone(&self.custom_bindings));
+        match input_state.key_cmd(key) {
+            None | Some(Cmd::Unknown) => None,
+            Some(cmd) => Some(cmd.name()),
+        }
+    }
+
+    /// All keys bound to a command, with the name of this command (see
+    /// `describe_key`). Printable characters, which insert themselves, are
+    /// only listed when bound by `bind_sequence`.
+    pub fn all_keybindings(&self) -> Vec<(KeyPress, &str)> {
+        let mut keys = vec![
+            KeyPress::Backspace,
+            KeyPress::BackTab,
+            KeyPress::ControlDown,
+            KeyPress::ControlLeft,
+            KeyPress::ControlRight,
+            KeyPress::ControlUp,
+            KeyPress::Delete,
+            KeyPress::Down,
+            KeyPress::End,
+            KeyPress::Enter,
+            KeyPress::Esc,
+            KeyPress::Home,
+            KeyPress::Insert,
+            KeyPress::Left,
+            KeyPress::PageDown,
+            KeyPress::PageUp,
+            KeyPress::Right,
+            KeyPress::ShiftDown,
+            KeyPress::ShiftLeft,
+            KeyPress::ShiftRight,
+            KeyPress::ShiftUp,
+            KeyPress::Tab,
+            KeyPress::Up,
+        ];
+        keys.extend((1..=12).map(KeyPress::F));
+        keys.extend(" ABCDEFGHIJKLMNOPQRSTUVWXYZ_".chars().map(KeyPress::Ctrl));
+        keys.extend(('A'..='Z').map(KeyPress::CtrlShift));
+        keys.extend(
+            ('!'..='~')
+                .chain(vec!['\x07', '\x08', '\x7f'])
+                .map(KeyPress::Meta),
+        );
+        if let Ok(bindings) = self.custom_bindings.read() {
+            for key in bindings.keys() {
+                if !keys.contains(key) {
+                    keys.push(*key);
+                }
+            }
+        }
+        keys.into_iter()
+            .filter_map(|key| self.describe_key(key).map(|name| (key, name)))
+            .collect()
+    }
+
+    /// Bind a raw byte sequence (as sent by the terminal) to a command.
+    ///
+    /// Raw sequences are matched before being decoded into keys so that
+    /// sequences unknown to rustyline can be used.
+    /// Only supported by the unix backend (not with the `crossterm` feature).
+    /// Named `bind_raw_sequence` because `bind_sequence` binds a `KeyPress`.
+    ///
+    /// Returns an error if `seq` is empty or longer than 16 bytes.
+    pub fn bind_raw_sequence(
+        &mut self,
+        seq: Vec<u8>,
+        cmd: Cmd,
+    ) -> result::Result<Option<Cmd>, ConfigError> {
+        if seq.is_empty() || seq.len() > keymap::MAX_SEQUENCE_LEN {
+            return Err(ConfigError::RawSequenceLength(seq.len()));
+        }
+        if let Ok(mut sequences) = self.custom_sequences.write() {
+            Ok(sequences.insert(&seq, cmd))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Remove a binding for the given raw byte sequence.
+    pub fn unbind_raw_sequence(&mut self, seq: &[u8]) -> Option<Cmd> {
+        if let Ok(mut sequences) = self.custom_sequences.write() {
+            sequences.remove(seq)
+        } else {
+            None
+        }
+    }
+
+    /// Sequences bound with `bind_raw_sequence` (serializable with the
+    /// `with-serde` feature).
+    pub fn raw_bindings(&self) -> Vec<RawBinding> {
+        if let Ok(sequences) = self.custom_sequences.read() {
+            sequences.bindings()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Apply the settings and key bindings of a GNU Readline init file
+    /// (like `~/.inputrc`).
+    ///
+    /// Only a subset is supported: the `editing-mode`, `bell-style`,
+    /// `completion-query-items`, `keyseq-timeout` and `history-size`
+    /// variables, and keys bound to the Readline commands known by
+    /// rustyline (see `describe_key`). Escape sequences are bound with
+    /// `bind_raw_sequence`. As rustyline has a single set of custom bindings,
+    /// only `$if mode=...` blocks matching the current edit mode are
+    /// applied. Macros, `$include` and other unsupported lines are ignored.
+    pub fn load_config_from_inputrc(&mut self, path: &Path) -> Result<()> {
+        use crate::config::Configurer;
+        use crate::inputrc::Directive;
+
+        let content = std::fs::read_to_string(path)?;
+        for directive in inputrc::parse(&content, self.config.edit_mode()) {
+            match directive {
+                Directive::EditMode(edit_mode) => self.set_edit_mode(edit_mode),
+                Directive::BellStyle(bell_style) => self.set_bell_style(bell_style),
+                Directive::CompletionQueryItems(limit) => self.set_completion_prompt_limit(limit),
+                Directive::KeyseqTimeout(timeout_ms) => self
+                    .set_keyseq_timeout(timeout_ms)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+                Directive::HistorySize(max_size) => self.set_max_history_size(max_size),
+                Directive::BindKey(key, cmd) => {
+                    self.bind_sequence(key, cmd);
+                }
+                Directive::BindSequence(seq, cmd) => {
+                    self.bind_raw_sequence(seq, cmd)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// ```
     /// let mut rl = rustyline::Editor::<()>::new();
     /// for readline in rl.iter("> ") {
@@ -914,9 +2014,11 @@ impl<H: Helper> Editor<H> {
     }
 
     /// If output stream is a tty, this function returns its width and height as
-    /// a number of characters.
+    /// a number of characters (or the size set by `resize`).
     pub fn dimensions(&mut self) -> Option<(usize, usize)> {
-        if self.term.is_output_tty() {
+        if let Some(size) = self.size {
+            Some((size.cols, size.rows))
+        } else if self.term.is_output_tty() {
             let out = self.term.create_writer();
             Some((out.get_columns(), out.get_rows()))
         } else {
@@ -925,16 +2027,47 @@ impl<H: Helper> Editor<H> {
     }
 }
 
+/// Input mode in which `readline` starts
+fn initial_input_mode(edit_mode: EditMode) -> InputMode {
+    match edit_mode {
+        EditMode::Emacs => InputMode::Emacs,
+        EditMode::Vi => InputMode::ViInsert,
+    }
+}
+
 impl<H: Helper> config::Configurer for Editor<H> {
     fn config_mut(&mut self) -> &mut Config {
         &mut self.config
     }
 
+    /// Choose between Emacs or Vi mode, for the next `readline` calls: each
+    /// call starts in insert mode with no pending numeric argument or
+    /// operator.
+    fn set_edit_mode(&mut self, edit_mode: EditMode) {
+        self.config_mut().set_edit_mode(edit_mode);
+        self.input_mode = initial_input_mode(edit_mode);
+    }
+
     fn set_max_history_size(&mut self, max_size: usize) {
         self.config_mut().set_max_history_size(max_size);
         self.history.set_max_len(max_size);
     }
 
+    fn set_max_history_file_size(&mut self, max_size: Option<usize>) {
+        self.config_mut().set_max_history_file_size(max_size);
+        self.history.max_file_size = max_size;
+    }
+
+    fn set_max_history_len(&mut self, max_len: Option<usize>) {
+        self.config_mut().set_max_history_len(max_len);
+        self.history.max_file_len = max_len;
+    }
+
+    fn set_history_backup(&mut self, yes: bool) {
+        self.config_mut().set_history_backup(yes);
+        self.history.backup = yes;
+    }
+
     fn set_history_ignore_dups(&mut self, yes: bool) {
         self.config_mut().set_history_ignore_dups(yes);
         self.history.ignore_dups = yes;
@@ -945,9 +2078,39 @@ impl<H: Helper> config::Configurer for Editor<H> {
         self.history.ignore_space = yes;
     }
 
+    fn set_bell_style(&mut self, bell_style: config::BellStyle) {
+        self.config_mut().set_bell_style(bell_style);
+        self.term.update_config(&self.config);
+    }
+
     fn set_color_mode(&mut self, color_mode: ColorMode) {
         self.config_mut().set_color_mode(color_mode);
-        self.term.color_mode = color_mode;
+        self.term.update_config(&self.config);
+    }
+
+    fn set_tab_stop(&mut self, tab_stop: usize) {
+        self.config_mut().set_tab_stop(tab_stop);
+        self.term.update_config(&self.config);
+    }
+
+    fn set_incremental_render(&mut self, yes: bool) {
+        self.config_mut().set_incremental_render(yes);
+        self.term.update_config(&self.config);
+    }
+
+    fn set_hyperlinks_enabled(&mut self, yes: bool) {
+        self.config_mut().set_hyperlinks_enabled(yes);
+        self.term.update_config(&self.config);
+    }
+
+    fn set_enable_focus_events(&mut self, yes: bool) {
+        self.config_mut().set_enable_focus_events(yes);
+        self.term.update_config(&self.config);
+    }
+
+    fn set_enable_bracketed_paste(&mut self, yes: bool) {
+        self.config_mut().set_enable_bracketed_paste(yes);
+        self.term.update_config(&self.config);
     }
 }
 
@@ -960,6 +2123,19 @@ impl<H: Helper> fmt::Debug for Editor<H> {
     }
 }
 
+impl<H: Helper> Drop for Editor<H> {
+    fn drop(&mut self) {
+        if !self.default_history_loaded {
+            return;
+        }
+        if let Some(ref path) = self.default_history_file {
+            if let Err(e) = self.history.save(path) {
+                warn!(target: "rustyline", "cannot save history to {:?}: {}", path, e);
+            }
+        }
+    }
+}
+
 /// Edited lines iterator
 pub struct Iter<'a, H: Helper> {
     editor: &'a mut Editor<H>,
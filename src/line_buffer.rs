@@ -1,5 +1,6 @@
 //! Line buffer with current cursor position
-use crate::keymap::{At, CharSearch, Movement, RepeatCount, Word};
+use crate::keymap::{At, CharSearch, Movement, RepeatCount, TextObject, Word};
+use memchr::memchr;
 use std::cell::RefCell;
 use std::fmt;
 use std::iter;
@@ -20,6 +21,67 @@ pub enum WordAction {
     UPPERCASE,
 }
 
+/// How `LineBuffer::word_at_cursor` finds word boundaries
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WordBreakPolicy<'b> {
+    /// Words are delimited by whitespaces.
+    Whitespace,
+    /// Words are delimited by any of `break_chars` unless escaped by
+    /// `esc_char` (like `completion::extract_word`).
+    BreakChars {
+        break_chars: &'b [u8],
+        esc_char: Option<char>,
+    },
+}
+
+impl WordBreakPolicy<'_> {
+    fn is_break_char(&self, c: char) -> bool {
+        match *self {
+            WordBreakPolicy::Whitespace => c.is_whitespace(),
+            WordBreakPolicy::BreakChars { break_chars, .. } => {
+                c.is_ascii() && memchr(c as u8, break_chars).is_some()
+            }
+        }
+    }
+
+    fn esc_char(&self) -> Option<char> {
+        match *self {
+            WordBreakPolicy::Whitespace => None,
+            WordBreakPolicy::BreakChars { esc_char, .. } => esc_char,
+        }
+    }
+}
+
+/// Byte range `[start, end)` of the word around `pos` in `line`
+pub(crate) fn word_range(line: &str, pos: usize, policy: &WordBreakPolicy<'_>) -> (usize, usize) {
+    let esc_char = policy.esc_char();
+    let mut start = 0;
+    let mut chars = line[..pos].char_indices().rev().peekable();
+    while let Some((i, c)) = chars.next() {
+        if policy.is_break_char(c) {
+            if let (Some(esc_char), Some(&(_, prev))) = (esc_char, chars.peek()) {
+                if prev == esc_char {
+                    // escaped break char
+                    chars.next();
+                    continue;
+                }
+            }
+            start = i + c.len_utf8();
+            break;
+        }
+    }
+    let mut end = line.len();
+    let mut prev = line[..pos].chars().next_back();
+    for (i, c) in line[pos..].char_indices() {
+        if policy.is_break_char(c) && (esc_char.is_none() || prev != esc_char) {
+            end = pos + i;
+            break;
+        }
+        prev = Some(c);
+    }
+    (start, end)
+}
+
 /// Delete (kill) direction
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum Direction {
@@ -51,9 +113,11 @@ pub(crate) trait ChangeListener: DeleteListener {
 ///
 /// The methods do text manipulations or/and cursor movements.
 pub struct LineBuffer {
-    buf: String,      // Edited line buffer (rl_line_buffer)
-    pos: usize,       // Current cursor position (byte position) (rl_point)
-    can_growth: bool, // Whether to allow dynamic growth
+    buf: String,            // Edited line buffer (rl_line_buffer)
+    pos: usize,             // Current cursor position (byte position) (rl_point)
+    mark: Option<usize>,    // Mark position (byte position) (rl_mark)
+    can_growth: bool,       // Whether to allow dynamic growth
+    max_len: Option<usize>, // Maximum number of chars inserted
     dl: Option<Arc<Mutex<dyn DeleteListener>>>,
     cl: Option<Rc<RefCell<dyn ChangeListener>>>,
 }
@@ -73,7 +137,9 @@ impl LineBuffer {
         Self {
             buf: String::with_capacity(capacity),
             pos: 0,
+            mark: None,
             can_growth: false,
+            max_len: None,
             dl: None,
             cl: None,
         }
@@ -89,6 +155,33 @@ impl LineBuffer {
         !self.can_growth && new_len > self.buf.capacity()
     }
 
+    /// Set the maximum number of characters which can be inserted (see
+    /// `Config::max_line_length`).
+    pub(crate) fn set_max_len(&mut self, max_len: Option<usize>) {
+        self.max_len = max_len;
+    }
+
+    /// Tell if inserting `n` more characters would exceed the maximum length.
+    fn exceeds_max_len(&self, n: usize) -> bool {
+        matches!(self.max_len, Some(max) if self.buf.chars().count() + n > max)
+    }
+
+    /// Tell if the line is longer than `Config::max_line_length` (which is
+    /// possible when it comes from the history).
+    pub fn is_too_long(&self) -> bool {
+        self.exceeds_max_len(0)
+    }
+
+    /// Maximum number of characters of the text replacing `range`: the line
+    /// cannot become longer than the maximum length, or than it already is.
+    fn max_replacement_len(&self, range: &Range<usize>) -> Option<usize> {
+        self.max_len.map(|max| {
+            let removed = self.buf[range.clone()].chars().count();
+            let kept = self.buf.chars().count() - removed;
+            max.saturating_sub(kept).max(removed)
+        })
+    }
+
     #[cfg(test)]
     pub(crate) fn init(
         line: &str,
@@ -125,12 +218,76 @@ impl LineBuffer {
         self.pos
     }
 
+    /// Byte range `[start, end)` of the word around the cursor.
+    pub fn word_at_cursor(&self, policy: &WordBreakPolicy<'_>) -> (usize, usize) {
+        word_range(&self.buf, self.pos, policy)
+    }
+
+    /// Word around the cursor.
+    pub fn word_at_cursor_str(&self, policy: &WordBreakPolicy<'_>) -> &str {
+        let (start, end) = self.word_at_cursor(policy);
+        &self.buf[start..end]
+    }
+
     /// Set cursor position (byte position)
     pub fn set_pos(&mut self, pos: usize) {
         assert!(pos <= self.buf.len());
         self.pos = pos;
     }
 
+    /// Mark position (byte position), if any
+    pub fn mark(&self) -> Option<usize> {
+        self.mark
+    }
+
+    /// Set the mark at the current cursor position
+    pub fn set_mark(&mut self) {
+        self.mark = Some(self.pos);
+    }
+
+    /// Swap the cursor and the mark.
+    /// Return `false` if there is no mark.
+    pub fn exchange_point_and_mark(&mut self) -> bool {
+        if let Some(mark) = self.mark {
+            self.mark = Some(self.pos);
+            self.pos = mark;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Text range between the mark and the cursor, if any
+    pub fn region(&self) -> Option<Range<usize>> {
+        self.mark.map(|mark| {
+            if mark < self.pos {
+                mark..self.pos
+            } else {
+                self.pos..mark
+            }
+        })
+    }
+
+    /// Keep the mark on the same text when `len` bytes are inserted at `idx`.
+    fn shift_mark(&mut self, idx: usize, len: usize) {
+        if let Some(ref mut mark) = self.mark {
+            if *mark > idx {
+                *mark += len;
+            }
+        }
+    }
+
+    /// Keep the mark on the same text when `range` is removed.
+    fn unshift_mark(&mut self, range: &Range<usize>) {
+        if let Some(ref mut mark) = self.mark {
+            if *mark >= range.end {
+                *mark -= range.end - range.start;
+            } else if *mark > range.start {
+                *mark = range.start;
+            }
+        }
+    }
+
     /// Returns the length of this buffer, in bytes.
     pub fn len(&self) -> usize {
         self.buf.len()
@@ -142,7 +299,16 @@ impl LineBuffer {
     }
 
     /// Set line content (`buf`) and cursor position (`pos`).
+    /// `buf` is truncated to `Config::max_line_length`.
     pub fn update(&mut self, buf: &str, pos: usize) {
+        assert!(pos <= buf.len());
+        let buf = truncate_chars(buf, self.max_len);
+        self.update_overflowing(buf, pos.min(buf.len()));
+    }
+
+    /// Same as `update` but `buf` is kept even when it is longer than
+    /// `Config::max_line_length` (history entries, saved lines).
+    pub(crate) fn update_overflowing(&mut self, buf: &str, pos: usize) {
         assert!(pos <= buf.len());
         let end = self.len();
         self.drain(0..end, Direction::default());
@@ -158,15 +324,7 @@ impl LineBuffer {
             self.insert_str(0, buf);
             self.pos = pos;
         }
-    }
-
-    /// Returns the character at current cursor position.
-    pub(crate) fn grapheme_at_cursor(&self) -> Option<&str> {
-        if self.pos == self.buf.len() {
-            None
-        } else {
-            self.buf[self.pos..].graphemes(true).next()
-        }
+        self.mark = None;
     }
 
     /// Returns the position of the character just after the current cursor
@@ -202,12 +360,13 @@ impl LineBuffer {
     /// `true` when the character has been appended to the end of the line.
     pub fn insert(&mut self, ch: char, n: RepeatCount) -> Option<bool> {
         let shift = ch.len_utf8() * n;
-        if self.must_truncate(self.buf.len() + shift) {
+        if self.must_truncate(self.buf.len() + shift) || self.exceeds_max_len(n) {
             return None;
         }
         let push = self.pos == self.buf.len();
         if n == 1 {
             self.buf.insert(self.pos, ch);
+            self.shift_mark(self.pos, shift);
             for cl in &self.cl {
                 if let Ok(mut cl) = cl.try_borrow_mut() {
                     cl.insert_char(self.pos, ch);
@@ -228,7 +387,10 @@ impl LineBuffer {
     /// `true` when the character has been appended to the end of the line.
     pub fn yank(&mut self, text: &str, n: RepeatCount) -> Option<bool> {
         let shift = text.len() * n;
-        if text.is_empty() || self.must_truncate(self.buf.len() + shift) {
+        if text.is_empty()
+            || self.must_truncate(self.buf.len() + shift)
+            || self.exceeds_max_len(text.chars().count() * n)
+        {
             return None;
         }
         let push = self.pos == self.buf.len();
@@ -355,17 +517,19 @@ impl LineBuffer {
     }
 
     /// Exchange the char before cursor with the character at cursor.
+    /// At the end of the line, the last two characters are exchanged.
     pub fn transpose_chars(&mut self) -> bool {
-        if self.pos == 0 || self.buf.graphemes(true).count() < 2 {
+        if self.pos == 0 {
             return false;
         }
-        if self.pos == self.buf.len() {
-            self.move_backward(1);
-        }
-        let chars = self.delete(1).unwrap();
-        self.move_backward(1);
-        self.yank(&chars, 1);
-        self.move_forward(1);
+        let end = self.next_pos(1).unwrap_or(self.pos);
+        let mut gis = self.buf[..end].grapheme_indices(true).rev();
+        let (second, first) = match (gis.next(), gis.next()) {
+            (Some((second, _)), Some((first, _))) => (second, first),
+            _ => return false,
+        };
+        let swapped = [&self.buf[second..end], &self.buf[first..second]].concat();
+        self.replace(first..end, &swapped);
         true
     }
 
@@ -399,6 +563,40 @@ impl LineBuffer {
         Some(sow)
     }
 
+    /// Return the position of the end of the word before `pos` (or `0` if
+    /// there is none).
+    pub fn prev_word_end(&self, pos: usize, word_def: Word) -> usize {
+        let mut next = self.buf[pos..].graphemes(true).next().unwrap_or(" ");
+        for (i, grapheme) in self.buf[..pos].grapheme_indices(true).rev() {
+            if is_end_of_word(word_def, grapheme, next) {
+                return i;
+            }
+            next = grapheme;
+        }
+        0
+    }
+
+    fn prev_word_end_pos(&self, word_def: Word, n: RepeatCount) -> Option<usize> {
+        if self.pos == 0 {
+            return None;
+        }
+        let mut pos = self.pos;
+        for _ in 0..n {
+            pos = self.prev_word_end(pos, word_def);
+        }
+        Some(pos)
+    }
+
+    /// Moves the cursor to the end of previous word.
+    pub fn move_to_prev_word_end(&mut self, word_def: Word, n: RepeatCount) -> bool {
+        if let Some(pos) = self.prev_word_end_pos(word_def, n) {
+            self.pos = pos;
+            true
+        } else {
+            false
+        }
+    }
+
     /// Moves the cursor to the beginning of previous word.
     pub fn move_to_prev_word(&mut self, word_def: Word, n: RepeatCount) -> bool {
         if let Some(pos) = self.prev_word_pos(self.pos, word_def, n) {
@@ -585,50 +783,74 @@ impl LineBuffer {
         }
     }
 
+    /// Byte position of the first `target` character at or after `from`, or
+    /// of the character just before it when not `inclusive`.
+    pub fn search_char_forward(&self, from: usize, target: char, inclusive: bool) -> Option<usize> {
+        let mut bytes = [0; 4];
+        let target = &*target.encode_utf8(&mut bytes);
+        let mut prev = self.buf[..from]
+            .grapheme_indices(true)
+            .next_back()
+            .map(|(i, _)| i);
+        for (i, g) in self.buf[from..].grapheme_indices(true) {
+            if g == target {
+                return if inclusive { Some(from + i) } else { prev };
+            }
+            prev = Some(from + i);
+        }
+        None
+    }
+
+    /// Byte position of the last `target` character before `from`, or just
+    /// after it when not `inclusive`.
+    pub fn search_char_backward(
+        &self,
+        from: usize,
+        target: char,
+        inclusive: bool,
+    ) -> Option<usize> {
+        let mut bytes = [0; 4];
+        let target = &*target.encode_utf8(&mut bytes);
+        self.buf[..from]
+            .grapheme_indices(true)
+            .rev()
+            .find(|&(_, g)| g == target)
+            .map(|(i, g)| if inclusive { i } else { i + g.len() })
+    }
+
     fn search_char_pos(&self, cs: CharSearch, n: RepeatCount) -> Option<usize> {
-        let mut shift = 0;
-        let search_result = match cs {
-            CharSearch::Backward(c) | CharSearch::BackwardAfter(c) => self.buf[..self.pos]
-                .char_indices()
-                .rev()
-                .filter(|&(_, ch)| ch == c)
-                .take(n)
-                .last()
-                .map(|(i, _)| i),
-            CharSearch::Forward(c) | CharSearch::ForwardBefore(c) => {
-                if let Some(cc) = self.grapheme_at_cursor() {
-                    shift = self.pos + cc.len();
-                    if shift < self.buf.len() {
-                        self.buf[shift..]
-                            .char_indices()
-                            .filter(|&(_, ch)| ch == c)
-                            .take(n)
-                            .last()
-                            .map(|(i, _)| i)
-                    } else {
-                        None
+        // start of the search which finds the n-th (or last) occurrence
+        let mut found = None;
+        match cs {
+            CharSearch::Backward(c) | CharSearch::BackwardAfter(c) => {
+                let mut from = self.pos;
+                for _ in 0..n {
+                    match self.search_char_backward(from, c, true) {
+                        Some(pos) => {
+                            found = Some(from);
+                            from = pos;
+                        }
+                        None => break,
                     }
-                } else {
-                    None
                 }
+                let inclusive = matches!(cs, CharSearch::Backward(_));
+                self.search_char_backward(found?, c, inclusive)
             }
-        };
-        if let Some(pos) = search_result {
-            Some(match cs {
-                CharSearch::Backward(_) => pos,
-                CharSearch::BackwardAfter(c) => pos + c.len_utf8(),
-                CharSearch::Forward(_) => shift + pos,
-                CharSearch::ForwardBefore(_) => {
-                    shift + pos
-                        - self.buf[..shift + pos]
-                            .chars()
-                            .next_back()
-                            .unwrap()
-                            .len_utf8()
+            CharSearch::Forward(c) | CharSearch::ForwardBefore(c) => {
+                // the character at the cursor is skipped
+                let mut from = self.next_pos(1)?;
+                for _ in 0..n {
+                    match self.search_char_forward(from, c, true) {
+                        Some(pos) => {
+                            found = Some(from);
+                            from = pos + c.len_utf8();
+                        }
+                        None => break,
+                    }
                 }
-            })
-        } else {
-            None
+                let inclusive = matches!(cs, CharSearch::Forward(_));
+                self.search_char_forward(found?, c, inclusive)
+            }
         }
     }
 
@@ -757,7 +979,10 @@ impl LineBuffer {
 
     /// Replaces the content between [`start`..`end`] with `text`
     /// and positions the cursor to the end of text.
+    /// `text` is truncated when the line would become longer than
+    /// `Config::max_line_length`.
     pub fn replace(&mut self, range: Range<usize>, text: &str) {
+        let text = truncate_chars(text, self.max_replacement_len(&range));
         let start = range.start;
         for cl in &self.cl {
             if let Ok(mut cl) = cl.try_borrow_mut() {
@@ -765,7 +990,9 @@ impl LineBuffer {
             } // Ok: while undoing, cl is borrowed. And we want to ignore
               // changes while undoing.
         }
+        self.unshift_mark(&range);
         self.buf.drain(range);
+        self.shift_mark(start, text.len());
         if start == self.buf.len() {
             self.buf.push_str(text);
         } else {
@@ -774,6 +1001,43 @@ impl LineBuffer {
         self.pos = start + text.len();
     }
 
+    /// Swap the case of the character at `pos`.
+    /// Return the position of the next character (or `pos` at the end of the
+    /// line).
+    pub fn swap_case_at(&mut self, pos: usize) -> usize {
+        let c = match self.buf[pos..].chars().next() {
+            Some(c) => c,
+            None => return pos,
+        };
+        let swapped: String = if c.is_lowercase() {
+            c.to_uppercase().collect()
+        } else if c.is_uppercase() {
+            c.to_lowercase().collect()
+        } else {
+            return pos + c.len_utf8();
+        };
+        self.replace(pos..pos + c.len_utf8(), &swapped);
+        pos + swapped.len()
+    }
+
+    /// Same as `replace` but checks that `range` is valid: return `false`
+    /// and leave the line unchanged when it is out of bounds, not on char
+    /// boundaries or when maximum buffer size or line length would be
+    /// exceeded.
+    pub fn replace_range(&mut self, range: Range<usize>, text: &str) -> bool {
+        if range.start > range.end
+            || range.end > self.buf.len()
+            || !self.buf.is_char_boundary(range.start)
+            || !self.buf.is_char_boundary(range.end)
+            || self.must_truncate(self.buf.len() - range.len() + text.len())
+            || matches!(self.max_replacement_len(&range), Some(max) if text.chars().count() > max)
+        {
+            return false;
+        }
+        self.replace(range, text);
+        true
+    }
+
     /// Insert the `s`tring at the specified position.
     /// Return `true` if the text has been inserted at the end of the line.
     pub fn insert_str(&mut self, idx: usize, s: &str) -> bool {
@@ -783,6 +1047,7 @@ impl LineBuffer {
             } // Ok: while undoing, cl is borrowed. And we want to ignore
               // changes while undoing.
         }
+        self.shift_mark(idx, s.len());
         if idx == self.buf.len() {
             self.buf.push_str(s);
             true
@@ -792,6 +1057,109 @@ impl LineBuffer {
         }
     }
 
+    /// Return the position of the bracket matching the one at `pos`.
+    /// When there is no bracket at `pos`, the first bracket after `pos` on
+    /// the current line is used instead.
+    pub fn find_matching_bracket(&self, pos: usize) -> Option<usize> {
+        let line_end = self.buf[pos..].find('\n').map_or(self.buf.len(), |i| pos + i);
+        let (start, bracket) = self.buf[pos..line_end]
+            .char_indices()
+            .find(|&(_, c)| matching_bracket(c).is_some())?;
+        let start = pos + start;
+        let (matching, forward) = matching_bracket(bracket)?;
+        let mut depth = 0;
+        if forward {
+            for (i, c) in self.buf[start..].char_indices() {
+                if c == bracket {
+                    depth += 1;
+                } else if c == matching {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(start + i);
+                    }
+                }
+            }
+        } else {
+            for (i, c) in self.buf[..=start].char_indices().rev() {
+                if c == bracket {
+                    depth += 1;
+                } else if c == matching {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(i);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Range between the cursor and the matching bracket, both included
+    fn matching_bracket_range(&self) -> Option<Range<usize>> {
+        let pos = self.find_matching_bracket(self.pos)?;
+        let (start, end) = if pos < self.pos {
+            (pos, self.pos)
+        } else {
+            (self.pos, pos)
+        };
+        let end = end + self.buf[end..].chars().next().map_or(0, char::len_utf8);
+        Some(start..end)
+    }
+
+    /// Moves the cursor to the matching bracket.
+    pub fn move_to_matching_bracket(&mut self) -> bool {
+        if let Some(pos) = self.find_matching_bracket(self.pos) {
+            self.pos = pos;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Return the range of the vi text object `obj` around `pos`.
+    /// Nested delimiters are not taken into account.
+    pub fn text_object_range(&self, pos: usize, obj: TextObject) -> Option<Range<usize>> {
+        let (open, close) = obj.delimiters();
+        let (start, end) = if open == close {
+            // quotes are paired from the start of the line
+            let before = self.buf[..pos].matches(open).count();
+            if self.buf[pos..].starts_with(open) {
+                if before % 2 == 0 {
+                    (
+                        pos,
+                        pos + open.len_utf8() + self.buf[pos + open.len_utf8()..].find(close)?,
+                    )
+                } else {
+                    (self.buf[..pos].rfind(open)?, pos)
+                }
+            } else if before % 2 == 1 {
+                (
+                    self.buf[..pos].rfind(open)?,
+                    pos + self.buf[pos..].find(close)?,
+                )
+            } else {
+                return None;
+            }
+        } else {
+            let at = if self.buf[pos..].starts_with(open) {
+                pos + open.len_utf8()
+            } else {
+                pos
+            };
+            let start = self.buf[..at].rfind(open)?;
+            let end = start + open.len_utf8() + self.buf[start + open.len_utf8()..].find(close)?;
+            if end < pos {
+                return None;
+            }
+            (start, end)
+        };
+        if obj.inner {
+            Some(start + open.len_utf8()..end)
+        } else {
+            Some(start..end + close.len_utf8())
+        }
+    }
+
     /// Remove the specified `range` in the line.
     pub fn delete_range(&mut self, range: Range<usize>) {
         self.set_pos(range.start);
@@ -811,6 +1179,7 @@ impl LineBuffer {
             } // Ok: while undoing, cl is borrowed. And we want to ignore
               // changes while undoing.
         }
+        self.unshift_mark(&range);
         self.buf.drain(range)
     }
 
@@ -845,6 +1214,9 @@ impl LineBuffer {
                     Some(self.buf[self.pos..].to_owned())
                 }
             }
+            Movement::ViMatchingBracket => self
+                .matching_bracket_range()
+                .map(|range| self.buf[range].to_owned()),
             Movement::BackwardWord(n, word_def) => {
                 if let Some(pos) = self.prev_word_pos(self.pos, word_def, n) {
                     Some(self.buf[pos..self.pos].to_owned())
@@ -852,6 +1224,15 @@ impl LineBuffer {
                     None
                 }
             }
+            Movement::BackwardWordEnd(n, word_def) => {
+                if let Some(pos) = self.prev_word_end_pos(word_def, n) {
+                    // inclusive: the character under the cursor is included
+                    let end = self.next_pos(1).unwrap_or(self.pos);
+                    Some(self.buf[pos..end].to_owned())
+                } else {
+                    None
+                }
+            }
             Movement::ForwardWord(n, at, word_def) => {
                 if let Some(pos) = self.next_word_pos(self.pos, at, word_def, n) {
                     Some(self.buf[self.pos..pos].to_owned())
@@ -904,6 +1285,9 @@ impl LineBuffer {
                     None
                 }
             }
+            Movement::TextObject(obj) => self
+                .text_object_range(self.pos, obj)
+                .map(|range| self.buf[range].to_owned()),
         }
     }
 
@@ -943,6 +1327,18 @@ impl LineBuffer {
                 // kill `n` words backward (until start of word)
                 self.delete_prev_word(word_def, n)
             }
+            Movement::BackwardWordEnd(n, word_def) => {
+                // kill backward until the end of the `n`th previous word,
+                // including the character under the cursor
+                if let Some(pos) = self.prev_word_end_pos(word_def, n) {
+                    let end = self.next_pos(1).unwrap_or(self.pos);
+                    self.drain(pos..end, Direction::Backward);
+                    self.pos = pos;
+                    true
+                } else {
+                    false
+                }
+            }
             Movement::ForwardWord(n, at, word_def) => {
                 // kill `n` words forward (until start/end of word)
                 self.delete_word(at, word_def, n)
@@ -967,6 +1363,25 @@ impl LineBuffer {
             Movement::ViFirstPrint => {
                 false // TODO
             }
+            Movement::ViMatchingBracket => {
+                if let Some(range) = self.matching_bracket_range() {
+                    self.delete_range(range);
+                    true
+                } else {
+                    false
+                }
+            }
+            Movement::TextObject(obj) => match self.text_object_range(self.pos, obj) {
+                Some(range) => {
+                    if range.start < range.end {
+                        self.delete_range(range);
+                    } else {
+                        self.set_pos(range.start);
+                    }
+                    true
+                }
+                None => false,
+            },
         };
         if notify {
             if let Some(dl) = self.dl.as_ref() {
@@ -986,6 +1401,27 @@ impl Deref for LineBuffer {
     }
 }
 
+/// Return the first `max_len` characters of `s`
+fn truncate_chars(s: &str, max_len: Option<usize>) -> &str {
+    match max_len.and_then(|max| s.char_indices().nth(max)) {
+        Some((i, _)) => &s[..i],
+        None => s,
+    }
+}
+
+/// Return the bracket matching `c` and whether it is after `c`
+fn matching_bracket(c: char) -> Option<(char, bool)> {
+    match c {
+        '(' => Some((')', true)),
+        '[' => Some((']', true)),
+        '{' => Some(('}', true)),
+        ')' => Some(('(', false)),
+        ']' => Some(('[', false)),
+        '}' => Some(('{', false)),
+        _ => None,
+    }
+}
+
 fn is_start_of_word(word_def: Word, previous: &str, grapheme: &str) -> bool {
     (!is_word_char(word_def, previous) && is_word_char(word_def, grapheme))
         || (word_def == Word::Vi && !is_other_char(previous) && is_other_char(grapheme))
@@ -1011,8 +1447,11 @@ fn is_other_char(grapheme: &str) -> bool {
 
 #[cfg(test)]
 mod test {
-    use super::{ChangeListener, DeleteListener, Direction, LineBuffer, WordAction, MAX_LINE};
-    use crate::keymap::{At, CharSearch, Word};
+    use super::{
+        ChangeListener, DeleteListener, Direction, LineBuffer, WordAction, WordBreakPolicy,
+        MAX_LINE,
+    };
+    use crate::keymap::{At, CharSearch, TextObject, Word};
     use std::cell::RefCell;
     use std::rc::Rc;
 
@@ -1093,6 +1532,37 @@ mod test {
         assert_eq!(false, push);
     }
 
+    #[test]
+    fn max_len() {
+        let mut s = LineBuffer::with_capacity(MAX_LINE);
+        s.set_max_len(Some(3));
+        assert_eq!(Some(true), s.insert('α', 2));
+        assert_eq!(None, s.insert('β', 2));
+        assert_eq!(None, s.yank("βγ", 1));
+        assert_eq!(Some(true), s.yank("β", 1));
+        assert_eq!("ααβ", s.buf);
+        assert!(!s.is_too_long());
+
+        s.update("αβγδ", 8);
+        assert_eq!("αβγ", s.buf);
+        assert_eq!(6, s.pos);
+        s.replace(0..2, "ab");
+        assert_eq!("aβγ", s.buf);
+        s.replace(1..1, "b");
+        assert_eq!("aβγ", s.buf);
+        assert!(!s.replace_range(0..1, "ab"));
+        assert!(s.replace_range(0..1, "b"));
+        assert_eq!("bβγ", s.buf);
+
+        s.update_overflowing("abcd", 4);
+        assert!(s.is_too_long());
+        // a replacement cannot make a too long line longer
+        s.replace(0..1, "xy");
+        assert_eq!("xbcd", s.buf);
+        s.set_max_len(None);
+        assert!(!s.is_too_long());
+    }
+
     #[test]
     fn yank_after() {
         let mut s = LineBuffer::init("αß", 2, None);
@@ -1165,6 +1635,21 @@ mod test {
         cl.borrow().assert_deleted_str_eq("α");
     }
 
+    #[test]
+    fn delete_grapheme() {
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        let line = format!("a{}b", family);
+        let mut s = LineBuffer::init(&line, 1, None);
+        let chars = s.delete(1);
+        assert_eq!("ab", s.buf);
+        assert_eq!(Some(family.to_owned()), chars);
+
+        let mut s = LineBuffer::init(&line, 1 + family.len(), None);
+        assert!(s.backspace(1));
+        assert_eq!("ab", s.buf);
+        assert_eq!(1, s.pos);
+    }
+
     #[test]
     fn kill() {
         let cl = Listener::new();
@@ -1183,6 +1668,69 @@ mod test {
         cl.borrow().assert_deleted_str_eq("αß");
     }
 
+    #[test]
+    fn text_object_range() {
+        let inner = |delim| TextObject { inner: true, delim };
+        let around = |delim| TextObject {
+            inner: false,
+            delim,
+        };
+        let s = LineBuffer::init("a \"b c\" (d) \"e\"", 0, None);
+        assert_eq!(None, s.text_object_range(0, inner('"')));
+        assert_eq!(Some(3..6), s.text_object_range(2, inner('"')));
+        assert_eq!(Some(3..6), s.text_object_range(4, inner('"')));
+        assert_eq!(Some(2..7), s.text_object_range(6, around('"')));
+        assert_eq!(None, s.text_object_range(8, inner('"')));
+        assert_eq!(Some(9..10), s.text_object_range(8, inner('(')));
+        assert_eq!(Some(8..11), s.text_object_range(10, around('(')));
+        assert_eq!(None, s.text_object_range(11, inner('(')));
+        assert_eq!(Some(13..14), s.text_object_range(12, inner('"')));
+        assert_eq!(None, s.text_object_range(4, inner('[')));
+        // nested delimiters are not handled
+        let s = LineBuffer::init("(a (b) c)", 0, None);
+        assert_eq!(Some(1..5), s.text_object_range(1, inner('(')));
+    }
+
+    #[test]
+    fn swap_case_at() {
+        let mut s = LineBuffer::init("aB-ßc", 0, None);
+        assert_eq!(1, s.swap_case_at(0));
+        assert_eq!(2, s.swap_case_at(1));
+        assert_eq!(3, s.swap_case_at(2));
+        assert_eq!("Ab-ßc", s.buf);
+        assert_eq!(5, s.swap_case_at(3));
+        assert_eq!("Ab-SSc", s.buf);
+        assert_eq!(6, s.swap_case_at(6));
+    }
+
+    #[test]
+    fn prev_word_end() {
+        let mut s = LineBuffer::init("a ßc, d", 7, None);
+        assert_eq!(5, s.prev_word_end(7, Word::Vi));
+        assert_eq!(4, s.prev_word_end(5, Word::Vi));
+        assert_eq!(0, s.prev_word_end(4, Word::Vi));
+        assert_eq!(5, s.prev_word_end(7, Word::Big));
+        assert_eq!(0, s.prev_word_end(5, Word::Big));
+        assert_eq!(0, s.prev_word_end(0, Word::Vi));
+
+        let ok = s.move_to_prev_word_end(Word::Vi, 2);
+        assert_eq!(4, s.pos);
+        assert_eq!(true, ok);
+    }
+
+    #[test]
+    fn find_matching_bracket() {
+        let s = LineBuffer::init("f(a[0], {b}) (\n)", 0, None);
+        assert_eq!(Some(11), s.find_matching_bracket(1));
+        assert_eq!(Some(1), s.find_matching_bracket(11));
+        assert_eq!(Some(5), s.find_matching_bracket(3));
+        assert_eq!(Some(10), s.find_matching_bracket(8));
+        assert_eq!(Some(11), s.find_matching_bracket(0));
+        assert_eq!(Some(3), s.find_matching_bracket(4));
+        assert_eq!(Some(15), s.find_matching_bracket(13));
+        assert_eq!(None, s.find_matching_bracket(14));
+    }
+
     #[test]
     fn transpose() {
         let mut s = LineBuffer::init("aßc", 1, None);
@@ -1204,6 +1752,26 @@ mod test {
         assert_eq!("acß", s.buf);
         assert_eq!(4, s.pos);
         assert_eq!(true, ok);
+
+        s.buf = String::from("a");
+        s.pos = 1;
+        assert!(!s.transpose_chars());
+        assert_eq!("a", s.buf);
+    }
+
+    #[test]
+    fn transpose_multi_byte() {
+        for &(pos, line, new_pos) in &[
+            (0, "日本語", 0),
+            (3, "本日語", 6),
+            (6, "日語本", 9),
+            (9, "日語本", 9),
+        ] {
+            let mut s = LineBuffer::init("日本語", pos, None);
+            assert_eq!(pos != 0, s.transpose_chars());
+            assert_eq!(line, s.buf);
+            assert_eq!(new_pos, s.pos);
+        }
     }
 
     #[test]
@@ -1300,6 +1868,26 @@ mod test {
         assert_eq!(2, s.pos);
     }
 
+    #[test]
+    fn search_char() {
+        // 'e' followed by a combining acute accent is not an 'e'
+        let s = LineBuffer::init("aé日e\u{301}bé日a", 0, None);
+        assert_eq!(Some(0), s.search_char_forward(0, 'a', true));
+        assert_eq!(None, s.search_char_forward(0, 'a', false));
+        assert_eq!(Some(15), s.search_char_forward(1, 'a', true));
+        assert_eq!(Some(12), s.search_char_forward(1, 'a', false));
+        assert_eq!(Some(3), s.search_char_forward(0, '日', true));
+        assert_eq!(Some(1), s.search_char_forward(0, '日', false));
+        assert_eq!(None, s.search_char_forward(0, 'e', true));
+
+        assert_eq!(Some(12), s.search_char_backward(15, '日', true));
+        assert_eq!(Some(15), s.search_char_backward(15, '日', false));
+        assert_eq!(Some(10), s.search_char_backward(12, 'é', true));
+        assert_eq!(Some(12), s.search_char_backward(12, 'é', false));
+        assert_eq!(None, s.search_char_backward(12, 'e', true));
+        assert_eq!(None, s.search_char_backward(0, 'a', true));
+    }
+
     #[test]
     fn delete_prev_word() {
         let cl = Listener::new();
@@ -1504,6 +2092,29 @@ mod test {
         assert_eq!(2, s.pos);
     }
 
+    #[test]
+    fn mark() {
+        let mut s = LineBuffer::init("αß γδ", 2, None);
+        assert!(!s.exchange_point_and_mark());
+        s.set_mark();
+        s.move_end();
+        assert_eq!(Some(2..9), s.region());
+        s.insert_str(0, "ε");
+        assert_eq!(Some(4), s.mark());
+        s.delete_range(0..2);
+        assert_eq!(Some(2), s.mark());
+        s.move_end();
+        assert!(s.exchange_point_and_mark());
+        assert_eq!(2, s.pos());
+        assert_eq!(Some(9), s.mark());
+        s.delete_range(4..7);
+        assert_eq!(Some(6), s.mark());
+        s.delete_range(2..4);
+        assert_eq!(Some(4), s.mark());
+        s.update("new", 3);
+        assert_eq!(None, s.mark());
+    }
+
     #[test]
     fn edit_word() {
         let mut s = LineBuffer::init("a ßeta  c", 1, None);
@@ -1545,4 +2156,48 @@ mod test {
         let mut s = LineBuffer::init("ßeta / __", 9, None);
         assert!(!s.transpose_words(1));
     }
+
+    #[test]
+    fn replace_range() {
+        let mut s = LineBuffer::init("ßeta δelta", 0, None);
+        assert!(s.replace_range(0..5, "beta"));
+        assert_eq!("beta δelta", s.buf);
+        assert_eq!(4, s.pos);
+
+        assert!(!s.replace_range(5..6, "d"));
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = 6..5;
+        assert!(!s.replace_range(reversed, "d"));
+        assert!(!s.replace_range(5..12, "d"));
+        assert_eq!("beta δelta", s.buf);
+        assert_eq!(4, s.pos);
+
+        assert!(s.replace_range(5..7, "d"));
+        assert_eq!("beta delta", s.buf);
+        assert_eq!(6, s.pos);
+    }
+
+    #[test]
+    fn word_at_cursor() {
+        let s = LineBuffer::init("ls /usr/lo cal", 8, None);
+        assert_eq!((3, 10), s.word_at_cursor(&WordBreakPolicy::Whitespace));
+        assert_eq!(
+            "/usr/lo",
+            s.word_at_cursor_str(&WordBreakPolicy::Whitespace)
+        );
+
+        let s = LineBuffer::init("ls", 0, None);
+        assert_eq!("ls", s.word_at_cursor_str(&WordBreakPolicy::Whitespace));
+        let s = LineBuffer::init("ls ", 3, None);
+        assert_eq!("", s.word_at_cursor_str(&WordBreakPolicy::Whitespace));
+
+        let policy = WordBreakPolicy::BreakChars {
+            break_chars: b" ",
+            esc_char: Some('\\'),
+        };
+        let s = LineBuffer::init("ls /User\\ Information x", 12, None);
+        assert_eq!("/User\\ Information", s.word_at_cursor_str(&policy));
+        let s = LineBuffer::init("ls /User\\ Information x", 8, None);
+        assert_eq!("/User\\ Information", s.word_at_cursor_str(&policy));
+    }
 }
@@ -283,29 +283,26 @@ fn ctrl_t() {
 
 #[test]
 fn ctrl_u() {
-    for mode in &[EditMode::Emacs, EditMode::Vi] {
-        assert_cursor(
-            *mode,
-            ("start of line ", "end"),
-            &[KeyPress::Ctrl('U'), KeyPress::Enter],
-            ("", "end"),
-        );
-        assert_cursor(
-            *mode,
-            ("", "end"),
-            &[KeyPress::Ctrl('U'), KeyPress::Enter],
-            ("", "end"),
-        );
-        if *mode == EditMode::Vi {
-            // vi command mode
-            assert_cursor(
-                *mode,
-                ("start of line ", "end"),
-                &[KeyPress::Esc, KeyPress::Ctrl('U'), KeyPress::Enter],
-                ("", " end"),
-            );
-        }
-    }
+    // vi insert mode (universal-argument in emacs mode: see emacs.rs)
+    assert_cursor(
+        EditMode::Vi,
+        ("start of line ", "end"),
+        &[KeyPress::Ctrl('U'), KeyPress::Enter],
+        ("", "end"),
+    );
+    assert_cursor(
+        EditMode::Vi,
+        ("", "end"),
+        &[KeyPress::Ctrl('U'), KeyPress::Enter],
+        ("", "end"),
+    );
+    // vi command mode
+    assert_cursor(
+        EditMode::Vi,
+        ("start of line ", "end"),
+        &[KeyPress::Esc, KeyPress::Ctrl('U'), KeyPress::Enter],
+        ("", " end"),
+    );
 }
 
 #[cfg(unix)]
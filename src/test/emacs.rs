@@ -185,6 +185,79 @@ fn ctrl_t() {
     );*/
 }
 
+#[test]
+fn ctrl_u() {
+    assert_cursor(
+        EditMode::Emacs,
+        ("", "Hello, world"),
+        &[KeyPress::Ctrl('U'), KeyPress::Delete, KeyPress::Enter],
+        ("", "o, world"),
+    );
+    assert_cursor(
+        EditMode::Emacs,
+        ("", ""),
+        &[
+            KeyPress::Ctrl('U'),
+            KeyPress::Ctrl('U'),
+            KeyPress::Char('a'),
+            KeyPress::Enter,
+        ],
+        ("aaaaaaaaaaaaaaaa", ""),
+    );
+    assert_cursor(
+        EditMode::Emacs,
+        ("", "Hello, world"),
+        &[
+            KeyPress::Ctrl('U'),
+            KeyPress::Char('3'),
+            KeyPress::Ctrl('F'),
+            KeyPress::Enter,
+        ],
+        ("Hel", "lo, world"),
+    );
+}
+
+#[test]
+fn ctrl_x_backspace() {
+    assert_cursor(
+        EditMode::Emacs,
+        ("start of line ", "end"),
+        &[KeyPress::Ctrl('X'), KeyPress::Backspace, KeyPress::Enter],
+        ("", "end"),
+    );
+}
+
+#[test]
+fn ctrl_x_ctrl_x() {
+    assert_cursor(
+        EditMode::Emacs,
+        ("Hello, ", "world"),
+        &[
+            KeyPress::Ctrl(' '),
+            KeyPress::Ctrl('A'),
+            KeyPress::Ctrl('X'),
+            KeyPress::Ctrl('X'),
+            KeyPress::Enter,
+        ],
+        ("Hello, ", "world"),
+    );
+    assert_cursor(
+        EditMode::Emacs,
+        ("Hello, ", "world"),
+        &[
+            KeyPress::Ctrl(' '),
+            KeyPress::Ctrl('A'),
+            KeyPress::Char('>'),
+            KeyPress::Ctrl('X'),
+            KeyPress::Ctrl('X'),
+            KeyPress::Ctrl('X'),
+            KeyPress::Ctrl('X'),
+            KeyPress::Enter,
+        ],
+        (">", "Hello, world"),
+    );
+}
+
 #[test]
 fn ctrl_x_ctrl_u() {
     assert_cursor(
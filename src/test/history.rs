@@ -236,3 +236,77 @@ fn meta_gt() {
         ("a", ""),
     );
 }
+
+#[test]
+fn meta_dot() {
+    assert_history(
+        EditMode::Emacs,
+        &["ls -l foo", "cat bar"],
+        &[KeyPress::Meta('.'), KeyPress::Enter],
+        "",
+        ("bar", ""),
+    );
+    assert_history(
+        EditMode::Emacs,
+        &["ls -l foo", "cat bar"],
+        &[KeyPress::Meta('.'), KeyPress::Meta('.'), KeyPress::Enter],
+        "",
+        ("foo", ""),
+    );
+    assert_history(
+        EditMode::Emacs,
+        &["ls -l foo", "cat bar"],
+        &[
+            KeyPress::Char('a'),
+            KeyPress::Meta('.'),
+            KeyPress::Ctrl('_'),
+            KeyPress::Enter,
+        ],
+        "",
+        ("a", ""),
+    );
+    assert_history(
+        EditMode::Emacs,
+        &["ls -l foo"],
+        &[KeyPress::Meta('1'), KeyPress::Meta('.'), KeyPress::Enter],
+        "",
+        ("-l", ""),
+    );
+    assert_history(
+        EditMode::Emacs,
+        &["ls -l foo"],
+        &[
+            KeyPress::Meta('-'),
+            KeyPress::Meta('2'),
+            KeyPress::Meta('.'),
+            KeyPress::Enter,
+        ],
+        "",
+        ("-l", ""),
+    );
+}
+
+#[test]
+fn history_search_no_duplicates() {
+    use crate::config::Config;
+    use crate::keymap::Cmd;
+    use crate::Editor;
+    for &no_duplicates in &[false, true] {
+        let config = Config::builder()
+            .history_search_no_duplicates(no_duplicates)
+            .build();
+        let mut editor = Editor::<()>::with_config(config);
+        editor.bind_sequence(KeyPress::PageUp, Cmd::HistorySearchBackward);
+        editor.term.keys.extend(&[
+            KeyPress::Char('l'),
+            KeyPress::Char('s'),
+            KeyPress::PageUp,
+            KeyPress::Enter,
+        ]);
+        for entry in &["ls", "ls -l", "ls"] {
+            editor.history.add(*entry);
+        }
+        let expected = if no_duplicates { "ls -l" } else { "ls" };
+        assert_eq!(expected, editor.readline("").unwrap());
+    }
+}
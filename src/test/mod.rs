@@ -1,13 +1,14 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 use std::vec::IntoIter;
 
-use crate::completion::Completer;
-use crate::config::{Config, EditMode};
+use crate::completion::{Candidate, Completer};
+use crate::config::{CompletionType, Config, EditMode};
 use crate::edit::init_state;
 use crate::highlight::Highlighter;
-use crate::hint::Hinter;
-use crate::keymap::{Cmd, InputState};
+use crate::hint::{Hinter, StyledHint};
+use crate::keymap::{Cmd, InputState, Movement, RawBinding};
 use crate::keys::KeyPress;
 use crate::tty::Sink;
 use crate::validate::Validator;
@@ -61,6 +62,897 @@ fn complete_line() {
     assert_eq!(4, s.line.pos());
 }
 
+/// Completer which only completes what it has prefetched
+#[derive(Default)]
+struct PrefetchCompleter {
+    prefetched: RefCell<Option<String>>,
+}
+impl Completer for PrefetchCompleter {
+    type Candidate = String;
+
+    fn completion_started(&self, line: &str, pos: usize) {
+        *self.prefetched.borrow_mut() = Some(line[..pos].to_owned() + "t");
+    }
+
+    fn complete(&self, _: &str, _: usize, _: &Context<'_>) -> Result<(usize, Vec<String>)> {
+        Ok((0, self.prefetched.borrow_mut().take().into_iter().collect()))
+    }
+}
+
+impl Helper for PrefetchCompleter {}
+impl Hinter for PrefetchCompleter {}
+impl Highlighter for PrefetchCompleter {}
+impl Validator for PrefetchCompleter {}
+
+#[test]
+fn completion_started() {
+    let mut out = Sink::new();
+    let history = crate::history::History::new();
+    let helper = Some(PrefetchCompleter::default());
+    let mut s = init_state(&mut out, "rus", 3, helper.as_ref(), &history);
+    let config = Config::default();
+    let mut input_state = InputState::new(&config, Arc::new(RwLock::new(HashMap::new())));
+    let keys = vec![KeyPress::Enter];
+    let mut rdr: IntoIter<KeyPress> = keys.into_iter();
+    let cmd = super::complete_line(&mut rdr, &mut s, &mut input_state, &config).unwrap();
+    assert_eq!(Some(Cmd::AcceptLine), cmd);
+    assert_eq!("rust", s.line.as_str());
+}
+
+/// Completer of the last segment of a dotted path
+struct PathCompleter;
+impl Completer for PathCompleter {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> Result<(usize, Vec<String>)> {
+        let segment = line[..pos].rsplit('.').next().unwrap();
+        Ok((0, vec![segment.to_owned() + "t"]))
+    }
+
+    fn custom_word_break(&self, line: &str, pos: usize) -> Option<usize> {
+        line[..pos].rfind('.').map(|i| i + 1)
+    }
+}
+
+impl Helper for PathCompleter {}
+impl Hinter for PathCompleter {}
+impl Highlighter for PathCompleter {}
+impl Validator for PathCompleter {}
+
+#[test]
+fn custom_word_break() {
+    let mut out = Sink::new();
+    let history = crate::history::History::new();
+    let helper = Some(PathCompleter);
+    let mut s = init_state(&mut out, "a.rus", 5, helper.as_ref(), &history);
+    let config = Config::default();
+    let mut input_state = InputState::new(&config, Arc::new(RwLock::new(HashMap::new())));
+    let keys = vec![KeyPress::Enter];
+    let mut rdr: IntoIter<KeyPress> = keys.into_iter();
+    let cmd = super::complete_line(&mut rdr, &mut s, &mut input_state, &config).unwrap();
+    assert_eq!(Some(Cmd::AcceptLine), cmd);
+    assert_eq!("a.rust", s.line.as_str());
+}
+
+struct QuoteCompleter;
+impl Completer for QuoteCompleter {
+    type Candidate = String;
+
+    fn complete(&self, _line: &str, pos: usize, ctx: &Context<'_>) -> Result<(usize, Vec<String>)> {
+        let quote = ctx
+            .quote()
+            .map_or("none".to_owned(), |quote| quote.to_string());
+        Ok((pos, vec![quote]))
+    }
+}
+
+impl Helper for QuoteCompleter {}
+impl Hinter for QuoteCompleter {}
+impl Highlighter for QuoteCompleter {}
+impl Validator for QuoteCompleter {}
+
+#[test]
+fn completion_quote_characters() {
+    let keys = [
+        KeyPress::Char('\''),
+        KeyPress::Tab,
+        KeyPress::Char(' '),
+        KeyPress::Char('`'),
+        KeyPress::Tab,
+        KeyPress::Enter,
+    ];
+    let config = Config::builder().completion_quote_characters("`").build();
+    let mut editor = Editor::with_config(config);
+    editor.set_helper(Some(QuoteCompleter));
+    editor.term.keys.extend(keys.iter().cloned());
+    assert_eq!("'none ``", editor.readline(">>").unwrap());
+}
+
+struct Described(&'static str, &'static str);
+impl Candidate for Described {
+    fn display(&self) -> &str {
+        self.0
+    }
+
+    fn replacement(&self) -> &str {
+        self.0
+    }
+
+    fn description(&self) -> Option<&str> {
+        Some(self.1)
+    }
+}
+
+struct MenuCompleter;
+impl Completer for MenuCompleter {
+    type Candidate = Box<dyn Candidate>;
+
+    fn complete(
+        &self,
+        _line: &str,
+        _pos: usize,
+        _ctx: &Context<'_>,
+    ) -> Result<(usize, Vec<Box<dyn Candidate>>)> {
+        Ok((
+            0,
+            vec![
+                Box::new("rust".to_owned()),
+                Box::new(Described("rusty", "adjective")),
+                Box::new(Described("rustyline", "readline implementation in Rust")),
+            ],
+        ))
+    }
+}
+
+impl Helper for MenuCompleter {}
+impl Hinter for MenuCompleter {}
+impl Highlighter for MenuCompleter {}
+impl Validator for MenuCompleter {}
+
+#[test]
+fn complete_menu() {
+    let mut out = Sink::new();
+    let history = crate::history::History::new();
+    let helper = Some(MenuCompleter);
+    let mut s = init_state(&mut out, "rus", 3, helper.as_ref(), &history);
+    let config = Config::builder()
+        .completion_type(CompletionType::Menu)
+        .completion_menu_rows(2)
+        .build();
+    let mut input_state = InputState::new(&config, Arc::new(RwLock::new(HashMap::new())));
+    let keys = vec![KeyPress::Down, KeyPress::Down, KeyPress::Enter];
+    let mut rdr: IntoIter<KeyPress> = keys.into_iter();
+    let cmd = super::complete_line(&mut rdr, &mut s, &mut input_state, &config).unwrap();
+    assert_eq!(None, cmd);
+    assert_eq!("rustyline", s.line.as_str());
+    assert_eq!(9, s.line.pos());
+
+    let keys = vec![KeyPress::Up, KeyPress::Esc];
+    let mut rdr: IntoIter<KeyPress> = keys.into_iter();
+    let cmd = super::complete_line(&mut rdr, &mut s, &mut input_state, &config).unwrap();
+    assert_eq!(None, cmd);
+    assert_eq!("rustyline", s.line.as_str());
+}
+
+#[test]
+fn complete_cycle() {
+    let config = Config::builder()
+        .completion_type(CompletionType::Cycle)
+        .build();
+    let mut editor = Editor::with_config(config);
+    editor.set_helper(Some(MenuCompleter));
+    editor.term.keys.extend(
+        [
+            KeyPress::Char('r'),
+            KeyPress::Char('u'),
+            KeyPress::Char('s'),
+            KeyPress::Tab,
+            KeyPress::Enter,
+        ]
+        .iter()
+        .cloned(),
+    );
+    assert_eq!("rust", editor.readline(">>").unwrap());
+
+    editor.term.keys.clear();
+    editor.term.keys.extend(
+        [
+            KeyPress::Tab,
+            KeyPress::Tab,
+            KeyPress::Tab,
+            KeyPress::Tab,
+            KeyPress::Enter,
+        ]
+        .iter()
+        .cloned(),
+    );
+    assert_eq!("rustyline", editor.readline(">>").unwrap());
+
+    editor.term.keys.clear();
+    editor.term.keys.extend(
+        [
+            KeyPress::Tab,
+            KeyPress::Tab,
+            KeyPress::BackTab,
+            KeyPress::BackTab,
+            KeyPress::Enter,
+        ]
+        .iter()
+        .cloned(),
+    );
+    assert_eq!("rusty", editor.readline(">>").unwrap());
+}
+
+#[cfg(not(all(unix, feature = "with-fuzzy")))]
+#[test]
+fn complete_fuzzy() {
+    let mut out = Sink::new();
+    let history = crate::history::History::new();
+    let helper = Some(MenuCompleter);
+    let mut s = init_state(&mut out, "rsl", 3, helper.as_ref(), &history);
+    let config = Config::builder()
+        .completion_type(CompletionType::Fuzzy)
+        .build();
+    let mut input_state = InputState::new(&config, Arc::new(RwLock::new(HashMap::new())));
+    let keys = vec![KeyPress::Enter];
+    let mut rdr: IntoIter<KeyPress> = keys.into_iter();
+    let cmd = super::complete_line(&mut rdr, &mut s, &mut input_state, &config).unwrap();
+    assert_eq!(None, cmd);
+    assert_eq!("rustyline", s.line.as_str());
+
+    s.line.update("rst", 3);
+    let keys = vec![KeyPress::Down, KeyPress::Enter];
+    let mut rdr: IntoIter<KeyPress> = keys.into_iter();
+    let cmd = super::complete_line(&mut rdr, &mut s, &mut input_state, &config).unwrap();
+    assert_eq!(None, cmd);
+    assert_eq!("rusty", s.line.as_str());
+
+    // invalid start (after the cursor)
+    let mut rdr: IntoIter<KeyPress> = vec![KeyPress::Enter].into_iter();
+    let helper = Some(AfterCursorCompleter);
+    let mut s = init_state(&mut out, "rst", 1, helper.as_ref(), &history);
+    let cmd = super::complete_line(&mut rdr, &mut s, &mut input_state, &config).unwrap();
+    assert_eq!(None, cmd);
+    assert_eq!("rst", s.line.as_str());
+}
+
+struct AfterCursorCompleter;
+impl Completer for AfterCursorCompleter {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        _pos: usize,
+        _ctx: &Context<'_>,
+    ) -> Result<(usize, Vec<String>)> {
+        Ok((line.len(), vec!["rust".to_owned()]))
+    }
+}
+impl Helper for AfterCursorCompleter {}
+impl Hinter for AfterCursorCompleter {}
+impl Highlighter for AfterCursorCompleter {}
+impl Validator for AfterCursorCompleter {}
+
+#[cfg(feature = "tokio")]
+struct AsyncMenuCompleter;
+#[cfg(feature = "tokio")]
+impl Completer for AsyncMenuCompleter {
+    type Candidate = String;
+
+    fn async_completer(&self) -> Option<&dyn crate::completion::AsyncCompleter> {
+        Some(self)
+    }
+}
+#[cfg(feature = "tokio")]
+impl crate::completion::AsyncCompleter for AsyncMenuCompleter {
+    fn complete_async<'a>(
+        &'a self,
+        line: &'a str,
+        _pos: usize,
+    ) -> crate::completion::BoxFuture<'a, Result<(usize, Vec<crate::completion::Pair>)>> {
+        Box::pin(async move {
+            if line == "slow" {
+                tokio::time::delay_for(std::time::Duration::from_secs(60)).await;
+            } else if line == "delayed" {
+                tokio::time::delay_for(std::time::Duration::from_millis(250)).await;
+            }
+            let candidates: &[&str] = if line == "delayed" {
+                &["delayed!"]
+            } else {
+                &["rust", "rustyline"]
+            };
+            let candidates = candidates
+                .iter()
+                .map(|s| crate::completion::Pair {
+                    display: s.to_string(),
+                    replacement: s.to_string(),
+                })
+                .collect();
+            Ok((0, candidates))
+        })
+    }
+}
+#[cfg(feature = "tokio")]
+impl Helper for AsyncMenuCompleter {}
+#[cfg(feature = "tokio")]
+impl Hinter for AsyncMenuCompleter {}
+#[cfg(feature = "tokio")]
+impl Highlighter for AsyncMenuCompleter {}
+#[cfg(feature = "tokio")]
+impl Validator for AsyncMenuCompleter {}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn complete_async() {
+    let mut out = Sink::new();
+    let history = crate::history::History::new();
+    let helper = Some(AsyncMenuCompleter);
+    let mut s = init_state(&mut out, "rus", 3, helper.as_ref(), &history);
+    let config = Config::default();
+    let mut input_state = InputState::new(&config, Arc::new(RwLock::new(HashMap::new())));
+    let keys = vec![KeyPress::Down, KeyPress::Enter];
+    let mut rdr: IntoIter<KeyPress> = keys.into_iter();
+    let cmd = super::complete_line(&mut rdr, &mut s, &mut input_state, &config).unwrap();
+    assert_eq!(None, cmd);
+    assert_eq!("rustyline", s.line.as_str());
+
+    // a key pressed before the candidates are available cancels the completion
+    s.line.update("slow", 4);
+    let keys = vec![KeyPress::Char('a')];
+    let mut rdr: IntoIter<KeyPress> = keys.into_iter();
+    let cmd = super::complete_line(&mut rdr, &mut s, &mut input_state, &config).unwrap();
+    assert_eq!(None, cmd);
+    assert_eq!("slow", s.line.as_str());
+    assert_eq!(1, rdr.len());
+
+    // the timer driver runs while waiting
+    s.line.update("delayed", 7);
+    let mut rdr: IntoIter<KeyPress> = Vec::new().into_iter();
+    let cmd = super::complete_line(&mut rdr, &mut s, &mut input_state, &config).unwrap();
+    assert_eq!(None, cmd);
+    assert_eq!("delayed!", s.line.as_str());
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn complete_async_in_runtime() {
+    let mut runtime = tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let mut out = Sink::new();
+        let history = crate::history::History::new();
+        let helper = Some(AsyncMenuCompleter);
+        let mut s = init_state(&mut out, "delayed", 7, helper.as_ref(), &history);
+        let config = Config::default();
+        let mut input_state = InputState::new(&config, Arc::new(RwLock::new(HashMap::new())));
+        let keys = vec![KeyPress::Down, KeyPress::Enter];
+        let mut rdr: IntoIter<KeyPress> = keys.into_iter();
+        let result = super::complete_line(&mut rdr, &mut s, &mut input_state, &config);
+        assert!(result.is_err());
+        assert_eq!("delayed", s.line.as_str());
+    });
+}
+
+struct StyledHinter;
+impl Completer for StyledHinter {
+    type Candidate = String;
+}
+impl Hinter for StyledHinter {
+    fn hint_styled(&self, line: &str, _pos: usize, _ctx: &Context<'_>) -> Option<StyledHint> {
+        if line == "ru" {
+            Some(StyledHint::styled(
+                "\x1b[1mst\x1b[0m".to_owned(),
+                "st".to_owned(),
+            ))
+        } else {
+            None
+        }
+    }
+}
+impl Highlighter for StyledHinter {}
+impl Validator for StyledHinter {}
+impl Helper for StyledHinter {}
+
+#[test]
+fn complete_styled_hint() {
+    let mut editor = Editor::new();
+    editor.set_helper(Some(StyledHinter));
+    editor.term.keys.extend(
+        [
+            KeyPress::Char('r'),
+            KeyPress::Char('u'),
+            KeyPress::Right,
+            KeyPress::Enter,
+        ]
+        .iter()
+        .cloned(),
+    );
+    assert_eq!("rust", editor.readline(">>").unwrap());
+}
+
+#[test]
+fn readline_try() {
+    let mut editor = init_editor(EditMode::Emacs, &[]);
+    assert_eq!(None, editor.readline_try(">>").unwrap());
+
+    editor.term.keys.extend([KeyPress::Char('a'), KeyPress::Enter].iter().cloned());
+    assert_eq!(Some("a".to_owned()), editor.readline_try(">>").unwrap());
+}
+
+#[test]
+fn abort_handle() {
+    use crate::error::ReadlineError;
+    let mut editor = init_editor(EditMode::Emacs, &[KeyPress::Enter]);
+    let handle = editor.abort_handle();
+    std::thread::spawn(move || handle.abort()).join().unwrap();
+    match editor.readline(">>") {
+        Err(ReadlineError::Interrupted) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+    // the abort request is consumed
+    assert_eq!("", editor.readline(">>").unwrap());
+}
+
+#[test]
+fn custom_command() {
+    use crate::keymap::CustomPayload;
+    let keys = [KeyPress::F(5), KeyPress::F(6), KeyPress::Enter];
+    let mut editor = init_editor(EditMode::Emacs, &keys);
+    editor.bind_sequence(
+        KeyPress::F(5),
+        Cmd::Custom("insert".to_owned(), CustomPayload::new("hello")),
+    );
+    editor.bind_sequence(
+        KeyPress::F(6),
+        Cmd::Custom("unknown".to_owned(), CustomPayload::new(())),
+    );
+    // without handler, custom commands are ignored
+    assert_eq!("", editor.readline(">>").unwrap());
+
+    editor.set_custom_command_handler(|name, payload| match name {
+        "insert" => payload
+            .downcast_ref::<&str>()
+            .map(|text| Cmd::Insert(1, (*text).to_owned())),
+        _ => None,
+    });
+    assert_eq!("hello", editor.readline(">>").unwrap());
+}
+
+struct MemoryClipboard(Arc<Mutex<String>>);
+impl crate::clipboard::ClipboardBackend for MemoryClipboard {
+    fn get_clipboard(&self) -> Option<String> {
+        Some(self.0.lock().unwrap().clone())
+    }
+
+    fn set_clipboard(&self, text: &str) {
+        *self.0.lock().unwrap() = text.to_owned();
+    }
+}
+
+#[test]
+fn paste_clipboard() {
+    let keys = [
+        KeyPress::Char('a'),
+        KeyPress::Char('b'),
+        KeyPress::Left,
+        KeyPress::F(5),
+        KeyPress::Enter,
+    ];
+    let mut editor = init_editor(EditMode::Emacs, &keys);
+    editor.bind_sequence(KeyPress::F(5), Cmd::PasteClipboard);
+    // without backend, nothing is pasted
+    assert_eq!("ab", editor.readline(">>").unwrap());
+
+    let clipboard = Arc::new(Mutex::new("xyz".to_owned()));
+    editor.set_clipboard_backend(Box::new(MemoryClipboard(clipboard)));
+    assert_eq!("axyzb", editor.readline(">>").unwrap());
+}
+
+#[test]
+fn max_line_length() {
+    let config = Config::builder().max_line_length(Some(2)).build();
+    let mut editor = Editor::<()>::with_config(config);
+    editor.term.keys.extend(
+        [
+            KeyPress::Char('a'),
+            KeyPress::Char('b'),
+            KeyPress::Char('c'),
+            KeyPress::Enter,
+        ]
+        .iter()
+        .cloned(),
+    );
+    assert_eq!("ab", editor.readline(">>").unwrap());
+
+    // the initial line is truncated
+    editor.term.keys.push(KeyPress::Enter);
+    let line = editor.readline_with_initial(">>", ("a", "bc")).unwrap();
+    assert_eq!("ab", line);
+
+    // lines recalled from the history are kept
+    let config = Config::builder().max_line_length(Some(2)).build();
+    let mut editor = Editor::<()>::with_config(config);
+    editor.add_history_entry("abcd");
+    editor
+        .term
+        .keys
+        .extend([KeyPress::Up, KeyPress::Enter].iter().cloned());
+    assert_eq!("abcd", editor.readline(">>").unwrap());
+}
+
+#[test]
+fn resize() {
+    let mut editor = init_editor(EditMode::Emacs, &[KeyPress::Char('a'), KeyPress::Enter]);
+    editor.resize(20, 10);
+    assert_eq!(Some((20, 10)), editor.dimensions());
+    assert_eq!("a", editor.readline(">>").unwrap());
+
+    editor.reset_size();
+    assert_eq!(None, editor.dimensions());
+}
+
+#[test]
+fn describe_key() {
+    let mut editor = init_editor(EditMode::Emacs, &[]);
+    assert_eq!(
+        Some("reverse-search-history"),
+        editor.describe_key(KeyPress::Ctrl('R'))
+    );
+    assert_eq!(
+        Some("forward-word"),
+        editor.describe_key(KeyPress::Meta('f'))
+    );
+    assert_eq!(None, editor.describe_key(KeyPress::Ctrl('X')));
+    assert_eq!(None, editor.describe_key(KeyPress::F(5)));
+    editor.bind_sequence(KeyPress::F(5), Cmd::ClearScreen);
+    assert_eq!(Some("clear-screen"), editor.describe_key(KeyPress::F(5)));
+
+    let bindings = editor.all_keybindings();
+    assert!(bindings.contains(&(KeyPress::Ctrl('A'), "beginning-of-line")));
+    assert!(bindings.contains(&(KeyPress::F(5), "clear-screen")));
+    assert!(!bindings.iter().any(|&(key, _)| key == KeyPress::Ctrl('X')));
+
+    let editor = init_editor(EditMode::Vi, &[]);
+    assert_eq!(None, editor.describe_key(KeyPress::Meta('f')));
+    assert_eq!(Some("accept-line"), editor.describe_key(KeyPress::Enter));
+}
+
+#[test]
+fn bind_raw_sequence() {
+    use crate::error::ConfigError;
+
+    let mut editor = init_editor(EditMode::Emacs, &[]);
+    assert_eq!(
+        Err(ConfigError::RawSequenceLength(0)),
+        editor.bind_raw_sequence(vec![], Cmd::ClearScreen)
+    );
+    assert_eq!(
+        Err(ConfigError::RawSequenceLength(17)),
+        editor.bind_raw_sequence(vec![b'x'; 17], Cmd::ClearScreen)
+    );
+    assert_eq!(
+        Ok(None),
+        editor.bind_raw_sequence(b"\x1b[1;5A".to_vec(), Cmd::BeginningOfHistory)
+    );
+    assert_eq!(
+        vec![RawBinding {
+            seq: b"\x1b[1;5A".to_vec(),
+            cmd: Cmd::BeginningOfHistory
+        }],
+        editor.raw_bindings()
+    );
+    assert_eq!(
+        Some(Cmd::BeginningOfHistory),
+        editor.unbind_raw_sequence(b"\x1b[1;5A")
+    );
+    assert!(editor.raw_bindings().is_empty());
+}
+
+#[test]
+#[cfg(feature = "with-serde")]
+fn serde_raw_bindings() {
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Bindings {
+        sequences: Vec<RawBinding>,
+    }
+
+    let mut editor = init_editor(EditMode::Emacs, &[]);
+    editor
+        .bind_raw_sequence(b"\x1b[1;5A".to_vec(), Cmd::BeginningOfHistory)
+        .unwrap();
+    editor
+        .bind_raw_sequence(b"\x1b[1;5B".to_vec(), Cmd::EndOfHistory)
+        .unwrap();
+    let toml = toml::to_string(&Bindings {
+        sequences: editor.raw_bindings(),
+    })
+    .unwrap();
+
+    let bindings: Bindings = toml::from_str(&toml).unwrap();
+    let mut other = init_editor(EditMode::Emacs, &[]);
+    for binding in bindings.sequences {
+        other.bind_raw_sequence(binding.seq, binding.cmd).unwrap();
+    }
+    assert_eq!(editor.raw_bindings(), other.raw_bindings());
+}
+
+#[test]
+fn execute_command() {
+    let mut editor = init_editor(EditMode::Emacs, &[KeyPress::Char('!'), KeyPress::Enter]);
+    editor.execute_command(Cmd::Insert(1, "world".to_owned()));
+    editor.execute_command(Cmd::Move(Movement::BeginningOfLine));
+    editor.execute_command(Cmd::Insert(1, "hello ".to_owned()));
+    editor.execute_command(Cmd::Move(Movement::EndOfLine));
+    assert_eq!("hello world!", editor.readline(">>").unwrap());
+
+    // commands after the accepted line are kept for the next call
+    editor.execute_command(Cmd::Insert(1, "a".to_owned()));
+    editor.execute_command(Cmd::Insert(1, "b".to_owned()));
+    editor.execute_command(Cmd::Undo(1));
+    editor.execute_command(Cmd::AcceptLine);
+    editor.execute_command(Cmd::Insert(1, "c".to_owned()));
+    editor.term.keys = vec![KeyPress::Enter];
+    assert_eq!("a", editor.readline(">>").unwrap());
+    assert_eq!("c", editor.readline(">>").unwrap());
+}
+
+#[test]
+fn load_config_from_inputrc() {
+    use std::fs;
+
+    let td = tempdir::TempDir::new("inputrc").unwrap();
+    let path = td.path().join(".inputrc");
+    fs::write(
+        &path,
+        "set completion-query-items 20\n\"\\C-o\": clear-screen\nC-y: kill-word\n",
+    )
+    .unwrap();
+    let mut editor = init_editor(EditMode::Emacs, &[]);
+    editor.load_config_from_inputrc(&path).unwrap();
+    assert_eq!(20, editor.config.completion_prompt_limit());
+    assert_eq!(
+        Some("clear-screen"),
+        editor.describe_key(KeyPress::Ctrl('O'))
+    );
+    assert_eq!(Some("kill-word"), editor.describe_key(KeyPress::Ctrl('Y')));
+
+    assert!(editor
+        .load_config_from_inputrc(&td.path().join("missing"))
+        .is_err());
+}
+
+#[test]
+fn config_setters() {
+    let mut config = Config::new();
+    config.set_edit_mode(EditMode::Vi);
+    config.set_max_history_size(10);
+    let editor = Editor::<()>::with_config(config);
+    assert_eq!(EditMode::Vi, editor.config.edit_mode());
+    assert_eq!(500, editor.config.keyseq_timeout());
+    assert_eq!(10, editor.config.max_history_size());
+}
+
+#[test]
+fn keyseq_timeout() {
+    use crate::error::ConfigError;
+    use std::time::Duration;
+
+    let config = Config::builder().keyseq_timeout(-1).unwrap().build();
+    assert_eq!(-1, config.keyseq_timeout_ms());
+    assert_eq!(
+        Err(ConfigError::KeyseqTimeout(-2)),
+        Config::builder().keyseq_timeout(-2).map(|_| ())
+    );
+    let config = Config::builder()
+        .keyseq_timeout_duration(Duration::from_millis(250))
+        .build();
+    assert_eq!(250, config.keyseq_timeout());
+    let config = Config::builder()
+        .keyseq_timeout_duration(Duration::from_secs(u64::MAX))
+        .build();
+    assert_eq!(i32::MAX, config.keyseq_timeout());
+}
+
+#[cfg(feature = "with-serde")]
+#[test]
+fn deserialize_keyseq_timeout() {
+    let config: Config = toml::from_str("keyseq_timeout = 250").unwrap();
+    assert_eq!(250, config.keyseq_timeout());
+    let config: Config = toml::from_str("keyseq_timeout = -1").unwrap();
+    assert_eq!(-1, config.keyseq_timeout());
+    assert!(toml::from_str::<Config>("keyseq_timeout = -2").is_err());
+}
+
+#[test]
+fn list_column_width() {
+    let config = Config::default();
+    assert_eq!(7, super::list_column_width(5, 80, &config));
+    assert_eq!(80, super::list_column_width(100, 80, &config));
+
+    let config = Config::builder()
+        .completion_menu_min_width(20)
+        .completion_menu_max_width(30)
+        .build();
+    assert_eq!(20, super::list_column_width(5, 80, &config));
+    assert_eq!(25, super::list_column_width(23, 80, &config));
+    assert_eq!(30, super::list_column_width(50, 80, &config));
+    assert_eq!(10, super::list_column_width(5, 10, &config));
+
+    let config = Config::builder().completion_menu_max_width(0).build();
+    assert_eq!(1, super::list_column_width(5, 80, &config));
+}
+
+#[test]
+fn ellipsize() {
+    let config = Config::builder().completion_menu_max_width(8).build();
+    let width = super::list_column_width(12, 80, &config);
+    // the candidate does not run into the next column
+    assert_eq!("abcde\u{2026}", super::ellipsize("abcdefghijkl", width - 2));
+    assert_eq!("abc", super::ellipsize("abc", width - 2));
+    assert_eq!("abcdef", super::ellipsize("abcdef", width - 2));
+    assert_eq!("\u{2026}", super::ellipsize("abc", 1));
+    assert_eq!("", super::ellipsize("abc", 0));
+}
+
+#[test]
+fn copy_region() {
+    let keys = [
+        KeyPress::Char('a'),
+        KeyPress::Char('b'),
+        KeyPress::Ctrl(' '),
+        KeyPress::Home,
+        KeyPress::CtrlShift('C'),
+        KeyPress::End,
+        KeyPress::CtrlShift('V'),
+        KeyPress::Enter,
+    ];
+    let mut editor = init_editor(EditMode::Emacs, &keys);
+    let clipboard = Arc::new(Mutex::new(String::new()));
+    editor.set_clipboard_backend(Box::new(MemoryClipboard(clipboard.clone())));
+    assert_eq!("abab", editor.readline(">>").unwrap());
+    assert_eq!("ab", *clipboard.lock().unwrap());
+}
+
+#[test]
+fn ctrl_shift_fallback() {
+    for mode in &[EditMode::Emacs, EditMode::Vi] {
+        assert_cursor(
+            *mode,
+            ("Hi", ""),
+            &[KeyPress::CtrlShift('H'), KeyPress::Enter],
+            ("H", ""),
+        );
+    }
+    let keys = [KeyPress::CtrlShift('A'), KeyPress::Enter];
+    let mut editor = init_editor(EditMode::Emacs, &keys);
+    editor.bind_sequence(KeyPress::CtrlShift('A'), Cmd::Move(Movement::EndOfLine));
+    assert_eq!("Hi", editor.readline_with_initial("", ("H", "i")).unwrap());
+}
+
+#[test]
+fn mode_change() {
+    use crate::keymap::InputMode;
+    let keys = [
+        KeyPress::Char('a'),
+        KeyPress::Esc,
+        KeyPress::Char('i'),
+        KeyPress::Char('b'),
+        KeyPress::Enter,
+    ];
+    let mut editor = init_editor(EditMode::Vi, &keys);
+    let modes = Arc::new(Mutex::new(Vec::new()));
+    let handler_modes = modes.clone();
+    editor.set_mode_change_handler(move |mode| handler_modes.lock().unwrap().push(mode));
+    assert_eq!("ba", editor.readline(">>").unwrap());
+    assert_eq!(
+        vec![InputMode::ViNormal, InputMode::ViInsert],
+        *modes.lock().unwrap()
+    );
+    assert_eq!(InputMode::ViInsert, editor.current_input_mode());
+}
+
+#[test]
+fn callback_panic() {
+    use std::panic::{self, AssertUnwindSafe};
+    let keys = [KeyPress::Char('a'), KeyPress::Esc, KeyPress::Enter];
+    let mut editor = init_editor(EditMode::Vi, &keys);
+    editor.set_mode_change_handler(|_| panic!("handler"));
+    let result = panic::catch_unwind(AssertUnwindSafe(|| editor.readline(">>")));
+    let payload = result.unwrap_err();
+    assert_eq!(Some(&"handler"), payload.downcast_ref::<&str>());
+}
+
+#[test]
+fn default_history_file() {
+    let td = tempdir::TempDir::new("histo").unwrap();
+    let path = td.path().join(".history");
+    {
+        let mut editor = init_editor(EditMode::Emacs, &[KeyPress::Enter]);
+        editor.set_default_history_file(&path);
+        editor.add_history_entry("not saved");
+    }
+    assert!(!path.exists());
+    {
+        let mut editor = init_editor(EditMode::Emacs, &[KeyPress::Char('a'), KeyPress::Enter]);
+        editor.set_default_history_file(&path);
+        assert_eq!("a", editor.readline(">>").unwrap());
+        editor.add_history_entry("a");
+    }
+    let mut editor = init_editor(EditMode::Emacs, &[KeyPress::Enter]);
+    editor.set_default_history_file(&path);
+    assert!(editor.history().is_empty());
+    editor.readline(">>").unwrap();
+    assert_eq!(Some(&"a".to_owned()), editor.history().last());
+}
+
+#[test]
+fn default_history_file_unreadable() {
+    let td = tempdir::TempDir::new("histo").unwrap();
+    // a directory cannot be read as a history file
+    let path = td.path();
+    {
+        let mut editor = init_editor(EditMode::Emacs, &[KeyPress::Char('a'), KeyPress::Enter]);
+        editor.set_default_history_file(path);
+        assert_eq!("a", editor.readline(">>").unwrap());
+        editor.add_history_entry("a");
+    }
+    assert!(path.is_dir());
+}
+
+#[test]
+fn update_term_config() {
+    use crate::config::{BellStyle, ColorMode, Configurer};
+    let mut editor = Editor::<()>::new();
+    editor.set_color_mode(ColorMode::Forced);
+    editor.set_bell_style(BellStyle::Visible);
+    editor.set_hyperlinks_enabled(!editor.term.hyperlinks);
+    editor.set_enable_focus_events(!editor.term.focus_events);
+    editor.set_enable_bracketed_paste(!editor.term.bracketed_paste);
+    assert_eq!(ColorMode::Forced, editor.term.color_mode);
+    assert_eq!(BellStyle::Visible, editor.term.bell_style);
+    assert_eq!(editor.config.hyperlinks_enabled(), editor.term.hyperlinks);
+    assert_eq!(
+        editor.config.enable_focus_events(),
+        editor.term.focus_events
+    );
+    assert_eq!(
+        editor.config.enable_bracketed_paste(),
+        editor.term.bracketed_paste
+    );
+    let defaults = Config::default();
+    assert_ne!(defaults.hyperlinks_enabled(), editor.term.hyperlinks);
+    assert_ne!(defaults.enable_focus_events(), editor.term.focus_events);
+    assert_ne!(
+        defaults.enable_bracketed_paste(),
+        editor.term.bracketed_paste
+    );
+}
+
+#[test]
+fn set_edit_mode() {
+    use crate::config::Configurer;
+    use crate::keymap::InputMode;
+    let keys = [
+        KeyPress::Char('a'),
+        KeyPress::Esc,
+        KeyPress::Char('i'),
+        KeyPress::Char('b'),
+        KeyPress::Enter,
+    ];
+    let mut editor = init_editor(EditMode::Emacs, &keys);
+    assert_eq!(InputMode::Emacs, editor.current_input_mode());
+    editor.set_edit_mode(EditMode::Vi);
+    assert_eq!(InputMode::ViInsert, editor.current_input_mode());
+    assert_eq!("ba", editor.readline(">>").unwrap());
+    editor.set_edit_mode(EditMode::Emacs);
+    assert_eq!(InputMode::Emacs, editor.current_input_mode());
+    assert_eq!("aib", editor.readline(">>").unwrap());
+}
+
 // `keys`: keys to press
 // `expected_line`: line after enter key
 fn assert_line(mode: EditMode, keys: &[KeyPress], expected_line: &str) {
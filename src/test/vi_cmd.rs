@@ -672,3 +672,196 @@ fn uppercase_t() {
         ("Hel", "lo, world!"),
     );
 }
+
+#[test]
+fn text_object() {
+    assert_cursor(
+        EditMode::Vi,
+        ("echo \"hello wo", "rld\""),
+        &[
+            KeyPress::Esc,
+            KeyPress::Char('d'),
+            KeyPress::Char('i'),
+            KeyPress::Char('"'),
+            KeyPress::Enter,
+        ],
+        ("echo \"", "\""),
+    );
+    assert_cursor(
+        EditMode::Vi,
+        ("f(a, ", "b) + 1"),
+        &[
+            KeyPress::Esc,
+            KeyPress::Char('c'),
+            KeyPress::Char('a'),
+            KeyPress::Char('('),
+            KeyPress::Char('x'),
+            KeyPress::Enter,
+        ],
+        ("fx", " + 1"),
+    );
+    assert_cursor(
+        EditMode::Vi,
+        ("[ab", "c]"),
+        &[
+            KeyPress::Esc,
+            KeyPress::Char('y'),
+            KeyPress::Char('i'),
+            KeyPress::Char(']'),
+            KeyPress::Char('0'),
+            KeyPress::Char('P'),
+            KeyPress::Enter,
+        ],
+        ("ab", "c[abc]"),
+    );
+}
+
+#[test]
+fn search() {
+    assert_history(
+        EditMode::Vi,
+        &["cargo build", "ls", "cargo test", "cd"],
+        &[
+            KeyPress::Esc,
+            KeyPress::Char('/'),
+            KeyPress::Char('c'),
+            KeyPress::Char('a'),
+            KeyPress::Enter,
+            KeyPress::Enter,
+        ],
+        "",
+        ("", "cargo test"),
+    );
+    assert_history(
+        EditMode::Vi,
+        &["cargo build", "ls", "cargo test", "cd"],
+        &[
+            KeyPress::Esc,
+            KeyPress::Char('/'),
+            KeyPress::Char('c'),
+            KeyPress::Char('a'),
+            KeyPress::Enter,
+            KeyPress::Char('n'),
+            KeyPress::Char('n'),
+            KeyPress::Char('N'),
+            KeyPress::Enter,
+        ],
+        "",
+        ("", "cargo test"),
+    );
+    assert_history(
+        EditMode::Vi,
+        &["cargo build", "ls"],
+        &[
+            KeyPress::Char('x'),
+            KeyPress::Esc,
+            KeyPress::Char('/'),
+            KeyPress::Char('c'),
+            KeyPress::Esc,
+            KeyPress::Enter,
+        ],
+        "",
+        ("", "x"),
+    );
+}
+
+#[test]
+fn tilde() {
+    assert_cursor(
+        EditMode::Vi,
+        ("", "hello"),
+        &[KeyPress::Esc, KeyPress::Char('~'), KeyPress::Enter],
+        ("H", "ello"),
+    );
+    assert_cursor(
+        EditMode::Vi,
+        ("", "Hi!"),
+        &[
+            KeyPress::Esc,
+            KeyPress::Char('5'),
+            KeyPress::Char('~'),
+            KeyPress::Enter,
+        ],
+        ("hI", "!"),
+    );
+}
+
+#[test]
+fn g() {
+    assert_cursor(
+        EditMode::Vi,
+        ("Hello, world", "!"),
+        &[
+            KeyPress::Esc,
+            KeyPress::Char('g'),
+            KeyPress::Char('e'),
+            KeyPress::Enter,
+        ],
+        ("Hello", ", world!"),
+    );
+    assert_cursor(
+        EditMode::Vi,
+        ("Hello, world", "!"),
+        &[
+            KeyPress::Esc,
+            KeyPress::Char('g'),
+            KeyPress::Char('E'),
+            KeyPress::Enter,
+        ],
+        ("Hello", ", world!"),
+    );
+    assert_cursor(
+        EditMode::Vi,
+        ("Hello, wor", "ld!"),
+        &[
+            KeyPress::Esc,
+            KeyPress::Char('g'),
+            KeyPress::Char('g'),
+            KeyPress::Enter,
+        ],
+        ("", "Hello, world!"),
+    );
+    assert_cursor(
+        EditMode::Vi,
+        ("Hello, wor", "ld!"),
+        &[
+            KeyPress::Esc,
+            KeyPress::Char('d'),
+            KeyPress::Char('g'),
+            KeyPress::Char('e'),
+            KeyPress::Enter,
+        ],
+        ("Hello", "ld!"),
+    );
+}
+
+#[test]
+fn uppercase_g() {
+    assert_cursor(
+        EditMode::Vi,
+        ("", "Hi"),
+        &[KeyPress::Esc, KeyPress::Char('G'), KeyPress::Enter],
+        ("Hi", ""), // FIXME
+    );
+}
+
+#[test]
+fn percent() {
+    assert_cursor(
+        EditMode::Vi,
+        ("", "f(a, (b))"),
+        &[KeyPress::Esc, KeyPress::Char('%'), KeyPress::Enter],
+        ("f(a, (b)", ")"),
+    );
+    assert_cursor(
+        EditMode::Vi,
+        ("f(a, (b)", ")"),
+        &[
+            KeyPress::Esc,
+            KeyPress::Char('d'),
+            KeyPress::Char('%'),
+            KeyPress::Enter,
+        ],
+        ("f(a, ", ")"),
+    );
+}
@@ -0,0 +1,684 @@
+//! Cross-platform definitions based on `crossterm` (`crossterm` feature)
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crossterm::cursor::{
+    MoveDown, MoveLeft, MoveRight, MoveTo, MoveToColumn, MoveUp, RestorePosition, SavePosition,
+};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::queue;
+use crossterm::terminal::{self, Clear, ClearType};
+use crossterm::tty::IsTty;
+#[cfg(not(feature = "tracing"))]
+use log::debug;
+#[cfg(feature = "tracing")]
+use tracing::debug;
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::{grapheme_width, RawMode, RawReader, Renderer, Term, TerminalCapabilities, WinSize};
+use crate::config::{BellStyle, ColorMode, Config, OutputStreamType};
+use crate::error;
+use crate::highlight::Highlighter;
+use crate::keymap::SequenceTrie;
+use crate::keys::KeyPress;
+use crate::layout::{Layout, Position};
+use crate::line_buffer::LineBuffer;
+use crate::Result;
+
+/// How often a blocked `next_key` checks whether it has been aborted
+const ABORT_POLL_MS: u64 = 100;
+/// DECSCNM: reverse video, used for the visible bell
+const FLASH_ON: &[u8] = b"\x1b[?5h";
+const FLASH_OFF: &[u8] = b"\x1b[?5l";
+
+fn is_output_tty(out: OutputStreamType) -> bool {
+    match out {
+        OutputStreamType::Stdout => io::stdout().is_tty(),
+        OutputStreamType::Stderr => io::stderr().is_tty(),
+        #[cfg(unix)]
+        OutputStreamType::Fd(fd) => unsafe { libc::isatty(fd) != 0 },
+    }
+}
+
+fn get_win_size() -> WinSize {
+    match terminal::size() {
+        Ok((cols, rows)) if cols > 0 && rows > 0 => WinSize {
+            cols: cols as usize,
+            rows: rows as usize,
+        },
+        _ => WinSize { cols: 80, rows: 24 },
+    }
+}
+
+pub fn stdout_size() -> WinSize {
+    get_win_size()
+}
+
+/// Caller-owned file descriptor (not closed on drop)
+#[cfg(unix)]
+struct FdWriter(std::os::unix::io::RawFd);
+
+#[cfg(unix)]
+impl Write for FdWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let res = unsafe {
+            libc::write(
+                self.0,
+                buf.as_ptr() as *const libc::c_void,
+                buf.len() as libc::size_t,
+            )
+        };
+        if res < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(res as usize)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // unbuffered: nothing to flush
+        Ok(())
+    }
+}
+
+/// Commands are executed on the output stream itself because legacy Windows
+/// consoles don't support escape sequences.
+fn output(out: OutputStreamType) -> Box<dyn Write> {
+    match out {
+        OutputStreamType::Stdout => Box::new(io::stdout()),
+        OutputStreamType::Stderr => Box::new(io::stderr()),
+        #[cfg(unix)]
+        OutputStreamType::Fd(fd) => Box::new(FdWriter(fd)),
+    }
+}
+
+pub struct CrosstermMode {}
+
+pub type Mode = CrosstermMode;
+
+impl RawMode for CrosstermMode {
+    /// Disable RAW mode for the terminal.
+    fn disable_raw_mode(&self) -> Result<()> {
+        terminal::disable_raw_mode()?;
+        Ok(())
+    }
+}
+
+/// Handle to interrupt `readline` from another thread
+/// (see `Editor::abort_handle`)
+#[derive(Clone, Debug)]
+pub struct AbortHandle {
+    aborted: Arc<AtomicBool>,
+}
+
+impl AbortHandle {
+    /// Make the pending or next `readline` call return
+    /// `ReadlineError::Interrupted`.
+    pub fn abort(&self) {
+        self.aborted.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Console input reader
+pub struct CrosstermRawReader {
+    aborted: Arc<AtomicBool>,
+}
+
+impl CrosstermRawReader {
+    fn new(aborted: Arc<AtomicBool>) -> Self {
+        Self { aborted }
+    }
+
+    /// Wait for an event, checking periodically if `readline` has been
+    /// aborted.
+    fn next_event(&mut self) -> Result<Event> {
+        loop {
+            if self.aborted.swap(false, Ordering::SeqCst) {
+                return Err(error::ReadlineError::Interrupted);
+            }
+            if event::poll(Duration::from_millis(ABORT_POLL_MS))? {
+                return Ok(event::read()?);
+            }
+        }
+    }
+}
+
+impl RawReader for CrosstermRawReader {
+    fn next_key(&mut self, _: bool) -> Result<KeyPress> {
+        loop {
+            match self.next_event()? {
+                Event::Key(key_event) => {
+                    if let Some(key) = key_press(key_event) {
+                        debug!(target: "rustyline", "key: {:?}", key);
+                        return Ok(key);
+                    }
+                }
+                Event::Resize(..) => {
+                    SIGWINCH.store(true, Ordering::SeqCst);
+                    debug!(target: "rustyline", "SIGWINCH");
+                    return Err(error::ReadlineError::WindowResize); // sigwinch +
+                                                                    // err => err
+                                                                    // ignored
+                }
+                Event::Mouse(_) => continue,
+            }
+        }
+    }
+
+    #[cfg(any(unix, windows))]
+    fn next_char(&mut self) -> Result<char> {
+        let c = match self.next_key(false)? {
+            KeyPress::Char(c) => c,
+            KeyPress::Ctrl(' ') => '\x00',
+            KeyPress::Ctrl(c) => ((c as u8) & 0x1f) as char,
+            KeyPress::Backspace => '\x7f',
+            KeyPress::Enter => '\r',
+            KeyPress::Esc => '\x1b',
+            KeyPress::Tab => '\t',
+            _ => return Err(error::ReadlineError::Utf8Error),
+        };
+        Ok(c)
+    }
+
+    /// `crossterm` doesn't report bracketed paste.
+    fn read_pasted_text(&mut self) -> Result<String> {
+        Ok(String::new())
+    }
+
+    fn wait_for_input(&mut self, timeout_ms: i32) -> Result<bool> {
+        if timeout_ms < 0 {
+            // wait indefinitely, unless aborted
+            loop {
+                if self.aborted.load(Ordering::SeqCst)
+                    || event::poll(Duration::from_millis(ABORT_POLL_MS))?
+                {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(self.aborted.load(Ordering::SeqCst)
+            || event::poll(Duration::from_millis(timeout_ms as u64))?)
+    }
+}
+
+/// Translate a `crossterm` key event, `None` for keys without equivalent.
+fn key_press(key_event: KeyEvent) -> Option<KeyPress> {
+    let ctrl = key_event.modifiers.contains(KeyModifiers::CONTROL);
+    let shift = key_event.modifiers.contains(KeyModifiers::SHIFT);
+    let meta = key_event.modifiers.contains(KeyModifiers::ALT);
+    let key = match key_event.code {
+        KeyCode::Char(c) if ctrl => match c {
+            ' ' => KeyPress::Ctrl(' '),
+            // `crossterm` reports `\x1c` to `\x1f` as Ctrl-4 to Ctrl-7
+            '4' => KeyPress::Ctrl('\\'),
+            '5' => KeyPress::Ctrl(']'),
+            '6' => KeyPress::Ctrl('^'),
+            '7' => KeyPress::Ctrl('_'),
+            'h' | 'H' => KeyPress::Backspace,
+            'i' | 'I' => KeyPress::Tab,
+            'm' | 'M' => KeyPress::Enter,
+            '[' => KeyPress::Esc,
+            c if shift && c.is_ascii_alphabetic() => KeyPress::CtrlShift(c.to_ascii_uppercase()),
+            c => KeyPress::Ctrl(c.to_ascii_uppercase()),
+        },
+        KeyCode::Char(c) if meta => KeyPress::Meta(c),
+        KeyCode::Char(c) => KeyPress::Char(c),
+        KeyCode::Backspace if meta => KeyPress::Meta('\x7f'),
+        KeyCode::Backspace => KeyPress::Backspace,
+        KeyCode::Enter => KeyPress::Enter,
+        KeyCode::Left if ctrl => KeyPress::ControlLeft,
+        KeyCode::Left if shift => KeyPress::ShiftLeft,
+        KeyCode::Left => KeyPress::Left,
+        KeyCode::Right if ctrl => KeyPress::ControlRight,
+        KeyCode::Right if shift => KeyPress::ShiftRight,
+        KeyCode::Right => KeyPress::Right,
+        KeyCode::Up if ctrl => KeyPress::ControlUp,
+        KeyCode::Up if shift => KeyPress::ShiftUp,
+        KeyCode::Up => KeyPress::Up,
+        KeyCode::Down if ctrl => KeyPress::ControlDown,
+        KeyCode::Down if shift => KeyPress::ShiftDown,
+        KeyCode::Down => KeyPress::Down,
+        KeyCode::Home => KeyPress::Home,
+        KeyCode::End => KeyPress::End,
+        KeyCode::PageUp => KeyPress::PageUp,
+        KeyCode::PageDown => KeyPress::PageDown,
+        KeyCode::Tab => KeyPress::Tab,
+        KeyCode::BackTab => KeyPress::BackTab,
+        KeyCode::Delete => KeyPress::Delete,
+        KeyCode::Insert => KeyPress::Insert,
+        KeyCode::F(n) => KeyPress::F(n),
+        KeyCode::Esc => KeyPress::Esc,
+        KeyCode::Null => return None,
+    };
+    Some(key)
+}
+
+/// Console output writer
+pub struct CrosstermRenderer {
+    out: OutputStreamType,
+    size: WinSize,    // Number of columns and rows in terminal
+    fixed_size: bool, // Whether `size` has been set by `set_size`
+    buffer: String,
+    tab_stop: usize,
+    colors_enabled: bool,
+    bell_style: BellStyle,
+    // Whether the screen is in reverse video for the visible bell
+    flash_pending: bool,
+}
+
+impl CrosstermRenderer {
+    fn new(
+        out: OutputStreamType,
+        tab_stop: usize,
+        colors_enabled: bool,
+        bell_style: BellStyle,
+    ) -> Self {
+        let size = get_win_size();
+        Self {
+            out,
+            size,
+            fixed_size: false,
+            buffer: String::with_capacity(1024),
+            tab_stop,
+            colors_enabled,
+            bell_style,
+            flash_pending: false,
+        }
+    }
+
+    /// Restore the screen after a visible bell
+    fn end_flash(&mut self) -> Result<()> {
+        if self.flash_pending {
+            self.flash_pending = false;
+            let mut out = output(self.out);
+            out.write_all(FLASH_OFF)?;
+            out.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for CrosstermRenderer {
+    fn drop(&mut self) {
+        if let Err(e) = self.end_flash() {
+            debug!(target: "rustyline", "cannot end visible bell: {}", e);
+        }
+    }
+}
+
+impl Renderer for CrosstermRenderer {
+    type Reader = CrosstermRawReader;
+
+    fn move_cursor(&mut self, old: Position, new: Position) -> Result<()> {
+        let mut out = output(self.out);
+        if new.row > old.row {
+            queue!(out, MoveDown((new.row - old.row) as u16))?;
+        } else if new.row < old.row {
+            queue!(out, MoveUp((old.row - new.row) as u16))?;
+        }
+        if new.col > old.col {
+            queue!(out, MoveRight((new.col - old.col) as u16))?;
+        } else if new.col < old.col {
+            queue!(out, MoveLeft((old.col - new.col) as u16))?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    fn refresh_line(
+        &mut self,
+        prompt: &str,
+        line: &LineBuffer,
+        hint: Option<&str>,
+        old_layout: &Layout,
+        new_layout: &Layout,
+        highlighter: Option<&dyn Highlighter>,
+    ) -> Result<()> {
+        self.end_flash()?;
+        let mut out = output(self.out);
+        self.buffer.clear();
+
+        let default_prompt = new_layout.default_prompt;
+        let cursor = new_layout.cursor;
+        let end_pos = new_layout.end;
+        let current_row = old_layout.cursor.row;
+        let old_rows = old_layout.end.row;
+
+        // old_rows < cursor.row if the prompt spans multiple lines and if
+        // this is the default State.
+        let cursor_row_movement = old_rows.saturating_sub(current_row);
+        // move the cursor down as required
+        if cursor_row_movement > 0 {
+            queue!(out, MoveDown(cursor_row_movement as u16))?;
+        }
+        // clear old rows
+        for _ in 0..old_rows {
+            queue!(
+                out,
+                MoveToColumn(0),
+                Clear(ClearType::UntilNewLine),
+                MoveUp(1)
+            )?;
+        }
+        // clear the line
+        queue!(out, MoveToColumn(0), Clear(ClearType::UntilNewLine))?;
+
+        if let Some(highlighter) = highlighter {
+            // display the prompt
+            self.buffer
+                .push_str(&highlighter.highlight_prompt(prompt, default_prompt));
+            // display the input line
+            if let Some(region) = line.region() {
+                self.buffer
+                    .push_str(&highlighter.highlight_region(line, line.pos(), region));
+            } else {
+                self.buffer.push_str(&highlighter.highlight_overflow(
+                    line,
+                    line.pos(),
+                    line.is_too_long(),
+                ));
+            }
+        } else {
+            // display the prompt
+            self.buffer.push_str(prompt);
+            // display the input line
+            self.buffer.push_str(line);
+        }
+        // display hint
+        if let Some(hint) = hint {
+            self.buffer.push_str(hint);
+        }
+        // we have to generate our own newline on line wrap
+        if end_pos.col == 0 && end_pos.row > 0 && !self.buffer.ends_with('\n') {
+            self.buffer.push('\n');
+        }
+        // raw mode disables output processing on unix
+        out.write_all(self.buffer.replace('\n', "\r\n").as_bytes())?;
+        // position the cursor
+        let new_cursor_row_movement = end_pos.row - cursor.row;
+        // move the cursor up as required
+        if new_cursor_row_movement > 0 {
+            queue!(out, MoveUp(new_cursor_row_movement as u16))?;
+        }
+        // position the cursor within the line
+        queue!(out, MoveToColumn(0))?;
+        if cursor.col > 0 {
+            queue!(out, MoveRight(cursor.col as u16))?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    fn write_and_flush(&self, buf: &[u8]) -> Result<()> {
+        let mut out = output(self.out);
+        // raw mode disables output processing on unix
+        for (i, line) in buf.split(|b| *b == b'\n').enumerate() {
+            if i > 0 {
+                out.write_all(b"\r\n")?;
+            }
+            out.write_all(line)?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Control characters are treated as having zero width.
+    /// Characters with 2 column width are correctly handled (not split).
+    fn calculate_position(&self, s: &str, orig: Position) -> Position {
+        let mut pos = orig;
+        let mut esc_seq = 0;
+        for c in s.graphemes(true) {
+            if c == "\n" {
+                pos.row += 1;
+                pos.col = 0;
+                continue;
+            }
+            let cw = if c == "\t" {
+                self.tab_stop - (pos.col % self.tab_stop)
+            } else {
+                width(c, &mut esc_seq)
+            };
+            pos.col += cw;
+            if pos.col > self.size.cols {
+                pos.row += 1;
+                pos.col = cw;
+            }
+        }
+        if pos.col == self.size.cols {
+            pos.col = 0;
+            pos.row += 1;
+        }
+        pos
+    }
+
+    fn beep(&mut self) -> Result<()> {
+        match self.bell_style {
+            BellStyle::Audible => {
+                io::stderr().write_all(b"\x07")?;
+                io::stderr().flush()?;
+                Ok(())
+            }
+            BellStyle::Visible => {
+                // restored by the next refresh (or when `readline` returns)
+                let mut out = output(self.out);
+                out.write_all(FLASH_ON)?;
+                out.flush()?;
+                self.flash_pending = true;
+                Ok(())
+            }
+            BellStyle::Callback(ref bell) => {
+                bell();
+                Ok(())
+            }
+            BellStyle::None => Ok(()),
+        }
+    }
+
+    /// Clear the screen. Used to handle ctrl+l
+    fn clear_screen(&mut self) -> Result<()> {
+        let mut out = output(self.out);
+        queue!(out, Clear(ClearType::All), MoveTo(0, 0))?;
+        out.flush()?;
+        Ok(())
+    }
+
+    fn save_cursor(&mut self) -> Result<()> {
+        let mut out = output(self.out);
+        queue!(out, SavePosition)?;
+        out.flush()?;
+        Ok(())
+    }
+
+    fn restore_cursor(&mut self) -> Result<()> {
+        let mut out = output(self.out);
+        queue!(out, RestorePosition)?;
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Check if a resize event has been received
+    fn sigwinch(&self) -> bool {
+        SIGWINCH.swap(false, Ordering::SeqCst)
+    }
+
+    /// Try to update the number of columns in the current terminal,
+    fn update_size(&mut self) {
+        if !self.fixed_size {
+            self.size = get_win_size();
+        }
+    }
+
+    fn get_columns(&self) -> usize {
+        self.size.cols
+    }
+
+    /// Try to get the number of rows in the current terminal,
+    /// or assume 24 if it fails.
+    fn get_rows(&self) -> usize {
+        self.size.rows
+    }
+
+    fn set_size(&mut self, size: WinSize) {
+        self.size = size;
+        self.fixed_size = true;
+    }
+
+    fn colors_enabled(&self) -> bool {
+        self.colors_enabled
+    }
+
+    fn move_cursor_at_leftmost(&mut self, _: &mut CrosstermRawReader) -> Result<()> {
+        match crossterm::cursor::position() {
+            Ok((0, _)) => Ok(()),
+            Ok(_) => self.write_and_flush(b"\n"),
+            Err(e) => {
+                debug!(target: "rustyline", "cannot request cursor location: {}", e);
+                Ok(())
+            }
+        }
+    }
+}
+
+fn width(s: &str, esc_seq: &mut u8) -> usize {
+    if *esc_seq == 1 {
+        if s == "[" {
+            // CSI
+            *esc_seq = 2;
+        } else {
+            // two-character sequence
+            *esc_seq = 0;
+        }
+        0
+    } else if *esc_seq == 2 {
+        if s != ";" && !s.as_bytes()[0].is_ascii_digit() {
+            // not supported
+            *esc_seq = 0;
+        }
+        0
+    } else if s == "\x1b" {
+        *esc_seq = 1;
+        0
+    } else if s == "\n" {
+        0
+    } else {
+        grapheme_width(s)
+    }
+}
+
+static SIGWINCH: AtomicBool = AtomicBool::new(false);
+
+pub type Terminal = CrosstermTerminal;
+
+#[derive(Clone, Debug)]
+pub struct CrosstermTerminal {
+    unsupported: bool,
+    stdin_isatty: bool,
+    stdstream_isatty: bool,
+    color_mode: ColorMode,
+    stream_type: OutputStreamType,
+    tab_stop: usize,
+    bell_style: BellStyle,
+    aborted: Arc<AtomicBool>,
+}
+
+impl CrosstermTerminal {
+    fn colors_enabled(&self) -> bool {
+        match self.color_mode {
+            ColorMode::Enabled => self.stdstream_isatty,
+            ColorMode::Forced => true,
+            ColorMode::Disabled => false,
+        }
+    }
+}
+
+impl Term for CrosstermTerminal {
+    type Mode = CrosstermMode;
+    type AbortHandle = AbortHandle;
+    type Reader = CrosstermRawReader;
+    type Writer = CrosstermRenderer;
+
+    fn new(config: &Config) -> Self {
+        let stream_type = config.output_stream();
+        Self {
+            unsupported: TerminalCapabilities::detect().unsupported,
+            stdin_isatty: io::stdin().is_tty(),
+            stdstream_isatty: is_output_tty(stream_type),
+            color_mode: config.color_mode(),
+            stream_type,
+            tab_stop: config.tab_stop(),
+            bell_style: config.bell_style(),
+            aborted: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    // Init checks:
+
+    /// Check if current terminal can provide a rich line-editing user
+    /// interface.
+    fn is_unsupported(&self) -> bool {
+        self.unsupported
+    }
+
+    /// check if stdin is connected to a terminal.
+    fn is_stdin_tty(&self) -> bool {
+        self.stdin_isatty
+    }
+
+    fn is_output_tty(&self) -> bool {
+        self.stdstream_isatty
+    }
+
+    // Interactive loop:
+
+    fn enable_raw_mode(&mut self) -> Result<Self::Mode> {
+        terminal::enable_raw_mode()?;
+        Ok(CrosstermMode {})
+    }
+
+    /// Create a RAW reader
+    fn create_reader(
+        &self,
+        _: &Config,
+        _: Arc<RwLock<SequenceTrie>>,
+    ) -> Result<CrosstermRawReader> {
+        Ok(CrosstermRawReader::new(Arc::clone(&self.aborted)))
+    }
+
+    fn create_writer(&self) -> CrosstermRenderer {
+        CrosstermRenderer::new(
+            self.stream_type,
+            self.tab_stop,
+            self.colors_enabled(),
+            self.bell_style.clone(),
+        )
+    }
+
+    fn abort_handle(&self) -> AbortHandle {
+        AbortHandle {
+            aborted: Arc::clone(&self.aborted),
+        }
+    }
+
+    fn update_config(&mut self, config: &Config) {
+        self.color_mode = config.color_mode();
+        self.tab_stop = config.tab_stop();
+        self.bell_style = config.bell_style();
+    }
+}
+
+#[cfg(unix)]
+pub fn suspend() -> Result<()> {
+    use nix::sys::signal;
+    use nix::unistd::Pid;
+    // suspend the whole process group
+    signal::kill(Pid::from_raw(0), signal::SIGTSTP)?;
+    Ok(())
+}
+
+/// `crossterm` retries interrupted reads: resumption is not reported.
+#[cfg(unix)]
+pub fn sigcont() -> bool {
+    false
+}
@@ -0,0 +1,576 @@
+//! Terminal emulation used to test `Helper`s without a real terminal
+//! (`mock` feature)
+use std::collections::VecDeque;
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::native;
+use super::{grapheme_width, RawMode, RawReader, Renderer, Term, WinSize};
+#[cfg(unix)]
+use crate::config::OutputStreamType;
+use crate::config::{ColorMode, Config};
+use crate::error::ReadlineError;
+use crate::highlight::Highlighter;
+use crate::keymap::{Cmd, SequenceTrie};
+use crate::keys::KeyPress;
+use crate::layout::{Layout, Position};
+use crate::line_buffer::LineBuffer;
+use crate::Result;
+
+const MOCK_SIZE: WinSize = WinSize { cols: 80, rows: 24 };
+
+#[derive(Debug, Default)]
+struct MockState {
+    keys: VecDeque<KeyPress>,
+    output: String,
+    /// Start of the line being edited in `output`
+    line_start: usize,
+}
+
+/// Terminal fed with `keys` and capturing what is displayed
+/// (see `Editor::set_mock_terminal`).
+///
+/// ```
+/// use rustyline::{Editor, KeyPress, MockTerminal};
+///
+/// let term = MockTerminal::new(vec![KeyPress::Char('a'), KeyPress::Enter]);
+/// let mut rl = Editor::<()>::new();
+/// rl.set_mock_terminal(term.clone());
+/// assert_eq!("a", rl.readline("> ").unwrap());
+/// assert_eq!("> a\n", term.output());
+/// ```
+#[derive(Clone, Debug)]
+pub struct MockTerminal {
+    state: Arc<Mutex<MockState>>,
+    aborted: Arc<AtomicBool>,
+    color_mode: ColorMode,
+}
+
+impl MockTerminal {
+    /// Create a terminal which will replay `keys`.
+    pub fn new(keys: Vec<KeyPress>) -> MockTerminal {
+        MockTerminal {
+            state: Arc::new(Mutex::new(MockState {
+                keys: keys.into(),
+                ..MockState::default()
+            })),
+            aborted: Arc::new(AtomicBool::new(false)),
+            color_mode: ColorMode::Enabled,
+        }
+    }
+
+    /// Append `keys` to the keys to be replayed.
+    pub fn push_keys(&self, keys: &[KeyPress]) {
+        let mut state = self.state.lock().unwrap();
+        state.keys.extend(keys.iter().cloned());
+    }
+
+    /// Lines displayed so far: the prompt, the highlighted line and hint as
+    /// last rendered, and anything written after them.
+    pub fn output(&self) -> String {
+        self.state.lock().unwrap().output.clone()
+    }
+}
+
+/// Reader replaying the keys of a `MockTerminal`
+pub struct MockReader {
+    state: Arc<Mutex<MockState>>,
+    aborted: Arc<AtomicBool>,
+}
+
+impl MockReader {
+    fn next(&mut self) -> Result<KeyPress> {
+        if self.aborted.swap(false, Ordering::SeqCst) {
+            return Err(ReadlineError::Interrupted);
+        }
+        let mut state = self.state.lock().unwrap();
+        state.keys.pop_front().ok_or(ReadlineError::Eof)
+    }
+}
+
+impl RawReader for MockReader {
+    fn next_key(&mut self, _: bool) -> Result<KeyPress> {
+        self.next()
+    }
+
+    #[cfg(any(unix, windows))]
+    fn next_char(&mut self) -> Result<char> {
+        match self.next()? {
+            KeyPress::Char(c) => Ok(c),
+            key => Err(ReadlineError::from(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("unexpected key: {:?}", key),
+            ))),
+        }
+    }
+
+    fn read_pasted_text(&mut self) -> Result<String> {
+        let mut buffer = String::new();
+        loop {
+            match self.next()? {
+                KeyPress::BracketedPasteEnd => break,
+                KeyPress::Char(c) => buffer.push(c),
+                KeyPress::Enter => buffer.push('\n'),
+                _ => {}
+            }
+        }
+        Ok(buffer)
+    }
+
+    fn wait_for_input(&mut self, _: i32) -> Result<bool> {
+        Ok(self.aborted.load(Ordering::SeqCst) || !self.state.lock().unwrap().keys.is_empty())
+    }
+
+    fn drain_input(&mut self) -> Result<()> {
+        self.state.lock().unwrap().keys.clear();
+        Ok(())
+    }
+}
+
+/// Renderer capturing the output of a `MockTerminal`
+pub struct MockRenderer {
+    state: Arc<Mutex<MockState>>,
+    size: WinSize,
+    colors_enabled: bool,
+}
+
+impl Renderer for MockRenderer {
+    type Reader = MockReader;
+
+    fn move_cursor(&mut self, _: Position, _: Position) -> Result<()> {
+        Ok(())
+    }
+
+    fn refresh_line(
+        &mut self,
+        prompt: &str,
+        line: &LineBuffer,
+        hint: Option<&str>,
+        _old_layout: &Layout,
+        _new_layout: &Layout,
+        highlighter: Option<&dyn Highlighter>,
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let line_start = state.line_start;
+        state.output.truncate(line_start);
+        if let Some(highlighter) = highlighter {
+            state
+                .output
+                .push_str(&highlighter.highlight_prompt(prompt, true));
+            if let Some(region) = line.region() {
+                state
+                    .output
+                    .push_str(&highlighter.highlight_region(line, line.pos(), region));
+            } else {
+                state.output.push_str(&highlighter.highlight_overflow(
+                    line,
+                    line.pos(),
+                    line.is_too_long(),
+                ));
+            }
+        } else {
+            state.output.push_str(prompt);
+            state.output.push_str(line);
+        }
+        if let Some(hint) = hint {
+            state.output.push_str(hint);
+        }
+        Ok(())
+    }
+
+    fn calculate_position(&self, s: &str, orig: Position) -> Position {
+        let mut pos = orig;
+        for c in s.graphemes(true) {
+            if c == "\n" {
+                pos.row += 1;
+                pos.col = 0;
+                continue;
+            }
+            let cw = grapheme_width(c);
+            pos.col += cw;
+            if pos.col > self.size.cols {
+                pos.row += 1;
+                pos.col = cw;
+            }
+        }
+        if pos.col == self.size.cols {
+            pos.col = 0;
+            pos.row += 1;
+        }
+        pos
+    }
+
+    fn write_and_flush(&self, buf: &[u8]) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.output.push_str(&String::from_utf8_lossy(buf));
+        if buf.contains(&b'\n') {
+            state.line_start = state.output.len();
+        }
+        Ok(())
+    }
+
+    fn beep(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn clear_screen(&mut self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.output.clear();
+        state.line_start = 0;
+        Ok(())
+    }
+
+    fn save_cursor(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn restore_cursor(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn sigwinch(&self) -> bool {
+        false
+    }
+
+    fn update_size(&mut self) {}
+
+    fn get_columns(&self) -> usize {
+        self.size.cols
+    }
+
+    fn get_rows(&self) -> usize {
+        self.size.rows
+    }
+
+    fn set_size(&mut self, size: WinSize) {
+        self.size = size;
+    }
+
+    fn colors_enabled(&self) -> bool {
+        self.colors_enabled
+    }
+
+    fn move_cursor_at_leftmost(&mut self, _: &mut MockReader) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Platform terminal or `MockTerminal`
+#[derive(Debug)]
+pub enum Terminal {
+    Native(native::Terminal),
+    Mock(MockTerminal),
+}
+
+pub enum Reader {
+    Native(<native::Terminal as Term>::Reader),
+    Mock(MockReader),
+}
+
+pub enum Writer {
+    Native(<native::Terminal as Term>::Writer),
+    Mock(MockRenderer),
+}
+
+pub enum Mode {
+    Native(native::Mode),
+    Mock,
+}
+
+/// Handle to interrupt `readline` from another thread
+/// (see `Editor::abort_handle`)
+#[derive(Clone, Debug)]
+pub struct AbortHandle(AbortHandleKind);
+
+#[derive(Clone, Debug)]
+enum AbortHandleKind {
+    Native(native::AbortHandle),
+    Mock(Arc<AtomicBool>),
+}
+
+impl AbortHandle {
+    /// Make the pending or next `readline` call return
+    /// `ReadlineError::Interrupted`.
+    pub fn abort(&self) {
+        match self.0 {
+            AbortHandleKind::Native(ref handle) => handle.abort(),
+            AbortHandleKind::Mock(ref aborted) => aborted.store(true, Ordering::SeqCst),
+        }
+    }
+}
+
+impl RawMode for Mode {
+    fn disable_raw_mode(&self) -> Result<()> {
+        match self {
+            Mode::Native(mode) => mode.disable_raw_mode(),
+            Mode::Mock => Ok(()),
+        }
+    }
+}
+
+impl RawReader for Reader {
+    fn next_key(&mut self, single_esc_abort: bool) -> Result<KeyPress> {
+        match self {
+            Reader::Native(rdr) => rdr.next_key(single_esc_abort),
+            Reader::Mock(rdr) => rdr.next_key(single_esc_abort),
+        }
+    }
+
+    #[cfg(any(unix, windows))]
+    fn next_char(&mut self) -> Result<char> {
+        match self {
+            Reader::Native(rdr) => rdr.next_char(),
+            Reader::Mock(rdr) => rdr.next_char(),
+        }
+    }
+
+    fn read_pasted_text(&mut self) -> Result<String> {
+        match self {
+            Reader::Native(rdr) => rdr.read_pasted_text(),
+            Reader::Mock(rdr) => rdr.read_pasted_text(),
+        }
+    }
+
+    fn wait_for_input(&mut self, timeout_ms: i32) -> Result<bool> {
+        match self {
+            Reader::Native(rdr) => rdr.wait_for_input(timeout_ms),
+            Reader::Mock(rdr) => rdr.wait_for_input(timeout_ms),
+        }
+    }
+
+    fn take_bound_cmd(&mut self) -> Option<Cmd> {
+        match self {
+            Reader::Native(rdr) => rdr.take_bound_cmd(),
+            Reader::Mock(rdr) => rdr.take_bound_cmd(),
+        }
+    }
+
+    fn drain_input(&mut self) -> Result<()> {
+        match self {
+            Reader::Native(rdr) => rdr.drain_input(),
+            Reader::Mock(rdr) => rdr.drain_input(),
+        }
+    }
+}
+
+impl Renderer for Writer {
+    type Reader = Reader;
+
+    fn move_cursor(&mut self, old: Position, new: Position) -> Result<()> {
+        match self {
+            Writer::Native(out) => out.move_cursor(old, new),
+            Writer::Mock(out) => out.move_cursor(old, new),
+        }
+    }
+
+    fn refresh_line(
+        &mut self,
+        prompt: &str,
+        line: &LineBuffer,
+        hint: Option<&str>,
+        old_layout: &Layout,
+        new_layout: &Layout,
+        highlighter: Option<&dyn Highlighter>,
+    ) -> Result<()> {
+        match self {
+            Writer::Native(out) => {
+                out.refresh_line(prompt, line, hint, old_layout, new_layout, highlighter)
+            }
+            Writer::Mock(out) => {
+                out.refresh_line(prompt, line, hint, old_layout, new_layout, highlighter)
+            }
+        }
+    }
+
+    fn calculate_position(&self, s: &str, orig: Position) -> Position {
+        match self {
+            Writer::Native(out) => out.calculate_position(s, orig),
+            Writer::Mock(out) => out.calculate_position(s, orig),
+        }
+    }
+
+    fn write_and_flush(&self, buf: &[u8]) -> Result<()> {
+        match self {
+            Writer::Native(out) => out.write_and_flush(buf),
+            Writer::Mock(out) => out.write_and_flush(buf),
+        }
+    }
+
+    fn beep(&mut self) -> Result<()> {
+        match self {
+            Writer::Native(out) => out.beep(),
+            Writer::Mock(out) => out.beep(),
+        }
+    }
+
+    fn clear_screen(&mut self) -> Result<()> {
+        match self {
+            Writer::Native(out) => out.clear_screen(),
+            Writer::Mock(out) => out.clear_screen(),
+        }
+    }
+
+    fn save_cursor(&mut self) -> Result<()> {
+        match self {
+            Writer::Native(out) => out.save_cursor(),
+            Writer::Mock(out) => out.save_cursor(),
+        }
+    }
+
+    fn restore_cursor(&mut self) -> Result<()> {
+        match self {
+            Writer::Native(out) => out.restore_cursor(),
+            Writer::Mock(out) => out.restore_cursor(),
+        }
+    }
+
+    fn sigwinch(&self) -> bool {
+        match self {
+            Writer::Native(out) => out.sigwinch(),
+            Writer::Mock(out) => out.sigwinch(),
+        }
+    }
+
+    fn update_size(&mut self) {
+        match self {
+            Writer::Native(out) => out.update_size(),
+            Writer::Mock(out) => out.update_size(),
+        }
+    }
+
+    fn get_columns(&self) -> usize {
+        match self {
+            Writer::Native(out) => out.get_columns(),
+            Writer::Mock(out) => out.get_columns(),
+        }
+    }
+
+    fn get_rows(&self) -> usize {
+        match self {
+            Writer::Native(out) => out.get_rows(),
+            Writer::Mock(out) => out.get_rows(),
+        }
+    }
+
+    fn set_size(&mut self, size: WinSize) {
+        match self {
+            Writer::Native(out) => out.set_size(size),
+            Writer::Mock(out) => out.set_size(size),
+        }
+    }
+
+    fn colors_enabled(&self) -> bool {
+        match self {
+            Writer::Native(out) => out.colors_enabled(),
+            Writer::Mock(out) => out.colors_enabled(),
+        }
+    }
+
+    fn move_cursor_at_leftmost(&mut self, rdr: &mut Reader) -> Result<()> {
+        match (self, rdr) {
+            (Writer::Native(out), Reader::Native(rdr)) => out.move_cursor_at_leftmost(rdr),
+            (Writer::Mock(out), Reader::Mock(rdr)) => out.move_cursor_at_leftmost(rdr),
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Term for Terminal {
+    type Mode = Mode;
+    type AbortHandle = AbortHandle;
+    type Reader = Reader;
+    type Writer = Writer;
+
+    fn new(config: &Config) -> Terminal {
+        Terminal::Native(native::Terminal::new(config))
+    }
+
+    fn is_unsupported(&self) -> bool {
+        match self {
+            Terminal::Native(term) => term.is_unsupported(),
+            Terminal::Mock(_) => false,
+        }
+    }
+
+    fn is_stdin_tty(&self) -> bool {
+        match self {
+            Terminal::Native(term) => term.is_stdin_tty(),
+            Terminal::Mock(_) => true,
+        }
+    }
+
+    fn is_output_tty(&self) -> bool {
+        match self {
+            Terminal::Native(term) => term.is_output_tty(),
+            Terminal::Mock(_) => true,
+        }
+    }
+
+    fn enable_raw_mode(&mut self) -> Result<Mode> {
+        match self {
+            Terminal::Native(term) => term.enable_raw_mode().map(Mode::Native),
+            Terminal::Mock(_) => Ok(Mode::Mock),
+        }
+    }
+
+    fn create_reader(
+        &self,
+        config: &Config,
+        sequences: Arc<RwLock<SequenceTrie>>,
+    ) -> Result<Reader> {
+        match self {
+            Terminal::Native(term) => term.create_reader(config, sequences).map(Reader::Native),
+            Terminal::Mock(term) => Ok(Reader::Mock(MockReader {
+                state: Arc::clone(&term.state),
+                aborted: Arc::clone(&term.aborted),
+            })),
+        }
+    }
+
+    fn create_writer(&self) -> Writer {
+        match self {
+            Terminal::Native(term) => Writer::Native(term.create_writer()),
+            Terminal::Mock(term) => Writer::Mock(MockRenderer {
+                state: Arc::clone(&term.state),
+                size: MOCK_SIZE,
+                colors_enabled: term.color_mode != ColorMode::Disabled,
+            }),
+        }
+    }
+
+    fn abort_handle(&self) -> AbortHandle {
+        match self {
+            Terminal::Native(term) => AbortHandle(AbortHandleKind::Native(term.abort_handle())),
+            Terminal::Mock(term) => AbortHandle(AbortHandleKind::Mock(Arc::clone(&term.aborted))),
+        }
+    }
+
+    fn update_config(&mut self, config: &Config) {
+        match self {
+            Terminal::Native(term) => term.update_config(config),
+            // the mock renderer never rings
+            Terminal::Mock(term) => term.color_mode = config.color_mode(),
+        }
+    }
+
+    #[cfg(unix)]
+    fn set_fds(
+        &mut self,
+        input: RawFd,
+        output: OutputStreamType,
+    ) -> Result<(RawFd, OutputStreamType)> {
+        match self {
+            Terminal::Native(term) => term.set_fds(input, output),
+            // keys are not read from a file descriptor
+            Terminal::Mock(_) => Ok((input, output)),
+        }
+    }
+}
+
+pub use self::native::stdout_size;
+#[cfg(unix)]
+pub use self::native::{sigcont, suspend};
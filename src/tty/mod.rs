@@ -1,6 +1,21 @@
 //! This module implements and describes common TTY methods & traits
-use crate::config::{BellStyle, ColorMode, Config, OutputStreamType};
+use crate::config::Config;
+#[cfg(unix)]
+use crate::config::OutputStreamType;
+#[cfg(unix)]
+use std::io;
+use std::ops::Deref;
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, RwLock};
+
+#[cfg(not(feature = "tracing"))]
+use log::warn;
+#[cfg(feature = "tracing")]
+use tracing::warn;
+
 use crate::highlight::Highlighter;
+use crate::keymap::{Cmd, SequenceTrie};
 use crate::keys::KeyPress;
 use crate::layout::{Layout, Position};
 use crate::line_buffer::LineBuffer;
@@ -12,15 +27,51 @@ pub trait RawMode: Sized {
     fn disable_raw_mode(&self) -> Result<()>;
 }
 
+/// Disable RAW mode when dropped
+pub struct RawModeGuard<M: RawMode>(M);
+
+impl<M: RawMode> RawModeGuard<M> {
+    pub fn new(mode: M) -> Self {
+        RawModeGuard(mode)
+    }
+}
+
+impl<M: RawMode> Deref for RawModeGuard<M> {
+    type Target = M;
+
+    fn deref(&self) -> &M {
+        &self.0
+    }
+}
+
+impl<M: RawMode> Drop for RawModeGuard<M> {
+    fn drop(&mut self) {
+        if let Err(e) = self.0.disable_raw_mode() {
+            warn!(target: "rustyline", "cannot disable raw mode: {}", e);
+        }
+    }
+}
+
 /// Translate bytes read from stdin to keys.
 pub trait RawReader {
     /// Blocking read of key pressed.
     fn next_key(&mut self, single_esc_abort: bool) -> Result<KeyPress>;
     /// For CTRL-V support
-    #[cfg(unix)]
+    #[cfg(any(unix, windows))]
     fn next_char(&mut self) -> Result<char>;
     /// Bracketed paste
     fn read_pasted_text(&mut self) -> Result<String>;
+    /// Wait at most `timeout_ms` for a key to be available.
+    fn wait_for_input(&mut self, timeout_ms: i32) -> Result<bool>;
+    /// Command bound to the raw sequence consumed by the last `next_key`
+    /// call (see `Editor::bind_raw_sequence`)
+    fn take_bound_cmd(&mut self) -> Option<Cmd> {
+        None
+    }
+    /// Discard the input already typed but not read yet
+    fn drain_input(&mut self) -> Result<()> {
+        Ok(())
+    }
 }
 
 /// Display prompt, line and cursor in terminal output
@@ -30,6 +81,7 @@ pub trait Renderer {
     fn move_cursor(&mut self, old: Position, new: Position) -> Result<()>;
 
     /// Display `prompt`, line and cursor in terminal output
+    /// (`hint` is already highlighted)
     #[allow(clippy::too_many_arguments)]
     fn refresh_line(
         &mut self,
@@ -54,6 +106,12 @@ pub trait Renderer {
     /// Clear the screen. Used to handle ctrl+l
     fn clear_screen(&mut self) -> Result<()>;
 
+    /// Save the cursor position, to draw somewhere else on the screen
+    /// (the position is lost if the screen scrolls)
+    fn save_cursor(&mut self) -> Result<()>;
+    /// Move the cursor back to the position saved by `save_cursor`
+    fn restore_cursor(&mut self) -> Result<()>;
+
     /// Check if a SIGWINCH signal has been received
     fn sigwinch(&self) -> bool;
     /// Update the number of columns/rows in the current terminal.
@@ -62,6 +120,9 @@ pub trait Renderer {
     fn get_columns(&self) -> usize;
     /// Get the number of rows in the current terminal.
     fn get_rows(&self) -> usize;
+    /// Use `size` instead of the actual size of the terminal, which is not
+    /// queried anymore (see `Editor::resize`).
+    fn set_size(&mut self, size: WinSize);
     /// Check if output supports colors.
     fn colors_enabled(&self) -> bool;
 
@@ -104,6 +165,14 @@ impl<'a, R: Renderer + ?Sized> Renderer for &'a mut R {
         (**self).clear_screen()
     }
 
+    fn save_cursor(&mut self) -> Result<()> {
+        (**self).save_cursor()
+    }
+
+    fn restore_cursor(&mut self) -> Result<()> {
+        (**self).restore_cursor()
+    }
+
     fn sigwinch(&self) -> bool {
         (**self).sigwinch()
     }
@@ -120,6 +189,10 @@ impl<'a, R: Renderer + ?Sized> Renderer for &'a mut R {
         (**self).get_rows()
     }
 
+    fn set_size(&mut self, size: WinSize) {
+        (**self).set_size(size)
+    }
+
     fn colors_enabled(&self) -> bool {
         (**self).colors_enabled()
     }
@@ -134,13 +207,9 @@ pub trait Term {
     type Reader: RawReader; // rl_instream
     type Writer: Renderer<Reader = Self::Reader>; // rl_outstream
     type Mode: RawMode;
+    type AbortHandle;
 
-    fn new(
-        color_mode: ColorMode,
-        stream: OutputStreamType,
-        tab_stop: usize,
-        bell_style: BellStyle,
-    ) -> Self;
+    fn new(config: &Config) -> Self;
     /// Check if current terminal can provide a rich line-editing user
     /// interface.
     fn is_unsupported(&self) -> bool;
@@ -151,24 +220,292 @@ pub trait Term {
     /// Enable RAW mode for the terminal.
     fn enable_raw_mode(&mut self) -> Result<Self::Mode>;
     /// Create a RAW reader
-    fn create_reader(&self, config: &Config) -> Result<Self::Reader>;
+    fn create_reader(
+        &self,
+        config: &Config,
+        sequences: Arc<RwLock<SequenceTrie>>,
+    ) -> Result<Self::Reader>;
     /// Create a writer
     fn create_writer(&self) -> Self::Writer;
+    /// Create a handle to interrupt `readline` from another thread
+    fn abort_handle(&self) -> Self::AbortHandle;
+    /// Apply the terminal settings of `config` (except the output stream) to
+    /// the writers and RAW modes created afterwards
+    fn update_config(&mut self, config: &Config);
+    /// Read from `input` and write to `output` instead of the current
+    /// streams. Returns the previous input and output.
+    #[cfg(unix)]
+    fn set_fds(
+        &mut self,
+        input: RawFd,
+        output: OutputStreamType,
+    ) -> Result<(RawFd, OutputStreamType)> {
+        let _ = (input, output);
+        Err(io::Error::from(io::ErrorKind::Other).into())
+    }
+}
+
+/// Number of columns used to display the grapheme cluster `g`.
+///
+/// An emoji presentation selector (VS16) makes terminals render the cluster
+/// on two columns while a text presentation selector (VS15) makes them use
+/// only one, whatever the width of the base character is.
+/// Emoji joined by a zero width joiner (ZWJ) are displayed as a single emoji.
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+pub(crate) fn grapheme_width(g: &str) -> usize {
+    use unicode_width::UnicodeWidthStr;
+    if g.contains('\u{FE0F}') {
+        2
+    } else if g.contains('\u{FE0E}') {
+        1
+    } else if g.contains('\u{200D}') {
+        g.width().min(2)
+    } else {
+        g.width()
+    }
+}
+
+/// Terminal dimensions
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WinSize {
+    /// Number of columns
+    pub cols: usize,
+    /// Number of rows
+    pub rows: usize,
+}
+
+impl From<WinSize> for (usize, usize) {
+    fn from(size: WinSize) -> Self {
+        (size.cols, size.rows)
+    }
+}
+
+impl From<(usize, usize)> for WinSize {
+    fn from((cols, rows): (usize, usize)) -> Self {
+        WinSize { cols, rows }
+    }
+}
+
+/// Return the number of columns and rows of the terminal connected to stdout.
+///
+/// When its size cannot be queried (stdout is not a terminal for example),
+/// 80x24 is returned, unless the `COLUMNS` and `LINES` environment variables
+/// are set (unix only).
+pub fn terminal_size() -> WinSize {
+    stdout_size()
+}
+
+/// Unsupported Terminals that don't support RAW mode
+const UNSUPPORTED_TERM: [&str; 3] = ["dumb", "cons25", "emacs"];
+
+/// Terminal features guessed from the environment: `TERM`, `TERM_PROGRAM`,
+/// `TERM_PROGRAM_VERSION`, `COLORTERM` and `VTE_VERSION`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TerminalCapabilities {
+    /// Terminal emulator name (`TERM_PROGRAM`)
+    pub program: Option<String>,
+    /// Terminal emulator version (`TERM_PROGRAM_VERSION`)
+    pub program_version: Option<String>,
+    /// RAW mode and escape sequences are not supported (`TERM` is `dumb`,
+    /// `cons25` or `emacs`)
+    pub unsupported: bool,
+    /// 24-bit colors
+    pub true_color: bool,
+    /// Clipboard access with OSC 52
+    pub osc52_clipboard: bool,
+    /// Sixel graphics
+    pub sixel: bool,
+    /// Kitty keyboard protocol
+    pub kitty_keyboard: bool,
+}
+
+impl TerminalCapabilities {
+    /// Inspect the environment of the current process.
+    pub fn detect() -> Self {
+        Self::from_env(|name| std::env::var(name).ok())
+    }
+
+    fn from_env<F: Fn(&str) -> Option<String>>(var: F) -> Self {
+        let term = var("TERM").unwrap_or_default();
+        let program = var("TERM_PROGRAM");
+        let colorterm = var("COLORTERM").unwrap_or_default();
+        let vte_version = var("VTE_VERSION").and_then(|v| v.parse::<u32>().ok());
+        let name = program.as_deref().unwrap_or_default();
+        let kitty = term == "xterm-kitty" || name == "kitty";
+        let iterm = name == "iTerm.app";
+        let wezterm = name == "WezTerm";
+        let alacritty = term == "alacritty" || name == "Alacritty";
+        // tmux >= 3.2 forwards OSC 52 to the outer terminal
+        let tmux = var("TMUX").is_some()
+            && name == "tmux"
+            && var("TERM_PROGRAM_VERSION").and_then(|v| tmux_version(&v)) >= Some((3, 2));
+        Self {
+            unsupported: UNSUPPORTED_TERM
+                .iter()
+                .any(|unsupported| unsupported.eq_ignore_ascii_case(&term)),
+            true_color: colorterm == "truecolor"
+                || colorterm == "24bit"
+                || kitty
+                || iterm
+                || wezterm
+                || alacritty
+                // VTE 0.36
+                || vte_version >= Some(3600),
+            osc52_clipboard: kitty || iterm || wezterm || alacritty || tmux,
+            sixel: iterm || wezterm,
+            kitty_keyboard: kitty,
+            program_version: var("TERM_PROGRAM_VERSION"),
+            program,
+        }
+    }
+}
+
+/// Major and minor numbers of a tmux version like `3.2a`
+fn tmux_version(version: &str) -> Option<(u32, u32)> {
+    let mut parts = version.splitn(2, '.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?;
+    let digits = minor
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(minor.len());
+    Some((major, minor[..digits].parse().ok()?))
 }
 
 cfg_if::cfg_if! {
     if #[cfg(any(test, target_arch = "wasm32"))] {
         mod test;
         pub use self::test::*;
-    } else if #[cfg(windows)] {
-        // If on Windows platform import Windows TTY module
-        // and re-export into mod.rs scope
-        mod windows;
-        pub use self::windows::*;
-    } else if #[cfg(unix)] {
-        // If on Unix platform import Unix TTY module
-        // and re-export into mod.rs scope
-        mod unix;
-        pub use self::unix::*;
+    } else {
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "crossterm")] {
+                // Same TTY module on all platforms
+                #[path = "crossterm.rs"]
+                mod native;
+            } else if #[cfg(all(
+                windows,
+                any(feature = "windows-backend", not(feature = "unix-backend"))
+            ))] {
+                // If on Windows platform import Windows TTY module
+                // (also when no backend feature is selected)
+                #[path = "windows.rs"]
+                mod native;
+            } else if #[cfg(all(
+                unix,
+                any(feature = "unix-backend", not(feature = "windows-backend"))
+            ))] {
+                // If on Unix platform import Unix TTY module
+                // (also when no backend feature is selected)
+                #[path = "unix.rs"]
+                mod native;
+            } else {
+                compile_error!(
+                    "the selected terminal backend is not available on this platform: \
+                     enable the `crossterm` feature or the backend of this platform"
+                );
+            }
+        }
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "mock")] {
+                // Dispatch between the platform terminal and `MockTerminal`
+                mod mock;
+                pub use self::mock::*;
+            } else {
+                // Re-export the platform terminal into mod.rs scope
+                pub use self::native::*;
+            }
+        }
+    }
+}
+
+// `cargo test` swaps the terminal for `test`: still build the Unix terminal
+// so that its escape sequence parser and renderer are tested.
+#[cfg(all(test, unix))]
+#[path = "unix.rs"]
+#[allow(dead_code)]
+mod unix;
+
+#[cfg(test)]
+mod tests {
+    use super::{grapheme_width, RawMode, RawModeGuard, TerminalCapabilities};
+    use std::cell::Cell;
+
+    fn capabilities(vars: &[(&str, &str)]) -> TerminalCapabilities {
+        TerminalCapabilities::from_env(|name| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| (*value).to_owned())
+        })
+    }
+
+    #[test]
+    fn terminal_capabilities() {
+        assert_eq!(TerminalCapabilities::default(), capabilities(&[]));
+        assert!(capabilities(&[("TERM", "dumb")]).unsupported);
+        assert!(!capabilities(&[("TERM", "xterm")]).unsupported);
+
+        let iterm = capabilities(&[
+            ("TERM", "xterm-256color"),
+            ("TERM_PROGRAM", "iTerm.app"),
+            ("TERM_PROGRAM_VERSION", "3.4.19"),
+        ]);
+        assert_eq!(Some("iTerm.app"), iterm.program.as_deref());
+        assert_eq!(Some("3.4.19"), iterm.program_version.as_deref());
+        assert!(iterm.true_color && iterm.osc52_clipboard && iterm.sixel);
+        assert!(!iterm.kitty_keyboard);
+
+        let kitty = capabilities(&[("TERM", "xterm-kitty")]);
+        assert!(kitty.true_color && kitty.kitty_keyboard && !kitty.sixel);
+
+        assert!(capabilities(&[("COLORTERM", "truecolor")]).true_color);
+        assert!(capabilities(&[("VTE_VERSION", "6003")]).true_color);
+        assert!(!capabilities(&[("VTE_VERSION", "3405")]).true_color);
+
+        let tmux = |version| {
+            capabilities(&[
+                ("TMUX", "/tmp/tmux-1000/default,1234,0"),
+                ("TERM_PROGRAM", "tmux"),
+                ("TERM_PROGRAM_VERSION", version),
+            ])
+        };
+        assert!(tmux("3.2a").osc52_clipboard);
+        assert!(tmux("3.10").osc52_clipboard);
+        assert!(!tmux("3.1c").osc52_clipboard);
+        assert!(!tmux("next-3.4").osc52_clipboard);
+    }
+
+    #[test]
+    fn variation_selectors() {
+        assert_eq!(1, grapheme_width("a"));
+        assert_eq!(2, grapheme_width("\u{4E2D}"));
+        // HEAVY BLACK HEART
+        assert_eq!(1, grapheme_width("\u{2764}"));
+        assert_eq!(2, grapheme_width("\u{2764}\u{FE0F}"));
+        // UMBRELLA WITH RAIN DROPS
+        assert_eq!(1, grapheme_width("\u{2614}\u{FE0E}"));
+    }
+
+    #[test]
+    fn zwj_sequences() {
+        // family: man, woman, girl, boy
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}\u{200D}\u{1F466}";
+        assert_eq!(2, grapheme_width(family));
+        assert_eq!(1, grapheme_width("a\u{200D}"));
+    }
+
+    struct Mode<'a>(&'a Cell<usize>);
+    impl RawMode for Mode<'_> {
+        fn disable_raw_mode(&self) -> crate::Result<()> {
+            self.0.set(self.0.get() + 1);
+            Err(crate::error::ReadlineError::Eof)
+        }
+    }
+
+    #[test]
+    fn raw_mode_guard() {
+        let disabled = Cell::new(0);
+        let guard = RawModeGuard::new(Mode(&disabled));
+        assert_eq!(0, disabled.get());
+        drop(guard);
+        assert_eq!(1, disabled.get());
     }
 }
@@ -1,12 +1,19 @@
 //! Tests specific definitions
 use std::iter::IntoIterator;
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
 use std::slice::Iter;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
 use std::vec::IntoIter;
 
-use super::{RawMode, RawReader, Renderer, Term};
-use crate::config::{BellStyle, ColorMode, Config, OutputStreamType};
+use super::{RawMode, RawReader, Renderer, Term, WinSize};
+#[cfg(unix)]
+use crate::config::OutputStreamType;
+use crate::config::{BellStyle, ColorMode, Config};
 use crate::error::ReadlineError;
 use crate::highlight::Highlighter;
+use crate::keymap::SequenceTrie;
 use crate::keys::KeyPress;
 use crate::layout::{Layout, Position};
 use crate::line_buffer::LineBuffer;
@@ -28,7 +35,7 @@ impl<'a> RawReader for Iter<'a, KeyPress> {
         }
     }
 
-    #[cfg(unix)]
+    #[cfg(any(unix, windows))]
     fn next_char(&mut self) -> Result<char> {
         unimplemented!();
     }
@@ -36,6 +43,10 @@ impl<'a> RawReader for Iter<'a, KeyPress> {
     fn read_pasted_text(&mut self) -> Result<String> {
         unimplemented!()
     }
+
+    fn wait_for_input(&mut self, _: i32) -> Result<bool> {
+        Ok(self.len() > 0)
+    }
 }
 
 impl RawReader for IntoIter<KeyPress> {
@@ -46,7 +57,7 @@ impl RawReader for IntoIter<KeyPress> {
         }
     }
 
-    #[cfg(unix)]
+    #[cfg(any(unix, windows))]
     fn next_char(&mut self) -> Result<char> {
         match self.next() {
             Some(KeyPress::Char(c)) => Ok(c),
@@ -58,13 +69,21 @@ impl RawReader for IntoIter<KeyPress> {
     fn read_pasted_text(&mut self) -> Result<String> {
         unimplemented!()
     }
+
+    fn wait_for_input(&mut self, _: i32) -> Result<bool> {
+        Ok(self.len() > 0)
+    }
 }
 
-pub struct Sink {}
+pub struct Sink {
+    size: WinSize,
+}
 
 impl Sink {
     pub fn new() -> Sink {
-        Sink {}
+        Sink {
+            size: WinSize { cols: 80, rows: 24 },
+        }
     }
 }
 
@@ -105,6 +124,14 @@ impl Renderer for Sink {
         Ok(())
     }
 
+    fn save_cursor(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn restore_cursor(&mut self) -> Result<()> {
+        Ok(())
+    }
+
     fn sigwinch(&self) -> bool {
         false
     }
@@ -112,11 +139,15 @@ impl Renderer for Sink {
     fn update_size(&mut self) {}
 
     fn get_columns(&self) -> usize {
-        80
+        self.size.cols
     }
 
     fn get_rows(&self) -> usize {
-        24
+        self.size.rows
+    }
+
+    fn set_size(&mut self, size: WinSize) {
+        self.size = size;
     }
 
     fn colors_enabled(&self) -> bool {
@@ -130,30 +161,48 @@ impl Renderer for Sink {
 
 pub type Terminal = DummyTerminal;
 
+/// Handle to interrupt `readline` (see `Editor::abort_handle`)
+#[derive(Clone, Debug)]
+pub struct AbortHandle {
+    aborted: Arc<AtomicBool>,
+}
+
+impl AbortHandle {
+    /// Make the pending or next `readline` call return
+    /// `ReadlineError::Interrupted`.
+    pub fn abort(&self) {
+        self.aborted.store(true, Ordering::SeqCst);
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct DummyTerminal {
     pub keys: Vec<KeyPress>,
     pub cursor: usize, // cursor position before last command
     pub color_mode: ColorMode,
     pub bell_style: BellStyle,
+    pub hyperlinks: bool,
+    pub focus_events: bool,
+    pub bracketed_paste: bool,
+    aborted: Arc<AtomicBool>,
 }
 
 impl Term for DummyTerminal {
     type Mode = Mode;
+    type AbortHandle = AbortHandle;
     type Reader = IntoIter<KeyPress>;
     type Writer = Sink;
 
-    fn new(
-        color_mode: ColorMode,
-        _stream: OutputStreamType,
-        _tab_stop: usize,
-        bell_style: BellStyle,
-    ) -> DummyTerminal {
+    fn new(config: &Config) -> DummyTerminal {
         DummyTerminal {
             keys: Vec::new(),
             cursor: 0,
-            color_mode,
-            bell_style,
+            color_mode: config.color_mode(),
+            bell_style: config.bell_style(),
+            hyperlinks: config.hyperlinks_enabled(),
+            focus_events: config.enable_focus_events(),
+            bracketed_paste: config.enable_bracketed_paste(),
+            aborted: Arc::new(AtomicBool::new(false)),
         }
     }
 
@@ -177,16 +226,55 @@ impl Term for DummyTerminal {
         Ok(())
     }
 
-    fn create_reader(&self, _: &Config) -> Result<IntoIter<KeyPress>> {
+    fn create_reader(
+        &self,
+        _: &Config,
+        _: Arc<RwLock<SequenceTrie>>,
+    ) -> Result<IntoIter<KeyPress>> {
+        if self.aborted.swap(false, Ordering::SeqCst) {
+            return Err(ReadlineError::Interrupted);
+        }
         Ok(self.keys.clone().into_iter())
     }
 
     fn create_writer(&self) -> Sink {
         Sink::new()
     }
+
+    fn abort_handle(&self) -> AbortHandle {
+        AbortHandle {
+            aborted: Arc::clone(&self.aborted),
+        }
+    }
+
+    fn update_config(&mut self, config: &Config) {
+        self.color_mode = config.color_mode();
+        self.bell_style = config.bell_style();
+        self.hyperlinks = config.hyperlinks_enabled();
+        self.focus_events = config.enable_focus_events();
+        self.bracketed_paste = config.enable_bracketed_paste();
+    }
+
+    #[cfg(unix)]
+    fn set_fds(
+        &mut self,
+        input: RawFd,
+        output: OutputStreamType,
+    ) -> Result<(RawFd, OutputStreamType)> {
+        Ok((input, output))
+    }
+}
+
+pub fn stdout_size() -> WinSize {
+    WinSize { cols: 80, rows: 24 }
 }
 
 #[cfg(unix)]
 pub fn suspend() -> Result<()> {
     Ok(())
 }
+
+#[cfg(unix)]
+pub fn sigcont() -> bool {
+    false
+}
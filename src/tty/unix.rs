@@ -1,26 +1,35 @@
 //! Unix specific definitions
 use std;
+use std::borrow::Cow::{self, Borrowed, Owned};
+use std::cell::Cell;
 use std::cmp::Ordering;
+use std::collections::VecDeque;
 use std::io::{self, Read, Write};
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::sync;
 use std::sync::atomic;
+use std::sync::{Arc, RwLock};
 
 use libc;
+#[cfg(not(feature = "tracing"))]
 use log::{debug, warn};
+#[cfg(feature = "tracing")]
+use tracing::{debug, warn};
 use nix;
 use nix::poll::{self, PollFlags};
 use nix::sys::signal;
 use nix::sys::termios;
 use nix::sys::termios::SetArg;
+use nix::sys::uio::{self, IoVec};
+use nix::unistd;
 use unicode_segmentation::UnicodeSegmentation;
-use unicode_width::UnicodeWidthStr;
 use utf8parse::{Parser, Receiver};
 
-use super::{RawMode, RawReader, Renderer, Term};
+use super::{grapheme_width, RawMode, RawReader, Renderer, Term, TerminalCapabilities, WinSize};
 use crate::config::{BellStyle, ColorMode, Config, OutputStreamType};
 use crate::error;
-use crate::highlight::Highlighter;
+use crate::highlight::{render_spans, Highlighter};
+use crate::keymap::{Cmd, SequenceTrie, MAX_SEQUENCE_LEN};
 use crate::keys::{self, KeyPress};
 use crate::layout::{Layout, Position};
 use crate::line_buffer::LineBuffer;
@@ -28,17 +37,22 @@ use crate::Result;
 
 const STDIN_FILENO: RawFd = libc::STDIN_FILENO;
 
-/// Unsupported Terminals that don't support RAW mode
-const UNSUPPORTED_TERM: [&str; 3] = ["dumb", "cons25", "emacs"];
-
 const BRACKETED_PASTE_ON: &[u8] = b"\x1b[?2004h";
 const BRACKETED_PASTE_OFF: &[u8] = b"\x1b[?2004l";
+const FOCUS_EVENTS_ON: &[u8] = b"\x1b[?1004h";
+const FOCUS_EVENTS_OFF: &[u8] = b"\x1b[?1004l";
+const HIDE_CURSOR: &str = "\x1b[?25l";
+const SHOW_CURSOR: &str = "\x1b[?25h";
+/// DECSCNM: reverse video, used for the visible bell
+const FLASH_ON: &[u8] = b"\x1b[?5h";
+const FLASH_OFF: &[u8] = b"\x1b[?5l";
 
 impl AsRawFd for OutputStreamType {
     fn as_raw_fd(&self) -> RawFd {
         match self {
             OutputStreamType::Stdout => libc::STDOUT_FILENO,
             OutputStreamType::Stderr => libc::STDERR_FILENO,
+            OutputStreamType::Fd(fd) => *fd,
         }
     }
 }
@@ -46,31 +60,37 @@ impl AsRawFd for OutputStreamType {
 nix::ioctl_read_bad!(win_size, libc::TIOCGWINSZ, libc::winsize);
 
 #[allow(clippy::identity_conversion)]
-fn get_win_size<T: AsRawFd + ?Sized>(fileno: &T) -> (usize, usize) {
+fn get_win_size<T: AsRawFd + ?Sized>(fileno: &T) -> WinSize {
     use std::mem::zeroed;
 
     unsafe {
         let mut size: libc::winsize = zeroed();
         match win_size(fileno.as_raw_fd(), &mut size) {
-            Ok(0) => (size.ws_col as usize, size.ws_row as usize), // TODO getCursorPosition
-            _ => (80, 24),
+            Ok(0) if size.ws_col > 0 && size.ws_row > 0 => WinSize {
+                cols: size.ws_col as usize,
+                rows: size.ws_row as usize,
+            }, // TODO getCursorPosition
+            _ => WinSize {
+                cols: env_size("COLUMNS", 80),
+                rows: env_size("LINES", 24),
+            },
         }
     }
 }
 
-/// Check TERM environment variable to see if current term is in our
-/// unsupported list
-fn is_unsupported_term() -> bool {
-    match std::env::var("TERM") {
-        Ok(term) => {
-            for iter in &UNSUPPORTED_TERM {
-                if (*iter).eq_ignore_ascii_case(&term) {
-                    return true;
-                }
-            }
-            false
-        }
-        Err(_) => false,
+pub fn stdout_size() -> WinSize {
+    get_win_size(&OutputStreamType::Stdout)
+}
+
+/// Terminal dimension from the `name` environment variable, or `default`
+/// when it is not set or invalid.
+fn env_size(name: &str, default: usize) -> usize {
+    match std::env::var(name)
+        .ok()
+        .and_then(|v| v.trim().parse::<usize>().ok())
+    {
+        Some(n) if n > 0 => n.min(usize::from(u16::MAX)),
+        _ => default,
     }
 }
 
@@ -81,7 +101,10 @@ fn is_a_tty(fd: RawFd) -> bool {
 
 pub struct PosixMode {
     termios: termios::Termios,
+    input: RawFd,
     out: Option<OutputStreamType>,
+    bracketed_paste: bool,
+    focus_events: bool,
 }
 
 #[cfg(not(test))]
@@ -90,10 +113,14 @@ pub type Mode = PosixMode;
 impl RawMode for PosixMode {
     /// Disable RAW mode for the terminal.
     fn disable_raw_mode(&self) -> Result<()> {
-        termios::tcsetattr(STDIN_FILENO, SetArg::TCSADRAIN, &self.termios)?;
-        // disable bracketed paste
+        termios::tcsetattr(self.input, SetArg::TCSADRAIN, &self.termios)?;
         if let Some(out) = self.out {
-            write_and_flush(out, BRACKETED_PASTE_OFF)?;
+            if self.bracketed_paste {
+                write_and_flush(out, BRACKETED_PASTE_OFF)?;
+            }
+            if self.focus_events {
+                write_and_flush(out, FOCUS_EVENTS_OFF)?;
+            }
         }
         Ok(())
     }
@@ -101,23 +128,24 @@ impl RawMode for PosixMode {
 
 // Rust std::io::Stdin is buffered with no way to know if bytes are available.
 // So we use low-level stuff instead...
-struct StdinRaw {}
+/// Raw reader of an input file descriptor (not closed on drop)
+struct StdinRaw {
+    fd: RawFd,
+}
 
 impl Read for StdinRaw {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         loop {
             let res = unsafe {
                 libc::read(
-                    STDIN_FILENO,
+                    self.fd,
                     buf.as_mut_ptr() as *mut libc::c_void,
                     buf.len() as libc::size_t,
                 )
             };
             if res == -1 {
                 let error = io::Error::last_os_error();
-                if error.kind() != io::ErrorKind::Interrupted
-                    || SIGWINCH.load(atomic::Ordering::Relaxed)
-                {
+                if error.kind() != io::ErrorKind::Interrupted || signal_received() {
                     return Err(error);
                 }
             } else {
@@ -128,13 +156,73 @@ impl Read for StdinRaw {
     }
 }
 
+/// Self-pipe used to interrupt a blocked read on stdin
+#[derive(Debug)]
+struct AbortPipe {
+    read: RawFd,
+    write: RawFd,
+}
+
+impl AbortPipe {
+    fn new() -> nix::Result<AbortPipe> {
+        use nix::fcntl::{fcntl, FcntlArg, FdFlag, OFlag};
+        let (read, write) = unistd::pipe()?;
+        let pipe = AbortPipe { read, write };
+        for fd in &[read, write] {
+            fcntl(*fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+            fcntl(*fd, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+        }
+        Ok(pipe)
+    }
+
+    /// Consume pending abort requests.
+    fn drain(&self) {
+        let mut buf = [0; 16];
+        while let Ok(n) = unistd::read(self.read, &mut buf) {
+            if n == 0 {
+                break;
+            }
+        }
+    }
+}
+
+impl Drop for AbortPipe {
+    fn drop(&mut self) {
+        let _ = unistd::close(self.read);
+        let _ = unistd::close(self.write);
+    }
+}
+
+/// Handle to interrupt `readline` from another thread
+/// (see `Editor::abort_handle`)
+#[derive(Clone, Debug)]
+pub struct AbortHandle {
+    pipe: Option<Arc<AbortPipe>>,
+}
+
+impl AbortHandle {
+    /// Make the pending or next `readline` call return
+    /// `ReadlineError::Interrupted`.
+    pub fn abort(&self) {
+        if let Some(ref pipe) = self.pipe {
+            // the pipe is non-blocking: ignore EAGAIN if it is already full
+            let _ = unistd::write(pipe.write, &[0]);
+        }
+    }
+}
+
 /// Console input reader
 pub struct PosixRawReader {
     stdin: StdinRaw,
+    abort: Option<Arc<AbortPipe>>,
     timeout_ms: i32,
     buf: [u8; 1],
+    /// Bytes read but not matched by a raw sequence
+    pending: VecDeque<u8>,
     parser: Parser,
     receiver: Utf8,
+    sequences: Arc<RwLock<SequenceTrie>>,
+    bound_cmd: Option<Cmd>,
 }
 
 struct Utf8 {
@@ -143,23 +231,125 @@ struct Utf8 {
 }
 
 impl PosixRawReader {
-    fn new(config: &Config) -> Result<Self> {
+    /// Create a reader of `fd` instead of stdin.
+    /// `fd` is owned by the caller: it is not closed by the reader.
+    fn new_from_fd(
+        fd: RawFd,
+        config: &Config,
+        sequences: Arc<RwLock<SequenceTrie>>,
+        abort: Option<Arc<AbortPipe>>,
+    ) -> Result<Self> {
         Ok(Self {
-            stdin: StdinRaw {},
+            stdin: StdinRaw { fd },
+            abort,
             timeout_ms: config.keyseq_timeout(),
             buf: [0; 1],
+            pending: VecDeque::with_capacity(MAX_SEQUENCE_LEN),
             parser: Parser::new(),
             receiver: Utf8 {
                 c: None,
                 valid: true,
             },
+            sequences,
+            bound_cmd: None,
         })
     }
 
+    fn next_byte(&mut self) -> Result<u8> {
+        if let Some(b) = self.pending.pop_front() {
+            return Ok(b);
+        }
+        self.wait_for_stdin()?;
+        let n = self.stdin.read(&mut self.buf)?;
+        if n == 0 {
+            return Err(error::ReadlineError::Eof);
+        }
+        Ok(self.buf[0])
+    }
+
+    /// Block until stdin is readable or `AbortHandle::abort` is called.
+    fn wait_for_stdin(&mut self) -> Result<()> {
+        let abort = match self.abort {
+            Some(ref abort) => abort,
+            None => return Ok(()),
+        };
+        let mut fds = [
+            poll::PollFd::new(self.stdin.fd, PollFlags::POLLIN),
+            poll::PollFd::new(abort.read, PollFlags::POLLIN),
+        ];
+        loop {
+            match poll::poll(&mut fds, -1) {
+                Ok(_) => break,
+                Err(nix::Error::Sys(nix::errno::Errno::EINTR)) if !signal_received() => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let aborted = match fds[1].revents() {
+            Some(revents) => revents.contains(PollFlags::POLLIN),
+            None => false,
+        };
+        if aborted {
+            abort.drain();
+            return Err(error::ReadlineError::Interrupted);
+        }
+        Ok(())
+    }
+
+    /// Try to match the longest raw sequence bound by the user.
+    /// Unmatched bytes are kept for the default key parser.
+    fn bound_sequence(&mut self) -> Result<Option<Cmd>> {
+        let sequences = Arc::clone(&self.sequences);
+        let sequences = sequences.read().unwrap();
+        if sequences.is_empty() {
+            return Ok(None);
+        }
+        let mut seq = Vec::with_capacity(MAX_SEQUENCE_LEN);
+        let mut found = None;
+        loop {
+            if !seq.is_empty() && self.poll(self.timeout_ms)? == 0 {
+                break;
+            }
+            seq.push(self.next_byte()?);
+            match sequences.lookup(&seq) {
+                Some((cmd, longer)) => {
+                    if let Some(cmd) = cmd {
+                        found = Some((seq.len(), cmd.clone()));
+                    }
+                    if !longer {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+        let matched = found.as_ref().map_or(0, |(len, _)| *len);
+        for b in seq.drain(matched..).rev() {
+            self.pending.push_front(b);
+        }
+        Ok(found.map(|(_, cmd)| cmd))
+    }
+
+    /// Read `;` separated numeric parameters up to (and including) the final
+    /// character of a CSI sequence.
+    fn csi_params(&mut self) -> Result<(Vec<u32>, char)> {
+        let mut params = vec![];
+        let mut param = None;
+        loop {
+            let c = self.next_char()?;
+            if let Some(digit) = c.to_digit(10) {
+                let n: u32 = param.unwrap_or(0);
+                param = Some(n.saturating_mul(10).saturating_add(digit));
+            } else {
+                params.push(param.take().unwrap_or(0));
+                if c != ';' {
+                    return Ok((params, c));
+                }
+            }
+        }
+    }
+
     /// Handle ESC <seq1> sequences
-    fn escape_sequence(&mut self) -> Result<KeyPress> {
-        // Read the next byte representing the escape sequence.
-        let seq1 = self.next_char()?;
+    fn escape_sequence(&mut self, seq1: char) -> Result<KeyPress> {
         if seq1 == '[' {
             // ESC [ sequences. (CSI)
             self.escape_csi()
@@ -190,6 +380,35 @@ impl PosixRawReader {
                     self.extended_escape(seq2)
                 }
             }
+        } else if seq2 == 'M' {
+            // X10 mouse: ESC [ M <btn> <col> <row>, each offset by 32
+            let btn = self.next_byte()?.wrapping_sub(32);
+            let col = u16::from(self.next_byte()?.saturating_sub(33));
+            let row = u16::from(self.next_byte()?.saturating_sub(33));
+            Ok(if btn & 3 == 3 && btn & 64 == 0 {
+                KeyPress::MouseRelease { col, row, btn }
+            } else {
+                KeyPress::MousePress { col, row, btn }
+            })
+        } else if seq2 == '<' {
+            // SGR mouse: ESC [ < <btn> ; <col> ; <row> M (press) or m (release)
+            let (params, last) = self.csi_params()?;
+            match (params.as_slice(), last) {
+                (&[btn, col, row], 'M') | (&[btn, col, row], 'm') => {
+                    let btn = btn as u8;
+                    let (col, row) = (col.saturating_sub(1) as u16, row.saturating_sub(1) as u16);
+                    Ok(if last == 'm' {
+                        KeyPress::MouseRelease { col, row, btn }
+                    } else {
+                        KeyPress::MousePress { col, row, btn }
+                    })
+                }
+                _ => {
+                    debug!(target: "rustyline",
+                           "unsupported esc sequence: ESC [ < {:?} {:?}", params, last);
+                    Ok(KeyPress::UnknownEscSeq)
+                }
+            }
         } else if seq2 == '[' {
             let seq3 = self.next_char()?;
             // Linux console
@@ -214,6 +433,8 @@ impl PosixRawReader {
                 'F' => KeyPress::End,
                 'H' => KeyPress::Home, // khome
                 'Z' => KeyPress::BackTab,
+                'I' => KeyPress::FocusGained,
+                'O' => KeyPress::FocusLost,
                 _ => {
                     debug!(target: "rustyline", "unsupported esc sequence: ESC [ {:?}", seq2);
                     KeyPress::UnknownEscSeq
@@ -262,6 +483,17 @@ impl PosixRawReader {
                         KeyPress::UnknownEscSeq
                     }
                 })
+            } else if seq4 == ';' && (seq2, seq3) == ('2', '7') {
+                // xterm modifyOtherKeys: ESC [ 27 ; <modifiers> ; <code> ~
+                let (params, last) = self.csi_params()?;
+                match (params.as_slice(), last) {
+                    (&[modifiers, code], '~') => Ok(modified_key(modifiers, code)),
+                    _ => {
+                        debug!(target: "rustyline",
+                               "unsupported esc sequence: ESC [ 27 ; {:?} {:?}", params, last);
+                        Ok(KeyPress::UnknownEscSeq)
+                    }
+                }
             } else if seq4 == ';' {
                 let seq5 = self.next_char()?;
                 if seq5.is_digit(10) {
@@ -374,13 +606,56 @@ impl PosixRawReader {
     }
 
     fn poll(&mut self, timeout_ms: i32) -> ::nix::Result<i32> {
-        let mut fds = [poll::PollFd::new(STDIN_FILENO, PollFlags::POLLIN)];
+        if !self.pending.is_empty() {
+            return Ok(1);
+        }
+        if let Some(ref abort) = self.abort {
+            let mut fds = [
+                poll::PollFd::new(self.stdin.fd, PollFlags::POLLIN),
+                poll::PollFd::new(abort.read, PollFlags::POLLIN),
+            ];
+            return poll::poll(&mut fds, timeout_ms);
+        }
+        let mut fds = [poll::PollFd::new(self.stdin.fd, PollFlags::POLLIN)];
         poll::poll(&mut fds, timeout_ms)
     }
+
+    /// Wait at most `timeout_ms` for a character: `None` on timeout (or when
+    /// interrupted by a signal).
+    fn next_char_timeout(&mut self, timeout_ms: i32) -> Result<Option<char>> {
+        if !self.pending.is_empty() {
+            return self.next_char().map(Some);
+        }
+        let mut fds = vec![poll::PollFd::new(self.stdin.fd, PollFlags::POLLIN)];
+        if let Some(ref abort) = self.abort {
+            fds.push(poll::PollFd::new(abort.read, PollFlags::POLLIN));
+        }
+        match poll::poll(&mut fds, timeout_ms) {
+            Ok(0) | Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => return Ok(None),
+            Ok(_) => {}
+            Err(e) => return Err(e.into()),
+        }
+        let stdin_ready = match fds[0].revents() {
+            Some(revents) => !revents.is_empty(),
+            None => false,
+        };
+        if !stdin_ready {
+            // only the abort pipe is ready: reading stdin would block
+            if let Some(ref abort) = self.abort {
+                abort.drain();
+            }
+            return Err(error::ReadlineError::Interrupted);
+        }
+        self.next_char().map(Some)
+    }
 }
 
 impl RawReader for PosixRawReader {
     fn next_key(&mut self, single_esc_abort: bool) -> Result<KeyPress> {
+        if let Some(cmd) = self.bound_sequence()? {
+            self.bound_cmd = Some(cmd);
+            return Ok(KeyPress::UnknownEscSeq);
+        }
         let c = self.next_char()?;
 
         let mut key = keys::char_to_key_press(c);
@@ -390,16 +665,9 @@ impl RawReader for PosixRawReader {
             } else {
                 self.timeout_ms
             };
-            match self.poll(timeout_ms) {
-                Ok(n) if n == 0 => {
-                    // single escape
-                }
-                Ok(_) => {
-                    // escape sequence
-                    key = self.escape_sequence()?
-                }
-                // Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
-                Err(e) => return Err(e.into()),
+            if let Some(seq1) = self.next_char_timeout(timeout_ms)? {
+                // escape sequence
+                key = self.escape_sequence(seq1)?
             }
         }
         debug!(target: "rustyline", "key: {:?}", key);
@@ -407,17 +675,25 @@ impl RawReader for PosixRawReader {
     }
 
     fn next_char(&mut self) -> Result<char> {
+        // bytes of the current sequence fed to the parser
+        let mut len = 0;
         loop {
-            let n = self.stdin.read(&mut self.buf)?;
-            if n == 0 {
-                return Err(error::ReadlineError::Eof);
-            }
-            let b = self.buf[0];
+            let b = self.next_byte()?;
             self.parser.advance(&mut self.receiver, b);
-            if !self.receiver.valid {
-                return Err(error::ReadlineError::Utf8Error);
-            } else if let Some(c) = self.receiver.c.take() {
+            len += 1;
+            if let Some(c) = self.receiver.c.take() {
                 return Ok(c);
+            } else if !self.receiver.valid {
+                self.receiver.valid = true;
+                // the parser drops the byte which interrupts a partial
+                // sequence: retry with it as it may start a new one
+                if len > 1 && !is_continuation_byte(b) {
+                    debug!(target: "rustyline", "incomplete UTF-8 sequence dropped");
+                    self.pending.push_front(b);
+                    len = 0;
+                    continue;
+                }
+                return Err(error::ReadlineError::Utf8Error);
             }
         }
     }
@@ -427,7 +703,8 @@ impl RawReader for PosixRawReader {
         loop {
             match self.next_char()? {
                 '\x1b' => {
-                    let key = self.escape_sequence()?;
+                    let seq1 = self.next_char()?;
+                    let key = self.escape_sequence(seq1)?;
                     if key == KeyPress::BracketedPasteEnd {
                         break;
                     } else {
@@ -441,6 +718,71 @@ impl RawReader for PosixRawReader {
         let buffer = buffer.replace("\r", "\n");
         Ok(buffer)
     }
+
+    fn wait_for_input(&mut self, timeout_ms: i32) -> Result<bool> {
+        match self.poll(timeout_ms) {
+            Ok(n) => Ok(n > 0),
+            Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn take_bound_cmd(&mut self) -> Option<Cmd> {
+        self.bound_cmd.take()
+    }
+
+    fn drain_input(&mut self) -> Result<()> {
+        self.pending.clear();
+        self.parser = Parser::new();
+        self.receiver.c = None;
+        self.receiver.valid = true;
+        let mut buf = [0; 64];
+        loop {
+            let mut fds = [poll::PollFd::new(self.stdin.fd, PollFlags::POLLIN)];
+            match poll::poll(&mut fds, 0) {
+                Ok(0) => return Ok(()),
+                Ok(_) => {}
+                Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+                Err(e) => return Err(e.into()),
+            }
+            if self.stdin.read(&mut buf)? == 0 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Key reported with its `modifiers` (1 + shift: 1, alt: 2, ctrl: 4) and its
+/// Unicode `code` by xterm modifyOtherKeys
+fn modified_key(modifiers: u32, code: u32) -> KeyPress {
+    let c = match std::char::from_u32(code) {
+        Some(c) => c,
+        None => return KeyPress::UnknownEscSeq,
+    };
+    let modifiers = modifiers.saturating_sub(1);
+    let (shift, alt, ctrl) = (modifiers & 1 != 0, modifiers & 2 != 0, modifiers & 4 != 0);
+    match (ctrl, alt) {
+        (true, true) => {
+            debug!(target: "rustyline", "unsupported key: Ctrl+Alt+{:?}", c);
+            KeyPress::UnknownEscSeq
+        }
+        (true, false) if c.is_ascii_alphabetic() => {
+            if shift {
+                KeyPress::CtrlShift(c.to_ascii_uppercase())
+            } else {
+                KeyPress::Ctrl(c.to_ascii_uppercase())
+            }
+        }
+        (true, false) if c.is_control() => keys::char_to_key_press(c),
+        (true, false) => KeyPress::Ctrl(c),
+        (false, true) => KeyPress::Meta(c),
+        (false, false) if c == '\t' && shift => KeyPress::BackTab,
+        (false, false) => keys::char_to_key_press(c),
+    }
+}
+
+fn is_continuation_byte(b: u8) -> bool {
+    b & 0xC0 == 0x80
 }
 
 impl Receiver for Utf8 {
@@ -460,11 +802,23 @@ impl Receiver for Utf8 {
 /// Console output writer
 pub struct PosixRenderer {
     out: OutputStreamType,
-    cols: usize, // Number of columns in terminal
+    size: WinSize,    // Number of columns and rows in terminal
+    fixed_size: bool, // Whether `size` has been set by `set_size`
     buffer: String,
     tab_stop: usize,
     colors_enabled: bool,
     bell_style: BellStyle,
+    incremental_render: bool,
+    hyperlinks: bool,
+    // Prompt, line and hint displayed by the last `refresh_line`
+    prev_display: String,
+    // Whether `prev_display` is still what is on screen
+    display_valid: Cell<bool>,
+    // Screen row (0-based) where the prompt starts, if known (reported by
+    // the terminal when `readline` starts)
+    cursor_row_offset: Cell<Option<usize>>,
+    // Whether the screen is in reverse video for the visible bell
+    flash_pending: bool,
 }
 
 impl PosixRenderer {
@@ -473,19 +827,119 @@ impl PosixRenderer {
         tab_stop: usize,
         colors_enabled: bool,
         bell_style: BellStyle,
+        incremental_render: bool,
+        hyperlinks: bool,
     ) -> Self {
-        let (cols, _) = get_win_size(&out);
+        let size = get_win_size(&out);
         Self {
             out,
-            cols,
+            size,
+            fixed_size: false,
             buffer: String::with_capacity(1024),
             tab_stop,
             colors_enabled,
             bell_style,
+            incremental_render,
+            hyperlinks,
+            prev_display: String::new(),
+            display_valid: Cell::new(false),
+            cursor_row_offset: Cell::new(None),
+            flash_pending: false,
+        }
+    }
+
+    /// Restore the screen after a visible bell
+    fn end_flash(&mut self) -> Result<()> {
+        if self.flash_pending {
+            self.flash_pending = false;
+            write_and_flush(self.out, FLASH_OFF)?;
         }
+        Ok(())
+    }
+
+    /// Only rewrite the part of a single row line which has changed since
+    /// the last refresh.
+    fn refresh_line_incremental(&mut self, display: String, cursor: Position) -> Result<()> {
+        use std::fmt::Write;
+        let start = unchanged_prefix(&self.prev_display, &display);
+        let col = self
+            .calculate_position(&display[..start], Position::default())
+            .col;
+        self.buffer.push('\r');
+        if col > 0 {
+            write!(self.buffer, "\x1b[{}C", col).unwrap();
+        }
+        self.buffer.push_str(&display[start..]);
+        self.buffer.push_str("\x1b[0K");
+        // position the cursor within the line
+        if cursor.col > 0 {
+            write!(self.buffer, "\r\x1b[{}C", cursor.col).unwrap();
+        } else {
+            self.buffer.push('\r');
+        }
+        write_and_flush(self.out, self.buffer.as_bytes())?;
+        self.prev_display = display;
+        Ok(())
     }
 }
 
+impl Drop for PosixRenderer {
+    fn drop(&mut self) {
+        if let Err(e) = self.end_flash() {
+            debug!(target: "rustyline", "cannot end visible bell: {}", e);
+        }
+    }
+}
+
+/// Length of the common prefix of `old` and `new` which can be left as is on
+/// screen: it must end on a grapheme boundary, outside of any escape sequence
+/// and with no style active.
+fn unchanged_prefix(old: &str, new: &str) -> usize {
+    let mut common = old
+        .char_indices()
+        .zip(new.chars())
+        .find(|((_, o), n)| o != n)
+        .map(|((i, _), _)| i)
+        .unwrap_or_else(|| old.len().min(new.len()));
+    if common < new.len() {
+        // the last grapheme may be extended by what follows
+        common = match new[..common].grapheme_indices(true).next_back() {
+            Some((i, _)) => i,
+            None => 0,
+        };
+    }
+    let bytes = new.as_bytes();
+    let mut safe = 0;
+    let mut plain = true;
+    let mut i = 0;
+    while i < common {
+        if bytes[i] == b'\x1b' {
+            if bytes.get(i + 1) != Some(&b'[') {
+                break;
+            }
+            // CSI: parameters then a final byte in 0x40..=0x7e
+            let end = match bytes[i + 2..common]
+                .iter()
+                .position(|b| (0x40..=0x7e).contains(b))
+            {
+                Some(n) => i + 2 + n + 1,
+                None => break,
+            };
+            let seq = &new[i..end];
+            if seq.ends_with('m') {
+                plain = seq == "\x1b[0m" || seq == "\x1b[m";
+            }
+            i = end;
+        } else {
+            i += 1;
+        }
+        if plain && new.is_char_boundary(i) {
+            safe = i;
+        }
+    }
+    safe
+}
+
 impl Renderer for PosixRenderer {
     type Reader = PosixRawReader;
 
@@ -528,7 +982,7 @@ impl Renderer for PosixRenderer {
                 write!(self.buffer, "\x1b[{}D", col_shift).unwrap();
             }
         }
-        self.write_and_flush(self.buffer.as_bytes())
+        write_and_flush(self.out, self.buffer.as_bytes())
     }
 
     fn refresh_line(
@@ -541,6 +995,7 @@ impl Renderer for PosixRenderer {
         highlighter: Option<&dyn Highlighter>,
     ) -> Result<()> {
         use std::fmt::Write;
+        self.end_flash()?;
         self.buffer.clear();
 
         let default_prompt = new_layout.default_prompt;
@@ -549,12 +1004,42 @@ impl Renderer for PosixRenderer {
         let current_row = old_layout.cursor.row;
         let old_rows = old_layout.end.row;
 
-        // old_rows < cursor.row if the prompt spans multiple lines and if
-        // this is the default State.
-        let cursor_row_movement = old_rows.saturating_sub(current_row);
-        // move the cursor down as required
-        if cursor_row_movement > 0 {
-            write!(self.buffer, "\x1b[{}B", cursor_row_movement).unwrap();
+        let incremental = self.incremental_render && old_rows == 0 && end_pos.row == 0;
+        if incremental && self.display_valid.get() {
+            let display = line_segments(
+                prompt,
+                line,
+                hint,
+                default_prompt,
+                highlighter,
+                self.hyperlinks,
+            )
+            .concat();
+            return self.refresh_line_incremental(display, cursor);
+        }
+
+        // hide the cursor while it moves across rows so that it does not
+        // flash at intermediate positions
+        let hide_cursor = old_rows > 0 || end_pos.row > 0;
+        if hide_cursor {
+            self.buffer.push_str(HIDE_CURSOR);
+        }
+
+        let prompt_row = self
+            .cursor_row_offset
+            .get()
+            .filter(|row| row + old_rows < self.size.rows);
+        if let Some(row) = prompt_row {
+            // move to the last old row, wherever the cursor actually is
+            write!(self.buffer, "\x1b[{};1H", row + old_rows + 1).unwrap();
+        } else {
+            // old_rows < cursor.row if the prompt spans multiple lines and if
+            // this is the default State.
+            let cursor_row_movement = old_rows.saturating_sub(current_row);
+            // move the cursor down as required
+            if cursor_row_movement > 0 {
+                write!(self.buffer, "\x1b[{}B", cursor_row_movement).unwrap();
+            }
         }
         // clear old rows
         for _ in 0..old_rows {
@@ -563,30 +1048,26 @@ impl Renderer for PosixRenderer {
         // clear the line
         self.buffer.push_str("\r\x1b[0K");
 
-        if let Some(highlighter) = highlighter {
-            // display the prompt
-            self.buffer
-                .push_str(&highlighter.highlight_prompt(prompt, default_prompt));
-            // display the input line
-            self.buffer
-                .push_str(&highlighter.highlight(line, line.pos()));
-        } else {
-            // display the prompt
-            self.buffer.push_str(prompt);
-            // display the input line
-            self.buffer.push_str(line);
-        }
-        // display hint
-        if let Some(hint) = hint {
-            if let Some(highlighter) = highlighter {
-                self.buffer.push_str(&highlighter.highlight_hint(hint));
-            } else {
-                self.buffer.push_str(hint);
-            }
+        // the content is written from its own slices, after this prefix
+        let prefix_len = self.buffer.len();
+        let segments = line_segments(
+            prompt,
+            line,
+            hint,
+            default_prompt,
+            highlighter,
+            self.hyperlinks,
+        );
+        if incremental {
+            self.prev_display = segments.concat();
         }
         // we have to generate our own newline on line wrap
-        if end_pos.col == 0 && end_pos.row > 0 && !self.buffer.ends_with('\n') {
-            self.buffer.push_str("\n");
+        let ends_with_newline = match segments.iter().rev().find(|s| !s.is_empty()) {
+            Some(s) => s.ends_with('\n'),
+            None => false,
+        };
+        if end_pos.col == 0 && end_pos.row > 0 && !ends_with_newline {
+            self.buffer.push('\n');
         }
         // position the cursor
         let new_cursor_row_movement = end_pos.row - cursor.row;
@@ -600,13 +1081,38 @@ impl Renderer for PosixRenderer {
         } else {
             self.buffer.push('\r');
         }
+        if hide_cursor {
+            self.buffer.push_str(SHOW_CURSOR);
+        }
 
-        self.write_and_flush(self.buffer.as_bytes())?;
+        let (prefix, suffix) = self.buffer.split_at(prefix_len);
+        write_vectored_and_flush(
+            self.out,
+            &[
+                prefix.as_bytes(),
+                segments[0].as_bytes(),
+                segments[1].as_bytes(),
+                segments[2].as_bytes(),
+                suffix.as_bytes(),
+            ],
+        )?;
+        self.display_valid.set(incremental);
+        if let Some(row) = prompt_row {
+            // the screen scrolls when the content goes past its last row
+            if row + end_pos.row >= self.size.rows {
+                self.cursor_row_offset
+                    .set(self.size.rows.saturating_sub(1).checked_sub(end_pos.row));
+            }
+        }
 
         Ok(())
     }
 
     fn write_and_flush(&self, buf: &[u8]) -> Result<()> {
+        // the screen may not match `prev_display` anymore
+        self.display_valid.set(false);
+        // nor the prompt start where it was
+        self.cursor_row_offset.set(None);
         write_and_flush(self.out, buf)
     }
 
@@ -627,12 +1133,12 @@ impl Renderer for PosixRenderer {
                 width(c, &mut esc_seq)
             };
             pos.col += cw;
-            if pos.col > self.cols {
+            if pos.col > self.size.cols {
                 pos.row += 1;
                 pos.col = cw;
             }
         }
-        if pos.col == self.cols {
+        if pos.col == self.size.cols {
             pos.col = 0;
             pos.row += 1;
         }
@@ -646,13 +1152,35 @@ impl Renderer for PosixRenderer {
                 io::stderr().flush()?;
                 Ok(())
             }
-            _ => Ok(()),
+            BellStyle::Visible => {
+                // restored by the next refresh (or when `readline` returns)
+                write_and_flush(self.out, FLASH_ON)?;
+                self.flash_pending = true;
+                Ok(())
+            }
+            BellStyle::Callback(ref bell) => {
+                bell();
+                Ok(())
+            }
+            BellStyle::None => Ok(()),
         }
     }
 
     /// Clear the screen. Used to handle ctrl+l
     fn clear_screen(&mut self) -> Result<()> {
-        self.write_and_flush(b"\x1b[H\x1b[2J")
+        self.write_and_flush(b"\x1b[H\x1b[2J")?;
+        self.cursor_row_offset.set(Some(0));
+        Ok(())
+    }
+
+    /// DECSC
+    fn save_cursor(&mut self) -> Result<()> {
+        write_and_flush(self.out, b"\x1b7")
+    }
+
+    /// DECRC
+    fn restore_cursor(&mut self) -> Result<()> {
+        write_and_flush(self.out, b"\x1b8")
     }
 
     /// Check if a SIGWINCH signal has been received
@@ -662,19 +1190,29 @@ impl Renderer for PosixRenderer {
 
     /// Try to update the number of columns in the current terminal,
     fn update_size(&mut self) {
-        let (cols, _) = get_win_size(&self.out);
-        self.cols = cols;
+        if !self.fixed_size {
+            self.size = get_win_size(&self.out);
+        }
+        self.display_valid.set(false);
+        // lines may have been rewrapped
+        self.cursor_row_offset.set(None);
     }
 
     fn get_columns(&self) -> usize {
-        self.cols
+        self.size.cols
     }
 
     /// Try to get the number of rows in the current terminal,
     /// or assume 24 if it fails.
     fn get_rows(&self) -> usize {
-        let (_, rows) = get_win_size(&self.out);
-        rows
+        self.size.rows
+    }
+
+    fn set_size(&mut self, size: WinSize) {
+        self.size = size;
+        self.fixed_size = true;
+        self.display_valid.set(false);
+        self.cursor_row_offset.set(None);
     }
 
     fn colors_enabled(&self) -> bool {
@@ -689,28 +1227,74 @@ impl Renderer for PosixRenderer {
         /* Report cursor location */
         self.write_and_flush(b"\x1b[6n")?;
         /* Read the response: ESC [ rows ; cols R */
-        if rdr.poll(100)? == 0
-            || rdr.next_char()? != '\x1b'
-            || rdr.next_char()? != '['
-            || read_digits_until(rdr, ';')?.is_none()
-        {
+        if rdr.poll(100)? == 0 || rdr.next_char()? != '\x1b' || rdr.next_char()? != '[' {
             warn!(target: "rustyline", "cannot read initial cursor location");
             return Ok(());
         }
+        let row = match read_digits_until(rdr, ';')? {
+            Some(row) => row,
+            None => {
+                warn!(target: "rustyline", "cannot read initial cursor location");
+                return Ok(());
+            }
+        };
         let col = read_digits_until(rdr, 'R')?;
-        debug!(target: "rustyline", "initial cursor location: {:?}", col);
+        debug!(target: "rustyline", "initial cursor location: {:?}, {:?}", row, col);
+        // 1-based
+        let mut row = (row as usize).saturating_sub(1);
         if col.is_some() && col != Some(1) {
             self.write_and_flush(b"\n")?;
+            row += 1;
+        }
+        if col.is_some() {
+            self.cursor_row_offset
+                .set(Some(row.min(self.size.rows.saturating_sub(1))));
         }
         Ok(())
     }
 }
 
+/// `prompt`, line and `hint` to display, highlighted if needed
+fn line_segments<'a>(
+    prompt: &'a str,
+    line: &'a LineBuffer,
+    hint: Option<&'a str>,
+    default_prompt: bool,
+    highlighter: Option<&'a dyn Highlighter>,
+    hyperlinks: bool,
+) -> [Cow<'a, str>; 3] {
+    let hint = Borrowed(hint.unwrap_or(""));
+    if let Some(highlighter) = highlighter {
+        let line = if let Some(region) = line.region() {
+            highlighter.highlight_region(line, line.pos(), region)
+        } else if hyperlinks {
+            let spans = highlighter.highlight_spans(line, line.pos());
+            if spans.iter().any(|(_, style)| style.link.is_some()) {
+                Owned(render_spans(line, &spans, true))
+            } else {
+                highlighter.highlight_overflow(line, line.pos(), line.is_too_long())
+            }
+        } else {
+            highlighter.highlight_overflow(line, line.pos(), line.is_too_long())
+        };
+        [
+            highlighter.highlight_prompt(prompt, default_prompt),
+            line,
+            hint,
+        ]
+    } else {
+        [Borrowed(prompt), Borrowed(line.as_str()), hint]
+    }
+}
+
 fn width(s: &str, esc_seq: &mut u8) -> usize {
     if *esc_seq == 1 {
         if s == "[" {
             // CSI
             *esc_seq = 2;
+        } else if s == "]" {
+            // OSC (like hyperlinks)
+            *esc_seq = 3;
         } else {
             // two-character sequence
             *esc_seq = 0;
@@ -726,13 +1310,24 @@ fn width(s: &str, esc_seq: &mut u8) -> usize {
             *esc_seq = 0;
         }
         0
+    } else if *esc_seq == 3 {
+        // OSC ends with BEL or ST (ESC \)
+        if s == "\x07" {
+            *esc_seq = 0;
+        } else if s == "\x1b" {
+            *esc_seq = 4;
+        }
+        0
+    } else if *esc_seq == 4 {
+        *esc_seq = 0;
+        0
     } else if s == "\x1b" {
         *esc_seq = 1;
         0
     } else if s == "\n" {
         0
     } else {
-        s.width()
+        grapheme_width(s)
     }
 }
 
@@ -755,7 +1350,10 @@ fn read_digits_until(rdr: &mut PosixRawReader, sep: char) -> Result<Option<u32>>
 
 static SIGWINCH_ONCE: sync::Once = sync::Once::new();
 static SIGWINCH: atomic::AtomicBool = atomic::AtomicBool::new(false);
+static SIGCONT: atomic::AtomicBool = atomic::AtomicBool::new(false);
 
+/// Install the SIGWINCH and SIGCONT handlers.
+/// No `SA_RESTART`: a blocking read is interrupted by these signals.
 fn install_sigwinch_handler() {
     SIGWINCH_ONCE.call_once(|| unsafe {
         let sigwinch = signal::SigAction::new(
@@ -764,6 +1362,12 @@ fn install_sigwinch_handler() {
             signal::SigSet::empty(),
         );
         let _ = signal::sigaction(signal::SIGWINCH, &sigwinch);
+        let sigcont = signal::SigAction::new(
+            signal::SigHandler::Handler(sigcont_handler),
+            signal::SaFlags::empty(),
+            signal::SigSet::empty(),
+        );
+        let _ = signal::sigaction(signal::SIGCONT, &sigcont);
     });
 }
 
@@ -772,18 +1376,40 @@ extern "C" fn sigwinch_handler(_: libc::c_int) {
     debug!(target: "rustyline", "SIGWINCH");
 }
 
+extern "C" fn sigcont_handler(_: libc::c_int) {
+    SIGCONT.store(true, atomic::Ordering::SeqCst);
+    debug!(target: "rustyline", "SIGCONT");
+}
+
+/// Whether an interrupted read should be reported to the caller
+fn signal_received() -> bool {
+    SIGWINCH.load(atomic::Ordering::Relaxed) || SIGCONT.load(atomic::Ordering::Relaxed)
+}
+
+/// Check if the process has been resumed after being stopped by another
+/// process (`kill -STOP`), which may have left the terminal in cooked mode.
+pub fn sigcont() -> bool {
+    SIGCONT.swap(false, atomic::Ordering::SeqCst)
+}
+
 #[cfg(not(test))]
 pub type Terminal = PosixTerminal;
 
 #[derive(Clone, Debug)]
 pub struct PosixTerminal {
-    unsupported: bool,
+    capabilities: TerminalCapabilities,
+    input: RawFd,
     stdin_isatty: bool,
     stdstream_isatty: bool,
     pub(crate) color_mode: ColorMode,
     stream_type: OutputStreamType,
     tab_stop: usize,
     bell_style: BellStyle,
+    incremental_render: bool,
+    hyperlinks: bool,
+    focus_events: bool,
+    bracketed_paste: bool,
+    abort: Option<Arc<AbortPipe>>,
 }
 
 impl PosixTerminal {
@@ -794,29 +1420,48 @@ impl PosixTerminal {
             ColorMode::Disabled => false,
         }
     }
+
+    /// Create a RAW reader of `fd` (which is not closed by the reader)
+    pub fn create_reader_from_fd(
+        &self,
+        fd: RawFd,
+        config: &Config,
+        sequences: Arc<RwLock<SequenceTrie>>,
+    ) -> Result<PosixRawReader> {
+        PosixRawReader::new_from_fd(fd, config, sequences, self.abort.clone())
+    }
 }
 
 impl Term for PosixTerminal {
     type Mode = PosixMode;
+    type AbortHandle = AbortHandle;
     type Reader = PosixRawReader;
     type Writer = PosixRenderer;
 
-    fn new(
-        color_mode: ColorMode,
-        stream_type: OutputStreamType,
-        tab_stop: usize,
-        bell_style: BellStyle,
-    ) -> Self {
+    fn new(config: &Config) -> Self {
+        let stream_type = config.output_stream();
         let term = Self {
-            unsupported: is_unsupported_term(),
+            capabilities: TerminalCapabilities::detect(),
+            input: STDIN_FILENO,
             stdin_isatty: is_a_tty(STDIN_FILENO),
             stdstream_isatty: is_a_tty(stream_type.as_raw_fd()),
-            color_mode,
+            color_mode: config.color_mode(),
             stream_type,
-            tab_stop,
-            bell_style,
+            tab_stop: config.tab_stop(),
+            bell_style: config.bell_style(),
+            incremental_render: config.incremental_render(),
+            hyperlinks: config.hyperlinks_enabled(),
+            focus_events: config.enable_focus_events(),
+            bracketed_paste: config.enable_bracketed_paste(),
+            abort: match AbortPipe::new() {
+                Ok(pipe) => Some(Arc::new(pipe)),
+                Err(e) => {
+                    debug!(target: "rustyline", "cannot create abort pipe: {}", e);
+                    None
+                }
+            },
         };
-        if !term.unsupported && term.stdin_isatty && term.stdstream_isatty {
+        if !term.capabilities.unsupported && term.stdin_isatty && term.stdstream_isatty {
             install_sigwinch_handler();
         }
         term
@@ -827,7 +1472,7 @@ impl Term for PosixTerminal {
     /// Check if current terminal can provide a rich line-editing user
     /// interface.
     fn is_unsupported(&self) -> bool {
-        self.unsupported
+        self.capabilities.unsupported
     }
 
     /// check if stdin is connected to a terminal.
@@ -847,7 +1492,7 @@ impl Term for PosixTerminal {
         if !self.stdin_isatty {
             return Err(nix::Error::from_errno(ENOTTY).into());
         }
-        let original_mode = termios::tcgetattr(STDIN_FILENO)?;
+        let original_mode = termios::tcgetattr(self.input)?;
         let mut raw = original_mode.clone();
         // disable BREAK interrupt, CR to NL conversion on input,
         // input parity check, strip high bit (bit 8), output flow control
@@ -867,24 +1512,39 @@ impl Term for PosixTerminal {
             !(LocalFlags::ECHO | LocalFlags::ICANON | LocalFlags::IEXTEN | LocalFlags::ISIG);
         raw.control_chars[SpecialCharacterIndices::VMIN as usize] = 1; // One character-at-a-time input
         raw.control_chars[SpecialCharacterIndices::VTIME as usize] = 0; // with blocking read
-        termios::tcsetattr(STDIN_FILENO, SetArg::TCSADRAIN, &raw)?;
+        termios::tcsetattr(self.input, SetArg::TCSADRAIN, &raw)?;
 
         // enable bracketed paste
-        let out = if let Err(e) = write_and_flush(self.stream_type, BRACKETED_PASTE_ON) {
+        let mut out = Some(self.stream_type);
+        let bracketed_paste = if !self.bracketed_paste {
+            false
+        } else if let Err(e) = write_and_flush(self.stream_type, BRACKETED_PASTE_ON) {
             debug!(target: "rustyline", "Cannot enable bracketed paste: {}", e);
-            None
+            out = None;
+            false
         } else {
-            Some(self.stream_type)
+            true
         };
+        // enable focus events
+        let focus_events = self.focus_events
+            && out.is_some()
+            && write_and_flush(self.stream_type, FOCUS_EVENTS_ON).is_ok();
         Ok(PosixMode {
             termios: original_mode,
+            input: self.input,
             out,
+            bracketed_paste,
+            focus_events,
         })
     }
 
     /// Create a RAW reader
-    fn create_reader(&self, config: &Config) -> Result<PosixRawReader> {
-        PosixRawReader::new(config)
+    fn create_reader(
+        &self,
+        config: &Config,
+        sequences: Arc<RwLock<SequenceTrie>>,
+    ) -> Result<PosixRawReader> {
+        self.create_reader_from_fd(self.input, config, sequences)
     }
 
     fn create_writer(&self) -> PosixRenderer {
@@ -892,9 +1552,43 @@ impl Term for PosixTerminal {
             self.stream_type,
             self.tab_stop,
             self.colors_enabled(),
-            self.bell_style,
+            self.bell_style.clone(),
+            self.incremental_render,
+            self.hyperlinks,
         )
     }
+
+    fn abort_handle(&self) -> AbortHandle {
+        AbortHandle {
+            pipe: self.abort.clone(),
+        }
+    }
+
+    fn update_config(&mut self, config: &Config) {
+        self.color_mode = config.color_mode();
+        self.tab_stop = config.tab_stop();
+        self.bell_style = config.bell_style();
+        self.incremental_render = config.incremental_render();
+        self.hyperlinks = config.hyperlinks_enabled();
+        self.focus_events = config.enable_focus_events();
+        self.bracketed_paste = config.enable_bracketed_paste();
+    }
+
+    fn set_fds(
+        &mut self,
+        input: RawFd,
+        output: OutputStreamType,
+    ) -> Result<(RawFd, OutputStreamType)> {
+        let previous = (self.input, self.stream_type);
+        self.input = input;
+        self.stream_type = output;
+        self.stdin_isatty = is_a_tty(input);
+        self.stdstream_isatty = is_a_tty(output.as_raw_fd());
+        if !self.capabilities.unsupported && self.stdin_isatty && self.stdstream_isatty {
+            install_sigwinch_handler();
+        }
+        Ok(previous)
+    }
 }
 
 #[cfg(not(test))]
@@ -902,9 +1596,45 @@ pub fn suspend() -> Result<()> {
     use nix::unistd::Pid;
     // suspend the whole process group
     signal::kill(Pid::from_raw(0), signal::SIGTSTP)?;
+    // resumed: the caller restores the terminal itself
+    SIGCONT.store(false, atomic::Ordering::SeqCst);
     Ok(())
 }
 
+/// Same as `write_and_flush` for several slices at once: a single `writev`
+/// call is used for a raw fd.
+fn write_vectored_and_flush(out: OutputStreamType, bufs: &[&[u8]]) -> Result<()> {
+    if let OutputStreamType::Fd(fd) = out {
+        let mut bufs: Vec<&[u8]> = bufs.iter().copied().filter(|b| !b.is_empty()).collect();
+        let mut bufs = &mut bufs[..];
+        while !bufs.is_empty() {
+            let iov: Vec<IoVec<&[u8]>> = bufs.iter().map(|b| IoVec::from_slice(b)).collect();
+            let mut n = match uio::writev(fd, &iov) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero).into()),
+                Ok(n) => n,
+                Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+                Err(e) => return Err(e.into()),
+            };
+            // skip what has been written
+            while n > 0 && n >= bufs[0].len() {
+                n -= bufs[0].len();
+                bufs = &mut bufs[1..];
+            }
+            if n > 0 {
+                bufs[0] = &bufs[0][n..];
+            }
+        }
+        return Ok(());
+    }
+    for buf in bufs {
+        match out {
+            OutputStreamType::Stderr => io::stderr().write_all(buf)?,
+            _ => io::stdout().write_all(buf)?,
+        }
+    }
+    write_and_flush(out, &[])
+}
+
 fn write_and_flush(out: OutputStreamType, buf: &[u8]) -> Result<()> {
     match out {
         OutputStreamType::Stdout => {
@@ -915,31 +1645,339 @@ fn write_and_flush(out: OutputStreamType, buf: &[u8]) -> Result<()> {
             io::stderr().write_all(buf)?;
             io::stderr().flush()?;
         }
+        OutputStreamType::Fd(fd) => {
+            // unbuffered: nothing to flush
+            let mut buf = buf;
+            while !buf.is_empty() {
+                match unistd::write(fd, buf) {
+                    Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero).into()),
+                    Ok(n) => buf = &buf[n..],
+                    Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
     }
     Ok(())
 }
 
 #[cfg(test)]
 mod test {
-    use super::{Position, PosixRenderer, PosixTerminal, Renderer};
+    use super::{Position, PosixRenderer, PosixTerminal, Renderer, TerminalCapabilities, WinSize};
     use crate::config::{BellStyle, OutputStreamType};
 
     #[test]
     #[ignore]
     fn prompt_with_ansi_escape_codes() {
-        let out = PosixRenderer::new(OutputStreamType::Stdout, 4, true, BellStyle::default());
+        let out = PosixRenderer::new(
+            OutputStreamType::Stdout,
+            4,
+            true,
+            BellStyle::default(),
+            false,
+            false,
+        );
         let pos = out.calculate_position("\x1b[1;32m>>\x1b[0m ", Position::default());
         assert_eq!(3, pos.col);
         assert_eq!(0, pos.row);
     }
 
+    #[test]
+    fn set_size() {
+        let mut out = PosixRenderer::new(
+            OutputStreamType::Stdout,
+            4,
+            true,
+            BellStyle::default(),
+            false,
+            false,
+        );
+        out.set_size(WinSize { cols: 10, rows: 5 });
+        out.update_size();
+        assert_eq!(10, out.get_columns());
+        assert_eq!(5, out.get_rows());
+        let pos = out.calculate_position("0123456789ab", Position::default());
+        assert_eq!(Position { col: 2, row: 1 }, pos);
+    }
+
     #[test]
     fn test_unsupported_term() {
         ::std::env::set_var("TERM", "xterm");
-        assert_eq!(false, super::is_unsupported_term());
+        assert!(!TerminalCapabilities::detect().unsupported);
 
         ::std::env::set_var("TERM", "dumb");
-        assert_eq!(true, super::is_unsupported_term());
+        assert!(TerminalCapabilities::detect().unsupported);
+    }
+
+    #[test]
+    fn next_char_after_incomplete_sequence() {
+        use super::{PosixRawReader, RawReader, SequenceTrie};
+        use crate::config::Config;
+        use crate::error::ReadlineError;
+        use nix::unistd;
+        use std::sync::{Arc, RwLock};
+
+        let (read, write) = unistd::pipe().unwrap();
+        // truncated "日", "a", lone continuation byte, "é"
+        unistd::write(write, b"\xe6\x97a\x80\xc3\xa9").unwrap();
+        let sequences = Arc::new(RwLock::new(SequenceTrie::default()));
+        let mut rdr =
+            PosixRawReader::new_from_fd(read, &Config::default(), sequences, None).unwrap();
+        assert_eq!('a', rdr.next_char().unwrap());
+        match rdr.next_char() {
+            Err(ReadlineError::Utf8Error) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+        assert_eq!('\u{e9}', rdr.next_char().unwrap());
+        unistd::close(read).unwrap();
+        unistd::close(write).unwrap();
+    }
+
+    #[test]
+    fn next_char_timeout() {
+        use super::{PosixRawReader, SequenceTrie};
+        use crate::config::Config;
+        use nix::unistd;
+        use std::sync::{Arc, RwLock};
+
+        let (read, write) = unistd::pipe().unwrap();
+        let sequences = Arc::new(RwLock::new(SequenceTrie::default()));
+        let mut rdr =
+            PosixRawReader::new_from_fd(read, &Config::default(), sequences, None).unwrap();
+        assert_eq!(None, rdr.next_char_timeout(10).unwrap());
+        unistd::write(write, b"x").unwrap();
+        assert_eq!(Some('x'), rdr.next_char_timeout(10).unwrap());
+        unistd::close(read).unwrap();
+        unistd::close(write).unwrap();
+    }
+
+    #[test]
+    fn next_char_timeout_aborted() {
+        use super::{AbortPipe, PosixRawReader, RawReader, SequenceTrie};
+        use crate::config::Config;
+        use crate::error::ReadlineError;
+        use nix::unistd;
+        use std::sync::{Arc, RwLock};
+        use std::thread;
+        use std::time::Duration;
+
+        let (read, write) = unistd::pipe().unwrap();
+        let abort = Arc::new(AbortPipe::new().unwrap());
+        let sequences = Arc::new(RwLock::new(SequenceTrie::default()));
+        let mut rdr =
+            PosixRawReader::new_from_fd(read, &Config::default(), sequences, Some(abort.clone()))
+                .unwrap();
+        // an ESC followed by an abort must not block waiting for stdin
+        unistd::write(write, b"\x1b").unwrap();
+        let handle = Arc::clone(&abort);
+        let aborter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            unistd::write(handle.write, &[0]).unwrap();
+        });
+        match rdr.next_key(false) {
+            Err(ReadlineError::Interrupted) => {}
+            key => panic!("unexpected {:?}", key),
+        }
+        aborter.join().unwrap();
+        // the abort has been consumed
+        assert_eq!(None, rdr.next_char_timeout(10).unwrap());
+        unistd::close(read).unwrap();
+        unistd::close(write).unwrap();
+    }
+
+    #[test]
+    fn modify_other_keys() {
+        use super::{PosixRawReader, RawReader, SequenceTrie};
+        use crate::config::Config;
+        use crate::keys::KeyPress;
+        use nix::unistd;
+        use std::sync::{Arc, RwLock};
+
+        let (read, write) = unistd::pipe().unwrap();
+        let sequences = Arc::new(RwLock::new(SequenceTrie::default()));
+        let mut rdr =
+            PosixRawReader::new_from_fd(read, &Config::default(), sequences, None).unwrap();
+        for (seq, key) in &[
+            ("\x1b[27;5;97~", KeyPress::Ctrl('A')),
+            ("\x1b[27;6;86~", KeyPress::CtrlShift('V')),
+            ("\x1b[27;3;120~", KeyPress::Meta('x')),
+            ("\x1b[27;2;9~", KeyPress::BackTab),
+            ("\x1b[27;5;13~", KeyPress::Enter),
+            ("\x1b[27;2;65~", KeyPress::Char('A')),
+            // cursor position report on row 27
+            ("\x1b[27;4R", KeyPress::UnknownEscSeq),
+        ] {
+            unistd::write(write, seq.as_bytes()).unwrap();
+            assert_eq!(*key, rdr.next_key(false).unwrap(), "{:?}", seq);
+        }
+        unistd::close(read).unwrap();
+        unistd::close(write).unwrap();
+    }
+
+    #[test]
+    fn mouse_and_focus() {
+        use super::{PosixRawReader, RawReader, SequenceTrie};
+        use crate::config::Config;
+        use crate::keys::KeyPress;
+        use nix::unistd;
+        use std::sync::{Arc, RwLock};
+
+        let (read, write) = unistd::pipe().unwrap();
+        let sequences = Arc::new(RwLock::new(SequenceTrie::default()));
+        let mut rdr =
+            PosixRawReader::new_from_fd(read, &Config::default(), sequences, None).unwrap();
+        for (seq, key) in &[
+            (
+                &b"\x1b[M !!"[..],
+                KeyPress::MousePress {
+                    col: 0,
+                    row: 0,
+                    btn: 0,
+                },
+            ),
+            (
+                b"\x1b[M#\xff*",
+                KeyPress::MouseRelease {
+                    col: 222,
+                    row: 9,
+                    btn: 3,
+                },
+            ),
+            (
+                b"\x1b[<2;120;45M",
+                KeyPress::MousePress {
+                    col: 119,
+                    row: 44,
+                    btn: 2,
+                },
+            ),
+            (
+                b"\x1b[<0;3;4m",
+                KeyPress::MouseRelease {
+                    col: 2,
+                    row: 3,
+                    btn: 0,
+                },
+            ),
+            (b"\x1b[<0;3M", KeyPress::UnknownEscSeq),
+            (b"\x1b[I", KeyPress::FocusGained),
+            (b"\x1b[O", KeyPress::FocusLost),
+        ] {
+            unistd::write(write, seq).unwrap();
+            assert_eq!(*key, rdr.next_key(false).unwrap(), "{:?}", seq);
+        }
+        unistd::close(read).unwrap();
+        unistd::close(write).unwrap();
+    }
+
+    #[test]
+    fn cursor_row_offset() {
+        use crate::layout::Layout;
+        use crate::line_buffer::LineBuffer;
+        use nix::unistd;
+
+        let (read, write) = unistd::pipe().unwrap();
+        let mut out = PosixRenderer::new(
+            OutputStreamType::Fd(write),
+            4,
+            true,
+            BellStyle::default(),
+            false,
+            false,
+        );
+        out.set_size(WinSize { cols: 80, rows: 5 });
+        out.cursor_row_offset.set(Some(3));
+        let line = LineBuffer::with_capacity(16);
+        let old_layout = Layout::default();
+        let mut new_layout = Layout::default();
+        new_layout.end.row = 2;
+        out.refresh_line("> ", &line, None, &old_layout, &new_layout, None)
+            .unwrap();
+        let mut buf = [0; 64];
+        let n = unistd::read(read, &mut buf).unwrap();
+        let written = String::from_utf8_lossy(&buf[..n]);
+        assert!(written.contains("\x1b[4;1H"), "{:?}", written);
+        // the screen has scrolled by one row
+        assert_eq!(Some(2), out.cursor_row_offset.get());
+
+        out.write_and_flush(b"\n").unwrap();
+        assert_eq!(None, out.cursor_row_offset.get());
+        unistd::close(read).unwrap();
+        unistd::close(write).unwrap();
+    }
+
+    #[test]
+    fn visible_bell() {
+        use crate::layout::Layout;
+        use crate::line_buffer::LineBuffer;
+        use nix::unistd;
+
+        let (read, write) = unistd::pipe().unwrap();
+        let mut out = PosixRenderer::new(
+            OutputStreamType::Fd(write),
+            4,
+            true,
+            BellStyle::Visible,
+            false,
+            false,
+        );
+        let mut buf = [0; 64];
+        out.beep().unwrap();
+        let n = unistd::read(read, &mut buf).unwrap();
+        assert_eq!(super::FLASH_ON, &buf[..n]);
+        // the next refresh restores the screen
+        let line = LineBuffer::with_capacity(16);
+        let layout = Layout::default();
+        out.refresh_line("> ", &line, None, &layout, &layout, None)
+            .unwrap();
+        let n = unistd::read(read, &mut buf).unwrap();
+        assert!(buf[..n].starts_with(super::FLASH_OFF));
+        // and only once
+        out.refresh_line("> ", &line, None, &layout, &layout, None)
+            .unwrap();
+        let n = unistd::read(read, &mut buf).unwrap();
+        assert!(!buf[..n].starts_with(super::FLASH_OFF));
+        // when readline returns without any refresh
+        out.beep().unwrap();
+        let n = unistd::read(read, &mut buf).unwrap();
+        assert_eq!(super::FLASH_ON, &buf[..n]);
+        drop(out);
+        let n = unistd::read(read, &mut buf).unwrap();
+        assert_eq!(super::FLASH_OFF, &buf[..n]);
+        unistd::close(read).unwrap();
+        unistd::close(write).unwrap();
+    }
+
+    #[test]
+    fn callback_bell() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&count);
+        let mut out = PosixRenderer::new(
+            OutputStreamType::Stdout,
+            4,
+            true,
+            BellStyle::Callback(Arc::new(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            })),
+            false,
+            false,
+        );
+        out.beep().unwrap();
+        out.beep().unwrap();
+        assert_eq!(2, count.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn sigcont() {
+        use nix::sys::signal;
+        super::install_sigwinch_handler();
+        assert!(!super::sigcont());
+        signal::raise(signal::SIGCONT).unwrap();
+        assert!(super::sigcont());
+        assert!(!super::sigcont());
     }
 
     #[test]
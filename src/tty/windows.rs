@@ -2,17 +2,22 @@
 use std::io::{self, Write};
 use std::mem;
 use std::sync::atomic;
+use std::sync::{Arc, RwLock};
 
+#[cfg(not(feature = "tracing"))]
 use log::debug;
-use unicode_width::UnicodeWidthChar;
-use winapi::shared::minwindef::{DWORD, WORD};
-use winapi::um::winnt::{CHAR, HANDLE};
-use winapi::um::{consoleapi, handleapi, processenv, winbase, wincon, winuser};
+#[cfg(feature = "tracing")]
+use tracing::debug;
+use unicode_segmentation::UnicodeSegmentation;
+use winapi::shared::minwindef::{DWORD, FALSE, WORD};
+use winapi::um::winnt::{CHAR, HANDLE, SHORT};
+use winapi::um::{consoleapi, handleapi, processenv, synchapi, winbase, wincon, winuser};
 
-use super::{RawMode, RawReader, Renderer, Term};
+use super::{grapheme_width, RawMode, RawReader, Renderer, Term, WinSize};
 use crate::config::{BellStyle, ColorMode, Config, OutputStreamType};
 use crate::error;
 use crate::highlight::Highlighter;
+use crate::keymap::SequenceTrie;
 use crate::keys::{self, KeyPress};
 use crate::layout::{Layout, Position};
 use crate::line_buffer::LineBuffer;
@@ -46,14 +51,21 @@ macro_rules! check {
     }};
 }
 
-fn get_win_size(handle: HANDLE) -> (usize, usize) {
+fn get_win_size(handle: HANDLE) -> WinSize {
     let mut info = unsafe { mem::zeroed() };
     match unsafe { wincon::GetConsoleScreenBufferInfo(handle, &mut info) } {
-        0 => (80, 24),
-        _ => (
-            info.dwSize.X as usize,
-            (1 + info.srWindow.Bottom - info.srWindow.Top) as usize,
-        ), // (info.srWindow.Right - info.srWindow.Left + 1)
+        0 => WinSize { cols: 80, rows: 24 },
+        _ => WinSize {
+            cols: info.dwSize.X as usize, // (info.srWindow.Right - info.srWindow.Left + 1)
+            rows: (1 + info.srWindow.Bottom - info.srWindow.Top) as usize,
+        },
+    }
+}
+
+pub fn stdout_size() -> WinSize {
+    match get_std_handle(STDOUT_FILENO) {
+        Ok(handle) => get_win_size(handle),
+        Err(_) => WinSize { cols: 80, rows: 24 },
     }
 }
 
@@ -66,17 +78,26 @@ fn get_console_mode(handle: HANDLE) -> Result<DWORD> {
 #[cfg(not(test))]
 pub type Mode = ConsoleMode;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub struct ConsoleMode {
     original_stdin_mode: DWORD,
     stdin_handle: HANDLE,
     original_stdstream_mode: Option<DWORD>,
     stdstream_handle: HANDLE,
+    // Shared with the `Console`
+    raw_mode: Arc<atomic::AtomicBool>,
 }
 
 impl RawMode for ConsoleMode {
     /// Disable RAW mode for the terminal.
+    ///
+    /// Nothing is done if it is already disabled: the console modes may
+    /// have been changed since.
     fn disable_raw_mode(&self) -> Result<()> {
+        if !self.raw_mode.swap(false, atomic::Ordering::SeqCst) {
+            debug!(target: "rustyline", "raw mode already disabled");
+            return Ok(());
+        }
         check!(consoleapi::SetConsoleMode(
             self.stdin_handle,
             self.original_stdin_mode,
@@ -91,15 +112,83 @@ impl RawMode for ConsoleMode {
     }
 }
 
+/// Event used to interrupt a blocked read on the console
+#[derive(Debug)]
+struct AbortEvent(HANDLE);
+
+// An event can be signaled from any thread.
+unsafe impl Send for AbortEvent {}
+unsafe impl Sync for AbortEvent {}
+
+impl AbortEvent {
+    fn new() -> Result<AbortEvent> {
+        use std::ptr;
+        // auto-reset event, initially nonsignaled
+        let handle = unsafe { synchapi::CreateEventW(ptr::null_mut(), FALSE, FALSE, ptr::null()) };
+        if handle.is_null() {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(AbortEvent(handle))
+    }
+}
+
+impl Drop for AbortEvent {
+    fn drop(&mut self) {
+        unsafe { handleapi::CloseHandle(self.0) };
+    }
+}
+
+/// Handle to interrupt `readline` from another thread
+/// (see `Editor::abort_handle`)
+#[derive(Clone, Debug)]
+pub struct AbortHandle {
+    event: Option<Arc<AbortEvent>>,
+}
+
+impl AbortHandle {
+    /// Make the pending or next `readline` call return
+    /// `ReadlineError::Interrupted`.
+    pub fn abort(&self) {
+        if let Some(ref event) = self.event {
+            unsafe { synchapi::SetEvent(event.0) };
+        }
+    }
+}
+
 /// Console input reader
 pub struct ConsoleRawReader {
     handle: HANDLE,
+    abort: Option<Arc<AbortEvent>>,
+    // Character of the last key read by `next_key`, if any
+    last_char: Option<char>,
 }
 
 impl ConsoleRawReader {
-    pub fn create() -> Result<ConsoleRawReader> {
+    fn create(abort: Option<Arc<AbortEvent>>) -> Result<ConsoleRawReader> {
         let handle = get_std_handle(STDIN_FILENO)?;
-        Ok(ConsoleRawReader { handle })
+        Ok(ConsoleRawReader {
+            handle,
+            abort,
+            last_char: None,
+        })
+    }
+
+    /// Wait at most `timeout_ms` for console input or `AbortHandle::abort`.
+    fn wait(&mut self, timeout_ms: DWORD) -> Result<bool> {
+        let rc = match self.abort {
+            Some(ref abort) => {
+                let handles = [self.handle, abort.0];
+                unsafe { synchapi::WaitForMultipleObjects(2, handles.as_ptr(), FALSE, timeout_ms) }
+            }
+            None => unsafe { synchapi::WaitForSingleObject(self.handle, timeout_ms) },
+        };
+        if rc == winbase::WAIT_FAILED {
+            Err(io::Error::last_os_error().into())
+        } else if rc == winbase::WAIT_OBJECT_0 + 1 {
+            Err(error::ReadlineError::Interrupted)
+        } else {
+            Ok(rc == winbase::WAIT_OBJECT_0)
+        }
     }
 }
 
@@ -114,8 +203,10 @@ impl RawReader for ConsoleRawReader {
         let mut rec: wincon::INPUT_RECORD = unsafe { mem::zeroed() };
         let mut count = 0;
         let mut surrogate = 0;
+        self.last_char = None;
         loop {
             // TODO GetNumberOfConsoleInputEvents
+            self.wait(winbase::INFINITE)?;
             check!(consoleapi::ReadConsoleInputW(
                 self.handle,
                 &mut rec,
@@ -209,9 +300,11 @@ impl RawReader for ConsoleRawReader {
                     _ => continue,
                 };
             } else if utf16 == 27 {
+                self.last_char = Some('\x1b');
                 return Ok(KeyPress::Esc);
             } else {
                 if utf16 >= 0xD800 && utf16 < 0xDC00 {
+                    // high surrogate: the low one comes with the next key event
                     surrogate = utf16;
                     continue;
                 }
@@ -226,6 +319,7 @@ impl RawReader for ConsoleRawReader {
                     return Err(error::ReadlineError::Eof);
                 };
                 let c = rc?;
+                self.last_char = Some(c);
                 if meta {
                     return Ok(KeyPress::Meta(c));
                 } else {
@@ -234,6 +328,10 @@ impl RawReader for ConsoleRawReader {
                         key = KeyPress::BackTab;
                     } else if key == KeyPress::Char(' ') && ctrl {
                         key = KeyPress::Ctrl(' ');
+                    } else if let KeyPress::Ctrl(c) = key {
+                        if shift {
+                            key = KeyPress::CtrlShift(c);
+                        }
                     }
                     return Ok(key);
                 }
@@ -241,18 +339,74 @@ impl RawReader for ConsoleRawReader {
         }
     }
 
+    /// Characters come with key events: the one of the next key which has
+    /// one is returned (a surrogate pair being combined by `next_key`).
+    fn next_char(&mut self) -> Result<char> {
+        loop {
+            let key = self.next_key(false)?;
+            if let Some(c) = self.last_char.take() {
+                return Ok(c);
+            }
+            debug!(target: "rustyline", "no character for key: {:?}", key);
+        }
+    }
+
+    /// The console reports pasted text as a burst of key events: their
+    /// characters are read until the input buffer is empty.
     fn read_pasted_text(&mut self) -> Result<String> {
-        unimplemented!()
+        let mut rec: wincon::INPUT_RECORD = unsafe { mem::zeroed() };
+        let mut count = 0;
+        let mut utf16 = Vec::new();
+        while self.wait(0)? {
+            check!(consoleapi::ReadConsoleInputW(
+                self.handle,
+                &mut rec,
+                1 as DWORD,
+                &mut count,
+            ));
+            if rec.EventType != wincon::KEY_EVENT {
+                continue;
+            }
+            let key_event = unsafe { rec.Event.KeyEvent() };
+            let c = unsafe { *key_event.uChar.UnicodeChar() };
+            if key_event.bKeyDown != 0 && c != 0 {
+                utf16.push(c);
+            }
+        }
+        let buffer = String::from_utf16_lossy(&utf16);
+        let buffer = buffer.replace("\r\n", "\n");
+        let buffer = buffer.replace("\r", "\n");
+        Ok(buffer)
     }
+
+    fn wait_for_input(&mut self, timeout_ms: i32) -> Result<bool> {
+        self.wait(timeout_ms as DWORD)
+    }
+}
+
+/// How styled output (ANSI escape sequences) is rendered
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ConsoleColorWriter {
+    /// Escape sequences are interpreted by the console
+    /// (`ENABLE_VIRTUAL_TERMINAL_PROCESSING`, Windows 10 / Server 2019 and
+    /// above)
+    Ansi,
+    /// SGR sequences are translated to console text attributes, other
+    /// escape sequences are dropped
+    Legacy,
 }
 
 pub struct ConsoleRenderer {
     out: OutputStreamType,
     handle: HANDLE,
-    cols: usize, // Number of columns in terminal
+    size: WinSize,    // Number of columns and rows in terminal
+    fixed_size: bool, // Whether `size` has been set by `set_size`
     buffer: String,
     colors_enabled: bool,
+    color_writer: ConsoleColorWriter,
+    default_attributes: WORD, // text attributes restored by `\x1b[0m`
     bell_style: BellStyle,
+    saved_cursor: Option<wincon::COORD>,
 }
 
 impl ConsoleRenderer {
@@ -260,17 +414,30 @@ impl ConsoleRenderer {
         handle: HANDLE,
         out: OutputStreamType,
         colors_enabled: bool,
+        color_writer: ConsoleColorWriter,
         bell_style: BellStyle,
     ) -> ConsoleRenderer {
         // Multi line editing is enabled by ENABLE_WRAP_AT_EOL_OUTPUT mode
-        let (cols, _) = get_win_size(handle);
+        let size = get_win_size(handle);
+        let default_attributes = {
+            let mut info: wincon::CONSOLE_SCREEN_BUFFER_INFO = unsafe { mem::zeroed() };
+            if unsafe { wincon::GetConsoleScreenBufferInfo(handle, &mut info) } == 0 {
+                wincon::FOREGROUND_RED | wincon::FOREGROUND_GREEN | wincon::FOREGROUND_BLUE
+            } else {
+                info.wAttributes
+            }
+        };
         ConsoleRenderer {
             out,
             handle,
-            cols,
+            size,
+            fixed_size: false,
             buffer: String::with_capacity(1024),
             colors_enabled,
+            color_writer,
+            default_attributes,
             bell_style,
+            saved_cursor: None,
         }
     }
 
@@ -296,23 +463,70 @@ impl ConsoleRenderer {
         ));
         Ok(())
     }
+
+    fn write_raw(&self, buf: &[u8]) -> Result<()> {
+        match self.out {
+            OutputStreamType::Stdout => {
+                io::stdout().write_all(buf)?;
+                io::stdout().flush()?;
+            }
+            OutputStreamType::Stderr => {
+                io::stderr().write_all(buf)?;
+                io::stderr().flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write `buf` translating SGR sequences to `SetConsoleTextAttribute`
+    /// calls (for consoles without virtual terminal processing)
+    fn write_legacy(&self, buf: &[u8]) -> Result<()> {
+        let mut attributes = self.default_attributes;
+        let mut start = 0;
+        let mut i = 0;
+        while i < buf.len() {
+            if buf[i] != b'\x1b' || buf.get(i + 1) != Some(&b'[') {
+                i += 1;
+                continue;
+            }
+            // CSI: parameters then a final byte in `@`..=`~`
+            let params = i + 2;
+            let end = match buf[params..].iter().position(|b| (0x40..=0x7e).contains(b)) {
+                Some(n) => params + n,
+                None => break,
+            };
+            self.write_raw(&buf[start..i])?;
+            if buf[end] == b'm' {
+                let params = String::from_utf8_lossy(&buf[params..end]);
+                attributes = sgr_attributes(&params, attributes, self.default_attributes);
+                check!(wincon::SetConsoleTextAttribute(self.handle, attributes));
+            }
+            i = end + 1;
+            start = i;
+        }
+        self.write_raw(&buf[start..])?;
+        if attributes != self.default_attributes {
+            check!(wincon::SetConsoleTextAttribute(
+                self.handle,
+                self.default_attributes
+            ));
+        }
+        Ok(())
+    }
 }
 
 impl Renderer for ConsoleRenderer {
     type Reader = ConsoleRawReader;
 
     fn move_cursor(&mut self, old: Position, new: Position) -> Result<()> {
-        let mut cursor = self.get_console_screen_buffer_info()?.dwCursorPosition;
-        if new.row > old.row {
-            cursor.Y += (new.row - old.row) as i16;
-        } else {
-            cursor.Y -= (old.row - new.row) as i16;
-        }
-        if new.col > old.col {
-            cursor.X += (new.col - old.col) as i16;
-        } else {
-            cursor.X -= (old.col - new.col) as i16;
-        }
+        let info = self.get_console_screen_buffer_info()?;
+        let mut cursor = info.dwCursorPosition;
+        // `old` may not match the screen anymore (after a resize): stay
+        // inside the screen buffer instead of overflowing
+        let row = i32::from(cursor.Y) + new.row as i32 - old.row as i32;
+        let col = i32::from(cursor.X) + new.col as i32 - old.col as i32;
+        cursor.Y = clamp_coord(row, info.dwSize.Y);
+        cursor.X = clamp_coord(col, info.dwSize.X);
         self.set_console_cursor_position(cursor)
     }
 
@@ -333,13 +547,20 @@ impl Renderer for ConsoleRenderer {
 
         self.buffer.clear();
         if let Some(highlighter) = highlighter {
-            // TODO handle ansi escape code (SetConsoleTextAttribute)
             // append the prompt
             self.buffer
                 .push_str(&highlighter.highlight_prompt(prompt, default_prompt));
             // append the input line
-            self.buffer
-                .push_str(&highlighter.highlight(line, line.pos()));
+            if let Some(region) = line.region() {
+                self.buffer
+                    .push_str(&highlighter.highlight_region(line, line.pos(), region));
+            } else {
+                self.buffer.push_str(&highlighter.highlight_overflow(
+                    line,
+                    line.pos(),
+                    line.is_too_long(),
+                ));
+            }
         } else {
             // append the prompt
             self.buffer.push_str(prompt);
@@ -348,11 +569,7 @@ impl Renderer for ConsoleRenderer {
         }
         // append hint
         if let Some(hint) = hint {
-            if let Some(highlighter) = highlighter {
-                self.buffer.push_str(&highlighter.highlight_hint(hint));
-            } else {
-                self.buffer.push_str(hint);
-            }
+            self.buffer.push_str(hint);
         }
         // position at the start of the prompt, clear to end of previous input
         let info = self.get_console_screen_buffer_info()?;
@@ -374,39 +591,29 @@ impl Renderer for ConsoleRenderer {
     }
 
     fn write_and_flush(&self, buf: &[u8]) -> Result<()> {
-        match self.out {
-            OutputStreamType::Stdout => {
-                io::stdout().write_all(buf)?;
-                io::stdout().flush()?;
-            }
-            OutputStreamType::Stderr => {
-                io::stderr().write_all(buf)?;
-                io::stderr().flush()?;
-            }
+        match self.color_writer {
+            ConsoleColorWriter::Ansi => self.write_raw(buf),
+            ConsoleColorWriter::Legacy => self.write_legacy(buf),
         }
-        Ok(())
     }
 
     /// Characters with 2 column width are correctly handled (not split).
     fn calculate_position(&self, s: &str, orig: Position) -> Position {
         let mut pos = orig;
-        for c in s.chars() {
-            let cw = if c == '\n' {
+        for c in s.graphemes(true) {
+            if c == "\n" || c == "\r\n" {
                 pos.col = 0;
                 pos.row += 1;
-                None
-            } else {
-                c.width()
-            };
-            if let Some(cw) = cw {
-                pos.col += cw;
-                if pos.col > self.cols {
-                    pos.row += 1;
-                    pos.col = cw;
-                }
+                continue;
+            }
+            let cw = grapheme_width(c);
+            pos.col += cw;
+            if pos.col > self.size.cols {
+                pos.row += 1;
+                pos.col = cw;
             }
         }
-        if pos.col == self.cols {
+        if pos.col == self.size.cols {
             pos.col = 0;
             pos.row += 1;
         }
@@ -420,6 +627,10 @@ impl Renderer for ConsoleRenderer {
                 io::stderr().flush()?;
                 Ok(())
             }
+            BellStyle::Callback(ref bell) => {
+                bell();
+                Ok(())
+            }
             _ => Ok(()),
         }
     }
@@ -433,6 +644,18 @@ impl Renderer for ConsoleRenderer {
         self.clear(n, coord)
     }
 
+    fn save_cursor(&mut self) -> Result<()> {
+        self.saved_cursor = Some(self.get_console_screen_buffer_info()?.dwCursorPosition);
+        Ok(())
+    }
+
+    fn restore_cursor(&mut self) -> Result<()> {
+        match self.saved_cursor {
+            Some(pos) => self.set_console_cursor_position(pos),
+            None => Ok(()),
+        }
+    }
+
     fn sigwinch(&self) -> bool {
         SIGWINCH.compare_and_swap(true, false, atomic::Ordering::SeqCst)
     }
@@ -440,19 +663,24 @@ impl Renderer for ConsoleRenderer {
     /// Try to get the number of columns in the current terminal,
     /// or assume 80 if it fails.
     fn update_size(&mut self) {
-        let (cols, _) = get_win_size(self.handle);
-        self.cols = cols;
+        if !self.fixed_size {
+            self.size = get_win_size(self.handle);
+        }
     }
 
     fn get_columns(&self) -> usize {
-        self.cols
+        self.size.cols
     }
 
     /// Try to get the number of rows in the current terminal,
     /// or assume 24 if it fails.
     fn get_rows(&self) -> usize {
-        let (_, rows) = get_win_size(self.handle);
-        rows
+        self.size.rows
+    }
+
+    fn set_size(&mut self, size: WinSize) {
+        self.size = size;
+        self.fixed_size = true;
     }
 
     fn colors_enabled(&self) -> bool {
@@ -472,6 +700,149 @@ impl Renderer for ConsoleRenderer {
     }
 }
 
+/// Clamp `value` to `[0, size)`
+fn clamp_coord(value: i32, size: SHORT) -> SHORT {
+    value.min(i32::from(size) - 1).max(0) as SHORT
+}
+
+/// Apply SGR `params` (like `1;31`) to console text `attributes`
+fn sgr_attributes(params: &str, mut attributes: WORD, default: WORD) -> WORD {
+    use winapi::um::wincon::{
+        BACKGROUND_BLUE, BACKGROUND_GREEN, BACKGROUND_INTENSITY, BACKGROUND_RED,
+        COMMON_LVB_REVERSE_VIDEO, COMMON_LVB_UNDERSCORE, FOREGROUND_BLUE, FOREGROUND_GREEN,
+        FOREGROUND_INTENSITY, FOREGROUND_RED,
+    };
+    const FOREGROUND: WORD = FOREGROUND_RED | FOREGROUND_GREEN | FOREGROUND_BLUE;
+    const BACKGROUND: WORD = BACKGROUND_RED | BACKGROUND_GREEN | BACKGROUND_BLUE;
+    // ANSI colors are ordered red = 1, green = 2, blue = 4
+    fn rgb(n: u16, red: WORD, green: WORD, blue: WORD) -> WORD {
+        let mut color = 0;
+        if n & 1 != 0 {
+            color |= red;
+        }
+        if n & 2 != 0 {
+            color |= green;
+        }
+        if n & 4 != 0 {
+            color |= blue;
+        }
+        color
+    }
+    let fg = |n| rgb(n, FOREGROUND_RED, FOREGROUND_GREEN, FOREGROUND_BLUE);
+    let bg = |n| rgb(n, BACKGROUND_RED, BACKGROUND_GREEN, BACKGROUND_BLUE);
+    let mut params = params.split(';').map(|param| {
+        // `\x1b[m` is the same as `\x1b[0m`
+        if param.is_empty() {
+            Some(0)
+        } else {
+            param.parse::<u16>().ok()
+        }
+    });
+    while let Some(n) = params.next() {
+        let n = match n {
+            Some(n) => n,
+            None => continue,
+        };
+        match n {
+            0 => attributes = default,
+            1 => attributes |= FOREGROUND_INTENSITY,
+            4 => attributes |= COMMON_LVB_UNDERSCORE,
+            7 => attributes |= COMMON_LVB_REVERSE_VIDEO,
+            22 => attributes &= !FOREGROUND_INTENSITY,
+            24 => attributes &= !COMMON_LVB_UNDERSCORE,
+            27 => attributes &= !COMMON_LVB_REVERSE_VIDEO,
+            30..=37 => attributes = (attributes & !FOREGROUND) | fg(n - 30),
+            39 => {
+                attributes = (attributes & !(FOREGROUND | FOREGROUND_INTENSITY))
+                    | (default & (FOREGROUND | FOREGROUND_INTENSITY))
+            }
+            40..=47 => attributes = (attributes & !BACKGROUND) | bg(n - 40),
+            49 => {
+                attributes = (attributes & !(BACKGROUND | BACKGROUND_INTENSITY))
+                    | (default & (BACKGROUND | BACKGROUND_INTENSITY))
+            }
+            90..=97 => attributes = (attributes & !FOREGROUND) | fg(n - 90) | FOREGROUND_INTENSITY,
+            100..=107 => {
+                attributes = (attributes & !BACKGROUND) | bg(n - 100) | BACKGROUND_INTENSITY
+            }
+            // 256 / true colors: `38;5;n` or `38;2;r;g;b`
+            38 => {
+                if let Some((color, bright)) = extended_color(&mut params) {
+                    attributes = (attributes & !(FOREGROUND | FOREGROUND_INTENSITY)) | fg(color);
+                    if bright {
+                        attributes |= FOREGROUND_INTENSITY;
+                    }
+                }
+            }
+            48 => {
+                if let Some((color, bright)) = extended_color(&mut params) {
+                    attributes = (attributes & !(BACKGROUND | BACKGROUND_INTENSITY)) | bg(color);
+                    if bright {
+                        attributes |= BACKGROUND_INTENSITY;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    attributes
+}
+
+/// Consume the arguments of an extended color (after `38` or `48`) and
+/// return the nearest of the 16 console colors: ANSI color number and
+/// intensity.
+fn extended_color<I: Iterator<Item = Option<u16>>>(params: &mut I) -> Option<(u16, bool)> {
+    let mut next = || params.next().and_then(|n| n);
+    match next()? {
+        5 => {
+            let n = next()?;
+            match n {
+                0..=7 => Some((n, false)),
+                8..=15 => Some((n - 8, true)),
+                16..=231 => {
+                    // 6x6x6 color cube
+                    const LEVELS: [u16; 6] = [0, 95, 135, 175, 215, 255];
+                    let i = usize::from(n - 16);
+                    let (r, g, b) = (LEVELS[i / 36], LEVELS[i / 6 % 6], LEVELS[i % 6]);
+                    Some(nearest_color(r, g, b))
+                }
+                232..=255 => {
+                    // grayscale ramp
+                    let level = 8 + 10 * (n - 232);
+                    Some(nearest_color(level, level, level))
+                }
+                _ => None,
+            }
+        }
+        2 => {
+            let (r, g, b) = (next()?, next()?, next()?);
+            Some(nearest_color(r, g, b))
+        }
+        _ => None,
+    }
+}
+
+/// Nearest console color of an RGB color
+fn nearest_color(r: u16, g: u16, b: u16) -> (u16, bool) {
+    let max = r.max(g).max(b);
+    if max < 64 {
+        return (0, false); // black
+    }
+    // keep the channels which are close to the brightest one
+    let threshold = max / 2;
+    let color =
+        u16::from(r > threshold) | u16::from(g > threshold) << 1 | u16::from(b > threshold) << 2;
+    if color == 7 {
+        // dark gray, light gray or white
+        return match max {
+            0..=143 => (0, true),
+            144..=224 => (7, false),
+            _ => (7, true),
+        };
+    }
+    (color, max > 191)
+}
+
 static SIGWINCH: atomic::AtomicBool = atomic::AtomicBool::new(false);
 
 #[cfg(not(test))]
@@ -487,31 +858,41 @@ pub struct Console {
     ansi_colors_supported: bool,
     stream_type: OutputStreamType,
     bell_style: BellStyle,
+    abort: Option<Arc<AbortEvent>>,
+    // Whether RAW mode is enabled by a `ConsoleMode` not disabled yet
+    raw_mode: Arc<atomic::AtomicBool>,
 }
 
 impl Console {
     fn colors_enabled(&self) -> bool {
-        // TODO ANSI Colors & Windows <10
         match self.color_mode {
-            ColorMode::Enabled => self.stdstream_isatty && self.ansi_colors_supported,
+            ColorMode::Enabled => self.stdstream_isatty,
             ColorMode::Forced => true,
             ColorMode::Disabled => false,
         }
     }
+
+    /// Without virtual terminal processing (Windows < 10, Windows Server <
+    /// 2019), escape sequences must be translated. When the output is not a
+    /// console (redirected), they are written as is.
+    fn color_writer(&self) -> ConsoleColorWriter {
+        if self.ansi_colors_supported || !self.stdstream_isatty {
+            ConsoleColorWriter::Ansi
+        } else {
+            ConsoleColorWriter::Legacy
+        }
+    }
 }
 
 impl Term for Console {
     type Mode = ConsoleMode;
+    type AbortHandle = AbortHandle;
     type Reader = ConsoleRawReader;
     type Writer = ConsoleRenderer;
 
-    fn new(
-        color_mode: ColorMode,
-        stream_type: OutputStreamType,
-        _tab_stop: usize,
-        bell_style: BellStyle,
-    ) -> Console {
+    fn new(config: &Config) -> Console {
         use std::ptr;
+        let stream_type = config.output_stream();
         let stdin_handle = get_std_handle(STDIN_FILENO);
         let stdin_isatty = match stdin_handle {
             Ok(handle) => {
@@ -539,10 +920,18 @@ impl Term for Console {
             stdin_handle: stdin_handle.unwrap_or(ptr::null_mut()),
             stdstream_isatty,
             stdstream_handle: stdstream_handle.unwrap_or(ptr::null_mut()),
-            color_mode,
+            color_mode: config.color_mode(),
             ansi_colors_supported: false,
             stream_type,
-            bell_style,
+            bell_style: config.bell_style(),
+            abort: match AbortEvent::new() {
+                Ok(event) => Some(Arc::new(event)),
+                Err(e) => {
+                    debug!(target: "rustyline", "cannot create abort event: {}", e);
+                    None
+                }
+            },
+            raw_mode: Arc::new(atomic::AtomicBool::new(false)),
         }
     }
 
@@ -602,16 +991,19 @@ impl Term for Console {
             None
         };
 
+        self.raw_mode.store(true, atomic::Ordering::SeqCst);
         Ok(ConsoleMode {
             original_stdin_mode,
             stdin_handle: self.stdin_handle,
             original_stdstream_mode,
             stdstream_handle: self.stdstream_handle,
+            raw_mode: Arc::clone(&self.raw_mode),
         })
     }
 
-    fn create_reader(&self, _: &Config) -> Result<ConsoleRawReader> {
-        ConsoleRawReader::create()
+    // Raw sequences cannot be bound: keys are read as input records.
+    fn create_reader(&self, _: &Config, _: Arc<RwLock<SequenceTrie>>) -> Result<ConsoleRawReader> {
+        ConsoleRawReader::create(self.abort.clone())
     }
 
     fn create_writer(&self) -> ConsoleRenderer {
@@ -619,9 +1011,21 @@ impl Term for Console {
             self.stdstream_handle,
             self.stream_type,
             self.colors_enabled(),
-            self.bell_style,
+            self.color_writer(),
+            self.bell_style.clone(),
         )
     }
+
+    fn abort_handle(&self) -> AbortHandle {
+        AbortHandle {
+            event: self.abort.clone(),
+        }
+    }
+
+    fn update_config(&mut self, config: &Config) {
+        self.color_mode = config.color_mode();
+        self.bell_style = config.bell_style();
+    }
 }
 
 unsafe impl Send for Console {}
@@ -629,7 +1033,65 @@ unsafe impl Sync for Console {}
 
 #[cfg(test)]
 mod test {
-    use super::Console;
+    use super::{clamp_coord, sgr_attributes, Console};
+    use winapi::um::wincon::{
+        BACKGROUND_BLUE, COMMON_LVB_UNDERSCORE, FOREGROUND_BLUE, FOREGROUND_GREEN,
+        FOREGROUND_INTENSITY, FOREGROUND_RED,
+    };
+
+    #[test]
+    fn clamp() {
+        assert_eq!(0, clamp_coord(-3, 80));
+        assert_eq!(5, clamp_coord(5, 80));
+        assert_eq!(79, clamp_coord(120, 80));
+        assert_eq!(0, clamp_coord(1, 0));
+    }
+
+    #[test]
+    fn sgr() {
+        let default = FOREGROUND_RED | FOREGROUND_GREEN | FOREGROUND_BLUE;
+        assert_eq!(FOREGROUND_RED, sgr_attributes("31", default, default));
+        assert_eq!(
+            FOREGROUND_BLUE | FOREGROUND_INTENSITY,
+            sgr_attributes("1;34", default, default)
+        );
+        assert_eq!(
+            FOREGROUND_GREEN | FOREGROUND_INTENSITY,
+            sgr_attributes("92", default, default)
+        );
+        assert_eq!(
+            default | BACKGROUND_BLUE | COMMON_LVB_UNDERSCORE,
+            sgr_attributes("44;4", default, default)
+        );
+        assert_eq!(default, sgr_attributes("0", FOREGROUND_RED, default));
+        assert_eq!(default, sgr_attributes("", FOREGROUND_RED, default));
+        assert_eq!(default, sgr_attributes("39", FOREGROUND_RED, default));
+    }
+
+    #[test]
+    fn sgr_extended_colors() {
+        let default = FOREGROUND_RED | FOREGROUND_GREEN | FOREGROUND_BLUE;
+        // the color arguments are not taken for attributes
+        assert_eq!(FOREGROUND_RED, sgr_attributes("38;5;1", default, default));
+        assert_eq!(
+            FOREGROUND_BLUE | FOREGROUND_INTENSITY,
+            sgr_attributes("38;5;12", default, default)
+        );
+        assert_eq!(
+            FOREGROUND_RED | FOREGROUND_INTENSITY,
+            sgr_attributes("38;2;255;0;0", default, default)
+        );
+        assert_eq!(
+            FOREGROUND_GREEN | FOREGROUND_INTENSITY | COMMON_LVB_UNDERSCORE,
+            sgr_attributes("38;5;46;4", default, default)
+        );
+        assert_eq!(
+            default | BACKGROUND_BLUE,
+            sgr_attributes("48;2;0;0;128", default, default)
+        );
+        // incomplete
+        assert_eq!(default, sgr_attributes("38;2;255", default, default));
+    }
 
     #[test]
     fn test_send() {
@@ -3,7 +3,10 @@ use std::fmt::Debug;
 
 use crate::keymap::RepeatCount;
 use crate::line_buffer::{ChangeListener, DeleteListener, Direction, LineBuffer};
+#[cfg(not(feature = "tracing"))]
 use log::debug;
+#[cfg(feature = "tracing")]
+use tracing::debug;
 use unicode_segmentation::UnicodeSegmentation;
 
 enum Change {
@@ -0,0 +1,37 @@
+//! `Editor::readline_from_fds` with a real pseudo-terminal
+#![cfg(all(unix, not(feature = "mock"), not(feature = "crossterm")))]
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::io::FromRawFd;
+use std::thread;
+
+use nix::pty::openpty;
+use rustyline::Editor;
+
+#[test]
+fn readline_from_fds() {
+    let pty = openpty(None, None).unwrap();
+    let mut master = unsafe { File::from_raw_fd(pty.master) };
+    master.write_all(b"ab\x02c\r").unwrap();
+    // drain the output so that the editor never blocks on a full pty
+    let mut output = master.try_clone().unwrap();
+    let reader = thread::spawn(move || {
+        let mut buf = [0; 1024];
+        while let Ok(n) = output.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+        }
+    });
+
+    let mut editor = Editor::<()>::new();
+    let line = editor.readline_from_fds(pty.slave, pty.slave, ">> ");
+    assert_eq!("acb", line.unwrap());
+
+    // the slave is owned by the caller: it is still open
+    assert_ne!(-1, unsafe { libc::fcntl(pty.slave, libc::F_GETFD) });
+    nix::unistd::close(pty.slave).unwrap();
+    drop(master);
+    reader.join().unwrap();
+}